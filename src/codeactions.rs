@@ -1,17 +1,29 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
+use itertools::Itertools;
 use pathdiff::diff_paths;
 use tower_lsp::lsp_types::{
-    CodeAction, CodeActionOrCommand, CodeActionParams, CreateFile, CreateFileOptions,
-    DocumentChangeOperation, DocumentChanges, OneOf, OptionalVersionedTextDocumentIdentifier,
-    Position, Range, ResourceOp, TextDocumentEdit, TextEdit, Url, WorkspaceEdit,
+    CodeAction, CodeActionKind, CodeActionOrCommand, CodeActionParams, Command, CreateFile,
+    CreateFileOptions, DocumentChangeOperation, DocumentChanges, OneOf,
+    OptionalVersionedTextDocumentIdentifier, Position, Range, ResourceOp, TextDocumentEdit,
+    TextEdit, Url, WorkspaceEdit,
 };
 
 use crate::{
+    completion::{
+        matcher::{fuzzy_match, Matchable},
+        new_block_id,
+    },
     config::Settings,
-    daily::filename_is_formatted,
-    diagnostics::path_unresolved_references,
-    vault::{Reference, Vault},
+    daily::{daily_note_date, daily_note_folder},
+    diagnostics::{broken_fragment, path_unresolved_references},
+    formatting::range_text,
+    selection::{self, LIST_ITEM},
+    vault::{
+        escape_link_brackets, escape_wiki_link_text, github_slug, normalize_for_match,
+        unescape_link_brackets, unescape_wiki_link_text, Block, MatchOpts, Rangeable, Reference,
+        Referenceable, Vault,
+    },
 };
 
 pub fn code_actions(
@@ -41,14 +53,7 @@ pub fn code_actions(
                     Reference::WikiFileLink(_data) => {
                         let filename = &reference.data().reference_text;
 
-                        let mut new_path_buf = vault.root_dir().clone();
-                        if filename_is_formatted(settings, filename) {
-                            new_path_buf.push(&settings.daily_notes_folder);
-                        } else {
-                            new_path_buf.push(&settings.new_file_folder_path);
-                        }
-                        new_path_buf.push(filename);
-                        new_path_buf.set_extension("md");
+                        let new_path_buf = new_file_path(vault, settings, filename);
 
                         let new_path = Url::from_file_path(&new_path_buf).ok()?;
 
@@ -72,14 +77,7 @@ pub fn code_actions(
                     }
                     Reference::WikiHeadingLink(_data, link_path, heading) => {
 
-                        let mut new_path_buf = vault.root_dir().clone();
-                        if filename_is_formatted(settings, link_path) {
-                            new_path_buf.push(&settings.daily_notes_folder);
-                        } else {
-                            new_path_buf.push(&settings.new_file_folder_path);
-                        }
-                        new_path_buf.push(link_path);
-                        new_path_buf.set_extension("md");
+                        let new_path_buf = new_file_path(vault, settings, link_path);
 
                         let new_path = Url::from_file_path(&new_path_buf).ok()?;
 
@@ -144,6 +142,1039 @@ pub fn code_actions(
                 }
 
             })
+            .chain(fragment_fix_actions(vault, params, path, settings))
+            .chain(convert_link_action(vault, params, path))
+            .chain(disambiguate_link_actions(vault, params, path))
+            .chain(extract_note_action(vault, params, path, settings))
+            .chain(block_reference_action(vault, params, path, settings))
             .collect(),
     )
 }
+
+/// A heading or block a broken link fragment could point to instead
+#[derive(Clone)]
+struct FragmentCandidate {
+    name: String,
+    fragment: String,
+}
+
+impl Matchable for FragmentCandidate {
+    fn match_string(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Point a link's missing `#heading` or `#^block` at the closest ones its note does have
+fn fragment_fix_actions(
+    vault: &Vault,
+    params: &CodeActionParams,
+    path: &Path,
+    settings: &Settings,
+) -> Vec<CodeActionOrCommand> {
+    let Some(reference) = vault.select_reference_at_position(path, params.range.start) else {
+        return vec![];
+    };
+    let Some((_, mdfile, range)) = broken_fragment(vault, path, reference) else {
+        return vec![];
+    };
+
+    let (query, candidates) = match reference {
+        Reference::WikiHeadingLink(.., heading) | Reference::MDHeadingLink(.., heading) => (
+            heading,
+            mdfile
+                .headings
+                .iter()
+                .map(|heading| FragmentCandidate {
+                    name: heading.plain_text.clone(),
+                    fragment: match reference {
+                        // markdown links keep resolving through the GitHub anchor whatever the heading contains
+                        Reference::MDHeadingLink(..) => heading.anchor.clone(),
+                        _ => heading.heading_text.clone(),
+                    },
+                })
+                .collect::<Vec<_>>(),
+        ),
+        Reference::WikiIndexedBlockLink(.., index) | Reference::MDIndexedBlockLink(.., index) => (
+            index,
+            mdfile
+                .indexed_blocks
+                .iter()
+                .map(|block| FragmentCandidate {
+                    name: block.index.clone(),
+                    fragment: format!("^{}", block.index),
+                })
+                .collect(),
+        ),
+        _ => return vec![],
+    };
+
+    let matched = fuzzy_match(query, candidates.clone(), &settings.case_matching)
+        .into_iter()
+        .sorted_by_key(|(_, score)| std::cmp::Reverse(*score))
+        .map(|(candidate, _)| candidate)
+        .collect::<Vec<_>>();
+    // nothing close; every heading or block is as good a guess as any
+    let closest = match matched.is_empty() {
+        true => candidates,
+        false => matched,
+    };
+
+    closest
+        .into_iter()
+        .take(5)
+        .filter_map(|candidate| {
+            Some(CodeActionOrCommand::CodeAction(CodeAction {
+                title: format!("Link to #{}", candidate.fragment),
+                kind: Some(CodeActionKind::QUICKFIX),
+                edit: Some(WorkspaceEdit {
+                    document_changes: Some(DocumentChanges::Edits(vec![TextDocumentEdit {
+                        text_document: OptionalVersionedTextDocumentIdentifier {
+                            uri: Url::from_file_path(path).ok()?,
+                            version: None,
+                        },
+                        edits: vec![OneOf::Left(TextEdit {
+                            range,
+                            new_text: format!("#{}", candidate.fragment),
+                        })],
+                    }])),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }))
+        })
+        .collect()
+}
+
+/// Point a wiki link to a name several notes share at one of them, by the shortest path only that note has:
+/// `[[note]]` as `[[work/note]]`
+fn disambiguate_link_actions(
+    vault: &Vault,
+    params: &CodeActionParams,
+    path: &Path,
+) -> Vec<CodeActionOrCommand> {
+    let Some(reference) = vault.select_reference_at_position(path, params.range.start) else {
+        return vec![];
+    };
+    let file = match reference {
+        Reference::WikiFileLink(data) => &data.reference_text,
+        Reference::WikiHeadingLink(_, file, _) | Reference::WikiIndexedBlockLink(_, file, _) => {
+            file
+        }
+        _ => return vec![],
+    };
+
+    let candidates = vault
+        .select_referenceables_for_reference(reference, path)
+        .into_iter()
+        .filter_map(|referenceable| match referenceable {
+            Referenceable::File(path, _)
+            | Referenceable::Heading(path, _)
+            | Referenceable::IndexedBlock(path, _) => Some(path),
+            _ => None,
+        })
+        .unique()
+        .sorted()
+        .collect_vec();
+    if candidates.len() < 2 {
+        return vec![];
+    }
+
+    let unambiguous = vault.select_unambiguous_refnames();
+    let range = *reference.data().range;
+    let Some(text) = vault
+        .ropes
+        .get(path)
+        .and_then(|rope| range_text(rope, range))
+    else {
+        return vec![];
+    };
+    // the link's target starts after its brackets, past any spaces
+    let Some(target_start) = text
+        .find("[[")
+        .and_then(|brackets| Some(brackets + 2 + text[brackets + 2..].find(file.as_str())?))
+    else {
+        return vec![];
+    };
+
+    candidates
+        .into_iter()
+        .filter_map(|candidate| {
+            let qualified = unambiguous.get(candidate.as_path())?;
+            let new_text = format!(
+                "{}{qualified}{}",
+                &text[..target_start],
+                &text[target_start + file.len()..]
+            );
+
+            Some(CodeActionOrCommand::CodeAction(CodeAction {
+                title: format!("Link to {qualified}"),
+                kind: Some(CodeActionKind::QUICKFIX),
+                edit: Some(WorkspaceEdit {
+                    document_changes: Some(DocumentChanges::Edits(vec![TextDocumentEdit {
+                        text_document: OptionalVersionedTextDocumentIdentifier {
+                            uri: Url::from_file_path(path).ok()?,
+                            version: None,
+                        },
+                        edits: vec![OneOf::Left(TextEdit { range, new_text })],
+                    }])),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }))
+        })
+        .collect()
+}
+
+/// Where the note for the unresolved link to `name` is created: daily notes go to their folder, other notes to
+/// `new_file_folder_path`
+fn new_file_path(vault: &Vault, settings: &Settings, name: &str) -> PathBuf {
+    let folder = match daily_note_date(settings, name) {
+        Some(date) => daily_note_folder(settings, vault.root_dir(), date),
+        None => vault.root_dir().join(&settings.new_file_folder_path),
+    };
+
+    folder.join(format!("{name}.md"))
+}
+
+/// Move the selected text to a new note, named after its first line, and leave a link or embed to it in its place
+fn extract_note_action(
+    vault: &Vault,
+    params: &CodeActionParams,
+    path: &Path,
+    settings: &Settings,
+) -> Option<CodeActionOrCommand> {
+    let range = params.range;
+    if range.start == range.end {
+        return None;
+    }
+
+    let rope = vault.ropes.get(path)?;
+    let offset = |position: Position| {
+        let line = vault.select_line(path, position.line as isize)?;
+        Some(
+            rope.try_line_to_char(position.line as usize).ok()?
+                + settings
+                    .position_encoding
+                    .char_index(&line, position.character),
+        )
+    };
+    let text = rope
+        .get_slice(offset(range.start)?..offset(range.end)?)?
+        .to_string();
+    if text.trim().is_empty() {
+        return None;
+    }
+
+    let title = extracted_note_title(&text);
+    let new_path_buf = (0..)
+        .map(|suffix| match suffix {
+            0 => title.clone(),
+            suffix => format!("{title} {suffix}"),
+        })
+        .map(|name| new_file_path(vault, settings, &name))
+        .find(|new_path| !note_name_taken(vault, new_path))?;
+    let name = new_path_buf.file_stem()?.to_str()?.to_string();
+    let new_path = Url::from_file_path(&new_path_buf).ok()?;
+
+    let link = match settings.extract_note_embed {
+        true => format!("![[{name}]]"),
+        false => format!("[[{name}]]"),
+    };
+    let content = format!("{}\n", text.trim_matches('\n'));
+    let start = Position {
+        line: 0,
+        character: 0,
+    };
+
+    Some(CodeActionOrCommand::CodeAction(CodeAction {
+        title: format!("Extract to new note: {name}"),
+        kind: Some(CodeActionKind::REFACTOR_EXTRACT),
+        edit: Some(WorkspaceEdit {
+            document_changes: Some(DocumentChanges::Operations(vec![
+                DocumentChangeOperation::Op(ResourceOp::Create(CreateFile {
+                    uri: new_path.clone(),
+                    options: Some(CreateFileOptions {
+                        ignore_if_exists: Some(false),
+                        overwrite: Some(false),
+                    }),
+                    annotation_id: None,
+                })),
+                DocumentChangeOperation::Edit(TextDocumentEdit {
+                    text_document: OptionalVersionedTextDocumentIdentifier {
+                        uri: new_path,
+                        version: None,
+                    },
+                    edits: vec![OneOf::Left(TextEdit {
+                        range: Range { start, end: start },
+                        new_text: content,
+                    })],
+                }),
+                DocumentChangeOperation::Edit(TextDocumentEdit {
+                    text_document: OptionalVersionedTextDocumentIdentifier {
+                        uri: Url::from_file_path(path).ok()?,
+                        version: None,
+                    },
+                    edits: vec![OneOf::Left(TextEdit {
+                        range,
+                        new_text: match text.ends_with('\n') {
+                            true => format!("{link}\n"),
+                            false => link,
+                        },
+                    })],
+                }),
+            ])),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }))
+}
+
+/// Run `createBlockReference` for the paragraph or list item under the cursor, which gives it a `^blockid` and returns
+/// a link to it
+fn block_reference_action(
+    vault: &Vault,
+    params: &CodeActionParams,
+    path: &Path,
+    settings: &Settings,
+) -> Option<CodeActionOrCommand> {
+    block_reference(vault, settings, path, params.range.start)?;
+
+    Some(CodeActionOrCommand::CodeAction(CodeAction {
+        title: "Create block reference here".into(),
+        kind: Some(CodeActionKind::REFACTOR),
+        command: Some(Command {
+            title: "Create block reference here".into(),
+            command: "createBlockReference".into(),
+            arguments: Some(vec![
+                serde_json::to_value(Url::from_file_path(path).ok()?).ok()?,
+                serde_json::to_value(params.range.start).ok()?,
+            ]),
+        }),
+        ..Default::default()
+    }))
+}
+
+/// A link to the paragraph or list item at `position`, and the edit that appends a new `^blockid` to the end of its
+/// text when it has none; a list item's text ends before the items nested in it
+pub fn block_reference(
+    vault: &Vault,
+    settings: &Settings,
+    path: &Path,
+    position: Position,
+) -> Option<(String, Option<TextEdit>)> {
+    let md_file = vault.md_files.get(path)?;
+    let block = selection::block_ranges(vault, path, position)
+        .into_iter()
+        .next()?;
+    if md_file
+        .codeblocks
+        .iter()
+        .any(|codeblock| codeblock.range().start.line == block.start.line)
+    {
+        return None;
+    }
+
+    let line_text = |line: u32| {
+        let mut chars = vault.select_line(path, line as isize).unwrap_or_default();
+        while chars.last().is_some_and(|c| c.is_whitespace()) {
+            chars.pop();
+        }
+        chars
+    };
+    let end_line = (block.start.line + 1..=block.end.line)
+        .take_while(|line| !LIST_ITEM.is_match(&String::from_iter(line_text(*line))))
+        .last()
+        .unwrap_or(block.start.line);
+
+    let existing = md_file
+        .indexed_blocks
+        .iter()
+        .find(|indexed| (block.start.line..=end_line).contains(&indexed.range.start.line));
+    let (id, edit) = match existing {
+        Some(indexed) => (indexed.index.clone(), None),
+        None => {
+            let chars = line_text(end_line);
+            let text = String::from_iter(&chars);
+            let id = new_block_id(
+                vault,
+                &Block {
+                    text: text.trim(),
+                    range: Default::default(),
+                    file: path,
+                },
+            );
+            let end = Position {
+                line: end_line,
+                character: settings.position_encoding.column(&chars, chars.len()),
+            };
+            let edit = TextEdit {
+                range: Range { start: end, end },
+                new_text: format!(" ^{id}"),
+            };
+
+            (id, Some(edit))
+        }
+    };
+
+    let link = format!(
+        "[[{}{}#^{id}]]",
+        path.file_stem()?.to_str()?,
+        match settings.include_md_extension_wikilink {
+            true => ".md",
+            false => "",
+        }
+    );
+
+    Some((link, edit))
+}
+
+/// The first line of `text` with heading markers and the characters links or file names can not hold left out; a `.`
+/// would read as the start of a file extension in the link
+fn extracted_note_title(text: &str) -> String {
+    let title = text
+        .lines()
+        .map(|line| line.trim().trim_start_matches('#').trim())
+        .find(|line| !line.is_empty())
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| {
+            !matches!(
+                c,
+                '[' | ']' | '#' | '^' | '|' | '\\' | '/' | ':' | '*' | '?' | '"' | '<' | '>' | '.'
+            )
+        })
+        .take(80)
+        .collect::<String>();
+
+    match title.trim() {
+        "" => "Untitled".to_string(),
+        title => title.to_string(),
+    }
+}
+
+/// Whether a note at `path` would clash with another: the path exists, or a note elsewhere has the same name, which
+/// would make the link ambiguous
+fn note_name_taken(vault: &Vault, path: &Path) -> bool {
+    let Some(name) = path.file_stem().and_then(|name| name.to_str()) else {
+        return true;
+    };
+
+    let name = normalize_for_match(name, &MatchOpts::CASEFOLD);
+
+    path.exists()
+        || vault.md_files.keys().any(|existing| {
+            existing
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .is_some_and(|stem| normalize_for_match(stem, &MatchOpts::CASEFOLD) == name)
+        })
+}
+
+/// Toggle the link under the cursor between `[[wiki]]` and `[markdown](link.md)` syntax
+fn convert_link_action(
+    vault: &Vault,
+    params: &CodeActionParams,
+    path: &Path,
+) -> Option<CodeActionOrCommand> {
+    let reference = vault.select_reference_at_position(path, params.range.start)?;
+
+    let (title, new_text) = match reference {
+        Reference::WikiFileLink(..)
+        | Reference::WikiHeadingLink(..)
+        | Reference::WikiIndexedBlockLink(..) => (
+            "Convert to Markdown link",
+            wiki_to_markdown(vault, path, reference)?,
+        ),
+        Reference::MDFileLink(..)
+        | Reference::MDHeadingLink(..)
+        | Reference::MDIndexedBlockLink(..) => (
+            "Convert to wiki link",
+            markdown_to_wiki(vault, path, reference)?,
+        ),
+        _ => return None,
+    };
+
+    Some(CodeActionOrCommand::CodeAction(CodeAction {
+        title: title.into(),
+        kind: Some(CodeActionKind::REFACTOR_REWRITE),
+        edit: Some(WorkspaceEdit {
+            document_changes: Some(DocumentChanges::Edits(vec![TextDocumentEdit {
+                text_document: OptionalVersionedTextDocumentIdentifier {
+                    uri: Url::from_file_path(path).ok()?,
+                    version: None,
+                },
+                edits: vec![OneOf::Left(TextEdit {
+                    range: *reference.data().range,
+                    new_text,
+                })],
+            }])),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }))
+}
+
+/// `[[file#heading|display]]` as `[display](file.md#heading-slug)`; without display text the wiki link's target is shown
+pub fn wiki_to_markdown(vault: &Vault, path: &Path, reference: &Reference) -> Option<String> {
+    let data = reference.data();
+
+    let (file, fragment) = match reference {
+        Reference::WikiFileLink(data) => (data.reference_text.as_str(), None),
+        Reference::WikiHeadingLink(_, file, heading) => (
+            file.as_str(),
+            Some(
+                vault
+                    .select_referenceables_for_reference(reference, path)
+                    .into_iter()
+                    .find_map(|referenceable| match referenceable {
+                        Referenceable::Heading(_, heading) => Some(heading.anchor.clone()),
+                        _ => None,
+                    })
+                    .unwrap_or_else(|| github_slug(heading)),
+            ),
+        ),
+        Reference::WikiIndexedBlockLink(_, file, index) => {
+            (file.as_str(), Some(format!("^{index}")))
+        }
+        _ => return None,
+    };
+
+    let display = escape_link_brackets(
+        &data
+            .display_text
+            .as_deref()
+            .map(unescape_wiki_link_text)
+            .unwrap_or_else(|| data.reference_text.clone()),
+    );
+    let destination = match fragment {
+        Some(fragment) => format!("{file}.md#{fragment}"),
+        None => format!("{file}.md"),
+    };
+
+    match destination.contains(' ') {
+        true => Some(format!("[{display}](<{destination}>)")),
+        false => Some(format!("[{display}]({destination})")),
+    }
+}
+
+/// `[display](file.md#heading-slug)` as `[[file#Heading|display]]`, dropping the display text when it matches the target
+pub fn markdown_to_wiki(vault: &Vault, path: &Path, reference: &Reference) -> Option<String> {
+    let data = reference.data();
+
+    let (file, infile) = match reference {
+        Reference::MDFileLink(data) => (data.reference_text.as_str(), None),
+        Reference::MDHeadingLink(_, file, heading) => (
+            file.as_str(),
+            Some(
+                vault
+                    .select_referenceables_for_reference(reference, path)
+                    .into_iter()
+                    .find_map(|referenceable| match referenceable {
+                        Referenceable::Heading(_, heading) => Some(heading.heading_text.clone()),
+                        _ => None,
+                    })
+                    .unwrap_or_else(|| decode_destination(heading)),
+            ),
+        ),
+        Reference::MDIndexedBlockLink(_, file, index) => (file.as_str(), Some(format!("^{index}"))),
+        _ => return None,
+    };
+
+    let file = decode_destination(file);
+    let file = file.trim_start_matches("./").trim_start_matches('/');
+    let target = match infile {
+        Some(infile) => format!("{file}#{infile}"),
+        None => file.to_string(),
+    };
+
+    match data.display_text.as_deref().map(unescape_link_brackets) {
+        Some(display) if !display.is_empty() && display != target => {
+            Some(format!("[[{target}|{}]]", escape_wiki_link_text(&display)))
+        }
+        _ => Some(format!("[[{target}]]")),
+    }
+}
+
+/// A markdown link destination as the path it names, without its `<...>` wrapper and with its `%XX` escapes decoded
+fn decode_destination(destination: &str) -> String {
+    let destination = destination
+        .strip_prefix('<')
+        .and_then(|destination| destination.strip_suffix('>'))
+        .unwrap_or(destination);
+
+    let bytes = destination.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let escaped = bytes
+            .get(index + 1..index + 3)
+            .filter(|hex| bytes[index] == b'%' && hex.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            None => {
+                decoded.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+
+    String::from_utf8(decoded).unwrap_or_else(|_| destination.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use tower_lsp::lsp_types::{
+        ClientCapabilities, CodeAction, CodeActionContext, CodeActionOrCommand, CodeActionParams,
+        DocumentChangeOperation, DocumentChanges, OneOf, PartialResultParams, Position, Range,
+        ResourceOp, TextDocumentIdentifier, TextEdit, Url, WorkDoneProgressParams, WorkspaceEdit,
+    };
+
+    use crate::{config::Settings, vault::Vault};
+
+    /// Applies the link conversion action to `line`, with the cursor on its first character
+    fn convert(line: &str) -> Option<String> {
+        let (vault, params, path) = setup(line);
+
+        match super::convert_link_action(&vault, &params, &path)? {
+            CodeActionOrCommand::CodeAction(action) => match action.edit?.document_changes? {
+                DocumentChanges::Edits(edits) => match &edits[0].edits[0] {
+                    OneOf::Left(edit) => Some(edit.new_text.clone()),
+                    OneOf::Right(edit) => Some(edit.text_edit.new_text.clone()),
+                },
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// A vault with `/vault/Note.md`, and code action params for the first character of `line` in `/vault/source.md`
+    fn setup(line: &str) -> (Vault, CodeActionParams, PathBuf) {
+        let root_dir = Path::new("/vault");
+        let settings = Settings::new(root_dir, &ClientCapabilities::default()).unwrap();
+        let mut vault = Vault::construct_vault(&settings, root_dir).unwrap();
+        Vault::update_vault(
+            &settings,
+            &mut vault,
+            (
+                &PathBuf::from("/vault/Note.md"),
+                "# Heading Two\n\nSome text ^abc\n",
+            ),
+        );
+        let path = PathBuf::from("/vault/source.md");
+        Vault::update_vault(&settings, &mut vault, (&path, line));
+
+        let position = Position {
+            line: 0,
+            character: 1,
+        };
+        let params = CodeActionParams {
+            text_document: TextDocumentIdentifier {
+                uri: Url::from_file_path(&path).unwrap(),
+            },
+            range: Range {
+                start: position,
+                end: position,
+            },
+            context: CodeActionContext::default(),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        };
+
+        (vault, params, path)
+    }
+
+    /// (title, replaced range, new text) of the fixes for the broken link fragment in `line`
+    fn fragment_fixes(line: &str) -> Vec<(String, Range, String)> {
+        let (vault, params, path) = setup(line);
+        let settings = Settings::new(Path::new("/vault"), &ClientCapabilities::default()).unwrap();
+
+        super::fragment_fix_actions(&vault, &params, &path, &settings)
+            .into_iter()
+            .map(|action| match action {
+                CodeActionOrCommand::CodeAction(CodeAction {
+                    title,
+                    edit:
+                        Some(WorkspaceEdit {
+                            document_changes: Some(DocumentChanges::Edits(edits)),
+                            ..
+                        }),
+                    ..
+                }) => match &edits[0].edits[0] {
+                    OneOf::Left(edit) => (title, edit.range, edit.new_text.clone()),
+                    OneOf::Right(edit) => panic!("unexpected annotated edit {:?}", edit),
+                },
+                action => panic!("unexpected action {:?}", action),
+            })
+            .collect()
+    }
+
+    /// The created note, its content and the text that replaces the selection of `text` from `start` to `end`
+    fn extract(
+        text: &str,
+        start: (u32, u32),
+        end: (u32, u32),
+        embed: bool,
+    ) -> Option<(PathBuf, String, Range, String)> {
+        let (vault, mut params, path) = setup(text);
+        let mut settings =
+            Settings::new(Path::new("/vault"), &ClientCapabilities::default()).unwrap();
+        settings.extract_note_embed = embed;
+        params.range = Range {
+            start: Position {
+                line: start.0,
+                character: start.1,
+            },
+            end: Position {
+                line: end.0,
+                character: end.1,
+            },
+        };
+
+        let CodeActionOrCommand::CodeAction(action) =
+            super::extract_note_action(&vault, &params, &path, &settings)?
+        else {
+            return None;
+        };
+        let Some(DocumentChanges::Operations(operations)) = action.edit?.document_changes else {
+            return None;
+        };
+        let [DocumentChangeOperation::Op(ResourceOp::Create(create)), DocumentChangeOperation::Edit(content), DocumentChangeOperation::Edit(replacement)] =
+            &operations[..]
+        else {
+            panic!("unexpected operations {:?}", operations);
+        };
+        assert_eq!(content.text_document.uri, create.uri);
+        assert_eq!(
+            replacement.text_document.uri,
+            Url::from_file_path(&path).unwrap()
+        );
+        let (OneOf::Left(content), OneOf::Left(replacement)) =
+            (&content.edits[0], &replacement.edits[0])
+        else {
+            panic!("unexpected annotated edits");
+        };
+
+        Some((
+            create.uri.to_file_path().unwrap(),
+            content.new_text.clone(),
+            replacement.range,
+            replacement.new_text.clone(),
+        ))
+    }
+
+    #[test]
+    fn selection_is_extracted_to_a_note_named_after_its_heading() {
+        let text = "intro\n## My Idea\nSome **text**\nafter\n";
+
+        assert_eq!(
+            extract(text, (1, 0), (3, 0), false),
+            Some((
+                PathBuf::from("/vault/My Idea.md"),
+                "## My Idea\nSome **text**\n".to_string(),
+                Range {
+                    start: Position {
+                        line: 1,
+                        character: 0
+                    },
+                    end: Position {
+                        line: 3,
+                        character: 0
+                    }
+                },
+                "[[My Idea]]\n".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn extracted_note_names_avoid_existing_notes() {
+        // Note.md exists, and the characters a link can not hold are left out
+        let (path, content, _, replacement) =
+            extract("a [note] about note: #Note", (0, 2), (0, 26), true).unwrap();
+
+        assert_eq!(path, PathBuf::from("/vault/note about note Note.md"));
+        assert_eq!(content, "[note] about note: #Note\n");
+        assert_eq!(replacement, "![[note about note Note]]");
+
+        let (path, _, _, replacement) = extract("# note\n", (0, 0), (0, 6), false).unwrap();
+        assert_eq!(path, PathBuf::from("/vault/note 1.md"));
+        assert_eq!(replacement, "[[note 1]]");
+    }
+
+    #[test]
+    fn extracted_note_names_leave_out_dots() {
+        let (path, _, _, replacement) =
+            extract("## v1.2 plan\ntext\n", (0, 0), (1, 4), false).unwrap();
+
+        assert_eq!(path, PathBuf::from("/vault/v12 plan.md"));
+        assert_eq!(replacement, "[[v12 plan]]");
+    }
+
+    #[test]
+    fn empty_selection_has_no_extract_action() {
+        assert_eq!(extract("some text", (0, 2), (0, 2), false), None);
+        assert_eq!(extract("a   b", (0, 1), (0, 4), false), None);
+    }
+
+    /// (title, new text) of the actions that point `line` at one of the notes named `dup`
+    fn disambiguations(line: &str) -> Vec<(String, String)> {
+        let (mut vault, params, path) = setup("");
+        let settings = Settings::new(Path::new("/vault"), &ClientCapabilities::default()).unwrap();
+        for note in ["/vault/work/a/dup.md", "/vault/home/a/dup.md"] {
+            Vault::update_vault(&settings, &mut vault, (&PathBuf::from(note), "# Part\n"));
+        }
+        Vault::update_vault(&settings, &mut vault, (&path, line));
+
+        super::disambiguate_link_actions(&vault, &params, &path)
+            .into_iter()
+            .map(|action| match action {
+                CodeActionOrCommand::CodeAction(CodeAction {
+                    title,
+                    edit:
+                        Some(WorkspaceEdit {
+                            document_changes: Some(DocumentChanges::Edits(edits)),
+                            ..
+                        }),
+                    ..
+                }) => match &edits[0].edits[0] {
+                    OneOf::Left(edit) => (title, edit.new_text.clone()),
+                    OneOf::Right(edit) => panic!("unexpected annotated edit {:?}", edit),
+                },
+                action => panic!("unexpected action {:?}", action),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn ambiguous_links_can_be_pointed_at_one_note() {
+        assert_eq!(
+            disambiguations("[[dup#Part|shown]]"),
+            vec![
+                (
+                    "Link to home/a/dup".to_string(),
+                    "[[home/a/dup#Part|shown]]".to_string()
+                ),
+                (
+                    "Link to work/a/dup".to_string(),
+                    "[[work/a/dup#Part|shown]]".to_string()
+                ),
+            ]
+        );
+        assert_eq!(disambiguations("[[work/a/dup]]"), vec![]);
+        assert_eq!(disambiguations("[[Note]]"), vec![]);
+    }
+
+    #[test]
+    fn created_daily_notes_go_to_their_folder() {
+        let (vault, _, _) = setup("");
+        let mut settings =
+            Settings::new(Path::new("/vault"), &ClientCapabilities::default()).unwrap();
+        settings.dailynote = "%d.%m.%Y".to_string();
+        settings.daily_notes_folder = "journals".to_string();
+        settings.daily_notes_subfolder = "%Y".to_string();
+        settings.new_file_folder_path = "inbox".to_string();
+
+        assert_eq!(
+            super::new_file_path(&vault, &settings, "15.01.2024"),
+            PathBuf::from("/vault/journals/2024/15.01.2024.md")
+        );
+        assert_eq!(
+            super::new_file_path(&vault, &settings, "Idea"),
+            PathBuf::from("/vault/inbox/Idea.md")
+        );
+    }
+
+    fn assert_round_trip(wiki: &str, markdown: &str) {
+        assert_eq!(convert(wiki).as_deref(), Some(markdown));
+        assert_eq!(convert(markdown).as_deref(), Some(wiki));
+    }
+
+    #[test]
+    fn file_link() {
+        assert_round_trip("[[Note]]", "[Note](Note.md)");
+        assert_round_trip("[[Note|Display]]", "[Display](Note.md)");
+    }
+
+    #[test]
+    fn heading_link() {
+        assert_round_trip(
+            "[[Note#Heading Two]]",
+            "[Note#Heading Two](Note.md#heading-two)",
+        );
+        assert_round_trip(
+            "[[Note#Heading Two|Display]]",
+            "[Display](Note.md#heading-two)",
+        );
+    }
+
+    #[test]
+    fn block_link() {
+        assert_round_trip("[[Note#^abc|Display]]", "[Display](Note.md#^abc)");
+    }
+
+    #[test]
+    fn display_text_is_escaped_for_the_other_style() {
+        assert_round_trip(r"[[Note|a \| b \[c\]]]", r"[a | b \[c\]](Note.md)");
+    }
+
+    #[test]
+    fn destination_with_spaces() {
+        assert_round_trip("[[My Note]]", "[My Note](<My Note.md>)");
+    }
+
+    #[test]
+    fn percent_encoded_destination() {
+        assert_eq!(
+            convert("[x](My%20Note.md)").as_deref(),
+            Some("[[My Note|x]]")
+        );
+        assert_eq!(convert("[x](Caf%C3%A9.md)").as_deref(), Some("[[Café|x]]"));
+        assert_round_trip("[[My Note|x]]", "[x](<My Note.md>)");
+    }
+
+    #[test]
+    fn empty_markdown_display() {
+        assert_eq!(convert("[](Note.md)").as_deref(), Some("[[Note]]"));
+        assert_eq!(
+            convert("[](./Note.md#^abc)").as_deref(),
+            Some("[[Note#^abc]]")
+        );
+    }
+
+    #[test]
+    fn no_action_outside_links() {
+        assert_eq!(convert("#tag"), None);
+    }
+
+    fn line_range(start: u32, end: u32) -> Range {
+        Range {
+            start: Position {
+                line: 0,
+                character: start,
+            },
+            end: Position {
+                line: 0,
+                character: end,
+            },
+        }
+    }
+
+    #[test]
+    fn broken_heading_fix_suggests_closest_heading() {
+        assert_eq!(
+            fragment_fixes("[[Note#Heading To]]"),
+            vec![(
+                "Link to #Heading Two".to_string(),
+                line_range(6, 17),
+                "#Heading Two".to_string()
+            )]
+        );
+        assert_eq!(
+            fragment_fixes("[x](Note.md#headin)"),
+            vec![(
+                "Link to #heading-two".to_string(),
+                line_range(11, 18),
+                "#heading-two".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn broken_block_fix_suggests_existing_block() {
+        assert_eq!(
+            fragment_fixes("[[Note#^xyz]]"),
+            vec![(
+                "Link to #^abc".to_string(),
+                line_range(6, 11),
+                "#^abc".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn resolved_link_has_no_fragment_fix() {
+        assert_eq!(fragment_fixes("[[Note#Heading Two]]"), vec![]);
+    }
+
+    /// The link and the id edit for a block reference at `line` of `text`
+    fn block_reference_at(text: &str, line: u32) -> Option<(String, Option<TextEdit>)> {
+        let (vault, _, path) = setup(text);
+        let settings = Settings::new(Path::new("/vault"), &ClientCapabilities::default()).unwrap();
+
+        super::block_reference(&vault, &settings, &path, Position { line, character: 0 })
+    }
+
+    /// Where the id edit of a block reference inserts its text
+    fn insertion(edit: &TextEdit) -> (u32, u32) {
+        assert_eq!(edit.range.start, edit.range.end);
+        (edit.range.start.line, edit.range.start.character)
+    }
+
+    #[test]
+    fn block_reference_goes_at_the_end_of_a_paragraph() {
+        let (link, edit) =
+            block_reference_at("# Title\n\nFirst line\nsecond line  \n\nAfter\n", 2).unwrap();
+        let edit = edit.unwrap();
+
+        assert_eq!(insertion(&edit), (3, 11));
+        let id = edit.new_text.strip_prefix(" ^").unwrap();
+        assert_eq!(id.len(), 5);
+        assert_eq!(link, format!("[[source#^{id}]]"));
+    }
+
+    #[test]
+    fn block_reference_goes_at_the_end_of_a_list_item_text() {
+        let text = "- parent item\n  continued\n  - child item\n- sibling\n";
+
+        let (_, edit) = block_reference_at(text, 0).unwrap();
+        assert_eq!(edit.as_ref().map(insertion), Some((1, 11)));
+
+        let (_, edit) = block_reference_at(text, 2).unwrap();
+        assert_eq!(edit.as_ref().map(insertion), Some((2, 14)));
+    }
+
+    #[test]
+    fn block_reference_reuses_an_existing_id() {
+        assert_eq!(
+            block_reference_at("Some text\nmore text ^abc\n", 0),
+            Some(("[[source#^abc]]".to_string(), None))
+        );
+    }
+
+    #[test]
+    fn block_reference_ids_are_unique_in_the_file() {
+        let first = "Repeated text";
+        let (_, edit) = block_reference_at(&format!("{first}\n"), 0).unwrap();
+        let taken = edit
+            .unwrap()
+            .new_text
+            .strip_prefix(" ^")
+            .unwrap()
+            .to_string();
+
+        let (link, _) = block_reference_at(&format!("Other ^{taken}\n\n{first}\n"), 2).unwrap();
+        assert_ne!(link, format!("[[source#^{taken}]]"));
+    }
+
+    #[test]
+    fn no_block_reference_on_blank_lines_and_headings() {
+        assert_eq!(block_reference_at("# Title\n\nText\n", 0), None);
+        assert_eq!(block_reference_at("# Title\n\nText\n", 1), None);
+    }
+
+    #[test]
+    fn no_block_reference_in_frontmatter_or_tables() {
+        let text = "---\ntags: [x]\naliases: [Other]\n---\nText\n\n| a | b |\n| - | - |\n";
+
+        for line in [0, 1, 2, 3, 6, 7] {
+            assert_eq!(block_reference_at(text, line), None, "line {line}");
+        }
+        assert!(block_reference_at(text, 4).is_some());
+    }
+}
@@ -1,23 +1,25 @@
+use std::collections::HashMap;
 use std::fs::File;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
+use crate::codeactions::block_reference;
 use crate::config::Settings;
+use crate::daily::{
+    adjacent_daily_note, create_daily_note, daily_note_date, daily_note_path, Adjacent,
+};
+use crate::references::{backlinks, NoteBacklinks};
+use crate::vault::Vault;
 use chrono::offset::Local;
-use chrono::NaiveDateTime;
+use chrono::{Days, NaiveDate, NaiveDateTime};
 use fuzzydate::parse;
 use serde_json::Value;
 use tower_lsp::jsonrpc::{Error, Result};
-use tower_lsp::lsp_types::{MessageType, ShowDocumentParams, Url};
+use tower_lsp::lsp_types::{
+    MessageType, Position, Range, ShowDocumentParams, TextEdit, Url, WorkspaceEdit,
+};
 
-fn datetime_to_file(
-    datetime: NaiveDateTime,
-    dailynote_format: &str,
-    root_dir: &Path,
-) -> Option<Url> {
-    let filename = datetime.format(dailynote_format).to_string();
-    let path = root_dir.join(&filename);
-
-    Url::from_file_path(path.with_extension("md")).ok()
+fn datetime_to_file(datetime: NaiveDateTime, settings: &Settings, root_dir: &Path) -> Option<Url> {
+    Url::from_file_path(daily_note_path(settings, root_dir, datetime.date())).ok()
 }
 
 pub async fn jump(
@@ -28,17 +30,11 @@ pub async fn jump(
 ) -> Result<Option<Value>> {
     // if jump_to is None, use the current time.
 
-    let daily_note_format = &settings.dailynote;
-    let daily_note_path = root_dir.join(&settings.daily_notes_folder);
     let note_file = match jump_to {
         Some(jmp_str) => parse(jmp_str)
             .ok()
-            .and_then(|dt| datetime_to_file(dt, &daily_note_format, &daily_note_path)),
-        None => datetime_to_file(
-            Local::now().naive_local(),
-            &daily_note_format,
-            &daily_note_path,
-        ),
+            .and_then(|dt| datetime_to_file(dt, settings, root_dir)),
+        None => datetime_to_file(Local::now().naive_local(), settings, root_dir),
     };
 
     if let Some(uri) = note_file {
@@ -74,24 +70,439 @@ pub async fn jump(
     }
 }
 
+/// The day a `createDailyNote` argument names: a number of days from `today`, a daily note name or a date like
+/// `tomorrow` or `last friday`; no argument is today
+fn daily_note_day(
+    settings: &Settings,
+    argument: Option<&Value>,
+    today: NaiveDate,
+) -> Option<NaiveDate> {
+    let offset = |days: i64| match days < 0 {
+        true => today.checked_sub_days(Days::new(days.unsigned_abs())),
+        false => today.checked_add_days(Days::new(days as u64)),
+    };
+
+    match argument {
+        None | Some(Value::Null) => Some(today),
+        Some(Value::Number(days)) => offset(days.as_i64()?),
+        Some(Value::String(text)) => match text.trim() {
+            "" => Some(today),
+            // a daily note name is read first, as with a format like `%Y%m%d` it is also a number
+            text => daily_note_date(settings, text)
+                .or_else(|| text.parse::<i64>().ok().and_then(offset))
+                .or_else(|| NaiveDate::parse_from_str(text, "%Y-%m-%d").ok())
+                .or_else(|| parse(text).ok().map(|datetime| datetime.date())),
+        },
+        _ => None,
+    }
+}
+
+/// Open the daily note of the day the argument names, creating it and its folder from the template if it is missing;
+/// returns the note's URI
+pub async fn create_daily_note_command(
+    client: &tower_lsp::Client,
+    root_dir: &Path,
+    settings: &Settings,
+    argument: Option<&Value>,
+) -> Result<Option<Value>> {
+    let date = daily_note_day(settings, argument, Local::now().date_naive()).ok_or_else(|| {
+        Error::invalid_params(format!(
+            "Could not parse {argument:?} as a date or a number of days"
+        ))
+    })?;
+
+    let path = create_daily_note(settings, root_dir, date).map_err(|err| Error {
+        message: format!("Could not create the daily note of {date}: {err}").into(),
+        ..Error::internal_error()
+    })?;
+    let uri = Url::from_file_path(&path)
+        .map_err(|_| Error::invalid_params(format!("{path:?} is not a valid uri")))?;
+
+    let _ = client
+        .show_document(ShowDocumentParams {
+            uri: uri.clone(),
+            external: Some(false),
+            take_focus: Some(true),
+            selection: None,
+        })
+        .await;
+
+    Ok(Some(Value::String(uri.to_string())))
+}
+
+/// The note at the uri argument that `insertDailyNoteLink`, `showBacklinks` and `createBlockReference` start with
+pub fn note_argument(arguments: &[Value]) -> Option<PathBuf> {
+    arguments
+        .first()
+        .and_then(Value::as_str)
+        .and_then(|uri| Url::parse(uri).ok())
+        .and_then(|uri| uri.to_file_path().ok())
+}
+
+/// The edit of `insertDailyNoteLink`: a link in the daily note at the uri argument to the nearest daily note before
+/// it, or after it with a `"next"` argument, inserted at the position argument or on a line of its own at the end
+pub fn daily_note_link_edit(
+    vault: &Vault,
+    settings: &Settings,
+    arguments: &[Value],
+) -> Result<WorkspaceEdit> {
+    let uri = arguments
+        .first()
+        .and_then(Value::as_str)
+        .and_then(|uri| Url::parse(uri).ok())
+        .ok_or_else(|| Error::invalid_params("Expected the uri of a daily note"))?;
+    let path = uri
+        .to_file_path()
+        .map_err(|_| Error::invalid_params(format!("{uri} is not a file")))?;
+    let direction = match arguments.get(1).and_then(Value::as_str) {
+        None | Some("previous") => Adjacent::Previous,
+        Some("next") => Adjacent::Next,
+        Some(other) => {
+            return Err(Error::invalid_params(format!(
+                "Expected \"previous\" or \"next\", not {other:?}"
+            )))
+        }
+    };
+
+    let date = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .and_then(|stem| daily_note_date(settings, stem))
+        .ok_or_else(|| Error::invalid_params(format!("{path:?} is not a daily note")))?;
+    let (_, adjacent) = adjacent_daily_note(
+        settings,
+        vault.md_files.keys().map(PathBuf::as_path),
+        date,
+        direction,
+    )
+    .ok_or_else(|| {
+        Error::invalid_params(match direction {
+            Adjacent::Previous => format!("There is no daily note before {date}"),
+            Adjacent::Next => format!("There is no daily note after {date}"),
+        })
+    })?;
+    let link = format!(
+        "[[{}{}]]",
+        adjacent
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or_default(),
+        if settings.include_md_extension_wikilink {
+            ".md"
+        } else {
+            ""
+        }
+    );
+
+    let (position, new_text) = match arguments.get(2) {
+        Some(position) => (
+            serde_json::from_value::<Position>(position.clone())
+                .map_err(|err| Error::invalid_params(format!("Expected a position: {err}")))?,
+            link,
+        ),
+        None => {
+            let last_line = vault
+                .ropes
+                .get(&path)
+                .map_or(0, |rope| rope.len_lines().saturating_sub(1));
+            match vault.select_line(&path, last_line as isize) {
+                Some(line) if !line.is_empty() => (
+                    Position {
+                        line: last_line as u32,
+                        character: settings.position_encoding.column(&line, line.len()),
+                    },
+                    format!("\n{link}\n"),
+                ),
+                _ => (
+                    Position {
+                        line: last_line as u32,
+                        character: 0,
+                    },
+                    format!("{link}\n"),
+                ),
+            }
+        }
+    };
+
+    Ok(WorkspaceEdit {
+        changes: Some(HashMap::from([(
+            uri,
+            vec![TextEdit {
+                range: Range {
+                    start: position,
+                    end: position,
+                },
+                new_text,
+            }],
+        )])),
+        ..Default::default()
+    })
+}
+
+/// The result of `showBacklinks`: the backlinks to the note at the uri argument, or to the heading, block or tag at the
+/// optional position argument, grouped by the note they are in
+pub fn show_backlinks(
+    vault: &Vault,
+    settings: &Settings,
+    arguments: &[Value],
+) -> Result<Vec<NoteBacklinks>> {
+    let uri = arguments
+        .first()
+        .and_then(Value::as_str)
+        .and_then(|uri| Url::parse(uri).ok())
+        .ok_or_else(|| Error::invalid_params("Expected the uri of a note"))?;
+    let path = uri
+        .to_file_path()
+        .map_err(|_| Error::invalid_params(format!("{uri} is not a file")))?;
+    let position = arguments
+        .get(1)
+        .map(|position| {
+            serde_json::from_value::<Position>(position.clone())
+                .map_err(|err| Error::invalid_params(format!("Expected a position: {err}")))
+        })
+        .transpose()?;
+
+    Ok(backlinks(vault, settings, &path, position))
+}
+
+/// The result of `createBlockReference`: a link to the paragraph or list item at the position argument in the note at
+/// the uri argument, and the edit giving it a `^blockid` if it has none
+pub fn create_block_reference(
+    vault: &Vault,
+    settings: &Settings,
+    arguments: &[Value],
+) -> Result<(String, Option<WorkspaceEdit>)> {
+    let uri = arguments
+        .first()
+        .and_then(Value::as_str)
+        .and_then(|uri| Url::parse(uri).ok())
+        .ok_or_else(|| Error::invalid_params("Expected the uri of a note"))?;
+    let path = uri
+        .to_file_path()
+        .map_err(|_| Error::invalid_params(format!("{uri} is not a file")))?;
+    let position = arguments
+        .get(1)
+        .ok_or_else(|| Error::invalid_params("Expected a position"))
+        .and_then(|position| {
+            serde_json::from_value::<Position>(position.clone())
+                .map_err(|err| Error::invalid_params(format!("Expected a position: {err}")))
+        })?;
+
+    let (link, edit) = block_reference(vault, settings, &path, position).ok_or_else(|| {
+        Error::invalid_params(format!(
+            "There is no paragraph or list item at {position:?}"
+        ))
+    })?;
+
+    Ok((
+        link,
+        edit.map(|edit| WorkspaceEdit {
+            changes: Some(HashMap::from([(uri, vec![edit])])),
+            ..Default::default()
+        }),
+    ))
+}
+
 // tests
 #[cfg(test)]
 mod tests {
+    use std::path::{Path, PathBuf};
+
+    use chrono::NaiveDate;
     use fuzzydate::parse;
+    use serde_json::json;
+    use tower_lsp::lsp_types::{ClientCapabilities, Position, Url};
+
+    use crate::{
+        config::{PositionEncoding, Settings},
+        vault::Vault,
+    };
 
-    use super::datetime_to_file;
+    use super::{daily_note_day, daily_note_link_edit, datetime_to_file, show_backlinks};
 
     #[test]
     fn test_string_to_file() {
         let input = "today";
 
         let parsed_datetime = parse(input).unwrap();
+        let root_dir = std::fs::canonicalize("./").unwrap();
+        let settings = Settings::new(&root_dir, &ClientCapabilities::default()).unwrap();
+
+        let _ = datetime_to_file(parsed_datetime, &settings, &root_dir).unwrap();
+    }
+
+    #[test]
+    fn daily_note_days_are_offsets_or_dates() {
+        let root_dir = std::fs::canonicalize("./").unwrap();
+        let mut settings = Settings::new(&root_dir, &ClientCapabilities::default()).unwrap();
+        settings.dailynote = "%d.%m.%Y".to_string();
+        let today = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+        let day = |argument| daily_note_day(&settings, Some(&argument), today);
+
+        assert_eq!(daily_note_day(&settings, None, today), Some(today));
+        assert_eq!(day(json!("")), Some(today));
+        assert_eq!(day(json!(-1)), NaiveDate::from_ymd_opt(2024, 2, 29));
+        assert_eq!(day(json!("+2")), NaiveDate::from_ymd_opt(2024, 3, 3));
+        assert_eq!(
+            day(json!("15.01.2024")),
+            NaiveDate::from_ymd_opt(2024, 1, 15)
+        );
+        assert_eq!(
+            day(json!("2024-01-16")),
+            NaiveDate::from_ymd_opt(2024, 1, 16)
+        );
+        assert!(day(json!("tomorrow")).is_some());
+        assert_eq!(day(json!([1])), None);
+        assert_eq!(day(json!(true)), None);
+
+        settings.dailynote = "%Y%m%d".to_string();
+        let day = |argument| daily_note_day(&settings, Some(&argument), today);
+        assert_eq!(day(json!("20240115")), NaiveDate::from_ymd_opt(2024, 1, 15));
+        assert_eq!(day(json!("-1")), NaiveDate::from_ymd_opt(2024, 2, 29));
+    }
+
+    #[test]
+    fn daily_note_links_go_to_the_nearest_existing_note() {
+        let root_dir = Path::new("/vault");
+        let settings = Settings::new(root_dir, &ClientCapabilities::default()).unwrap();
+        let mut vault = Vault::construct_vault(&settings, root_dir).unwrap();
+        for (name, text) in [
+            ("2024-01-10", ""),
+            ("2024-01-14", "# Sunday\n"),
+            ("2024-01-20", "# Saturday\n\nlast line"),
+            ("notes", ""),
+        ] {
+            let path = PathBuf::from(format!("/vault/{name}.md"));
+            Vault::update_vault(&settings, &mut vault, (&path, text));
+        }
+        let edit = |name: &str, arguments: &[serde_json::Value]| {
+            let uri = Url::from_file_path(format!("/vault/{name}.md")).unwrap();
+            let arguments = [&[json!(uri.to_string())], arguments].concat();
+            daily_note_link_edit(&vault, &settings, &arguments).map(|edit| {
+                let edit = &edit.changes.unwrap()[&uri][0];
+                (edit.range.start, edit.new_text.clone())
+            })
+        };
+        let position = |line, character| Position { line, character };
+
+        assert_eq!(
+            edit("2024-01-14", &[json!("previous")]).unwrap(),
+            (position(1, 0), "[[2024-01-10]]\n".to_string())
+        );
+        // the days between without a note are skipped
+        assert_eq!(
+            edit("2024-01-14", &[json!("next")]).unwrap(),
+            (position(1, 0), "[[2024-01-20]]\n".to_string())
+        );
+        assert_eq!(
+            edit("2024-01-20", &[]).unwrap(),
+            (position(2, 9), "\n[[2024-01-14]]\n".to_string())
+        );
+        assert_eq!(
+            edit(
+                "2024-01-20",
+                &[json!("previous"), json!({"line": 0, "character": 2})]
+            )
+            .unwrap(),
+            (position(0, 2), "[[2024-01-14]]".to_string())
+        );
+
+        assert!(edit("2024-01-10", &[json!("previous")]).is_err());
+        assert!(edit("notes", &[json!("next")]).is_err());
+        assert!(edit("2024-01-14", &[json!("sideways")]).is_err());
+    }
+
+    #[test]
+    fn backlinks_are_grouped_by_note_with_their_lines() {
+        let root_dir = Path::new("/vault");
+        let settings = Settings::new(root_dir, &ClientCapabilities::default()).unwrap();
+        let mut vault = Vault::construct_vault(&settings, root_dir).unwrap();
+        for (name, text) in [
+            ("target", "---\naliases: [T]\n---\n# Intro\n"),
+            ("b", "See [x](target.md)  \n"),
+            ("a", "[[target]] and [[T]]\n\n- [[target#Intro]]\n"),
+            ("lonely", "nothing links here\n"),
+        ] {
+            let path = PathBuf::from(format!("/vault/{name}.md"));
+            Vault::update_vault(&settings, &mut vault, (&path, text));
+        }
+
+        let backlinks = |name: &str, position: Option<serde_json::Value>| {
+            let uri = Url::from_file_path(format!("/vault/{name}.md")).unwrap();
+            let arguments = [json!(uri)].into_iter().chain(position).collect::<Vec<_>>();
+            show_backlinks(&vault, &settings, &arguments).map(|notes| {
+                notes
+                    .into_iter()
+                    .map(|note| {
+                        let name = note.uri.path().trim_start_matches("/vault/").to_string();
+                        let backlinks = note
+                            .backlinks
+                            .into_iter()
+                            .map(|backlink| (backlink.range.start.line, backlink.line))
+                            .collect::<Vec<_>>();
+                        (name, backlinks)
+                    })
+                    .collect::<Vec<_>>()
+            })
+        };
+
+        // links through the alias count, and notes come in path order
+        assert_eq!(
+            backlinks("target", None).unwrap(),
+            vec![
+                (
+                    "a.md".to_string(),
+                    vec![
+                        (0, "[[target]] and [[T]]".to_string()),
+                        (0, "[[target]] and [[T]]".to_string()),
+                        (2, "- [[target#Intro]]".to_string()),
+                    ]
+                ),
+                (
+                    "b.md".to_string(),
+                    vec![(0, "See [x](target.md)".to_string())]
+                ),
+            ]
+        );
+        // at a heading, only the links to the heading
+        assert_eq!(
+            backlinks("target", Some(json!({"line": 3, "character": 2}))).unwrap(),
+            vec![(
+                "a.md".to_string(),
+                vec![(2, "- [[target#Intro]]".to_string())]
+            )]
+        );
+        assert_eq!(backlinks("lonely", None).unwrap(), vec![]);
+        assert!(show_backlinks(&vault, &settings, &[]).is_err());
+    }
+
+    #[test]
+    fn backlink_columns_follow_the_position_encoding() {
+        let root_dir = Path::new("/vault");
+        let mut settings = Settings::new(root_dir, &ClientCapabilities::default()).unwrap();
+        let mut vault = Vault::construct_vault(&settings, root_dir).unwrap();
+        for (name, text) in [
+            ("target", "# 😀 Intro\n"),
+            ("a", "😀 [[target#😀 Intro]]\n"),
+        ] {
+            let path = PathBuf::from(format!("/vault/{name}.md"));
+            Vault::update_vault(&settings, &mut vault, (&path, text));
+        }
+
+        let uri = Url::from_file_path("/vault/target.md").unwrap();
+        let mut columns = |encoding, character: u32| {
+            settings.position_encoding = encoding;
+            let arguments = [json!(uri), json!({"line": 0, "character": character})];
+            show_backlinks(&vault, &settings, &arguments)
+                .unwrap()
+                .into_iter()
+                .flat_map(|note| note.backlinks)
+                .map(|backlink| (backlink.range.start.character, backlink.range.end.character))
+                .collect::<Vec<_>>()
+        };
 
-        let _ = datetime_to_file(
-            parsed_datetime,
-            "%Y-%m-%d",
-            &std::fs::canonicalize("./").unwrap(),
-        )
-        .unwrap();
+        // the cursor is on the `I` of the heading, after the emoji
+        assert_eq!(columns(PositionEncoding::Utf16, 5), vec![(3, 22)]);
+        assert_eq!(columns(PositionEncoding::Utf32, 4), vec![(2, 20)]);
     }
 }
@@ -1,3 +1,5 @@
+use std::collections::BTreeSet;
+
 use once_cell::sync::Lazy;
 use regex::Regex;
 use tower_lsp::lsp_types::{
@@ -5,29 +7,76 @@ use tower_lsp::lsp_types::{
     InsertTextFormat, Position, Range, TextEdit,
 };
 
-use super::{Completable, Completer};
+use super::{
+    matcher::{fuzzy_match, Matchable},
+    Completable, Completer, Context,
+};
 
-pub struct CalloutCompleter {
+pub struct CalloutCompleter<'a> {
     nested_level: usize,
     line: u32,
     character: u32,
     preceding_text: String,
+    /// The type being typed after `> [!`
+    callout_type: Option<CalloutType>,
+    /// What was typed after the `>`s, up to the cursor
+    query: String,
+    context: Context<'a>,
 }
 
-impl<'a> Completer<'a> for CalloutCompleter {
+/// The type in a `> [!type` header
+struct CalloutType {
+    /// Where the type starts, right after the `[!`
+    start: u32,
+    end: u32,
+    /// Whether a `]` already closes the header; only the type is replaced then
+    closed: bool,
+}
+
+impl<'a> Completer<'a> for CalloutCompleter<'a> {
     fn construct(context: super::Context<'a>, line: usize, character: usize) -> Option<Self>
     where
         Self: Sized + Completer<'a>,
     {
         let line_chars = context.vault.select_line(context.path, line as isize)?;
 
-        static PARTIAL_CALLOUT: Lazy<Regex> =
-            Lazy::new(|| Regex::new(r"^(?<preceding>(> *)+)").unwrap()); // [display](relativePath)
+        static PARTIAL_CALLOUT: Lazy<Regex> = Lazy::new(|| {
+            Regex::new(r"^(?<preceding>(> *)+)(\[!(?<type>[^\]\s]*)(?<closed>\])?)?").unwrap()
+        }); // > > [!type]
 
-        let binding = String::from_iter(line_chars);
+        let binding = String::from_iter(&line_chars);
         let captures = PARTIAL_CALLOUT.captures(&binding)?;
 
-        let (_full, preceding) = (captures.get(0)?, captures.name("preceding")?);
+        let preceding = captures.name("preceding")?;
+        let char_index = |byte_index: usize| binding[..byte_index].chars().count();
+        let preceding_end = char_index(preceding.end());
+        if character < preceding_end {
+            return None;
+        }
+
+        let callout_type = match captures.name("type") {
+            Some(callout_type) => {
+                let (start, end) = (
+                    char_index(callout_type.start()),
+                    char_index(callout_type.end()),
+                );
+                // only the type of a header is completed, not its title
+                if character < start || character > end {
+                    return None;
+                }
+                Some(CalloutType {
+                    start: start as u32,
+                    end: end as u32,
+                    closed: captures.name("closed").is_some(),
+                })
+            }
+            None => None,
+        };
+
+        let query_start = callout_type
+            .as_ref()
+            .map_or(preceding_end, |callout_type| callout_type.start as usize);
+        let query = String::from_iter(line_chars.get(query_start..character)?);
 
         let nested_level = preceding.as_str().matches('>').count();
 
@@ -36,6 +85,9 @@ impl<'a> Completer<'a> for CalloutCompleter {
             preceding_text: preceding.as_str().to_string(),
             line: line as u32,
             character: character as u32,
+            callout_type,
+            query,
+            context,
         });
     }
 
@@ -43,45 +95,91 @@ impl<'a> Completer<'a> for CalloutCompleter {
     where
         Self: Sized,
     {
-        vec![
-            CalloutCompletion::Note,
-            CalloutCompletion::Abstract,
-            CalloutCompletion::Summary,
-            CalloutCompletion::Tldr,
-            CalloutCompletion::Info,
-            CalloutCompletion::Todo,
-            CalloutCompletion::Tip,
-            CalloutCompletion::Hint,
-            CalloutCompletion::Important,
-            CalloutCompletion::Success,
-            CalloutCompletion::Check,
-            CalloutCompletion::Done,
-            CalloutCompletion::Question,
-            CalloutCompletion::Help,
-            CalloutCompletion::Faq,
-            CalloutCompletion::Warning,
-            CalloutCompletion::Caution,
-            CalloutCompletion::Attention,
-            CalloutCompletion::Failure,
-            CalloutCompletion::Fail,
-            CalloutCompletion::Missing,
-            CalloutCompletion::Danger,
-            CalloutCompletion::Error,
-            CalloutCompletion::Bug,
-            CalloutCompletion::Example,
-            CalloutCompletion::Quote,
-            CalloutCompletion::Cite,
-        ]
+        let builtin = [
+            Callout::Note,
+            Callout::Abstract,
+            Callout::Summary,
+            Callout::Tldr,
+            Callout::Info,
+            Callout::Todo,
+            Callout::Tip,
+            Callout::Hint,
+            Callout::Important,
+            Callout::Success,
+            Callout::Check,
+            Callout::Done,
+            Callout::Question,
+            Callout::Help,
+            Callout::Faq,
+            Callout::Warning,
+            Callout::Caution,
+            Callout::Attention,
+            Callout::Failure,
+            Callout::Fail,
+            Callout::Missing,
+            Callout::Danger,
+            Callout::Error,
+            Callout::Bug,
+            Callout::Example,
+            Callout::Quote,
+            Callout::Cite,
+        ];
+
+        // custom types are the ones used in the vault that are not built in
+        let custom = self
+            .custom_types()
+            .into_iter()
+            .filter(|name| builtin.iter().all(|callout| callout.name() != name))
+            .map(Callout::Custom)
+            .collect::<Vec<_>>();
+
+        // `+` and `-` after the header start a callout expanded or collapsed; a closed header keeps its own
+        let folds: &[&'static str] = match self.callout_type {
+            Some(CalloutType { closed: true, .. }) => &[""],
+            _ => &["", "+", "-"],
+        };
+
+        let items = folds.iter().flat_map(|fold| {
+            builtin
+                .iter()
+                .cloned()
+                .chain(custom.iter().cloned())
+                .map(|callout| CalloutCompletion::new(callout, fold))
+        });
+
+        fuzzy_match(&self.query, items, &self.context.settings.case_matching)
+            .into_iter()
+            .map(|(item, _)| item)
+            .collect()
     }
 
     // TODO: get rid of this in the API
-    type FilterParams = &'static str;
+    type FilterParams = String;
     fn completion_filter_text(&self, params: Self::FilterParams) -> String {
-        format!("{}{}", self.preceding_text, params)
+        match self.callout_type {
+            Some(CalloutType { closed: true, .. }) => params,
+            Some(_) => format!("{}[!{}", self.preceding_text, params),
+            None => format!("{}{}", self.preceding_text, params),
+        }
     }
 }
 
-enum CalloutCompletion {
+impl CalloutCompleter<'_> {
+    /// The types of the callouts in the vault, leaving out the header being typed
+    fn custom_types(&self) -> BTreeSet<String> {
+        self.context
+            .vault
+            .select_callouts()
+            .filter(|(path, callout)| {
+                !(*path == self.context.path && callout.range.start.line == self.line)
+            })
+            .map(|(_, callout)| callout.callout_type.to_lowercase())
+            .collect()
+    }
+}
+
+#[derive(Clone)]
+enum Callout {
     Note,
     Abstract,
     Summary,
@@ -109,11 +207,13 @@ enum CalloutCompletion {
     Example,
     Quote,
     Cite,
+    /// A type that is not built into Obsidian, found in the vault
+    Custom(String),
 }
 
-impl Completable<'_, CalloutCompleter> for CalloutCompletion {
-    fn completions(&self, completer: &CalloutCompleter) -> Option<CompletionItem> {
-        let name = match self {
+impl Callout {
+    fn name(&self) -> &str {
+        match self {
             Self::Note => "note",
             Self::Abstract => "abstract",
             Self::Summary => "summary",
@@ -141,9 +241,12 @@ impl Completable<'_, CalloutCompleter> for CalloutCompletion {
             Self::Example => "example",
             Self::Quote => "quote",
             Self::Cite => "cite",
-        };
+            Self::Custom(name) => name,
+        }
+    }
 
-        let label_detail = match self {
+    fn label_detail(&self) -> Option<&'static str> {
+        match self {
             Self::Summary | Self::Tldr => Some("alias of Abstract"),
             Self::Hint | Self::Important => Some("alias of Tip"),
             Self::Check | Self::Done => Some("alias of Success"),
@@ -152,21 +255,70 @@ impl Completable<'_, CalloutCompleter> for CalloutCompletion {
             Self::Fail | Self::Missing => Some("alias of Failure"),
             Self::Error => Some("alias of Danger"),
             Self::Cite => Some("alias of Quote"),
+            Self::Custom(_) => Some("used in the vault"),
             _ => None,
-        };
+        }
+    }
+}
 
-        let snippet = format!(
-            "{prefix}[!{name}] ${{1:Title}}\n{prefix}${{2:Description}}",
-            prefix = "> ".repeat(completer.nested_level)
-        );
+/// A callout type with the fold modifier to end its header with: `+` for expanded, `-` for collapsed
+struct CalloutCompletion {
+    callout: Callout,
+    fold: &'static str,
+    match_string: String,
+}
 
-        let filter_text = completer.completion_filter_text(name);
+impl CalloutCompletion {
+    fn new(callout: Callout, fold: &'static str) -> Self {
+        let match_string = format!("{}{}", callout.name(), fold);
+        Self {
+            callout,
+            fold,
+            match_string,
+        }
+    }
+}
+
+impl Matchable for CalloutCompletion {
+    fn match_string(&self) -> &str {
+        &self.match_string
+    }
+}
+
+impl<'a> Completable<'a, CalloutCompleter<'a>> for CalloutCompletion {
+    fn completions(&self, completer: &CalloutCompleter<'a>) -> Option<CompletionItem> {
+        let name = self.callout.name();
+
+        let (start, end, new_text) = match completer.callout_type {
+            Some(CalloutType {
+                start,
+                end,
+                closed: true,
+            }) => (start, end, name.to_string()),
+            ref callout_type => (
+                0,
+                callout_type
+                    .as_ref()
+                    .map_or(completer.character, |callout_type| callout_type.end),
+                format!(
+                    "{prefix}[!{name}]{fold} ${{1:Title}}\n{prefix}${{2:Description}}",
+                    prefix = "> ".repeat(completer.nested_level),
+                    fold = self.fold
+                ),
+            ),
+        };
+
+        let filter_text = completer.completion_filter_text(self.match_string.clone());
 
         let completion_item = CompletionItem {
-            label: name.to_string(),
-            label_details: label_detail.map(|detail| CompletionItemLabelDetails {
-                detail: Some(detail.to_string()),
-                description: None,
+            label: self.match_string.clone(),
+            label_details: Some(CompletionItemLabelDetails {
+                detail: self.callout.label_detail().map(|detail| detail.to_string()),
+                description: match self.fold {
+                    "+" => Some("expanded".to_string()),
+                    "-" => Some("collapsed".to_string()),
+                    _ => None,
+                },
             }),
             insert_text_format: Some(InsertTextFormat::SNIPPET),
             kind: Some(CompletionItemKind::SNIPPET),
@@ -174,14 +326,14 @@ impl Completable<'_, CalloutCompleter> for CalloutCompletion {
                 range: Range {
                     start: Position {
                         line: completer.line,
-                        character: 0,
+                        character: start,
                     },
                     end: Position {
                         line: completer.line,
-                        character: completer.character,
+                        character: end,
                     },
                 },
-                new_text: snippet,
+                new_text,
             })),
             filter_text: Some(filter_text),
             ..Default::default()
@@ -190,3 +342,147 @@ impl Completable<'_, CalloutCompleter> for CalloutCompletion {
         Some(completion_item)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use tower_lsp::lsp_types::{
+        ClientCapabilities, CompletionItem, CompletionParams, CompletionResponse,
+        CompletionTextEdit, Position, TextDocumentIdentifier, TextDocumentPositionParams, Url,
+    };
+
+    use crate::{
+        completion::{get_completions, Recency},
+        config::Settings,
+        vault::Vault,
+    };
+
+    /// Completions at `character` of `line`, with `/vault/other.md` holding `other`
+    fn completions(line: &str, character: u32, other: &str) -> Vec<CompletionItem> {
+        let root_dir = Path::new("/vault");
+        let settings = Settings::new(root_dir, &ClientCapabilities::default()).unwrap();
+        let mut vault = Vault::construct_vault(&settings, root_dir).unwrap();
+        let path = PathBuf::from("/vault/note.md");
+        Vault::update_vault(
+            &settings,
+            &mut vault,
+            (&PathBuf::from("/vault/other.md"), other),
+        );
+        Vault::update_vault(&settings, &mut vault, (&path, line));
+
+        let params = CompletionParams {
+            text_document_position: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier {
+                    uri: Url::from_file_path(&path).unwrap(),
+                },
+                position: Position { line: 0, character },
+            },
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+            context: None,
+        };
+
+        match get_completions(
+            &vault,
+            &[],
+            &params,
+            &path,
+            &settings,
+            &Recency::default(),
+            &Default::default(),
+        ) {
+            Some(CompletionResponse::List(list)) => list.items,
+            None => vec![],
+            Some(response) => panic!("expected a completion list, got {:?}", response),
+        }
+    }
+
+    /// The (start, end, inserted text) of the completion labeled `label`
+    fn edit(items: &[CompletionItem], label: &str) -> Option<(u32, u32, String)> {
+        items
+            .iter()
+            .find(|item| item.label == label)
+            .and_then(|item| match &item.text_edit {
+                Some(CompletionTextEdit::Edit(edit)) => Some((
+                    edit.range.start.character,
+                    edit.range.end.character,
+                    edit.new_text.clone(),
+                )),
+                _ => None,
+            })
+    }
+
+    #[test]
+    fn bang_completes_types_with_fold_modifiers() {
+        let items = completions("> [!wa", 6, "");
+
+        assert_eq!(
+            items.first().map(|item| item.label.as_str()),
+            Some("warning")
+        );
+        assert_eq!(
+            items.first().and_then(|item| item.filter_text.as_deref()),
+            Some("> [!warning")
+        );
+        assert_eq!(
+            edit(&items, "warning-"),
+            Some((
+                0,
+                6,
+                "> [!warning]- ${1:Title}\n> ${2:Description}".to_string()
+            ))
+        );
+        assert!(items.iter().any(|item| item.label == "warning+"));
+    }
+
+    #[test]
+    fn every_type_can_be_completed() {
+        let items = completions("> [!ci", 6, "");
+
+        assert_eq!(items.first().map(|item| item.label.as_str()), Some("cite"));
+    }
+
+    #[test]
+    fn closed_header_only_replaces_the_type() {
+        let items = completions("> [!wa]- Title", 6, "");
+
+        assert_eq!(edit(&items, "warning"), Some((4, 6, "warning".to_string())));
+        assert!(items.iter().all(|item| item.label != "warning-"));
+    }
+
+    #[test]
+    fn nested_callouts_keep_their_depth() {
+        let items = completions("> > [!no", 8, "");
+
+        assert_eq!(
+            edit(&items, "note"),
+            Some((
+                0,
+                8,
+                "> > [!note] ${1:Title}\n> > ${2:Description}".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn custom_types_come_from_the_vault() {
+        let other = "> [!Recipe] Pie\n> [!note] Builtin\n```\n> [!snippet] Code\n```";
+
+        let items = completions("> [!rec", 7, other);
+        assert_eq!(
+            items.first().map(|item| item.label.as_str()),
+            Some("recipe")
+        );
+
+        // a builtin type used in the vault is not offered twice
+        let items = completions("> [!", 4, other);
+        assert_eq!(items.iter().filter(|item| item.label == "note").count(), 1);
+
+        // neither the header being typed nor one in a code block is a custom type
+        let items = completions("> [!rec]", 7, other);
+        assert!(items.iter().all(|item| item.label != "rec"));
+        let items = completions("> [!sni", 7, other);
+        assert!(items.iter().all(|item| item.label != "snippet"));
+    }
+}
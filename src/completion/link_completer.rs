@@ -1,30 +1,38 @@
 use std::{
-    collections::HashSet,
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
     iter::once,
     path::{Path, PathBuf},
+    sync::Mutex,
     time::SystemTime,
 };
 
 use chrono::{Duration, NaiveDate};
 use itertools::Itertools;
 use once_cell::sync::Lazy;
+use pathdiff::diff_paths;
 use rayon::prelude::*;
 use regex::Regex;
+use serde::{Deserialize, Serialize};
 use tower_lsp::lsp_types::{
     CompletionItem, CompletionItemKind, CompletionItemLabelDetails, CompletionTextEdit,
-    Documentation, InsertTextFormat, Position, Range, TextEdit,
+    Documentation, InsertTextFormat, MarkupContent, MarkupKind, Position, Range, TextEdit, Url,
 };
 
 use crate::{
-    completion::util::check_in_code_block,
+    completion::util::{check_in_code_block, in_table_row},
     config::Settings,
+    daily::{daily_note_date, daily_note_dates_matching, daily_note_path},
     ui::preview_referenceable,
-    vault::{MDFile, MDHeading, Reference, Referenceable, Vault},
+    vault::{
+        escape_link_brackets, escape_wiki_link_text, get_obsidian_ref_path, is_attachment,
+        normalize_for_match, MDFile, MDHeading, MatchOpts, Reference, Referenceable, Vault,
+    },
 };
 
 use super::{
-    matcher::{fuzzy_match_completions, Matchable, OrderedCompletion},
-    Completable, Completer, Context,
+    matcher::{fuzzy_match, Matchable, OrderedCompletion},
+    Completable, Completer, CompletionCache, Context, Recency,
 };
 
 /// Range on a single line; assumes that the line number is known.
@@ -44,6 +52,8 @@ pub struct MarkdownLinkCompleter<'a> {
     pub vault: &'a Vault,
     pub context_path: &'a Path,
     pub settings: &'a Settings,
+    pub recency: &'a Recency,
+    pub completion_cache: &'a Mutex<CompletionCache>,
 }
 
 pub trait LinkCompleter<'a>: Completer<'a> {
@@ -53,6 +63,67 @@ pub trait LinkCompleter<'a>: Completer<'a> {
     fn vault(&self) -> &'a Vault;
     fn position(&self) -> Position;
     fn path(&self) -> &'a Path;
+    fn recency(&self) -> &'a Recency;
+    fn completion_cache(&self) -> &'a Mutex<CompletionCache>;
+
+    /// Fuzzy match `completions`; notes that match equally well are ordered by how recently they were opened or saved
+    fn rank_link_completions(
+        &self,
+        filter_text: &str,
+        completions: Vec<LinkCompletion<'a>>,
+    ) -> Vec<OrderedCompletion<'a, Self, LinkCompletion<'a>>>
+    where
+        LinkCompletion<'a>: Completable<'a, Self>,
+    {
+        let recency = self.recency();
+        let (path, line) = (self.path(), self.position().line);
+
+        // a query that extends the last one only needs to match what that one matched
+        let candidates = completions.len();
+        let completions = match self.completion_cache().lock() {
+            Ok(cache) => cache.candidates(path, line, filter_text, completions),
+            Err(_) => completions,
+        };
+        let matched = fuzzy_match(filter_text, completions, &self.settings().case_matching);
+        if let Ok(mut cache) = self.completion_cache().lock() {
+            cache.store(
+                path,
+                line,
+                filter_text,
+                candidates,
+                matched
+                    .iter()
+                    .map(|(completion, _)| completion.match_string()),
+            );
+        }
+
+        // a note that matches by its name and by its aliases is offered once
+        let matched = best_name_per_note(matched);
+
+        let relevance = relevance(self.vault(), self.settings(), recency, &matched);
+
+        // only the best are sent, so a short query in a large vault does not sort every note
+        matched
+            .into_iter()
+            .zip(relevance)
+            .enumerate()
+            .k_smallest_by(
+                self.settings().completion_max_results,
+                |(i, ((_, score), (relevance, recent))),
+                 (other_i, ((_, other_score), (other_relevance, other_recent)))| {
+                    other_relevance
+                        .total_cmp(relevance)
+                        .then(other_score.cmp(score))
+                        .then(other_recent.cmp(recent))
+                        .then(i.cmp(other_i))
+                },
+            )
+            .enumerate()
+            .map(|(i, (_, ((completion, _), _)))| {
+                OrderedCompletion::new(completion, format!("{:06}", i))
+            })
+            .collect()
+    }
     fn link_completions(&self) -> Vec<LinkCompletion<'a>>
     where
         Self: Sync,
@@ -106,22 +177,132 @@ pub trait LinkCompleter<'a>: Completer<'a> {
             .flatten()
             .collect::<Vec<_>>();
 
+        // `[[alias#` completes the headings of the aliased file; split on # before looking up the alias
+        let alias_headings = match self.entered_refname().split_once('#') {
+            Some((entered_file, _)) if heading_completions => {
+                alias_heading_completions(self.vault(), entered_file)
+            }
+            _ => vec![],
+        };
+        // notes that share their name are linked by the shortest path that tells them apart
+        let unambiguous_refnames = self.vault().select_unambiguous_refnames();
+        let completions = completions
+            .into_iter()
+            .map(|completion| completion.unambiguous(&unambiguous_refnames))
+            .chain(alias_headings)
+            .collect_vec();
+
         // TODO: This could be slow
         let refnames = completions
             .par_iter()
             .map(|completion| completion.refname())
             .collect::<HashSet<_>>();
 
-        // Get daily notes for convienience
+        // Get daily notes for convienience: the days around today, and those whose name starts with what is typed
         let today = chrono::Local::now().date_naive();
+        let typed_dates =
+            daily_note_dates_matching(self.settings(), &self.entered_refname(), today, 31);
         let days = (-7..=7)
             .flat_map(|i| Some(today + Duration::try_days(i)?))
+            .chain(typed_dates)
+            .unique()
             .flat_map(|date| MDDailyNote::from_date(date, self))
             .filter(|date| !refnames.contains(&date.ref_name))
             .map(LinkCompletion::DailyNote);
 
-        completions.into_iter().chain(days).collect::<Vec<_>>()
+        // once the link reaches into a folder, folders complete to their folder note as `folder/`
+        let folders = match self.entered_refname().contains('/') {
+            true => folder_completions(self.vault(), self.settings()),
+            false => vec![],
+        };
+
+        completions
+            .into_iter()
+            .chain(days)
+            .chain(folders)
+            .collect::<Vec<_>>()
+    }
+}
+
+/// The folders that have a folder note, each linking to its note
+fn folder_completions<'a>(vault: &'a Vault, settings: &Settings) -> Vec<LinkCompletion<'a>> {
+    vault
+        .md_files
+        .iter()
+        .filter_map(|(path, mdfile)| {
+            let folder = path
+                .parent()?
+                .strip_prefix(vault.root_dir())
+                .ok()?
+                .to_str()?;
+            (settings.folder_note.name(folder)? == path.file_stem()?.to_str()?).then(|| Folder {
+                match_string: format!("{folder}/"),
+                referenceable: Referenceable::File(path, mdfile),
+            })
+        })
+        .collect()
+}
+
+/// Each note by whichever of its name and aliases matched best, the name winning a tie; other completions are kept
+fn best_name_per_note(matched: Vec<(LinkCompletion<'_>, u32)>) -> Vec<(LinkCompletion<'_>, u32)> {
+    let note = |completion: &LinkCompletion| match completion {
+        File { referenceable, .. } | Alias { referenceable, .. } => {
+            Some(referenceable.get_path().to_path_buf())
+        }
+        _ => None,
+    };
+
+    let mut best: HashMap<PathBuf, (usize, (u32, bool))> = HashMap::new();
+    for (i, (completion, score)) in matched.iter().enumerate() {
+        let Some(path) = note(completion) else {
+            continue;
+        };
+        let rank = (*score, matches!(completion, File { .. }));
+        if best
+            .get(&path)
+            .is_none_or(|(_, best_rank)| rank > *best_rank)
+        {
+            best.insert(path, (i, rank));
+        }
     }
+
+    matched
+        .into_iter()
+        .enumerate()
+        .filter(|(i, (completion, _))| {
+            note(completion).is_none_or(|path| best.get(&path).is_some_and(|(best, _)| best == i))
+        })
+        .map(|(_, matched)| matched)
+        .collect()
+}
+
+/// Completions for the headings of the file that has the alias `entered_alias`
+fn alias_heading_completions<'a>(vault: &'a Vault, entered_alias: &str) -> Vec<LinkCompletion<'a>> {
+    vault
+        .md_files
+        .iter()
+        .find_map(|(path, mdfile)| {
+            let alias = mdfile.metadata.as_ref()?.aliases().iter().find(|alias| {
+                normalize_for_match(alias, &MatchOpts::CASEFOLD)
+                    == normalize_for_match(entered_alias, &MatchOpts::CASEFOLD)
+            })?;
+            let filename = mdfile.file_name()?;
+
+            Some(
+                mdfile
+                    .headings
+                    .iter()
+                    .map(|heading| LinkCompletion::AliasHeading {
+                        alias,
+                        filename,
+                        heading,
+                        match_string: format!("{}#{}", alias, heading.heading_text),
+                        referenceable: Referenceable::Heading(path, heading),
+                    })
+                    .collect_vec(),
+            )
+        })
+        .unwrap_or_default()
 }
 
 impl<'a> LinkCompleter<'a> for MarkdownLinkCompleter<'a> {
@@ -132,6 +313,14 @@ impl<'a> LinkCompleter<'a> for MarkdownLinkCompleter<'a> {
     fn path(&self) -> &'a Path {
         self.context_path
     }
+
+    fn recency(&self) -> &'a Recency {
+        self.recency
+    }
+
+    fn completion_cache(&self) -> &'a Mutex<CompletionCache> {
+        self.completion_cache
+    }
     fn position(&self) -> Position {
         self.position
     }
@@ -146,14 +335,14 @@ impl<'a> LinkCompleter<'a> for MarkdownLinkCompleter<'a> {
             self.path.0,
             self.infile_ref
                 .as_ref()
-                .map(|infile| infile.0.to_string())
+                .map(|infile| format!("#{}", infile.0.to_string()))
                 .unwrap_or("".to_string())
         )
     }
 
     /// Will add <$1> to the refname if it contains spaces
     fn completion_text_edit(&self, display: Option<&str>, refname: &str) -> CompletionTextEdit {
-        let ext = if self.settings().include_md_extension_md_link {
+        let ext = if self.settings().include_md_extension_md_link && !refname.ends_with('/') {
             ".md"
         } else {
             ""
@@ -264,7 +453,12 @@ impl<'a> Completer<'a> for MarkdownLinkCompleter<'a> {
         });
 
         let partial = Some(MarkdownLinkCompleter {
-            path: (reftext.map(|it| it.as_str().to_string()).unwrap_or(file_name.to_string()), reftext.map(|it| it.range()).unwrap_or(character-1..character-1)), // range shouldn't matter if no path specified.
+            // `[display](#heading)` links into the current file; `[display](` has no path yet
+            path: match (reftext, &partial_infileref) {
+                (Some(reftext), _) => (reftext.as_str().to_string(), reftext.range()),
+                (None, Some(_)) => (file_name.to_string(), character - 1..character - 1), // range shouldn't matter if no path specified.
+                (None, None) => (String::new(), character..character),
+            },
             display: (display.as_str().to_string(), display.range()),
             infile_ref: partial_infileref,
             full_range,
@@ -276,6 +470,8 @@ impl<'a> Completer<'a> for MarkdownLinkCompleter<'a> {
             vault,
             context_path: context.path,
             settings: context.settings,
+            recency: context.recency,
+            completion_cache: context.completion_cache,
         });
 
         partial
@@ -293,10 +489,18 @@ impl<'a> Completer<'a> for MarkdownLinkCompleter<'a> {
 
         let link_completions = self.link_completions();
 
-        let matches =
-            fuzzy_match_completions(&filter_text, link_completions, &self.settings.case_matching);
+        // Nothing typed after `(` yet: offer every file, in name order
+        if filter_text.is_empty() {
+            return link_completions
+                .into_iter()
+                .filter(|completion| matches!(completion, File { .. }))
+                .sorted_by(|a, b| a.match_string().cmp(b.match_string()))
+                .enumerate()
+                .map(|(i, completion)| OrderedCompletion::new(completion, format!("{:06}", i)))
+                .collect_vec();
+        }
 
-        matches
+        self.rank_link_completions(&filter_text, link_completions)
     }
 
     /// The completions refname
@@ -309,6 +513,65 @@ impl<'a> Completer<'a> for MarkdownLinkCompleter<'a> {
     }
 }
 
+/// When a completion's note was last touched: opened or saved in this session, then changed
+type Recent = (u64, Option<SystemTime>);
+
+/// How relevant each matched completion is, with when its note was last touched: the weighted sum of its match score,
+/// the links pointing to its note and how recently the note was touched, each scaled to 0..=1 among the matches
+fn relevance(
+    vault: &Vault,
+    settings: &Settings,
+    recency: &Recency,
+    matched: &[(LinkCompletion, u32)],
+) -> Vec<(f64, Recent)> {
+    let recent = matched
+        .iter()
+        .map(|(completion, _)| match completion.target_path() {
+            Some(path) => (recency.rank(path), vault.select_modified(path)),
+            None => (0, None),
+        })
+        .collect_vec();
+    // recency is ranked rather than measured, so a week and a year apart weigh the same as a minute
+    let recent_order = recent.iter().sorted().dedup().collect_vec();
+    let recent_rank = |recent: &Recent| {
+        let position = recent_order.binary_search(&recent).unwrap_or(0);
+        position as f64 / (recent_order.len() - 1).max(1) as f64
+    };
+
+    let inbound = vault.select_inbound_link_counts();
+    let backlinks = matched
+        .iter()
+        .map(|(completion, _)| {
+            completion
+                .target_path()
+                .and_then(|path| path.file_stem()?.to_str())
+                .and_then(|name| inbound.get(&normalize_for_match(name, &MatchOpts::CASEFOLD)))
+                .map_or(0.0, |count| (*count as f64).ln_1p())
+        })
+        .collect_vec();
+    let most_backlinks = backlinks.iter().copied().fold(0.0, f64::max).max(1.0);
+    let best_score = matched
+        .iter()
+        .map(|(_, score)| *score)
+        .max()
+        .unwrap_or(0)
+        .max(1) as f64;
+
+    matched
+        .iter()
+        .zip(backlinks)
+        .zip(&recent)
+        .map(|(((_, score), backlinks), recent)| {
+            (
+                settings.completion_match_weight * *score as f64 / best_score
+                    + settings.completion_backlink_weight * backlinks / most_backlinks
+                    + settings.completion_recency_weight * recent_rank(recent),
+                *recent,
+            )
+        })
+        .collect()
+}
+
 #[derive(Debug, Clone)]
 pub enum PartialInfileRef {
     HeadingRef(String),
@@ -334,6 +597,166 @@ impl PartialInfileRef {
     }
 }
 
+/// The wiki link being typed at a cursor, read from its line alone; parsing it needs no vault, so completions for a
+/// line can be checked without one
+#[derive(Debug, PartialEq, Eq)]
+pub struct PartialWikiLink {
+    /// Index of the second `[` of the `[[`
+    pub index: usize,
+    /// What is typed between the `[[` and the cursor
+    pub query: String,
+    /// The link is an embed, `![[`
+    pub embed: bool,
+    /// The line is a table row
+    pub table_row: bool,
+    /// Where the part of the link the completion replaces ends, if the line already closes the link: after its `]]`,
+    /// or before the `#heading` or `|display` that follows the part under the cursor
+    pub link_end: Option<u32>,
+    /// The closed link goes on after `link_end` with a heading or display text, which the completion keeps
+    pub link_continues: bool,
+    /// The `]` right after the cursor, at most two
+    pub closing_brackets: u32,
+    /// The cursor is between the brackets of the link's `[[`, so the brackets are replaced with its file part
+    pub before_brackets: bool,
+}
+
+impl PartialWikiLink {
+    /// The wiki link the cursor at `character` is in, if the `[[` before it is not closed before the cursor
+    pub fn parse(line: &str, character: usize) -> Option<PartialWikiLink> {
+        let line_chars = line.chars().collect_vec();
+
+        // `[|[`; a cursor before the `[[` is not in the link
+        if character > 0
+            && line_chars.get(character - 1) == Some(&'[')
+            && line_chars.get(character) == Some(&'[')
+        {
+            return Self::parse_at_opening(line, &line_chars, character - 1);
+        }
+
+        let index = line_chars
+            .get(0..=(character.min(line_chars.len().checked_sub(1)?)))? // select only the characters up to the cursor
+            .iter()
+            .enumerate() // attach indexes
+            .tuple_windows() // window into pairs of characters
+            .collect::<Vec<(_, _)>>()
+            .into_iter()
+            .rev() // search from the cursor back
+            .find(|((_, &c1), (_, &c2))| c1 == '[' && c2 == '[')
+            .map(|(_, (i, _))| i)?; // only take the index; using map because find returns an option
+
+        // an escaped bracket, `\]`, does not close the link
+        if line_chars
+            .get(index..character)?
+            .iter()
+            .tuple_windows()
+            .any(|(prev, c)| *c == ']' && *prev != '\\')
+        {
+            return None;
+        }
+
+        let query = line_chars.get(index + 1..character)?;
+        let embed = index > 1 && line_chars.get(index - 2) == Some(&'!');
+
+        // The cursor is in the size parameter of an embed, like ![[image.png|200]]; there is nothing to link here
+        if embed && query.contains(&'|') {
+            return None;
+        }
+
+        let query = String::from_iter(query);
+        let (link_end, link_continues) = Reference::new(line, "")
+            .find(|reference| {
+                matches!(
+                    reference,
+                    Reference::WikiFileLink(..)
+                        | Reference::WikiHeadingLink(..)
+                        | Reference::WikiIndexedBlockLink(..)
+                ) && reference.range.start.character <= index as u32
+                    && reference.range.end.character >= character as u32
+            })
+            .map(|reference| {
+                let (end, continues) = replaced_end(
+                    &line_chars,
+                    character,
+                    reference.range.end.character,
+                    &query,
+                );
+                (Some(end), continues)
+            })
+            .unwrap_or((None, false));
+
+        Some(PartialWikiLink {
+            index,
+            query,
+            embed,
+            table_row: line_chars.iter().find(|c| !c.is_whitespace()) == Some(&'|'),
+            link_end,
+            link_continues,
+            closing_brackets: line_chars
+                .get(character..)
+                .unwrap_or_default()
+                .iter()
+                .take(2)
+                .take_while(|c| **c == ']')
+                .count() as u32,
+            before_brackets: false,
+        })
+    }
+
+    /// A cursor between the `[[` of a link that the line closes completes its file part, as if nothing was typed in
+    /// it, and keeps its heading and display text
+    fn parse_at_opening(
+        line: &str,
+        line_chars: &[char],
+        opening: usize,
+    ) -> Option<PartialWikiLink> {
+        let link_end = Reference::new(line, "")
+            .find(|reference| {
+                matches!(
+                    reference,
+                    Reference::WikiFileLink(..)
+                        | Reference::WikiHeadingLink(..)
+                        | Reference::WikiIndexedBlockLink(..)
+                ) && reference.range.start.character == opening as u32
+            })
+            .map(|reference| reference.range.end.character)?;
+        let (link_end, link_continues) = replaced_end(line_chars, opening + 2, link_end, "");
+
+        Some(PartialWikiLink {
+            index: opening + 1,
+            query: String::new(),
+            embed: opening > 0 && line_chars.get(opening - 1) == Some(&'!'),
+            table_row: line_chars.iter().find(|c| !c.is_whitespace()) == Some(&'|'),
+            link_end: Some(link_end),
+            link_continues,
+            closing_brackets: 0,
+            before_brackets: true,
+        })
+    }
+}
+
+/// Where the part of a closed link ending at `link_end` that a completion of `query` replaces ends, from the cursor at
+/// `character`, and whether the link goes on after it. A file part stops at its `#heading` or `|display`, and a heading
+/// part at its `|display`, so completing them keeps the rest of the link
+fn replaced_end(line_chars: &[char], character: usize, link_end: u32, query: &str) -> (u32, bool) {
+    let ends_part = |c: &char| match (query.contains('#'), query.contains('|')) {
+        (_, true) => false,
+        (true, false) => *c == '|',
+        (false, false) => *c == '|' || *c == '#',
+    };
+    let rest = line_chars
+        .get(character..(link_end as usize).saturating_sub(2))
+        .unwrap_or_default();
+
+    match rest.iter().position(ends_part) {
+        // the escaped pipe of a table row, `\|`, ends the part at its backslash
+        Some(offset) if offset > 0 && rest[offset] == '|' && rest[offset - 1] == '\\' => {
+            ((character + offset - 1) as u32, true)
+        }
+        Some(offset) => ((character + offset) as u32, true),
+        None => (link_end, false),
+    }
+}
+
 pub struct WikiLinkCompleter<'a> {
     vault: &'a Vault,
     cmp_text: Vec<char>,
@@ -343,7 +766,21 @@ pub struct WikiLinkCompleter<'a> {
     line: u32,
     context_path: &'a Path,
     settings: &'a Settings,
-    chars_in_line: u32,
+    /// Where the part of the wiki link under the cursor ends, if the line already closes the link
+    link_end: Option<u32>,
+    /// The link goes on after `link_end` with a heading or display text that the completion keeps
+    link_continues: bool,
+    /// The `]` right after the cursor, at most two, that the completion's own `]]` replaces
+    closing_brackets: u32,
+    /// The link is an embed, `![[`
+    embed: bool,
+    /// The link is in a table row, where the display text follows an escaped pipe, `\|`, that does not end the cell
+    table_row: bool,
+    /// The cursor is between the brackets of the link's `[[` rather than after them, so the completion replaces the
+    /// brackets as well
+    before_brackets: bool,
+    recency: &'a Recency,
+    completion_cache: &'a Mutex<CompletionCache>,
 }
 
 impl<'a> LinkCompleter<'a> for WikiLinkCompleter<'a> {
@@ -355,6 +792,14 @@ impl<'a> LinkCompleter<'a> for WikiLinkCompleter<'a> {
         self.context_path
     }
 
+    fn recency(&self) -> &'a Recency {
+        self.recency
+    }
+
+    fn completion_cache(&self) -> &'a Mutex<CompletionCache> {
+        self.completion_cache
+    }
+
     fn position(&self) -> Position {
         Position {
             line: self.line,
@@ -371,7 +816,11 @@ impl<'a> LinkCompleter<'a> for WikiLinkCompleter<'a> {
     }
 
     fn completion_text_edit(&self, display: Option<&str>, refname: &str) -> CompletionTextEdit {
-        let ext = if self.settings().include_md_extension_wikilink {
+        let ext = if self.settings().include_md_extension_wikilink
+            && !refname.ends_with('/')
+            && !is_attachment(Path::new(refname))
+            && self.vault.select_attachment(refname).is_none()
+        {
             ".md"
         } else {
             ""
@@ -380,22 +829,42 @@ impl<'a> LinkCompleter<'a> for WikiLinkCompleter<'a> {
             range: Range {
                 start: Position {
                     line: self.line,
-                    character: self.index + 1_u32, // index is right at the '[' in [[link]]; we want one more than that
+                    character: match self.before_brackets {
+                        true => self.index - 1,
+                        false => self.index + 1_u32, // index is right at the '[' in [[link]]; we want one more than that
+                    },
                 },
                 end: Position {
                     line: self.line,
-                    character: (self.chars_in_line - 1).min(self.character + 2_u32), // TODO: in zed, you cannot zed end to be out of the line count index
+                    // replace the whole parsed link, so text after the cursor like the `le` in `[[fi|le]]` does not linger;
+                    // an unclosed link only loses the brackets an editor may have paired after the cursor
+                    character: self
+                        .link_end
+                        .unwrap_or(self.character + self.closing_brackets),
                 },
             },
 
-            new_text: format!(
-                "{}{}{}]]${{2:}}",
-                refname,
-                ext,
-                display
-                    .map(|display| format!("|{}", display))
-                    .unwrap_or("".to_string())
-            ),
+            new_text: match self.link_continues {
+                // the link's own heading or display text follows, and so does its `]]`
+                true => format!(
+                    "{}{}{}",
+                    if self.before_brackets { "[[" } else { "" },
+                    refname,
+                    ext
+                ),
+                false => format!(
+                    "{}{}{}{}]]$0",
+                    if self.before_brackets { "[[" } else { "" },
+                    refname,
+                    ext,
+                    display
+                        .map(|display| match self.table_row {
+                            true => format!("\\|{}", display),
+                            false => format!("|{}", display),
+                        })
+                        .unwrap_or("".to_string())
+                ),
+            },
         })
     }
 }
@@ -419,40 +888,25 @@ impl<'a> Completer<'a> for WikiLinkCompleter<'a> {
         } = context;
 
         let line_chars = vault.select_line(path, line as isize)?;
+        let link = PartialWikiLink::parse(&String::from_iter(line_chars), character)?;
 
-        let index = line_chars
-            .get(0..=(character.min(line_chars.len() - 1)))? // select only the characters up to the cursor
-            .iter()
-            .enumerate() // attach indexes
-            .tuple_windows() // window into pairs of characters
-            .collect::<Vec<(_, _)>>()
-            .into_iter()
-            .rev() // search from the cursor back
-            .find(|((_, &c1), (_, &c2))| c1 == '[' && c2 == '[')
-            .map(|(_, (i, _))| i); // only take the index; using map because find returns an option
-
-        let index = index.and_then(|index| {
-            if line_chars.get(index..character)?.iter().contains(&']') {
-                None
-            } else {
-                Some(index)
-            }
-        });
-
-        index.and_then(|index| {
-            let cmp_text = line_chars.get(index + 1..character)?;
-
-            Some(WikiLinkCompleter {
-                vault,
-                cmp_text: cmp_text.to_vec(),
-                files: opened_files,
-                index: index as u32,
-                character: character as u32,
-                line: line as u32,
-                context_path: context.path,
-                settings: context.settings,
-                chars_in_line: line_chars.len() as u32,
-            })
+        Some(WikiLinkCompleter {
+            vault,
+            cmp_text: link.query.chars().collect(),
+            files: opened_files,
+            index: link.index as u32,
+            character: character as u32,
+            line: line as u32,
+            context_path: context.path,
+            settings: context.settings,
+            link_end: link.link_end,
+            link_continues: link.link_continues,
+            closing_brackets: link.closing_brackets,
+            embed: link.embed,
+            table_row: link.table_row || in_table_row(&context, line),
+            before_brackets: link.before_brackets,
+            recency: context.recency,
+            completion_cache: context.completion_cache,
         })
     }
 
@@ -460,67 +914,46 @@ impl<'a> Completer<'a> for WikiLinkCompleter<'a> {
     where
         Self: Sized,
     {
-        let WikiLinkCompleter { vault, .. } = self;
-
         match *self.cmp_text {
-            // Give recent referenceables; TODO: improve this;
+            // `[[#heading` and `[[#^block` link into the current file
+            ['#', ..] if !self.cmp_text.contains(&']') => self.rank_link_completions(
+                &String::from_iter(&self.cmp_text),
+                self.infile_completions(),
+            ),
+            // All attachments, in path order
+            [] if self.embed => self
+                .embed_completions()
+                .into_iter()
+                .enumerate()
+                .map(|(i, completion)| OrderedCompletion::new(completion, format!("{:06}", i)))
+                .collect_vec(),
+            _ if self.embed && !self.cmp_text.contains(&']') => self.rank_link_completions(
+                &String::from_iter(&self.cmp_text),
+                self.embed_completions(),
+            ),
+            // Every note before anything is typed: the open ones first, then the most recently opened or saved
             [] => self
-                .files
-                .iter()
-                .map(
-                    |path| match std::fs::metadata(path).and_then(|meta| meta.modified()) {
-                        Ok(modified) => (path, modified),
-                        Err(_) => (path, SystemTime::UNIX_EPOCH),
-                    },
-                )
-                .sorted_by_key(|(_, modified)| *modified)
-                .flat_map(|(path, modified)| {
-                    let referenceables = vault
-                        .select_referenceable_nodes(Some(path))
-                        .into_iter()
-                        .filter(|referenceable| {
-                            self.settings().heading_completions
-                                || !matches!(
-                                    referenceable,
-                                    Referenceable::Heading(..)
-                                        | Referenceable::UnresolvedHeading(..)
-                                )
-                        })
-                        .collect::<Vec<_>>();
-
-                    let modified_string = modified
-                        .duration_since(SystemTime::UNIX_EPOCH)
-                        .ok()?
-                        .as_secs()
-                        .to_string();
-
-                    Some(
-                        referenceables
-                            .into_iter()
-                            .flat_map(move |referenceable| LinkCompletion::new(referenceable, self))
-                            .flatten()
-                            .flat_map(move |completion| {
-                                Some(OrderedCompletion::<WikiLinkCompleter, LinkCompletion>::new(
-                                    completion,
-                                    modified_string.clone(),
-                                ))
-                            }),
+                .link_completions()
+                .into_iter()
+                .sorted_by_cached_key(|completion| {
+                    let path = completion.target_path();
+                    (
+                        Reverse(
+                            path.is_some_and(|path| self.files.iter().any(|file| file == path)),
+                        ),
+                        Reverse(path.map(|path| self.recency.rank(path))),
+                        completion.match_string().to_string(),
                     )
                 })
-                .flatten()
+                .enumerate()
+                .map(|(i, completion)| OrderedCompletion::new(completion, format!("{:06}", i)))
                 .collect_vec(),
             ref filter_text @ [..] if !filter_text.contains(&']') => {
                 let filter_text = &self.cmp_text;
 
                 let link_completions = self.link_completions();
 
-                let matches = fuzzy_match_completions(
-                    &String::from_iter(filter_text),
-                    link_completions,
-                    &self.settings.case_matching,
-                );
-
-                matches
+                self.rank_link_completions(&String::from_iter(filter_text), link_completions)
             }
             _ => vec![],
         }
@@ -532,6 +965,85 @@ impl<'a> Completer<'a> for WikiLinkCompleter<'a> {
     }
 }
 
+impl<'a> WikiLinkCompleter<'a> {
+    /// The headings of the current file, or its blocks once the query starts with `#^`
+    fn infile_completions(&self) -> Vec<LinkCompletion<'a>> {
+        let blocks = self.cmp_text.get(1) == Some(&'^');
+
+        self.vault
+            .select_referenceable_nodes(Some(self.context_path))
+            .into_iter()
+            .filter_map(|referenceable| match referenceable {
+                Referenceable::Heading(_, heading)
+                    if !blocks && self.settings().heading_completions =>
+                {
+                    Some(Heading {
+                        heading,
+                        match_string: format!("#{}", heading.heading_text),
+                        referenceable,
+                    })
+                }
+                Referenceable::IndexedBlock(_, block) if blocks => Some(Block {
+                    match_string: format!("#^{}", block.index),
+                    referenceable,
+                }),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Embeds complete to attachments. Notes are offered as well once the query names one, or reaches into one with `#`
+    fn embed_completions(&self) -> Vec<LinkCompletion<'a>> {
+        let query = normalize_for_match(&String::from_iter(&self.cmp_text), &MatchOpts::CASEFOLD);
+        let (query_file, infile) = match query.split_once('#') {
+            Some((file, _)) => (file, true),
+            None => (query.as_str(), false),
+        };
+
+        let attachments = self.vault.select_attachments();
+        let name_counts = attachments.iter().counts_by(|path| path.file_name());
+        let attachment_completions = attachments.iter().flat_map(|path| {
+            let file_name = path.file_name()?.to_str()?;
+            let relative_path = diff_paths(path, self.vault.root_dir())?;
+            let relative_path = relative_path.to_str()?;
+            // the file name is enough, unless another attachment shares it
+            let shared_name = name_counts[&path.file_name()] > 1;
+
+            Some(Attachment {
+                path,
+                match_string: relative_path.to_string(),
+                refname: match shared_name {
+                    true => relative_path.to_string(),
+                    false => file_name.to_string(),
+                },
+            })
+        });
+
+        let note_completions = self.link_completions().into_iter().filter(|completion| {
+            let file = match completion {
+                DailyNote(_) => return false,
+                File { .. } | Alias { .. } if !infile => completion.match_string().to_string(),
+                _ if !infile => return false,
+                completion => completion.refname(),
+            };
+            let file = normalize_for_match(
+                file.split('#').next().unwrap_or_default(),
+                &MatchOpts::CASEFOLD,
+            );
+
+            match infile {
+                true => file == query_file,
+                false => !query_file.is_empty() && file.starts_with(query_file),
+            }
+        });
+
+        match infile {
+            true => note_completions.collect(),
+            false => attachment_completions.chain(note_completions).collect(),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum LinkCompletion<'a> {
     File {
@@ -549,6 +1061,14 @@ pub enum LinkCompletion<'a> {
         match_string: String,
         referenceable: Referenceable<'a>,
     },
+    /// A heading of a file, reached through one of the file's aliases: `[[alias#heading`
+    AliasHeading {
+        alias: &'a str,
+        filename: &'a str,
+        heading: &'a MDHeading,
+        match_string: String,
+        referenceable: Referenceable<'a>,
+    },
     Block {
         match_string: String,
         referenceable: Referenceable<'a>,
@@ -560,6 +1080,18 @@ pub enum LinkCompletion<'a> {
         referenceable: Referenceable<'a>,
     },
     DailyNote(MDDailyNote<'a>),
+    /// A folder, `folder/`, linked through its folder note
+    Folder {
+        match_string: String,
+        referenceable: Referenceable<'a>,
+    },
+    /// A non-markdown file for an embed, like an image
+    Attachment {
+        path: &'a PathBuf,
+        /// Vault relative path, with the extension
+        match_string: String,
+        refname: String,
+    },
 }
 
 use LinkCompletion::*;
@@ -650,19 +1182,48 @@ impl LinkCompletion<'_> {
         let referenceable = match self {
             Self::File { referenceable, .. }
             | Self::Heading { referenceable, .. }
+            | Self::AliasHeading { referenceable, .. }
             | Self::Block { referenceable, .. }
             | Self::Unresolved { referenceable, .. }
-            | Self::Alias { referenceable, .. } => referenceable.to_owned(),
-            Self::DailyNote(daily) => daily.referenceable(completer),
+            | Self::Alias { referenceable, .. }
+            | Self::Folder { referenceable, .. } => Some(referenceable.to_owned()),
+            Self::DailyNote(daily) => Some(daily.referenceable(completer)),
+            Self::Attachment { .. } => None,
         };
 
         let label = self.match_string();
 
+        let target = match self {
+            Self::Attachment { .. } | Self::Unresolved { .. } => None,
+            _ if !completer.settings().completion_previews => None,
+            _ => referenceable.as_ref().and_then(CompletionTarget::new),
+        };
+
+        let relative_path = referenceable
+            .as_ref()
+            .and_then(|referenceable| diff_paths(referenceable.get_path(), vault.root_dir()))
+            .and_then(|path| path.to_str().map(String::from));
+
         CompletionItem {
             label: label.to_string(),
+            detail: relative_path.and_then(|path| match self {
+                File { .. } => Some(format!("{path} (filename)")),
+                Folder { .. } => Some(format!("{path} (folder note)")),
+                Alias { .. } => Some(format!("alias of {path}")),
+                Heading { .. } => Some(format!("heading in {path}")),
+                AliasHeading { alias, .. } => Some(format!("heading in {path} (alias {alias})")),
+                Block { .. } => Some(format!("block in {path}")),
+                DailyNote(daily) if daily.real_referenceaable.is_some() => {
+                    Some(format!("{path} (daily note)"))
+                }
+                DailyNote(_) => Some(format!("{path} (new daily note)")),
+                Unresolved { .. } | Attachment { .. } => None,
+            }),
             kind: Some(match self {
                 Self::File { .. } => CompletionItemKind::FILE,
-                Self::Heading { .. } | Self::Block { .. } => CompletionItemKind::REFERENCE,
+                Self::Heading { .. } | Self::AliasHeading { .. } | Self::Block { .. } => {
+                    CompletionItemKind::REFERENCE
+                }
                 Self::Unresolved {
                     match_string: _,
                     infile_ref: _,
@@ -670,6 +1231,8 @@ impl LinkCompletion<'_> {
                 } => CompletionItemKind::KEYWORD,
                 Self::Alias { .. } => CompletionItemKind::ENUM,
                 Self::DailyNote { .. } => CompletionItemKind::EVENT,
+                Self::Attachment { .. } => CompletionItemKind::FILE,
+                Self::Folder { .. } => CompletionItemKind::FOLDER,
             }),
             label_details: match self {
                 Self::Unresolved {
@@ -680,14 +1243,41 @@ impl LinkCompletion<'_> {
                     detail: Some("Unresolved".into()),
                     description: None,
                 }),
-                Alias { filename, .. } => Some(CompletionItemLabelDetails {
-                    detail: Some(format!("Alias: {}.md", filename)),
+                Alias {
+                    filename,
+                    referenceable,
+                    ..
+                }
+                | AliasHeading {
+                    filename,
+                    referenceable,
+                    ..
+                } => Some(CompletionItemLabelDetails {
+                    detail: Some(format!(
+                        "Alias: {}.md",
+                        get_obsidian_ref_path(vault.root_dir(), referenceable.get_path())
+                            .as_deref()
+                            .unwrap_or(filename)
+                    )),
+                    description: None,
+                }),
+                Attachment { .. } => Some(CompletionItemLabelDetails {
+                    detail: Some("Attachment".into()),
                     description: None,
                 }),
                 File { .. } => None,
+                Folder { .. } => None,
                 Heading { .. } => None,
                 Block { .. } => None,
-                DailyNote(_) => None,
+                // the date the note is for, whatever the format of its name
+                DailyNote(daily) => {
+                    daily
+                        .get_self_date(completer)
+                        .map(|date| CompletionItemLabelDetails {
+                            detail: None,
+                            description: Some(date.format("%Y-%m-%d").to_string()),
+                        })
+                }
             },
             text_edit: Some(text_edit),
             preselect: Some(match self {
@@ -697,12 +1287,65 @@ impl LinkCompletion<'_> {
                 link_completion => link_completion.refname() == completer.entered_refname(),
             }),
             filter_text: Some(filter_text.to_string()),
-            documentation: preview_referenceable(vault, &referenceable)
-                .map(Documentation::MarkupContent),
+            documentation: match self {
+                Self::Attachment { path, refname, .. } => {
+                    Url::from_file_path(path).ok().map(|url| MarkupContent {
+                        kind: MarkupKind::Markdown,
+                        value: format!("![{}]({})", refname, url),
+                    })
+                }
+                // notes are previewed when the item is resolved
+                _ if target.is_some() || !completer.settings().completion_previews => None,
+                _ => referenceable
+                    .and_then(|referenceable| preview_referenceable(vault, &referenceable)),
+            }
+            .map(Documentation::MarkupContent),
+            data: target.and_then(|target| serde_json::to_value(target).ok()),
             ..Default::default()
         }
     }
 
+    /// The file the completion links to
+    fn target_path(&self) -> Option<&Path> {
+        match self {
+            File { mdfile, .. } => Some(&mdfile.path),
+            Alias { referenceable, .. }
+            | Heading { referenceable, .. }
+            | AliasHeading { referenceable, .. }
+            | Block { referenceable, .. }
+            | Folder { referenceable, .. } => Some(referenceable.get_path()),
+            DailyNote(daily) => daily
+                .real_referenceaable
+                .as_ref()
+                .map(|referenceable| referenceable.get_path()),
+            Attachment { path, .. } => Some(path),
+            Unresolved { .. } => None,
+        }
+    }
+
+    /// The completion with its file named by its entry in `unambiguous_refnames`, if it has one
+    fn unambiguous(mut self, unambiguous_refnames: &HashMap<&Path, String>) -> Self {
+        let Some(refname) = self
+            .target_path()
+            .and_then(|path| unambiguous_refnames.get(path))
+        else {
+            return self;
+        };
+
+        if let File { match_string, .. }
+        | Heading { match_string, .. }
+        | Block { match_string, .. } = &mut self
+        {
+            let infile_ref = match_string
+                .find('#')
+                .map(|index| match_string[index..].to_string())
+                .unwrap_or_default();
+            *match_string = format!("{refname}{infile_ref}");
+        }
+
+        self
+    }
+
     /// Refname to be inserted into the document
     fn refname(&self) -> String {
         match self {
@@ -710,8 +1353,27 @@ impl LinkCompletion<'_> {
             File { match_string, .. }
             | Heading { match_string, .. }
             | Block { match_string, .. }
-            | Unresolved { match_string, .. } => match_string.to_string(),
+            | Unresolved { match_string, .. }
+            | Folder { match_string, .. } => match_string.to_string(),
             Alias { filename, .. } => filename.to_string(),
+            AliasHeading {
+                filename, heading, ..
+            } => format!("{}#{}", filename, heading.heading_text),
+            Attachment { refname, .. } => refname.to_string(),
+        }
+    }
+
+    /// Refname for markdown links; headings are referenced by their GitHub anchor
+    fn markdown_refname(&self) -> String {
+        match self {
+            Heading { heading, .. } | AliasHeading { heading, .. } => {
+                let refname = self.refname();
+                refname
+                    .split_once('#')
+                    .map(|(file, _)| format!("{}#{}", file, heading.anchor))
+                    .unwrap_or_else(|| refname.clone())
+            }
+            _ => self.refname(),
         }
     }
 }
@@ -721,7 +1383,7 @@ impl<'a> Completable<'a, MarkdownLinkCompleter<'a>> for LinkCompletion<'a> {
         &self,
         markdown_link_completer: &MarkdownLinkCompleter<'a>,
     ) -> Option<CompletionItem> {
-        let refname = self.refname();
+        let refname = self.markdown_refname();
         let match_string = self.match_string();
 
         let display = &markdown_link_completer.display;
@@ -734,8 +1396,11 @@ impl<'a> Completable<'a, MarkdownLinkCompleter<'a>> for LinkCompletion<'a> {
             }
             | Self::Block {
                 match_string: _, ..
-            } => None,
+            }
+            | Self::Folder { .. }
+            | Self::Attachment { .. } => None,
             Self::Alias { match_string, .. } => Some(match_string.to_string()),
+            Self::AliasHeading { alias, .. } => Some(alias.to_string()),
             Self::DailyNote(daily) => daily.relative_name(markdown_link_completer),
             Self::Heading {
                 heading,
@@ -768,7 +1433,7 @@ impl<'a> Completable<'a, MarkdownLinkCompleter<'a>> for LinkCompletion<'a> {
             (display, _) => display,
         };
 
-        let link_display_text = format!("${{1:{}}}", link_display_text,);
+        let link_display_text = format!("${{1:{}}}", escape_link_brackets(link_display_text));
 
         let text_edit =
             markdown_link_completer.completion_text_edit(Some(&link_display_text), &refname);
@@ -784,17 +1449,57 @@ impl<'a> Completable<'a, MarkdownLinkCompleter<'a>> for LinkCompletion<'a> {
 
 impl<'a> Completable<'a, WikiLinkCompleter<'a>> for LinkCompletion<'a> {
     fn completions(&self, completer: &WikiLinkCompleter<'a>) -> Option<CompletionItem> {
-        let refname = self.refname();
         let match_text = self.match_string();
 
+        // Aliases resolve on their own, unless the alias is also another file's name or alias, or has
+        // characters that can not be in the file part of a link; those link the file and show the alias
+        let insert_alias = match self {
+            Alias { match_string, .. } if match_string.contains(['[', ']', '|', '#']) => false,
+            Alias { match_string, .. } => {
+                completer
+                    .vault()
+                    .md_files
+                    .values()
+                    .filter(|mdfile| {
+                        mdfile.has_alias(match_string)
+                            || mdfile.file_name().is_some_and(|name| {
+                                normalize_for_match(name, &MatchOpts::CASEFOLD)
+                                    == normalize_for_match(match_string, &MatchOpts::CASEFOLD)
+                            })
+                    })
+                    .count()
+                    == 1
+            }
+            _ => false,
+        };
+
+        let refname = match self {
+            Alias { match_string, .. } if insert_alias => match_string.to_string(),
+            _ => self.refname(),
+        };
+
         let wikilink_display_text = match self {
             File { .. } => None,
-            Alias { match_string, .. } => Some(format!("${{1:{}}}", match_string)),
+            Folder { .. } => None,
+            Alias { .. } if insert_alias => None,
+            Alias { match_string, .. } => {
+                Some(format!("${{1:{}}}", escape_wiki_link_text(match_string)))
+            }
+            AliasHeading { alias, .. } => Some(format!("${{1:{}}}", escape_wiki_link_text(alias))),
             Heading { .. } => None,
             Block { .. } => None,
             Unresolved { .. } => None,
             DailyNote(_) => None,
-        };
+            Attachment { .. } => None,
+        }
+        .or_else(|| {
+            // an empty display text is only useful with the cursor in it, which plain text can not do
+            let settings = completer.settings();
+            (settings.wikilink_display_tabstop
+                && settings.snippet_support
+                && !matches!(self, Attachment { .. }))
+            .then(|| "${1:}".to_string())
+        });
 
         let text_edit = completer.completion_text_edit(wikilink_display_text.as_deref(), &refname);
 
@@ -807,6 +1512,90 @@ impl<'a> Completable<'a, WikiLinkCompleter<'a>> for LinkCompletion<'a> {
     }
 }
 
+/// The note, heading or block a link completion points to; sent as the item's `data` so that its preview is only read when the item is resolved
+#[derive(Debug, Serialize, Deserialize)]
+struct CompletionTarget {
+    path: PathBuf,
+    heading: Option<String>,
+    block: Option<String>,
+}
+
+impl CompletionTarget {
+    fn new(referenceable: &Referenceable) -> Option<CompletionTarget> {
+        let (path, heading, block) = match referenceable {
+            Referenceable::File(path, _) => (path, None, None),
+            Referenceable::Heading(path, heading) => {
+                (path, Some(heading.heading_text.clone()), None)
+            }
+            Referenceable::IndexedBlock(path, block) => (path, None, Some(block.index.clone())),
+            _ => return None,
+        };
+
+        Some(CompletionTarget {
+            path: path.to_path_buf(),
+            heading,
+            block,
+        })
+    }
+
+    fn referenceable<'a>(&self, vault: &'a Vault) -> Option<Referenceable<'a>> {
+        let (path, mdfile) = vault.md_files.get_key_value(&self.path)?;
+
+        match (&self.heading, &self.block) {
+            (Some(heading), _) => mdfile
+                .headings
+                .iter()
+                .find(|it| &it.heading_text == heading)
+                .map(|heading| Referenceable::Heading(path, heading)),
+            (_, Some(block)) => mdfile
+                .indexed_blocks
+                .iter()
+                .find(|it| &it.index == block)
+                .map(|block| Referenceable::IndexedBlock(path, block)),
+            (None, None) => Some(Referenceable::File(path, mdfile)),
+        }
+    }
+}
+
+/// The note a resolvable link completion is for
+pub fn completion_target_path(item: &CompletionItem) -> Option<PathBuf> {
+    item.data
+        .clone()
+        .and_then(|data| serde_json::from_value::<CompletionTarget>(data).ok())
+        .map(|target| target.path)
+}
+
+/// Fills in the preview of a link completion's target, and how many links point to it
+pub fn resolve_link_completion(vault: &Vault, item: CompletionItem) -> CompletionItem {
+    let Some(referenceable) = item
+        .data
+        .clone()
+        .and_then(|data| serde_json::from_value::<CompletionTarget>(data).ok())
+        .and_then(|target| target.referenceable(vault))
+    else {
+        return item;
+    };
+
+    let backlinks = match vault
+        .select_references_for_referenceable(&referenceable)
+        .map(|references| references.len())
+        .unwrap_or(0)
+    {
+        1 => "1 backlink".to_string(),
+        n => format!("{n} backlinks"),
+    };
+
+    CompletionItem {
+        detail: Some(match &item.detail {
+            Some(detail) => format!("{detail}, {backlinks}"),
+            None => backlinks,
+        }),
+        documentation: preview_referenceable(vault, &referenceable)
+            .map(Documentation::MarkupContent),
+        ..item
+    }
+}
+
 impl Matchable for LinkCompletion<'_> {
     /// The string used for fuzzy matching
     fn match_string(&self) -> &str {
@@ -821,8 +1610,11 @@ impl Matchable for LinkCompletion<'_> {
                 match_string,
                 ..
             }
+            | AliasHeading { match_string, .. }
             | Block { match_string, .. }
             | Unresolved { match_string, .. }
+            | Attachment { match_string, .. }
+            | Folder { match_string, .. }
             | DailyNote(MDDailyNote { match_string, .. }) => match_string,
             Alias { match_string, .. } => match_string,
         }
@@ -844,9 +1636,7 @@ impl MDDailyNote<'_> {
     }
 
     pub fn get_self_date<'a>(&self, completer: &impl LinkCompleter<'a>) -> Option<NaiveDate> {
-        let dailynote_format = &completer.settings().dailynote;
-
-        chrono::NaiveDate::parse_from_str(&self.ref_name, dailynote_format).ok()
+        daily_note_date(completer.settings(), &self.ref_name)
     }
 
     fn relative_date_string(date: NaiveDate) -> Option<String> {
@@ -903,7 +1693,10 @@ impl MDDailyNote<'_> {
         completer: &impl LinkCompleter<'a>,
     ) -> Option<MDDailyNote<'a>> {
         let filerefname = date.format(&completer.settings().dailynote).to_string();
-        let match_string = format!("{}: {}", Self::relative_date_string(date)?, filerefname);
+        let match_string = match Self::relative_date_string(date) {
+            Some(relative) => format!("{}: {}", relative, filerefname),
+            None => filerefname.clone(),
+        };
 
         // path on unresolved file is useless
         Some(MDDailyNote {
@@ -919,11 +1712,1216 @@ impl MDDailyNote<'_> {
             return referencaable.clone();
         }
 
-        let mut path = completer.vault().root_dir().to_path_buf();
-        path.push(format!("{}.md", self.ref_name));
+        // where the note would be created, so that the completion shows it
+        let path = match daily_note_date(completer.settings(), &self.ref_name) {
+            Some(date) => daily_note_path(completer.settings(), completer.vault().root_dir(), date),
+            None => completer
+                .vault()
+                .root_dir()
+                .join(format!("{}.md", self.ref_name)),
+        };
 
         let unresolved_file = Referenceable::UnresovledFile(path.to_path_buf(), &self.ref_name);
 
         unresolved_file
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use itertools::Itertools;
+    use tower_lsp::lsp_types::{
+        ClientCapabilities, CompletionItem, CompletionList, CompletionParams, CompletionResponse,
+        CompletionTextEdit, Documentation, Position, TextDocumentIdentifier,
+        TextDocumentPositionParams, Url,
+    };
+
+    use crate::{
+        completion::{
+            get_completions, resolve_completion, util::plain_text_completion, Completer, Context,
+            Recency,
+        },
+        config::{PositionEncoding, Settings},
+        vault::{unescape_wiki_link_text, Reference, Vault},
+    };
+
+    use super::{MarkdownLinkCompleter, PartialWikiLink};
+
+    /// Completions for the end of `line`, as (label, inserted text) pairs
+    fn complete(line: &str) -> Vec<(String, String)> {
+        completion_items(&[line], "\n")
+            .into_iter()
+            .map(|item| match item.text_edit {
+                Some(CompletionTextEdit::Edit(edit)) => (item.label, edit.new_text),
+                _ => (item.label, String::new()),
+            })
+            .collect_vec()
+    }
+
+    /// Completions for the end of the last of `lines`, in a file whose lines end with `ending`
+    fn completion_items(lines: &[&str], ending: &str) -> Vec<CompletionItem> {
+        completion_items_with_recency(lines, ending, &Recency::default())
+    }
+
+    fn completion_items_with_recency(
+        lines: &[&str],
+        ending: &str,
+        recency: &Recency,
+    ) -> Vec<CompletionItem> {
+        let character = lines.last().unwrap().chars().count() as u32;
+        completion_items_at(lines, ending, character, recency)
+    }
+
+    /// Completions with the cursor at `character` of the last of `lines`
+    fn completion_items_at(
+        lines: &[&str],
+        ending: &str,
+        character: u32,
+        recency: &Recency,
+    ) -> Vec<CompletionItem> {
+        let text = lines.iter().map(|line| format!("{line}{ending}")).join("");
+        let (settings, vault) = test_vault(&text);
+
+        completion_items_in(
+            &vault,
+            &settings,
+            lines.len() as u32 - 1,
+            character,
+            recency,
+        )
+    }
+
+    /// A vault of a few notes, with `text` as `/vault/note.md`
+    fn test_vault(text: &str) -> (Settings, Vault) {
+        let root_dir = Path::new("/vault");
+        let settings = Settings::new(root_dir, &ClientCapabilities::default()).unwrap();
+        let mut vault = Vault::construct_vault(&settings, root_dir).unwrap();
+
+        Vault::update_vault(
+            &settings,
+            &mut vault,
+            (
+                &PathBuf::from("/vault/RealNote.md"),
+                "---\naliases: [Foo]\n---\n# Intro\n## Details\n",
+            ),
+        );
+        for (file, text) in [
+            ("people/robert-smith.md", "---\naliases: [Bob]\n---\n"),
+            ("Meeting.md", "# Meeting\n"),
+            ("notes.md", "---\naliases: [Meeting]\n---\n"),
+            ("proj1.md", ""),
+            ("proj2.md", ""),
+        ] {
+            Vault::update_vault(
+                &settings,
+                &mut vault,
+                (&PathBuf::from("/vault").join(file), text),
+            );
+        }
+        Vault::update_vault(
+            &settings,
+            &mut vault,
+            (&PathBuf::from("/vault/note.md"), text),
+        );
+
+        (settings, vault)
+    }
+
+    /// Completions at `line` and `character` of `/vault/note.md`
+    fn completion_items_in(
+        vault: &Vault,
+        settings: &Settings,
+        line: u32,
+        character: u32,
+        recency: &Recency,
+    ) -> Vec<CompletionItem> {
+        completion_list_in(vault, settings, line, character, recency)
+            .map(|list| list.items)
+            .unwrap_or_default()
+    }
+
+    fn completion_list_in(
+        vault: &Vault,
+        settings: &Settings,
+        line: u32,
+        character: u32,
+        recency: &Recency,
+    ) -> Option<CompletionList> {
+        let path = PathBuf::from("/vault/note.md");
+        let params = CompletionParams {
+            text_document_position: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier {
+                    uri: Url::from_file_path(&path).unwrap(),
+                },
+                position: Position { line, character },
+            },
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+            context: None,
+        };
+
+        match get_completions(
+            vault,
+            &[],
+            &params,
+            &path,
+            settings,
+            recency,
+            &Default::default(),
+        ) {
+            Some(CompletionResponse::List(list)) => Some(list),
+            None => None,
+            Some(response) => panic!("expected a completion list, got {:?}", response),
+        }
+    }
+
+    fn completion_for<'a>(completions: &'a [(String, String)], label: &str) -> Option<&'a str> {
+        completions
+            .iter()
+            .find(|(other, _)| other == label)
+            .map(|(_, text)| text.as_str())
+    }
+
+    #[test]
+    fn wiki_alias_heading_completion() {
+        let completions = complete("[[Foo#Det");
+
+        assert_eq!(
+            completions.first(),
+            Some(&(
+                "Foo#Details".to_string(),
+                "RealNote#Details|${1:Foo}]]$0".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn markdown_alias_heading_completion() {
+        let completions = complete("[](Foo#Int");
+
+        assert_eq!(
+            completions.first(),
+            Some(&(
+                "Foo#Intro".to_string(),
+                "[${1:Foo}](RealNote#intro)".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn alias_without_heading_query_completes_alias() {
+        let completions = complete("[[Fo");
+
+        assert!(completions
+            .iter()
+            .all(|(label, _)| !label.starts_with("Foo#")));
+        assert!(completions.iter().any(|(label, _)| label == "Foo"));
+    }
+
+    #[test]
+    fn embed_size_parameter_has_no_completions() {
+        assert_eq!(complete("![[RealNote|20"), vec![]);
+        assert_eq!(complete("![[RealNote|200|10"), vec![]);
+        assert!(!complete("![[RealN").is_empty());
+    }
+
+    #[test]
+    fn note_matching_by_name_and_aliases_is_completed_once() {
+        let (settings, mut vault) = test_vault("[[roadmap");
+        Vault::update_vault(
+            &settings,
+            &mut vault,
+            (
+                &PathBuf::from("/vault/Road Trip Map.md"),
+                "---\naliases: [Roadmap]\n---\n",
+            ),
+        );
+
+        let labels = completion_items_in(&vault, &settings, 0, 9, &Recency::default())
+            .into_iter()
+            .map(|item| item.label)
+            .filter(|label| label == "Road Trip Map" || label == "Roadmap")
+            .collect_vec();
+
+        // the alias is the whole query, which the name only matches in pieces
+        assert_eq!(labels, vec!["Roadmap"]);
+
+        // a query only the name matches completes the name
+        let completions = complete("[[RealN");
+        assert_eq!(
+            completions
+                .iter()
+                .filter(|(label, _)| label == "RealNote" || label == "Foo")
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn wiki_link_names_may_have_parentheses() {
+        let (settings, mut vault) = test_vault("[[Rock (mu");
+        Vault::update_vault(
+            &settings,
+            &mut vault,
+            (&PathBuf::from("/vault/Rock (music).md"), "# Genres\n"),
+        );
+
+        let completions = completion_items_in(&vault, &settings, 0, 10, &Recency::default())
+            .into_iter()
+            .filter_map(|item| match item.text_edit {
+                Some(CompletionTextEdit::Edit(edit)) => Some((item.label, edit.new_text)),
+                _ => None,
+            })
+            .collect_vec();
+
+        assert_eq!(
+            completion_for(&completions, "Rock (music)"),
+            Some("Rock (music)]]$0")
+        );
+    }
+
+    #[test]
+    fn folders_with_a_folder_note_complete_once_the_query_has_a_slash() {
+        let completions = |line: &str| {
+            let (settings, mut vault) = test_vault(line);
+            for path in [
+                "/vault/projects/projects.md",
+                "/vault/projects/alpha/alpha.md",
+                "/vault/archive/old.md",
+            ] {
+                Vault::update_vault(&settings, &mut vault, (&PathBuf::from(path), ""));
+            }
+
+            let character = line.chars().count() as u32;
+            completion_items_in(&vault, &settings, 0, character, &Recency::default())
+                .into_iter()
+                .filter_map(|item| match item.text_edit {
+                    Some(CompletionTextEdit::Edit(edit)) => Some((item.label, edit.new_text)),
+                    _ => None,
+                })
+                .filter(|(label, _)| label.ends_with('/'))
+                .sorted()
+                .collect_vec()
+        };
+
+        assert_eq!(
+            completions("[[projects/"),
+            vec![
+                ("projects/".to_string(), "projects/]]$0".to_string()),
+                (
+                    "projects/alpha/".to_string(),
+                    "projects/alpha/]]$0".to_string()
+                ),
+            ]
+        );
+        assert_eq!(completions("[[projects"), vec![]);
+    }
+
+    #[test]
+    fn wiki_alias_completion_inserts_alias() {
+        let completions = complete("[[Bo");
+
+        assert_eq!(completion_for(&completions, "Bob"), Some("Bob]]$0"));
+    }
+
+    #[test]
+    fn wiki_alias_colliding_with_file_name_inserts_file() {
+        let completions = complete("[[Meet");
+
+        assert!(completions
+            .iter()
+            .any(|(label, text)| label == "Meeting" && text == "Meeting]]$0"));
+        assert!(completions
+            .iter()
+            .any(|(label, text)| label == "Meeting" && text == "notes|${1:Meeting}]]$0"));
+    }
+
+    #[test]
+    fn crlf_lines_complete_like_lf_lines() {
+        for lines in [
+            &["[[Rea"][..],
+            &["intro", "[[RealNote#Int"],
+            &["intro", "[text](RealN"],
+        ] {
+            let lf = completion_items(lines, "\n");
+            let crlf = completion_items(lines, "\r\n");
+
+            assert!(!lf.is_empty());
+            assert_eq!(crlf, lf);
+        }
+    }
+
+    #[test]
+    fn recently_touched_notes_rank_first_among_equal_matches() {
+        let labels = |recency: &Recency| {
+            completion_items_with_recency(&["[[proj"], "\n", recency)
+                .into_iter()
+                .sorted_by(|a, b| a.sort_text.cmp(&b.sort_text))
+                .map(|item| item.label)
+                .filter(|label| label.starts_with("proj"))
+                .collect_vec()
+        };
+
+        let mut recency = Recency::default();
+        recency.touch(Path::new("/vault/proj2.md"));
+        assert_eq!(labels(&recency), vec!["proj2", "proj1"]);
+
+        recency.touch(Path::new("/vault/proj1.md"));
+        assert_eq!(labels(&recency), vec!["proj1", "proj2"]);
+    }
+
+    /// The edit of the completion labeled `label`, with the cursor at the `|` in `line`
+    fn edit_at_cursor(line: &str, label: &str) -> Option<(u32, u32, String)> {
+        let character = line.find('|').unwrap();
+        let line = line.replacen('|', "", 1);
+        edit_at_line(
+            &completion_items_at(&[&line], "\n", character as u32, &Recency::default()),
+            label,
+        )
+    }
+
+    #[test]
+    fn wiki_completion_replaces_text_after_the_cursor() {
+        assert_eq!(
+            edit_at_cursor("see [[Real|Note]] here", "RealNote"),
+            Some((6, 16, "RealNote]]$0".to_string()))
+        );
+    }
+
+    #[test]
+    fn unclosed_wiki_completion_keeps_the_text_after_the_cursor() {
+        assert_eq!(
+            edit_at_cursor("[[Real| text", "RealNote"),
+            Some((2, 6, "RealNote]]$0".to_string()))
+        );
+        assert_eq!(
+            edit_at_cursor("[[Real|] text", "RealNote"),
+            Some((2, 7, "RealNote]]$0".to_string()))
+        );
+    }
+
+    #[test]
+    fn wiki_completion_replaces_the_whole_embed() {
+        assert_eq!(
+            edit_at_cursor("![[Real|Note]] text", "RealNote"),
+            Some((3, 13, "RealNote]]$0".to_string()))
+        );
+    }
+
+    #[test]
+    fn markdown_completion_replaces_the_whole_link() {
+        assert_eq!(
+            edit_at_cursor("see [x](Real|Note) here", "RealNote"),
+            Some((4, 17, "[${1:x}](RealNote)".to_string()))
+        );
+    }
+
+    #[test]
+    fn hash_completes_headings_of_the_current_file() {
+        let (settings, vault) = test_vault("# Plan\n## Steps\ntext ^blk\n[[#");
+        let items = completion_items_in(&vault, &settings, 3, 3, &Recency::default());
+
+        assert_eq!(
+            items
+                .iter()
+                .map(|item| item.label.as_str())
+                .collect::<Vec<_>>(),
+            vec!["#Plan", "#Steps"]
+        );
+        assert_eq!(
+            edit_at_line(&items, "#Steps"),
+            Some((2, 3, "#Steps]]$0".to_string()))
+        );
+    }
+
+    #[test]
+    fn hash_caret_completes_blocks_of_the_current_file() {
+        let (settings, vault) = test_vault("# Plan\n## Steps\ntext ^blk\n[[#^b]]");
+        let items = completion_items_in(&vault, &settings, 3, 5, &Recency::default());
+
+        assert_eq!(
+            items
+                .iter()
+                .map(|item| item.label.as_str())
+                .collect::<Vec<_>>(),
+            vec!["#^blk"]
+        );
+        assert_eq!(
+            edit_at_line(&items, "#^blk"),
+            Some((2, 7, "#^blk]]$0".to_string()))
+        );
+    }
+
+    /// The (start, end, inserted text) of the item labeled `label`
+    fn edit_at_line(items: &[CompletionItem], label: &str) -> Option<(u32, u32, String)> {
+        items
+            .iter()
+            .find(|item| item.label == label)
+            .and_then(|item| match &item.text_edit {
+                Some(CompletionTextEdit::Edit(edit)) => Some((
+                    edit.range.start.character,
+                    edit.range.end.character,
+                    edit.new_text.clone(),
+                )),
+                _ => None,
+            })
+    }
+
+    #[test]
+    fn aliases_with_link_syntax_are_escaped() {
+        for (line, character) in [("[[A", 3), ("[](A", 4)] {
+            let (settings, mut vault) = test_vault(line);
+            Vault::update_vault(
+                &settings,
+                &mut vault,
+                (
+                    &PathBuf::from("/vault/Odd.md"),
+                    "---\naliases: [\"A|B [1]\"]\n---\n",
+                ),
+            );
+
+            let item = completion_items_in(&vault, &settings, 0, character, &Recency::default())
+                .into_iter()
+                .find(|item| item.label == "A|B [1]")
+                .expect("alias completion");
+            let Some(CompletionTextEdit::Edit(edit)) = plain_text_completion(item).text_edit else {
+                panic!("expected an edit")
+            };
+
+            let start = edit.range.start.character as usize;
+            let inserted = format!("{}{}", &line[..start], edit.new_text);
+            let parsed = Reference::new(&inserted, "note").collect_vec();
+            let [Reference::WikiFileLink(data) | Reference::MDFileLink(data)] = parsed.as_slice()
+            else {
+                panic!("{:?} parsed to {:?}", inserted, parsed)
+            };
+            assert_eq!(data.reference_text, "Odd");
+            assert_eq!(
+                data.display_text.as_deref().map(unescape_wiki_link_text),
+                Some("A|B [1]".to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn partial_wiki_links_parse_without_a_vault() {
+        let parse = |line: &str, character| PartialWikiLink::parse(line, character);
+
+        assert_eq!(
+            parse("see [[Not", 9),
+            Some(PartialWikiLink {
+                index: 5,
+                query: "Not".to_string(),
+                embed: false,
+                table_row: false,
+                link_end: None,
+                link_continues: false,
+                closing_brackets: 0,
+                before_brackets: false,
+            })
+        );
+        assert_eq!(
+            parse("| ![[img]] |", 7),
+            Some(PartialWikiLink {
+                index: 4,
+                query: "im".to_string(),
+                embed: true,
+                table_row: true,
+                link_end: Some(10),
+                link_continues: false,
+                closing_brackets: 0,
+                before_brackets: false,
+            })
+        );
+        assert_eq!(
+            parse("[[]]", 2).map(|link| (link.query, link.closing_brackets)),
+            Some((String::new(), 2))
+        );
+        assert_eq!(parse("[[done]] after", 12), None);
+        assert_eq!(parse("no link", 3), None);
+        assert_eq!(parse("![[image.png|20", 15), None);
+        assert_eq!(parse("", 0), None);
+    }
+
+    #[test]
+    fn cursor_between_the_opening_brackets_completes_the_file_part() {
+        let parse = |line: &str, character| PartialWikiLink::parse(line, character);
+
+        assert_eq!(
+            parse("see [[file]] after", 5),
+            Some(PartialWikiLink {
+                index: 5,
+                query: String::new(),
+                embed: false,
+                table_row: false,
+                link_end: Some(12),
+                link_continues: false,
+                closing_brackets: 0,
+                before_brackets: true,
+            })
+        );
+        // before the brackets is not in the link
+        assert_eq!(parse("see [[file]] after", 4), None);
+        assert_eq!(parse("see [[file]] after", 3), None);
+        // an unclosed link has nothing to replace yet
+        assert_eq!(parse("see [[fil", 5), None);
+        assert!(parse("![[note]]", 2).is_some_and(|link| link.embed));
+        // the heading and display text stay
+        assert!(parse("see [[file#Heading|text]]", 5)
+            .is_some_and(|link| link.link_end == Some(10) && link.link_continues));
+
+        let edit = |line: &str| {
+            let (settings, vault) = test_vault(line);
+            let edits = completion_items_in(&vault, &settings, 0, 5, &Recency::default())
+                .into_iter()
+                .filter_map(|item| match item.text_edit {
+                    Some(CompletionTextEdit::Edit(edit)) if item.label == "RealNote" => Some(edit),
+                    _ => None,
+                })
+                .collect_vec();
+            let [edit] = edits.as_slice() else {
+                panic!("{edits:?}")
+            };
+            (
+                edit.range.start.character,
+                edit.range.end.character,
+                edit.new_text.clone(),
+            )
+        };
+        assert_eq!(
+            edit("see [[proj]] after"),
+            (4, 12, "[[RealNote]]$0".to_string())
+        );
+        assert_eq!(
+            edit("see [[proj#Heading|text]] after"),
+            (4, 10, "[[RealNote".to_string())
+        );
+    }
+
+    #[test]
+    fn completing_part_of_a_link_keeps_its_heading_and_display_text() {
+        // the line after accepting the completion whose text starts with `accepted`
+        let complete = |line: &str, character: u32, accepted: &str| {
+            let (settings, vault) = test_vault(line);
+            let edit = completion_items_in(&vault, &settings, 0, character, &Recency::default())
+                .into_iter()
+                .find_map(|item| match item.text_edit {
+                    Some(CompletionTextEdit::Edit(edit)) if edit.new_text.starts_with(accepted) => {
+                        Some(edit)
+                    }
+                    _ => None,
+                })
+                .unwrap();
+            let chars = line.chars().collect_vec();
+
+            format!(
+                "{}{}{}",
+                String::from_iter(&chars[..edit.range.start.character as usize]),
+                edit.new_text,
+                String::from_iter(&chars[edit.range.end.character as usize..])
+            )
+        };
+
+        assert_eq!(
+            complete("[[Rea#Intro|shown]] after", 5, "RealNote"),
+            "[[RealNote#Intro|shown]] after"
+        );
+        assert_eq!(
+            complete("[[Rea|shown]]", 5, "RealNote"),
+            "[[RealNote|shown]]"
+        );
+        assert_eq!(
+            complete("[[RealNote#Deta|shown]]", 15, "RealNote#Details"),
+            "[[RealNote#Details|shown]]"
+        );
+        assert_eq!(complete("[[Rea]]", 5, "RealNote"), "[[RealNote]]$0");
+    }
+
+    #[test]
+    fn empty_query_lists_every_note() {
+        for (text, character) in [("[[", 2), ("before [[after", 9), ("[[]]", 2)] {
+            let (settings, vault) = test_vault(text);
+            let labels = completion_items_in(&vault, &settings, 0, character, &Recency::default())
+                .into_iter()
+                .map(|item| item.label)
+                .collect_vec();
+
+            for note in ["RealNote", "Meeting", "proj1", "robert-smith"] {
+                assert!(
+                    labels.iter().any(|label| label == note),
+                    "{note} for {text:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn completions_are_capped_to_the_best_matches() {
+        let (mut settings, vault) = test_vault("[[");
+        let mut recency = Recency::default();
+        recency.touch(Path::new("/vault/proj2.md"));
+        recency.touch(Path::new("/vault/Meeting.md"));
+        let labels = |list: CompletionList| {
+            list.items
+                .into_iter()
+                .sorted_by(|a, b| a.sort_text.cmp(&b.sort_text))
+                .map(|item| item.label)
+                .collect_vec()
+        };
+
+        let all = labels(completion_list_in(&vault, &settings, 0, 2, &recency).unwrap());
+        settings.completion_max_results = 3;
+        let list = completion_list_in(&vault, &settings, 0, 2, &recency).unwrap();
+
+        assert!(list.is_incomplete);
+        assert_eq!(labels(list), all[..3]);
+        assert_eq!(all[0], "Meeting");
+    }
+
+    #[test]
+    fn empty_query_orders_recent_notes_first() {
+        let (settings, vault) = test_vault("[[");
+        let mut recency = Recency::default();
+        recency.touch(Path::new("/vault/proj2.md"));
+        recency.touch(Path::new("/vault/Meeting.md"));
+
+        let labels = completion_items_in(&vault, &settings, 0, 2, &recency)
+            .into_iter()
+            .sorted_by(|a, b| a.sort_text.cmp(&b.sort_text))
+            .map(|item| item.label)
+            .collect_vec();
+
+        let position = |label: &str| labels.iter().position(|it| it == label).unwrap();
+        assert!(position("Meeting") < position("proj2"));
+        assert!(position("proj2") < position("proj1"));
+    }
+
+    #[test]
+    fn often_linked_notes_outrank_equal_matches() {
+        let root_dir = Path::new("/vault");
+        let mut settings = Settings::new(root_dir, &ClientCapabilities::default()).unwrap();
+        let mut vault = Vault::construct_vault(&settings, root_dir).unwrap();
+        for (path, text) in [
+            ("/vault/project alpha.md", ""),
+            ("/vault/a.md", "[[project alpha]] [[project alpha#Part]]"),
+            ("/vault/b.md", "[x](project%20alpha.md) [[Project Alpha]]"),
+            ("/vault/project beta.md", ""),
+            ("/vault/note.md", "[[project"),
+        ] {
+            Vault::update_vault(&settings, &mut vault, (&PathBuf::from(path), text));
+        }
+
+        // beta was opened last, so it is the more recent of the two
+        let mut recency = Recency::default();
+        recency.touch(Path::new("/vault/project beta.md"));
+        let order = |settings: &Settings| {
+            completion_items_in(&vault, settings, 0, 9, &recency)
+                .into_iter()
+                .sorted_by(|a, b| a.sort_text.cmp(&b.sort_text))
+                .map(|item| item.label)
+                .filter(|label| ["project alpha", "project beta"].contains(&label.as_str()))
+                .collect_vec()
+        };
+
+        assert_eq!(order(&settings), vec!["project alpha", "project beta"]);
+
+        settings.completion_backlink_weight = 0.0;
+        assert_eq!(order(&settings), vec!["project beta", "project alpha"]);
+    }
+
+    #[test]
+    fn display_text_follows_an_escaped_pipe_in_tables() {
+        let (mut settings, vault) = test_vault("| [[Real | cell |");
+        settings.wikilink_display_tabstop = true;
+        let new_text = completion_items_in(&vault, &settings, 0, 8, &Recency::default())
+            .into_iter()
+            .find(|item| item.label == "RealNote")
+            .and_then(|item| match item.text_edit {
+                Some(CompletionTextEdit::Edit(edit)) => Some(edit.new_text),
+                _ => None,
+            });
+
+        assert_eq!(new_text.as_deref(), Some(r"RealNote\|${1:}]]$0"));
+    }
+
+    #[test]
+    fn rows_without_a_leading_pipe_are_table_rows() {
+        let new_text = |text: &str, line: u32| {
+            let (mut settings, vault) = test_vault(text);
+            settings.wikilink_display_tabstop = true;
+            let character = text
+                .lines()
+                .nth(line as usize)
+                .unwrap()
+                .find("[[Real")
+                .unwrap()
+                + 6;
+            completion_items_in(
+                &vault,
+                &settings,
+                line,
+                character as u32,
+                &Recency::default(),
+            )
+            .into_iter()
+            .find(|item| item.label == "RealNote")
+            .and_then(|item| match item.text_edit {
+                Some(CompletionTextEdit::Edit(edit)) => Some(edit.new_text),
+                _ => None,
+            })
+        };
+
+        let table = "Name | Link | Notes\n--- | :---: | ---\none | [[Real | first\ntwo | [[Real";
+        assert_eq!(new_text(table, 2).as_deref(), Some(r"RealNote\|${1:}]]$0"));
+        assert_eq!(new_text(table, 3).as_deref(), Some(r"RealNote\|${1:}]]$0"));
+        assert_eq!(
+            new_text("[[Real | Link\n--- | ---", 0).as_deref(),
+            Some(r"RealNote\|${1:}]]$0")
+        );
+
+        // a blank line ends the table, and a pipe alone does not make one
+        assert_eq!(
+            new_text(&format!("{table}\n\nafter | [[Real"), 5).as_deref(),
+            Some("RealNote|${1:}]]$0")
+        );
+        assert_eq!(
+            new_text("a | [[Real | b", 0).as_deref(),
+            Some("RealNote|${1:}]]$0")
+        );
+    }
+
+    #[test]
+    fn display_tabstop_puts_the_cursor_after_the_pipe() {
+        let (mut settings, vault) = test_vault("[[Real");
+        settings.wikilink_display_tabstop = true;
+        let new_text = |settings: &Settings, label: &str| {
+            completion_items_in(&vault, settings, 0, 6, &Recency::default())
+                .into_iter()
+                .find(|item| item.label == label)
+                .and_then(|item| match item.text_edit {
+                    Some(CompletionTextEdit::Edit(edit)) => Some(edit.new_text),
+                    _ => None,
+                })
+        };
+
+        assert_eq!(
+            new_text(&settings, "RealNote").as_deref(),
+            Some("RealNote|${1:}]]$0")
+        );
+        assert_eq!(
+            new_text(&settings, "RealNote#Intro").as_deref(),
+            Some("RealNote#Intro|${1:}]]$0")
+        );
+
+        settings.snippet_support = false;
+        assert_eq!(
+            new_text(&settings, "RealNote").as_deref(),
+            Some("RealNote]]")
+        );
+    }
+
+    #[test]
+    fn completion_columns_follow_the_position_encoding() {
+        let (mut settings, vault) = test_vault("é 😀 [[Real");
+        let edit_range = |settings: &Settings, character: u32| {
+            completion_items_in(&vault, settings, 0, character, &Recency::default())
+                .into_iter()
+                .find(|item| item.label == "RealNote")
+                .and_then(|item| match item.text_edit {
+                    Some(CompletionTextEdit::Edit(edit)) => {
+                        Some((edit.range.start.character, edit.range.end.character))
+                    }
+                    _ => None,
+                })
+        };
+
+        // é is one UTF-16 unit, 😀 is two
+        assert_eq!(edit_range(&settings, 11), Some((7, 11)));
+
+        settings.position_encoding = PositionEncoding::Utf32;
+        assert_eq!(edit_range(&settings, 10), Some((6, 10)));
+    }
+
+    #[test]
+    fn note_previews_are_read_on_resolve() {
+        let (mut settings, vault) = test_vault("[[RealNote]] [[RealNote#Intro]]\n[[RealNo");
+        let items = completion_items_in(&vault, &settings, 1, 8, &Recency::default());
+        let item = |label: &str| {
+            items
+                .iter()
+                .find(|item| item.label == label)
+                .cloned()
+                .unwrap()
+        };
+
+        let file = item("RealNote");
+        assert_eq!(file.documentation, None);
+        let resolved = resolve_completion(&vault, &settings, file.clone());
+        assert_eq!(
+            resolved.detail.as_deref(),
+            Some("RealNote.md (filename), 2 backlinks")
+        );
+        assert!(matches!(
+            resolved.documentation,
+            Some(Documentation::MarkupContent(preview)) if preview.value.contains("# Intro")
+        ));
+
+        let heading = resolve_completion(&vault, &settings, item("RealNote#Intro"));
+        assert_eq!(
+            heading.detail.as_deref(),
+            Some("heading in RealNote.md, 1 backlink")
+        );
+
+        settings.completion_previews = false;
+        assert_eq!(resolve_completion(&vault, &settings, file.clone()), file);
+    }
+
+    #[test]
+    fn empty_markdown_destination_has_empty_file_query() {
+        let root_dir = Path::new("/vault");
+        let settings = Settings::new(root_dir, &ClientCapabilities::default()).unwrap();
+        let mut vault = Vault::construct_vault(&settings, root_dir).unwrap();
+        let path = PathBuf::from("/vault/note.md");
+        Vault::update_vault(&settings, &mut vault, (&path, "[disp]("));
+
+        let context = Context {
+            vault: &vault,
+            opened_files: &[],
+            path: &path,
+            settings: &settings,
+            recency: &Recency::default(),
+            completion_cache: &Default::default(),
+        };
+        let completer = MarkdownLinkCompleter::construct(context, 0, 7).unwrap();
+
+        assert_eq!(completer.path, (String::new(), 7..7));
+        assert!(completer.infile_ref.is_none());
+    }
+
+    #[test]
+    fn empty_markdown_destination_completes_all_files() {
+        let completions = complete("[disp](");
+
+        for file in ["Meeting", "RealNote", "notes", "robert-smith"] {
+            assert_eq!(
+                completion_for(&completions, file),
+                Some(format!("[${{1:disp}}]({})", file).as_str())
+            );
+        }
+    }
+
+    fn detail_for(line: &str, label: &str) -> Option<String> {
+        completion_items(&[line], "\n")
+            .into_iter()
+            .find(|item| item.label == label)
+            .and_then(|item| item.detail)
+    }
+
+    #[test]
+    fn file_detail_shows_path_and_filename_match() {
+        assert_eq!(
+            detail_for("[[RealN", "RealNote").as_deref(),
+            Some("RealNote.md (filename)")
+        );
+        assert_eq!(
+            detail_for("[[RealNote#Det", "RealNote#Details").as_deref(),
+            Some("heading in RealNote.md")
+        );
+    }
+
+    #[test]
+    fn nested_file_detail_shows_vault_relative_path() {
+        assert_eq!(
+            detail_for("[[robert", "robert-smith").as_deref(),
+            Some("people/robert-smith.md (filename)")
+        );
+    }
+
+    #[test]
+    fn alias_detail_names_the_aliased_file() {
+        assert_eq!(
+            detail_for("[[Bo", "Bob").as_deref(),
+            Some("alias of people/robert-smith.md")
+        );
+    }
+
+    /// Completions for the end of `line` in a vault on disk with attachments
+    fn complete_embed(line: &str) -> Vec<(String, String)> {
+        complete_with_attachments_folder(line, "")
+    }
+
+    /// Completions for the end of `line` in a vault on disk with attachments, those in `attachments_folder` included
+    fn complete_with_attachments_folder(
+        line: &str,
+        attachments_folder: &str,
+    ) -> Vec<(String, String)> {
+        let root_dir = std::env::temp_dir().join(format!(
+            "moxide-embed-{}-{}-{}",
+            std::process::id(),
+            line.len(),
+            attachments_folder
+        ));
+        for (file, text) in [
+            ("images/diagram.png", ""),
+            ("scans/receipt.pdf", ""),
+            ("a/photo.jpg", ""),
+            ("b/photo.jpg", ""),
+            ("note.md", "# Heading\n"),
+            ("diary.md", ""),
+            ("files/dataset.csv", ""),
+            ("files/readme.md", ""),
+        ] {
+            let path = root_dir.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, text).unwrap();
+        }
+
+        let mut settings = Settings::new(&root_dir, &ClientCapabilities::default()).unwrap();
+        settings.attachments_folder = attachments_folder.to_string();
+        let mut vault = Vault::construct_vault(&settings, &root_dir).unwrap();
+        std::fs::remove_dir_all(&root_dir).unwrap();
+
+        let path = root_dir.join("source.md");
+        Vault::update_vault(&settings, &mut vault, (&path, &format!("{line}\n")));
+        let params = CompletionParams {
+            text_document_position: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier {
+                    uri: Url::from_file_path(&path).unwrap(),
+                },
+                position: Position {
+                    line: 0,
+                    character: line.chars().count() as u32,
+                },
+            },
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+            context: None,
+        };
+
+        match get_completions(
+            &vault,
+            &[],
+            &params,
+            &path,
+            &settings,
+            &Recency::default(),
+            &Default::default(),
+        ) {
+            Some(CompletionResponse::List(list)) => list
+                .items
+                .into_iter()
+                .map(|item| match item.text_edit {
+                    Some(CompletionTextEdit::Edit(edit)) => (item.label, edit.new_text),
+                    _ => (item.label, String::new()),
+                })
+                .sorted()
+                .collect_vec(),
+            None => vec![],
+            Some(response) => panic!("expected a completion list, got {:?}", response),
+        }
+    }
+
+    #[test]
+    fn embed_completes_attachments() {
+        assert_eq!(
+            complete_embed("![["),
+            vec![
+                ("a/photo.jpg".to_string(), "a/photo.jpg]]$0".to_string()),
+                ("b/photo.jpg".to_string(), "b/photo.jpg]]$0".to_string()),
+                (
+                    "images/diagram.png".to_string(),
+                    "diagram.png]]$0".to_string()
+                ),
+                (
+                    "scans/receipt.pdf".to_string(),
+                    "receipt.pdf]]$0".to_string()
+                ),
+            ]
+        );
+        assert_eq!(
+            complete_embed("![[recei"),
+            vec![(
+                "scans/receipt.pdf".to_string(),
+                "receipt.pdf]]$0".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn embed_offers_notes_the_query_names() {
+        let completions = complete_embed("![[not");
+        assert!(completions.contains(&("note".to_string(), "note]]$0".to_string())));
+        assert!(!completions.iter().any(|(label, _)| label == "diary"));
+
+        assert_eq!(
+            complete_embed("![[note#"),
+            vec![("note#Heading".to_string(), "note#Heading]]$0".to_string())]
+        );
+    }
+
+    #[test]
+    fn links_do_not_complete_attachments() {
+        assert!(!complete_embed("[[diag")
+            .iter()
+            .any(|(label, _)| label.ends_with(".png")));
+    }
+
+    #[test]
+    fn files_in_the_attachments_folder_are_embedded_not_linked() {
+        assert_eq!(complete_embed("![[datas"), vec![]);
+        assert_eq!(
+            complete_with_attachments_folder("![[datas", "files"),
+            vec![(
+                "files/dataset.csv".to_string(),
+                "dataset.csv]]$0".to_string()
+            )]
+        );
+        assert!(!complete_with_attachments_folder("[[datas", "files")
+            .iter()
+            .any(|(label, _)| label.contains("dataset")));
+
+        // notes in the folder are still notes
+        assert!(complete_with_attachments_folder("[[readm", "files")
+            .contains(&("readme".to_string(), "readme]]$0".to_string())));
+        assert!(!complete_with_attachments_folder("![[", "files")
+            .iter()
+            .any(|(label, _)| label.contains("readme")));
+    }
+
+    #[test]
+    fn notes_sharing_a_name_insert_their_shortest_unique_path() {
+        let root_dir = Path::new("/vault");
+        let settings = Settings::new(root_dir, &ClientCapabilities::default()).unwrap();
+        let mut vault = Vault::construct_vault(&settings, root_dir).unwrap();
+        for (path, text) in [
+            ("/vault/work/a/dup.md", "# Part\n"),
+            ("/vault/work/b/dup.md", ""),
+            ("/vault/solo.md", ""),
+            ("/vault/note.md", "[[dup\n[[solo\n"),
+        ] {
+            Vault::update_vault(&settings, &mut vault, (&PathBuf::from(path), text));
+        }
+
+        let new_texts = |line: u32, character: u32| {
+            completion_items_in(&vault, &settings, line, character, &Recency::default())
+                .into_iter()
+                .filter_map(|item| match item.text_edit {
+                    Some(CompletionTextEdit::Edit(edit)) => Some(edit.new_text),
+                    _ => None,
+                })
+                .collect_vec()
+        };
+
+        let dup = new_texts(0, 5);
+        for expected in ["a/dup]]$0", "b/dup]]$0", "a/dup#Part]]$0"] {
+            assert!(dup.contains(&expected.to_string()), "{expected} in {dup:?}");
+        }
+        assert!(!dup.iter().any(|text| text.starts_with("dup")));
+        assert!(new_texts(1, 6).contains(&"solo]]$0".to_string()));
+    }
+
+    #[test]
+    fn typed_dates_complete_to_daily_notes() {
+        let root_dir = Path::new("/vault");
+        let mut settings = Settings::new(root_dir, &ClientCapabilities::default()).unwrap();
+        settings.dailynote = "%d.%m.%Y".to_string();
+        let mut vault = Vault::construct_vault(&settings, root_dir).unwrap();
+
+        // a month well outside the days around today
+        let date = chrono::Local::now().date_naive() - chrono::Days::new(60);
+        let month = date.format(".%m.%Y").to_string();
+        let text = format!("[[15{month}");
+        Vault::update_vault(
+            &settings,
+            &mut vault,
+            (&PathBuf::from("/vault/note.md"), &text),
+        );
+
+        let items = completion_items_in(
+            &vault,
+            &settings,
+            0,
+            text.chars().count() as u32,
+            &Recency::default(),
+        );
+        let daily = items
+            .iter()
+            .find(|item| item.label == format!("15{month}"))
+            .unwrap_or_else(|| panic!("no daily note in {items:?}"));
+
+        assert_eq!(
+            daily
+                .label_details
+                .as_ref()
+                .and_then(|details| details.description.as_deref()),
+            Some(date.format("%Y-%m-15").to_string().as_str())
+        );
+        match &daily.text_edit {
+            Some(CompletionTextEdit::Edit(edit)) => {
+                assert_eq!(edit.new_text, format!("15{month}]]$0"))
+            }
+            edit => panic!("{edit:?}"),
+        }
+    }
+
+    #[test]
+    fn daily_notes_are_offered_in_their_folder() {
+        let root_dir = Path::new("/vault");
+        let mut settings = Settings::new(root_dir, &ClientCapabilities::default()).unwrap();
+        settings.dailynote = "%d.%m.%Y".to_string();
+        settings.daily_notes_folder = "journals".to_string();
+        settings.daily_notes_subfolder = "%Y/%m".to_string();
+        let mut vault = Vault::construct_vault(&settings, root_dir).unwrap();
+
+        let today = chrono::Local::now().date_naive();
+        let yesterday = today.pred_opt().unwrap();
+        let folder = |date: chrono::NaiveDate| date.format("journals/%Y/%m").to_string();
+        let name = |date: chrono::NaiveDate| date.format("%d.%m.%Y").to_string();
+        // yesterday's note exists, today's is yet to be created
+        Vault::update_vault(
+            &settings,
+            &mut vault,
+            (
+                &root_dir.join(format!("{}/{}.md", folder(yesterday), name(yesterday))),
+                "",
+            ),
+        );
+        Vault::update_vault(
+            &settings,
+            &mut vault,
+            (&PathBuf::from("/vault/note.md"), "[[today\n[[yesterday\n"),
+        );
+
+        let daily = |line: u32, character: u32| {
+            completion_items_in(&vault, &settings, line, character, &Recency::default())
+                .into_iter()
+                .find(|item| item.kind == Some(tower_lsp::lsp_types::CompletionItemKind::EVENT))
+                .map(|item| (item.label, item.detail))
+        };
+
+        assert_eq!(
+            daily(0, 7),
+            Some((
+                format!("today: {}", name(today)),
+                Some(format!(
+                    "{}/{}.md (new daily note)",
+                    folder(today),
+                    name(today)
+                ))
+            ))
+        );
+        assert_eq!(
+            daily(1, 11),
+            Some((
+                format!("yesterday: {}", name(yesterday)),
+                Some(format!(
+                    "{}/{}.md (daily note)",
+                    folder(yesterday),
+                    name(yesterday)
+                ))
+            ))
+        );
+    }
+}
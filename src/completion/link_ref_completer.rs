@@ -0,0 +1,260 @@
+use itertools::Itertools;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use tower_lsp::lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionTextEdit, Position, Range, TextEdit,
+};
+
+use crate::{
+    config::Case,
+    vault::{normalize_for_match, MDLinkReferenceDefinition, MatchOpts},
+};
+
+use super::{
+    matcher::{fuzzy_match, Matchable},
+    Completable, Completer, Context,
+};
+
+/// Completes the label of a reference-style link, `[text][label]`, with the link reference definitions of the file
+pub struct LinkRefCompleter<'a> {
+    line: u32,
+    /// Where the label starts, right after the second `[`
+    start: u32,
+    /// The end of the replaced text: the cursor, or past the `]` that already closes the label
+    end: u32,
+    query: String,
+    context: Context<'a>,
+}
+
+impl<'a> Completer<'a> for LinkRefCompleter<'a> {
+    fn construct(context: Context<'a>, line: usize, character: usize) -> Option<Self>
+    where
+        Self: Sized + Completer<'a>,
+    {
+        static PARTIAL_LINK_REF: Lazy<Regex> =
+            Lazy::new(|| Regex::new(r"\[[^\[\]]*\]\[(?<label>[^\[\]\^][^\[\]]*)?$").unwrap()); // [text][label
+
+        static REST_OF_LABEL: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[^\[\]]*\]").unwrap());
+
+        let line_chars = context.vault.select_line(context.path, line as isize)?;
+        let before = String::from_iter(line_chars.get(..character)?);
+        let after = String::from_iter(line_chars.get(character..)?);
+
+        let captures = PARTIAL_LINK_REF.captures(&before)?;
+        let query = captures
+            .name("label")
+            .map(|label| label.as_str().to_string())
+            .unwrap_or_default();
+        let closed = REST_OF_LABEL
+            .find(&after)
+            .map(|rest| rest.as_str().chars().count())
+            .unwrap_or(0);
+
+        Some(Self {
+            line: line as u32,
+            start: (character - query.chars().count()) as u32,
+            end: (character + closed) as u32,
+            query,
+            context,
+        })
+    }
+
+    fn completions(&self) -> Vec<impl Completable<'a, Self>>
+    where
+        Self: Sized,
+    {
+        let Some(mdfile) = self.context.vault.md_files.get(self.context.path) else {
+            return vec![];
+        };
+
+        // labels match case insensitively and, as in CommonMark, the first definition of a label is the one used
+        let definitions = mdfile
+            .link_reference_definitions
+            .iter()
+            .sorted_by_key(|definition| {
+                (
+                    definition.range.start.line,
+                    definition.range.start.character,
+                )
+            })
+            .unique_by(|definition| normalize_for_match(&definition.link_ref_name, &LABEL_MATCH))
+            .map(|definition| LinkRefCompletion { definition });
+
+        fuzzy_match(&self.query, definitions, &Case::Ignore)
+            .into_iter()
+            .map(|(item, _)| item)
+            .collect()
+    }
+
+    type FilterParams = &'a str;
+    fn completion_filter_text(&self, params: Self::FilterParams) -> String {
+        params.to_string()
+    }
+}
+
+const LABEL_MATCH: MatchOpts = MatchOpts {
+    casefold: true,
+    collapse_whitespace: true,
+    unify_separators: false,
+    strip_accents: false,
+    alphanumeric_only: false,
+};
+
+struct LinkRefCompletion<'a> {
+    definition: &'a MDLinkReferenceDefinition,
+}
+
+impl Matchable for LinkRefCompletion<'_> {
+    fn match_string(&self) -> &str {
+        &self.definition.link_ref_name
+    }
+}
+
+impl<'a> Completable<'a, LinkRefCompleter<'a>> for LinkRefCompletion<'a> {
+    fn completions(&self, completer: &LinkRefCompleter<'a>) -> Option<CompletionItem> {
+        let label = &self.definition.link_ref_name;
+
+        Some(CompletionItem {
+            label: label.clone(),
+            kind: Some(CompletionItemKind::REFERENCE),
+            detail: Some(self.definition.url.clone()),
+            filter_text: Some(completer.completion_filter_text(label)),
+            text_edit: Some(CompletionTextEdit::Edit(TextEdit {
+                range: Range {
+                    start: Position {
+                        line: completer.line,
+                        character: completer.start,
+                    },
+                    end: Position {
+                        line: completer.line,
+                        character: completer.end,
+                    },
+                },
+                new_text: format!("{label}]"),
+            })),
+            ..Default::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use tower_lsp::lsp_types::{
+        ClientCapabilities, CompletionItem, CompletionParams, CompletionResponse,
+        CompletionTextEdit, Position, TextDocumentIdentifier, TextDocumentPositionParams, Url,
+    };
+
+    use crate::{
+        completion::{get_completions, Recency},
+        config::Settings,
+        vault::Vault,
+    };
+
+    /// Completions at `character` of the first line of a note holding `text`
+    fn completions(text: &str, character: u32) -> Vec<CompletionItem> {
+        let root_dir = Path::new("/vault");
+        let settings = Settings::new(root_dir, &ClientCapabilities::default()).unwrap();
+        let mut vault = Vault::construct_vault(&settings, root_dir).unwrap();
+        let path = PathBuf::from("/vault/note.md");
+        Vault::update_vault(&settings, &mut vault, (&path, text));
+
+        let params = CompletionParams {
+            text_document_position: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier {
+                    uri: Url::from_file_path(&path).unwrap(),
+                },
+                position: Position { line: 0, character },
+            },
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+            context: None,
+        };
+
+        match get_completions(
+            &vault,
+            &[],
+            &params,
+            &path,
+            &settings,
+            &Recency::default(),
+            &Default::default(),
+        ) {
+            Some(CompletionResponse::List(list)) => list.items,
+            None => vec![],
+            Some(response) => panic!("expected a completion list, got {:?}", response),
+        }
+    }
+
+    /// The (label, url, start, end, inserted text) of each completion
+    fn edits(items: &[CompletionItem]) -> Vec<(String, String, u32, u32, String)> {
+        items
+            .iter()
+            .filter_map(|item| match &item.text_edit {
+                Some(CompletionTextEdit::Edit(edit)) => Some((
+                    item.label.clone(),
+                    item.detail.clone()?,
+                    edit.range.start.character,
+                    edit.range.end.character,
+                    edit.new_text.clone(),
+                )),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn labels_of_the_file_complete_with_their_url() {
+        let items = completions(
+            "See [the docs][do\n\n[docs]: https://docs.example.com\n[blog]: https://blog.example.com",
+            17,
+        );
+
+        assert_eq!(
+            edits(&items),
+            vec![(
+                "docs".to_string(),
+                "https://docs.example.com".to_string(),
+                15,
+                17,
+                "docs]".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn closing_bracket_is_not_doubled() {
+        let items = completions("[text][]\n\n[docs]: https://docs.example.com", 7);
+
+        assert_eq!(
+            edits(&items),
+            vec![(
+                "docs".to_string(),
+                "https://docs.example.com".to_string(),
+                7,
+                8,
+                "docs]".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn duplicate_labels_match_case_insensitively_and_the_first_wins() {
+        let items = completions(
+            "[text][DOC\n\n[Docs]: https://first.example.com\n[docs]: https://second.example.com",
+            10,
+        );
+
+        assert_eq!(
+            edits(&items),
+            vec![(
+                "Docs".to_string(),
+                "https://first.example.com".to_string(),
+                7,
+                10,
+                "Docs]".to_string()
+            )]
+        );
+    }
+}
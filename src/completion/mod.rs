@@ -1,20 +1,34 @@
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
 
-use tower_lsp::lsp_types::{CompletionItem, CompletionList, CompletionParams, CompletionResponse};
+use tower_lsp::lsp_types::{
+    CompletionItem, CompletionList, CompletionOptions, CompletionParams, CompletionResponse,
+    CompletionTextEdit, Position, Range,
+};
 
 use crate::{config::Settings, vault::Vault};
 
+pub use self::cache::CompletionCache;
 use self::callout_completer::CalloutCompleter;
 use self::link_completer::WikiLinkCompleter;
+use self::link_ref_completer::LinkRefCompleter;
+pub(crate) use self::unindexed_block_completer::new_block_id;
 use self::{
     footnote_completer::FootnoteCompleter, link_completer::MarkdownLinkCompleter,
-    tag_completer::TagCompleter, unindexed_block_completer::UnindexedBlockCompleter,
+    property_completer::PropertyCompleter, tag_completer::TagCompleter,
+    unindexed_block_completer::UnindexedBlockCompleter,
 };
 
+mod cache;
 mod callout_completer;
 mod footnote_completer;
 mod link_completer;
-mod matcher;
+mod link_ref_completer;
+pub mod matcher;
+mod property_completer;
 mod tag_completer;
 mod unindexed_block_completer;
 mod util;
@@ -25,6 +39,27 @@ pub struct Context<'a> {
     opened_files: &'a [PathBuf],
     path: &'a Path,
     settings: &'a Settings,
+    recency: &'a Recency,
+    completion_cache: &'a Mutex<CompletionCache>,
+}
+
+/// Which notes were opened or saved most recently; link completions that match equally well are ordered by it
+#[derive(Debug, Default, Clone)]
+pub struct Recency {
+    clock: u64,
+    touched: HashMap<PathBuf, u64>,
+}
+
+impl Recency {
+    pub fn touch(&mut self, path: &Path) {
+        self.clock += 1;
+        self.touched.insert(path.to_path_buf(), self.clock);
+    }
+
+    /// Higher is more recent; notes that were never touched are 0
+    pub fn rank(&self, path: &Path) -> u64 {
+        self.touched.get(path).copied().unwrap_or(0)
+    }
 }
 
 pub trait Completer<'a>: Sized {
@@ -56,12 +91,16 @@ pub fn get_completions(
     params: &CompletionParams,
     path: &Path,
     config: &Settings,
+    recency: &Recency,
+    completion_cache: &Mutex<CompletionCache>,
 ) -> Option<CompletionResponse> {
     let completion_context = Context {
         vault,
         opened_files: initial_completion_files,
         path,
         settings: config,
+        recency,
+        completion_cache,
     };
 
     // I would refactor this if I could figure out generic closures
@@ -91,6 +130,13 @@ pub fn get_completions(
             params.text_document_position.position.character,
         )
     })
+    .or_else(|| {
+        run_completer::<PropertyCompleter>(
+            completion_context,
+            params.text_document_position.position.line,
+            params.text_document_position.position.character,
+        )
+    })
     .or_else(|| {
         run_completer::<TagCompleter>(
             completion_context,
@@ -98,6 +144,13 @@ pub fn get_completions(
             params.text_document_position.position.character,
         )
     })
+    .or_else(|| {
+        run_completer::<LinkRefCompleter>(
+            completion_context,
+            params.text_document_position.position.line,
+            params.text_document_position.position.character,
+        )
+    })
     .or_else(|| {
         run_completer::<FootnoteCompleter>(
             completion_context,
@@ -114,6 +167,55 @@ pub fn get_completions(
     })
 }
 
+/// Fills in what is too slow to compute for every completion item, like note previews
+pub fn resolve_completion(
+    vault: &Vault,
+    settings: &Settings,
+    item: CompletionItem,
+) -> CompletionItem {
+    match settings.completion_previews {
+        true => link_completer::resolve_link_completion(vault, item),
+        false => item,
+    }
+}
+
+/// The note a link completion is for, which is in the vault the completion has to be resolved in
+pub fn completion_target_path(item: &CompletionItem) -> Option<PathBuf> {
+    link_completer::completion_target_path(item)
+}
+
+/// The completion capability advertised to the client
+pub fn completion_options(settings: &Settings) -> CompletionOptions {
+    CompletionOptions {
+        resolve_provider: Some(settings.completion_previews),
+        trigger_characters: Some(settings.completion_trigger_characters.clone()),
+        work_done_progress_options: Default::default(),
+        all_commit_characters: None,
+        completion_item: None,
+    }
+}
+
+/// Coalesces bursts of completion requests so that fast typing only computes completions for the last keystroke
+#[derive(Debug, Default)]
+pub struct Debouncer {
+    latest: AtomicU64,
+}
+
+impl Debouncer {
+    /// Waits out `window`; false if a newer request came in meanwhile and this one should be dropped
+    pub async fn settle(&self, window: Duration) -> bool {
+        let request = self.latest.fetch_add(1, Ordering::SeqCst) + 1;
+
+        if window.is_zero() {
+            return true;
+        }
+
+        tokio::time::sleep(window).await;
+
+        self.latest.load(Ordering::SeqCst) == request
+    }
+}
+
 // #[cfg(test)]
 // mod tests {
 //     use itertools::Itertools;
@@ -311,12 +413,20 @@ fn run_completer<'a, T: Completer<'a>>(
     line: u32,
     character: u32,
 ) -> Option<CompletionResponse> {
-    let completer = T::construct(context, line as usize, character as usize)?;
+    // completers count columns in chars; the client counts them in the negotiated encoding
+    let encoding = context.settings.position_encoding;
+    let line_chars = context
+        .vault
+        .select_line(context.path, line as isize)
+        .unwrap_or_default();
+    let character = encoding.char_index(&line_chars, character);
+
+    let completer = T::construct(context, line as usize, character)?;
     let completions = completer.completions();
 
     let completions = completions
         .into_iter()
-        .take(20)
+        .take(context.settings.completion_max_results)
         .flat_map(|completable| {
             completable
                 .completions(&completer)
@@ -324,6 +434,11 @@ fn run_completer<'a, T: Completer<'a>>(
                 .collect::<Vec<_>>()
                 .into_iter()
         })
+        .map(|item| match context.settings.snippet_support {
+            true => item,
+            false => util::plain_text_completion(item),
+        })
+        .map(|item| encode_edit_columns(item, context))
         .collect::<Vec<CompletionItem>>();
 
     Some(CompletionResponse::List(CompletionList {
@@ -331,3 +446,95 @@ fn run_completer<'a, T: Completer<'a>>(
         items: completions,
     }))
 }
+
+fn encode_edit_columns(mut item: CompletionItem, context: Context) -> CompletionItem {
+    let encoding = context.settings.position_encoding;
+    let encode = |position: Position| {
+        let line = context
+            .vault
+            .select_line(context.path, position.line as isize)
+            .unwrap_or_default();
+        Position {
+            character: encoding.column(&line, position.character as usize),
+            ..position
+        }
+    };
+    let encode_range = |range: Range| Range {
+        start: encode(range.start),
+        end: encode(range.end),
+    };
+
+    match item.text_edit.as_mut() {
+        Some(CompletionTextEdit::Edit(edit)) => edit.range = encode_range(edit.range),
+        Some(CompletionTextEdit::InsertAndReplace(edit)) => {
+            edit.insert = encode_range(edit.insert);
+            edit.replace = encode_range(edit.replace);
+        }
+        None => (),
+    }
+
+    item
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, sync::Arc, time::Duration};
+
+    use tower_lsp::lsp_types::ClientCapabilities;
+
+    use crate::config::Settings;
+
+    use super::{completion_options, Debouncer};
+
+    #[tokio::test]
+    async fn debouncer_answers_only_the_last_of_a_burst() {
+        // the clock only moves when the test advances it, so a slow machine cannot spread out the burst
+        tokio::time::pause();
+        let debouncer = Arc::new(Debouncer::default());
+        let window = Duration::from_millis(50);
+
+        let mut burst = vec![];
+        for _ in 0..3 {
+            let debouncer = debouncer.clone();
+            burst.push(tokio::spawn(async move { debouncer.settle(window).await }));
+            tokio::time::advance(Duration::from_millis(5)).await;
+        }
+
+        let mut answered = vec![];
+        for request in burst {
+            answered.push(request.await.unwrap());
+        }
+
+        assert_eq!(answered, vec![false, false, true]);
+    }
+
+    #[tokio::test]
+    async fn debouncer_answers_spaced_out_requests() {
+        let debouncer = Debouncer::default();
+        let window = Duration::from_millis(10);
+
+        assert!(debouncer.settle(window).await);
+        assert!(debouncer.settle(window).await);
+        assert!(debouncer.settle(Duration::ZERO).await);
+    }
+
+    #[test]
+    fn advertised_trigger_characters_match_settings() {
+        let mut settings =
+            Settings::new(Path::new("/vault"), &ClientCapabilities::default()).unwrap();
+        assert_eq!(
+            completion_options(&settings).trigger_characters,
+            Some(
+                ["[", " ", "(", "#", ">", "^", "|"]
+                    .map(String::from)
+                    .to_vec()
+            )
+        );
+
+        settings.completion_trigger_characters = vec!["[".into(), "#".into()];
+        assert_eq!(
+            completion_options(&settings).trigger_characters,
+            Some(vec!["[".to_string(), "#".to_string()])
+        );
+    }
+}
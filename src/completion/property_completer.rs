@@ -0,0 +1,424 @@
+use std::collections::{HashMap, HashSet};
+
+use itertools::Itertools;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use tower_lsp::lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionItemLabelDetails, CompletionTextEdit, Position,
+    Range, TextEdit,
+};
+
+use super::{
+    matcher::{fuzzy_match_completions, Matchable, OrderedCompletion},
+    Completable, Completer, Context, LineRange,
+};
+
+/// Completes the keys of the frontmatter, and the values of a key, from the properties of the other notes
+pub struct PropertyCompleter<'a> {
+    line: usize,
+    position: PropertyPosition,
+    /// What was typed of the key or value, up to the cursor
+    query: String,
+    /// The key or value the completion replaces
+    range: LineRange<usize>,
+    context: Context<'a>,
+}
+
+enum PropertyPosition {
+    /// Naming a key; `colon` is whether the line already has the `:` after it
+    Key { colon: bool },
+    /// One of the values of `key`, after its `:` or in the list under it
+    Value { key: String, in_flow_list: bool },
+}
+
+impl<'a> Completer<'a> for PropertyCompleter<'a> {
+    fn construct(context: Context<'a>, line: usize, character: usize) -> Option<Self>
+    where
+        Self: Sized + Completer<'a>,
+    {
+        let frontmatter_end = context.vault.select_frontmatter_end_line(context.path)?;
+        if line == 0 || line >= frontmatter_end {
+            return None;
+        }
+
+        let mut line_chars = context.vault.select_line(context.path, line as isize)?;
+        if line_chars.last() == Some(&'\n') {
+            line_chars.pop();
+        }
+        let line_string = String::from_iter(&line_chars);
+
+        static LIST_ITEM: Lazy<Regex> = Lazy::new(|| Regex::new(r"^\s*- ?").unwrap());
+        static KEY_LINE: Lazy<Regex> =
+            Lazy::new(|| Regex::new(r"^(?<key>[^\s#\-][^:]*):\s*$").unwrap());
+
+        let (position, start, end) = if let Some(item) = LIST_ITEM.find(&line_string) {
+            // the key a list belongs to is the closest key above it without a value of its own
+            let key = (1..line).rev().find_map(|above| {
+                let above =
+                    String::from_iter(context.vault.select_line(context.path, above as isize)?);
+                match above.starts_with(|c: char| c.is_whitespace() || c == '-') {
+                    true => None,
+                    false => Some(
+                        KEY_LINE.captures(above.trim_end())?["key"]
+                            .trim()
+                            .to_string(),
+                    ),
+                }
+            })?;
+
+            let start = line_string[..item.end()].chars().count();
+            let position = PropertyPosition::Value {
+                key,
+                in_flow_list: false,
+            };
+            (position, start, line_chars.len())
+        } else if line_string.starts_with(|c: char| c.is_whitespace() || c == '#') {
+            return None;
+        } else {
+            match line_chars.iter().position(|c| *c == ':') {
+                Some(colon) if character > colon => {
+                    let key = String::from_iter(&line_chars[..colon]).trim().to_string();
+                    let value_start = (colon + 1..line_chars.len())
+                        .find(|i| !line_chars[*i].is_whitespace())
+                        .unwrap_or(line_chars.len());
+
+                    // in `key: [a, b]` the item under the cursor is completed
+                    let in_flow_list = line_chars.get(value_start) == Some(&'[');
+                    let (start, end) = match in_flow_list {
+                        true => {
+                            let item_start = (value_start..character)
+                                .rev()
+                                .find(|i| matches!(line_chars[*i], '[' | ','))
+                                .map_or(value_start + 1, |separator| separator + 1);
+                            let item_start = (item_start..character)
+                                .find(|i| !line_chars[*i].is_whitespace())
+                                .unwrap_or(character);
+                            let item_end = (character..line_chars.len())
+                                .find(|i| matches!(line_chars[*i], ']' | ','))
+                                .unwrap_or(line_chars.len());
+                            (item_start, item_end)
+                        }
+                        false => (value_start, line_chars.len()),
+                    };
+
+                    (PropertyPosition::Value { key, in_flow_list }, start, end)
+                }
+                colon => (
+                    PropertyPosition::Key {
+                        colon: colon.is_some(),
+                    },
+                    0,
+                    colon.unwrap_or(line_chars.len()),
+                ),
+            }
+        };
+
+        if character < start || character > end {
+            return None;
+        }
+
+        // a `#tag` value is left to the tag completer, which knows the tags of the whole vault
+        let query = String::from_iter(&line_chars[start..character]);
+        if matches!(position, PropertyPosition::Value { .. }) && query.starts_with('#') {
+            return None;
+        }
+
+        Some(PropertyCompleter {
+            line,
+            position,
+            query,
+            range: start..end,
+            context,
+        })
+    }
+
+    fn completions(&self) -> Vec<impl Completable<'a, Self>>
+    where
+        Self: Sized,
+    {
+        let properties = self
+            .context
+            .vault
+            .md_files
+            .iter()
+            .filter(|(path, _)| *path != self.context.path)
+            .flat_map(|(_, md_file)| md_file.metadata.iter())
+            .flat_map(|metadata| metadata.properties());
+
+        // how many notes have each key or value
+        let counts: HashMap<&str, usize> = match &self.position {
+            PropertyPosition::Key { .. } => {
+                let defined = self.defined_keys();
+                properties
+                    .map(|(key, _)| key.as_str())
+                    .filter(|key| !defined.contains(*key))
+                    .counts()
+            }
+            PropertyPosition::Value { key, .. } => properties
+                .filter(|(property, _)| property == key)
+                .flat_map(|(_, values)| values.iter().unique())
+                .map(String::as_str)
+                .filter(|value| !value.is_empty() && !value.contains('\n'))
+                .counts(),
+        };
+
+        let properties = counts
+            .into_iter()
+            .map(|(name, notes)| PropertyCompletion {
+                name: name.to_string(),
+                notes,
+            })
+            .collect_vec();
+
+        match self.query.as_str() {
+            "" => properties
+                .into_iter()
+                .sorted_by(|a, b| b.notes.cmp(&a.notes).then_with(|| a.name.cmp(&b.name)))
+                .enumerate()
+                .map(|(i, property)| OrderedCompletion::new(property, format!("{:06}", i)))
+                .collect(),
+            query => {
+                fuzzy_match_completions(query, properties, &self.context.settings.case_matching)
+            }
+        }
+    }
+
+    type FilterParams = String;
+    fn completion_filter_text(&self, params: Self::FilterParams) -> String {
+        params
+    }
+}
+
+impl PropertyCompleter<'_> {
+    /// Keys the note already has, which are not offered again
+    fn defined_keys(&self) -> HashSet<String> {
+        let frontmatter_end = self
+            .context
+            .vault
+            .select_frontmatter_end_line(self.context.path)
+            .unwrap_or(0);
+
+        (1..frontmatter_end)
+            .filter(|line| *line != self.line)
+            .filter_map(|line| {
+                let line = String::from_iter(
+                    self.context
+                        .vault
+                        .select_line(self.context.path, line as isize)?,
+                );
+                match line.starts_with(|c: char| c.is_whitespace() || c == '#' || c == '-') {
+                    true => None,
+                    false => Some(line.split_once(':')?.0.trim().to_string()),
+                }
+            })
+            .collect()
+    }
+}
+
+struct PropertyCompletion {
+    name: String,
+    notes: usize,
+}
+
+impl Matchable for PropertyCompletion {
+    fn match_string(&self) -> &str {
+        &self.name
+    }
+}
+
+impl<'a> Completable<'a, PropertyCompleter<'a>> for PropertyCompletion {
+    fn completions(&self, completer: &PropertyCompleter<'a>) -> Option<CompletionItem> {
+        let (kind, new_text) = match &completer.position {
+            PropertyPosition::Key { colon: true } => {
+                (CompletionItemKind::PROPERTY, self.name.clone())
+            }
+            PropertyPosition::Key { colon: false } => {
+                (CompletionItemKind::PROPERTY, format!("{}: ", self.name))
+            }
+            PropertyPosition::Value { in_flow_list, .. } => (
+                CompletionItemKind::VALUE,
+                yaml_scalar(&self.name, *in_flow_list),
+            ),
+        };
+
+        Some(CompletionItem {
+            label: self.name.clone(),
+            kind: Some(kind),
+            label_details: Some(CompletionItemLabelDetails {
+                detail: Some(match self.notes {
+                    1 => "1 note".to_string(),
+                    n => format!("{} notes", n),
+                }),
+                description: None,
+            }),
+            filter_text: Some(completer.completion_filter_text(self.name.clone())),
+            text_edit: Some(CompletionTextEdit::Edit(TextEdit {
+                new_text,
+                range: Range {
+                    start: Position {
+                        line: completer.line as u32,
+                        character: completer.range.start as u32,
+                    },
+                    end: Position {
+                        line: completer.line as u32,
+                        character: completer.range.end as u32,
+                    },
+                },
+            })),
+            ..Default::default()
+        })
+    }
+}
+
+/// The value as written in YAML, quoted when it would otherwise be read as something else
+fn yaml_scalar(value: &str, in_flow_list: bool) -> String {
+    let needs_quotes = value
+        .starts_with(|c: char| "-?:,[]{}#&*!|>'\"%@`".contains(c) || c.is_whitespace())
+        || value.ends_with(char::is_whitespace)
+        || value.contains(": ")
+        || value.contains(" #")
+        || (in_flow_list && value.contains([',', '[', ']', '{', '}']));
+
+    match needs_quotes {
+        true => format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\"")),
+        false => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use itertools::Itertools;
+    use tower_lsp::lsp_types::{
+        ClientCapabilities, CompletionParams, CompletionResponse, CompletionTextEdit, Position,
+        TextDocumentIdentifier, TextDocumentPositionParams, Url,
+    };
+
+    use crate::{
+        completion::{get_completions, Recency},
+        config::Settings,
+        vault::Vault,
+    };
+
+    /// Completions at the end of line `line` of `text`, as (label, replaced start column, inserted text), in rank order
+    fn complete(text: &str, line: u32) -> Vec<(String, u32, String)> {
+        let root_dir = Path::new("/vault");
+        let settings = Settings::new(root_dir, &ClientCapabilities::default()).unwrap();
+        let mut vault = Vault::construct_vault(&settings, root_dir).unwrap();
+        for (name, text) in [
+            ("one", "---\nstatus: draft\ntags: [project]\n---\n"),
+            ("two", "---\nstatus: done\npriority: 1\n---\n"),
+            ("three", "---\nstatus: draft\ntopic: \"a: b\"\n---\n"),
+        ] {
+            let path = PathBuf::from(format!("/vault/{name}.md"));
+            Vault::update_vault(&settings, &mut vault, (&path, text));
+        }
+        let path = PathBuf::from("/vault/note.md");
+        Vault::update_vault(&settings, &mut vault, (&path, text));
+
+        let params = CompletionParams {
+            text_document_position: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier {
+                    uri: Url::from_file_path(&path).unwrap(),
+                },
+                position: Position {
+                    line,
+                    character: text.lines().nth(line as usize).unwrap().chars().count() as u32,
+                },
+            },
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+            context: None,
+        };
+
+        match get_completions(
+            &vault,
+            &[],
+            &params,
+            &path,
+            &settings,
+            &Recency::default(),
+            &Default::default(),
+        ) {
+            Some(CompletionResponse::List(list)) => list
+                .items
+                .into_iter()
+                .sorted_by(|a, b| a.sort_text.cmp(&b.sort_text))
+                .map(|item| match item.text_edit {
+                    Some(CompletionTextEdit::Edit(edit)) => {
+                        (item.label, edit.range.start.character, edit.new_text)
+                    }
+                    _ => panic!("expected a text edit"),
+                })
+                .collect(),
+            None => vec![],
+            Some(response) => panic!("expected a completion list, got {:?}", response),
+        }
+    }
+
+    #[test]
+    fn keys_are_completed_from_other_notes() {
+        assert_eq!(
+            complete("---\nstatus: draft\n\n---\n", 2),
+            vec![
+                ("priority".to_string(), 0, "priority: ".to_string()),
+                ("tags".to_string(), 0, "tags: ".to_string()),
+                ("topic".to_string(), 0, "topic: ".to_string()),
+            ]
+        );
+        assert_eq!(
+            complete("---\npri\n---\n", 1),
+            vec![("priority".to_string(), 0, "priority: ".to_string())]
+        );
+    }
+
+    #[test]
+    fn values_are_completed_for_a_seen_key() {
+        assert_eq!(
+            complete("---\nstatus: \n---\n", 1),
+            vec![
+                ("draft".to_string(), 8, "draft".to_string()),
+                ("done".to_string(), 8, "done".to_string()),
+            ]
+        );
+        assert_eq!(
+            complete("---\nstatus: do\n---\n", 1),
+            vec![("done".to_string(), 8, "done".to_string())]
+        );
+        assert_eq!(
+            complete("---\ntopic: \n---\n", 1),
+            vec![("a: b".to_string(), 7, "\"a: b\"".to_string())]
+        );
+    }
+
+    #[test]
+    fn list_items_are_values_of_their_key() {
+        assert_eq!(
+            complete("---\ntags: [area, pro\n---\n", 1),
+            vec![("project".to_string(), 13, "project".to_string())]
+        );
+        assert_eq!(
+            complete("---\ntags:\n  - \n---\n", 2),
+            vec![("project".to_string(), 4, "project".to_string())]
+        );
+    }
+
+    #[test]
+    fn tag_values_are_left_to_the_tag_completer() {
+        assert_eq!(
+            complete("---\ntags:\n  - #pro\n---\n#progress\n", 2)
+                .into_iter()
+                .map(|(label, ..)| label)
+                .sorted()
+                .collect_vec(),
+            vec!["progress".to_string(), "project".to_string()]
+        );
+    }
+
+    #[test]
+    fn outside_the_frontmatter_nothing_is_completed() {
+        assert_eq!(complete("---\nstatus: draft\n---\nstatus: ", 3), vec![]);
+        assert_eq!(complete("status: ", 0), vec![]);
+    }
+}
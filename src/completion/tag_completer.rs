@@ -11,11 +11,11 @@ use tower_lsp::lsp_types::{
 use crate::{
     completion::util::check_in_code_block,
     ui,
-    vault::{MDTag, Referenceable, Vault},
+    vault::{MDTag, Reference, Referenceable, Vault},
 };
 
 use super::{
-    matcher::{fuzzy_match_completions, Matchable},
+    matcher::{fuzzy_match_completions, Matchable, OrderedCompletion},
     Completable, Completer, Context, LineRange,
 };
 
@@ -42,8 +42,9 @@ impl<'a> Completer<'a> for TagCompleter<'a> {
             return None;
         }
 
+        // like the vault's tags, a `#` only starts a tag at the start of a line or after whitespace; `file#` is a heading query
         static PARTIAL_TAG_REGEX: Lazy<Regex> =
-            Lazy::new(|| Regex::new(r"\#(?<text>[a-zA-Z0-9\/]*)").unwrap());
+            Lazy::new(|| Regex::new(r"(?:^|\s)(?<full>\#(?<text>[a-zA-Z0-9\/:]*))").unwrap());
 
         let line_chars = context.vault.select_line(context.path, line as isize)?;
         let line_string = String::from_iter(line_chars);
@@ -52,7 +53,7 @@ impl<'a> Completer<'a> for TagCompleter<'a> {
 
         captures_iter
             .flat_map(|captures| {
-                let (full, tag_text) = (captures.get(0)?, captures.name("text")?);
+                let (full, tag_text) = (captures.name("full")?, captures.name("text")?);
 
                 // check if the cursor is in the tag
                 let preceding_character = character - 1; // User is inserting into the position after the character they are looking at; "#tag|"  cursor is a position 4; I want pos 3; the end of the tag
@@ -84,8 +85,10 @@ impl<'a> Completer<'a> for TagCompleter<'a> {
             .select_referenceable_nodes(None)
             .into_par_iter()
             .flat_map(TagCompletable::from_referenceable)
+            // the tag being typed is not offered
             .filter(|tag| {
-                !(tag.tag.1.range.start.line <= self.line as u32
+                !(tag.tag.0 == self.context.path
+                    && tag.tag.1.range.start.line <= self.line as u32
                     && tag.tag.1.range.start.character <= self.character as u32
                     && tag.tag.1.range.end.line >= self.line as u32
                     && tag.tag.1.range.end.character >= self.character as u32)
@@ -100,13 +103,85 @@ impl<'a> Completer<'a> for TagCompleter<'a> {
 
         let filter_text = &self.inputted_tag.0;
 
-        let filtered = fuzzy_match_completions(
-            filter_text,
-            tag_referenceables,
-            &self.context.settings.case_matching,
-        );
+        // `#base:` completes the values already used with that base
+        if let Some((base, value)) = filter_text.split_once(':') {
+            let values = tag_referenceables
+                .into_iter()
+                .filter(|tag| tag.tag.1.tag_ref.contains(':'))
+                .filter(|tag| tag.tag.1.base().eq_ignore_ascii_case(base))
+                .filter_map(|tag| {
+                    let child = tag.tag.1.value()?.to_string();
+                    Some(TagCompletable {
+                        child: Some(child),
+                        ..tag
+                    })
+                })
+                .unique_by(|tag| tag.match_string().to_lowercase())
+                .collect::<Vec<_>>();
+
+            return match value {
+                "" => values
+                    .into_iter()
+                    .sorted_by(|a, b| a.match_string().cmp(b.match_string()))
+                    .enumerate()
+                    .map(|(i, value)| OrderedCompletion::new(value, format!("{:06}", i)))
+                    .collect(),
+                value => {
+                    fuzzy_match_completions(value, values, &self.context.settings.case_matching)
+                }
+            };
+        }
 
-        filtered
+        // `#` and `#parent/` complete one segment of a nested tag at a time
+        let (parent, segment) = match filter_text.rsplit_once('/') {
+            Some((parent, segment)) => (Some(parent), segment),
+            None if filter_text.is_empty() => (None, ""),
+            None => {
+                return fuzzy_match_completions(
+                    filter_text,
+                    tag_referenceables,
+                    &self.context.settings.case_matching,
+                )
+            }
+        };
+
+        let children = tag_referenceables
+            .into_iter()
+            .filter_map(|tag| {
+                let rest = match parent {
+                    Some(parent) => {
+                        let tag_ref = &tag.tag.1.tag_ref;
+                        let rest = tag_ref.get(parent.len()..)?.strip_prefix('/')?;
+                        tag_ref[..parent.len()]
+                            .eq_ignore_ascii_case(parent)
+                            .then_some(rest)?
+                    }
+                    None => tag.tag.1.tag_ref.as_str(),
+                };
+                let child = rest
+                    .split(['/', ':'])
+                    .next()
+                    .filter(|child| !child.is_empty())?;
+
+                Some(TagCompletable {
+                    child: Some(child.to_string()),
+                    ..tag
+                })
+            })
+            .unique_by(|tag| tag.match_string().to_lowercase())
+            .collect::<Vec<_>>();
+
+        match segment {
+            "" => children
+                .into_iter()
+                .sorted_by(|a, b| a.match_string().cmp(b.match_string()))
+                .enumerate()
+                .map(|(i, child)| OrderedCompletion::new(child, format!("{:06}", i)))
+                .collect(),
+            segment => {
+                fuzzy_match_completions(segment, children, &self.context.settings.case_matching)
+            }
+        }
     }
 
     type FilterParams = &'a str;
@@ -118,12 +193,17 @@ impl<'a> Completer<'a> for TagCompleter<'a> {
 
 struct TagCompletable<'a> {
     tag: (&'a Path, &'a MDTag),
+    /// The one segment of a nested tag being completed, below the parent already typed; the tag is then just one that has it
+    child: Option<String>,
 }
 
 impl TagCompletable<'_> {
     fn from_referenceable(referenceable: Referenceable<'_>) -> Option<TagCompletable<'_>> {
         match referenceable {
-            Referenceable::Tag(path, tag) => Some(TagCompletable { tag: (path, tag) }),
+            Referenceable::Tag(path, tag) => Some(TagCompletable {
+                tag: (path, tag),
+                child: None,
+            }),
             _ => None,
         }
     }
@@ -131,18 +211,45 @@ impl TagCompletable<'_> {
 
 impl Matchable for TagCompletable<'_> {
     fn match_string(&self) -> &str {
-        &self.tag.1.tag_ref
+        match &self.child {
+            Some(child) => child,
+            None => &self.tag.1.tag_ref,
+        }
     }
 }
 
 impl<'a> Completable<'a, TagCompleter<'a>> for TagCompletable<'a> {
     fn completions(&self, completer: &TagCompleter<'a>) -> Option<CompletionItem> {
+        // a nested segment replaces only the segment being typed, keeping the parent as written
+        let (label, new_text, start) = match &self.child {
+            Some(child) => {
+                let separator = completer.inputted_tag.0.rfind(['/', ':']);
+                let segment_start = match separator {
+                    Some(separator) => completer.inputted_tag.1.start + separator + 1,
+                    None => completer.inputted_tag.1.start,
+                };
+                let label = match separator {
+                    Some(separator) => {
+                        format!("{}{}", &completer.inputted_tag.0[..=separator], child)
+                    }
+                    None => child.clone(),
+                };
+
+                (label, child.clone(), segment_start)
+            }
+            None => (
+                self.tag.1.tag_ref.clone(),
+                format!("#{}", self.tag.1.tag_ref),
+                completer.full_range.start,
+            ),
+        };
+
         let text_edit = CompletionTextEdit::Edit(TextEdit {
-            new_text: format!("#{}", self.tag.1.tag_ref),
+            new_text,
             range: Range {
                 start: Position {
                     line: completer.line as u32,
-                    character: completer.full_range.start as u32,
+                    character: start as u32,
                 },
                 end: Position {
                     line: completer.line as u32,
@@ -155,18 +262,45 @@ impl<'a> Completable<'a, TagCompleter<'a>> for TagCompletable<'a> {
         let path_buf = path.to_path_buf();
         let self_as_referenceable = Referenceable::Tag(&path_buf, self.tag.1);
 
-        let num_references = completer
-            .vault
-            .select_references_for_referenceable(&self_as_referenceable)
-            .map(|references| references.len())
-            .unwrap_or(0);
+        // a segment counts the references to every tag under it
+        let num_references = match &self.child {
+            Some(_) => completer
+                .vault
+                .select_references(None)
+                .map(|references| {
+                    references
+                        .iter()
+                        .filter(|(_, reference)| match reference {
+                            Reference::Tag(data) => {
+                                let tag = data.reference_text.trim_start_matches('#');
+                                tag.get(..label.len())
+                                    .is_some_and(|prefix| prefix.eq_ignore_ascii_case(&label))
+                                    && matches!(tag[label.len()..].chars().next(), None | Some('/'))
+                            }
+                            _ => false,
+                        })
+                        .count()
+                })
+                .unwrap_or(0),
+            None => completer
+                .vault
+                .select_references_for_referenceable(&self_as_referenceable)
+                .map(|references| references.len())
+                .unwrap_or(0),
+        };
 
         Some(CompletionItem {
-            label: self.tag.1.tag_ref.clone(),
+            filter_text: Some(match &self.child {
+                Some(child) => child.clone(),
+                None => completer.completion_filter_text(&self.tag.1.tag_ref.clone()),
+            }),
+            documentation: match self.child {
+                Some(_) if label != self.tag.1.tag_ref => None,
+                _ => ui::preview_referenceable(completer.vault, &self_as_referenceable)
+                    .map(Documentation::MarkupContent),
+            },
+            label,
             kind: Some(CompletionItemKind::KEYWORD),
-            filter_text: Some(completer.completion_filter_text(&self.tag.1.tag_ref.clone())),
-            documentation: ui::preview_referenceable(completer.vault, &self_as_referenceable)
-                .map(Documentation::MarkupContent),
             label_details: Some(CompletionItemLabelDetails {
                 detail: Some(match num_references {
                     1 => "1 reference".to_string(),
@@ -179,3 +313,191 @@ impl<'a> Completable<'a, TagCompleter<'a>> for TagCompletable<'a> {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use itertools::Itertools;
+    use tower_lsp::lsp_types::{
+        ClientCapabilities, CompletionItem, CompletionParams, CompletionResponse,
+        CompletionTextEdit, Position, TextDocumentIdentifier, TextDocumentPositionParams, Url,
+    };
+
+    use crate::{
+        completion::{get_completions, Recency},
+        config::Settings,
+        vault::Vault,
+    };
+
+    /// Tag completions for the end of `line`, in rank order
+    fn completion_items(line: &str) -> Vec<CompletionItem> {
+        let root_dir = Path::new("/vault");
+        let settings = Settings::new(root_dir, &ClientCapabilities::default()).unwrap();
+        let mut vault = Vault::construct_vault(&settings, root_dir).unwrap();
+        Vault::update_vault(
+            &settings,
+            &mut vault,
+            (
+                &PathBuf::from("/vault/tags.md"),
+                "# Tagged\n#area/work #area/home #area/work/meetings #project\n",
+            ),
+        );
+        let path = PathBuf::from("/vault/note.md");
+        Vault::update_vault(&settings, &mut vault, (&path, line));
+
+        let params = CompletionParams {
+            text_document_position: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier {
+                    uri: Url::from_file_path(&path).unwrap(),
+                },
+                position: Position {
+                    line: 0,
+                    character: line.chars().count() as u32,
+                },
+            },
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+            context: None,
+        };
+
+        match get_completions(
+            &vault,
+            &[],
+            &params,
+            &path,
+            &settings,
+            &Recency::default(),
+            &Default::default(),
+        ) {
+            Some(CompletionResponse::List(list)) => list
+                .items
+                .into_iter()
+                .sorted_by(|a, b| a.sort_text.cmp(&b.sort_text))
+                .collect(),
+            None => vec![],
+            Some(response) => panic!("expected a completion list, got {:?}", response),
+        }
+    }
+
+    /// Tag completions for the end of `line`, as (label, replaced start column, inserted text), in rank order
+    fn complete(line: &str) -> Vec<(String, u32, String)> {
+        completion_items(line)
+            .into_iter()
+            .map(|item| match item.text_edit {
+                Some(CompletionTextEdit::Edit(edit)) => {
+                    (item.label, edit.range.start.character, edit.new_text)
+                }
+                _ => panic!("expected a text edit"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn root_offers_top_level_tags() {
+        assert_eq!(
+            complete("see #"),
+            vec![
+                ("area".to_string(), 5, "area".to_string()),
+                ("project".to_string(), 5, "project".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn one_segment_offers_children() {
+        assert_eq!(
+            complete("see #area/"),
+            vec![
+                ("area/home".to_string(), 10, "home".to_string()),
+                ("area/work".to_string(), 10, "work".to_string()),
+            ]
+        );
+        assert_eq!(
+            complete("see #area/wo"),
+            vec![("area/work".to_string(), 10, "work".to_string())]
+        );
+    }
+
+    #[test]
+    fn two_segments_offer_grandchildren() {
+        assert_eq!(
+            complete("see #area/work/"),
+            vec![("area/work/meetings".to_string(), 15, "meetings".to_string())]
+        );
+    }
+
+    #[test]
+    fn colon_offers_known_values_of_the_base() {
+        assert_eq!(
+            complete("#status:done #status:todo #area:work see #status:"),
+            vec![
+                ("status:done".to_string(), 49, "done".to_string()),
+                ("status:todo".to_string(), 49, "todo".to_string()),
+            ]
+        );
+        assert_eq!(
+            complete("#status:done #status:todo see #status:to"),
+            vec![("status:todo".to_string(), 38, "todo".to_string())]
+        );
+        assert_eq!(
+            complete("#status:done see #"),
+            vec![
+                ("area".to_string(), 18, "area".to_string()),
+                ("project".to_string(), 18, "project".to_string()),
+                ("status".to_string(), 18, "status".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn segments_count_the_references_to_the_tags_under_them() {
+        let counts = |line: &str| {
+            completion_items(line)
+                .into_iter()
+                .map(|item| {
+                    (
+                        item.label,
+                        item.label_details.and_then(|details| details.detail),
+                    )
+                })
+                .collect_vec()
+        };
+
+        assert_eq!(
+            counts("see #"),
+            vec![
+                ("area".to_string(), Some("3 references".to_string())),
+                ("project".to_string(), Some("1 reference".to_string())),
+            ]
+        );
+        assert_eq!(
+            counts("see #area/"),
+            vec![
+                ("area/home".to_string(), Some("1 reference".to_string())),
+                ("area/work".to_string(), Some("2 references".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn hash_at_line_start_completes_tags() {
+        assert_eq!(
+            complete("#pro"),
+            vec![("project".to_string(), 0, "#project".to_string())]
+        );
+    }
+
+    #[test]
+    fn hash_after_a_word_is_not_a_tag() {
+        assert_eq!(complete("see file#pro"), vec![]);
+    }
+
+    #[test]
+    fn hash_in_a_link_completes_headings() {
+        assert_eq!(
+            complete("see [[tags#"),
+            vec![("tags#Tagged".to_string(), 6, "tags#Tagged]]$0".to_string())]
+        );
+    }
+}
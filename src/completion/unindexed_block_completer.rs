@@ -1,3 +1,8 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashSet},
+    hash::{Hash, Hasher},
+};
+
 use itertools::Itertools;
 use rayon::prelude::*;
 use tower_lsp::lsp_types::{
@@ -7,9 +12,11 @@ use tower_lsp::lsp_types::{
 
 use crate::{
     ui::preview_referenceable,
-    vault::{get_obsidian_ref_path, Block, Referenceable},
+    vault::{
+        escape_link_brackets, get_obsidian_ref_path, unescape_link_brackets, Block, Referenceable,
+        Vault,
+    },
 };
-use nanoid::nanoid;
 
 use super::{
     link_completer::{LinkCompleter, MarkdownLinkCompleter, WikiLinkCompleter},
@@ -19,7 +26,6 @@ use super::{
 
 pub struct UnindexedBlockCompleter<'a, T: LinkCompleter<'a>> {
     link_completer: T,
-    new_id: String,
     __phantom: std::marker::PhantomData<&'a T>,
 }
 
@@ -33,36 +39,38 @@ impl<'a, C: LinkCompleter<'a>> UnindexedBlockCompleter<'a, C> {
     }
 
     fn new(completer: C) -> Self {
-        let rand_id = nanoid!(
-            5,
-            &['a', 'b', 'c', 'd', 'e', 'f', 'g', '1', '2', '3', '4', '5', '6', '7', '8', '9']
-        );
-
         Self {
             link_completer: completer,
-            new_id: rand_id,
             __phantom: std::marker::PhantomData,
         }
     }
 
     fn completables(&self) -> Vec<UnindexedBlock<'a>> {
-        let blocks = self.link_completer.vault().select_blocks();
+        let vault = self.link_completer.vault();
+        let blocks = vault.select_blocks();
         let position = self.link_completer.position();
+        let path = self.link_completer.path();
 
         blocks
             .into_par_iter()
             .filter(|block| {
-                !(block.range.start.line <= position.line
+                !(block.file == path
+                    && block.range.start.line <= position.line
                     && block.range.start.character <= position.character
                     && block.range.end.line >= position.line
                     && block.range.end.character >= position.character)
             })
-            .map(UnindexedBlock)
+            .map(|block| UnindexedBlock::new(vault, block))
             .collect::<Vec<_>>()
     }
 
     fn grep_match_text(&self) -> String {
-        self.link_completer.entered_refname()
+        unescape_link_brackets(&self.link_completer.entered_refname())
+    }
+
+    /// The entered text as the display text of the link
+    fn grep_display_text(&self) -> String {
+        escape_link_brackets(self.link_completer.entered_refname().trim())
     }
 }
 
@@ -132,21 +140,45 @@ impl<'a> Completer<'a> for UnindexedBlockCompleter<'a, WikiLinkCompleter<'a>> {
     }
 }
 
-struct UnindexedBlock<'a>(Block<'a>);
+struct UnindexedBlock<'a> {
+    block: Block<'a>,
+    /// The block's text after the nearest heading above it, `Heading > text`; query words naming the heading
+    /// prefer the blocks under it
+    match_string: String,
+}
 
 impl<'a> UnindexedBlock<'a> {
+    fn new(vault: &'a Vault, block: Block<'a>) -> Self {
+        let heading = vault.md_files.get(block.file).and_then(|mdfile| {
+            mdfile
+                .headings
+                .iter()
+                .take_while(|heading| heading.range.start.line < block.range.start.line)
+                .last()
+        });
+        let match_string = match heading {
+            Some(heading) => format!("{} > {}", heading.heading_text, block.text),
+            None => block.text.to_string(),
+        };
+
+        Self {
+            block,
+            match_string,
+        }
+    }
+
     /// Return the refname and completion item
     fn partial_completion<T: LinkCompleter<'a>>(
         &self,
         completer: &'a UnindexedBlockCompleter<'a, T>,
     ) -> Option<(String, CompletionItem)> {
-        let rand_id = &completer.new_id;
+        let new_id = new_block_id(completer.link_completer.vault(), &self.block);
 
         let path_ref =
-            get_obsidian_ref_path(completer.link_completer.vault().root_dir(), self.0.file)?;
-        let url = Url::from_file_path(self.0.file).ok()?;
+            get_obsidian_ref_path(completer.link_completer.vault().root_dir(), self.block.file)?;
+        let url = Url::from_file_path(self.block.file).ok()?;
 
-        let block = self.0;
+        let block = self.block;
 
         // check if the block is already indexed
         let (documentation, command, kind, label_detail, refname): (
@@ -220,7 +252,7 @@ impl<'a> UnindexedBlock<'a> {
                                                 character: block.range.end.character - 1,
                                             },
                                         },
-                                        new_text: format!("   ^{}", rand_id),
+                                        new_text: format!("   ^{}", new_id),
                                     }],
                                 )]
                                 .into_iter()
@@ -234,14 +266,14 @@ impl<'a> UnindexedBlock<'a> {
                 }),
                 CompletionItemKind::TEXT,
                 None,
-                format!("{}#^{}", path_ref, rand_id),
+                format!("{}#^{}", path_ref, new_id),
             ),
         };
 
         Some((
             refname,
             CompletionItem {
-                label: block.text.to_string(),
+                label: self.match_string.clone(),
                 documentation,
                 // Insert the index for the block
                 command,
@@ -253,6 +285,35 @@ impl<'a> UnindexedBlock<'a> {
     }
 }
 
+/// A five character id for a block that has none yet. It is derived from the block's file and text, so the same
+/// block is offered the same id on every completion request, and skips ids already used in the file.
+pub(crate) fn new_block_id(vault: &Vault, block: &Block) -> String {
+    const ALPHABET: [char; 16] = [
+        'a', 'b', 'c', 'd', 'e', 'f', 'g', '1', '2', '3', '4', '5', '6', '7', '8', '9',
+    ];
+
+    let taken: HashSet<&str> = vault
+        .md_files
+        .get(block.file)
+        .iter()
+        .flat_map(|mdfile| mdfile.indexed_blocks.iter())
+        .map(|indexed| indexed.index.as_str())
+        .collect();
+
+    (0u64..)
+        .map(|salt| {
+            let mut hasher = DefaultHasher::new();
+            (block.file, block.text, salt).hash(&mut hasher);
+            let hash = hasher.finish();
+
+            (0..5)
+                .map(|i| ALPHABET[(hash >> (i * 4)) as usize & 0xf])
+                .collect::<String>()
+        })
+        .find(|id| !taken.contains(id.as_str()))
+        .expect("the id space is larger than the ids in one file")
+}
+
 impl<'a> Completable<'a, UnindexedBlockCompleter<'a, MarkdownLinkCompleter<'a>>>
     for UnindexedBlock<'a>
 {
@@ -262,8 +323,7 @@ impl<'a> Completable<'a, UnindexedBlockCompleter<'a, MarkdownLinkCompleter<'a>>>
     ) -> Option<CompletionItem> {
         let (refname, partial_completion) = self.partial_completion(completer)?;
 
-        let binding = completer.link_completer.entered_refname();
-        let display = &binding.trim();
+        let display = completer.grep_display_text();
 
         Some(CompletionItem {
             text_edit: Some(
@@ -289,8 +349,7 @@ impl<'a> Completable<'a, UnindexedBlockCompleter<'a, WikiLinkCompleter<'a>>>
     ) -> Option<CompletionItem> {
         let (refname, partial_completion) = self.partial_completion(completer)?;
 
-        let binding = completer.link_completer.entered_refname();
-        let display = &binding.trim();
+        let display = completer.grep_display_text();
 
         Some(CompletionItem {
             text_edit: Some(
@@ -309,6 +368,192 @@ impl<'a> Completable<'a, UnindexedBlockCompleter<'a, WikiLinkCompleter<'a>>>
 
 impl Matchable for UnindexedBlock<'_> {
     fn match_string(&self) -> &str {
-        self.0.text
+        &self.match_string
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use itertools::Itertools;
+    use tower_lsp::lsp_types::{
+        ClientCapabilities, CompletionItem, CompletionParams, CompletionResponse,
+        CompletionTextEdit, Position, TextDocumentIdentifier, TextDocumentPositionParams, Url,
+    };
+
+    use crate::{
+        completion::{get_completions, Recency},
+        config::Settings,
+        vault::{Block, Vault},
+    };
+
+    use super::new_block_id;
+
+    fn vault() -> (Settings, Vault) {
+        let root_dir = Path::new("/vault");
+        let settings = Settings::new(root_dir, &ClientCapabilities::default()).unwrap();
+        let mut vault = Vault::construct_vault(&settings, root_dir).unwrap();
+        Vault::update_vault(
+            &settings,
+            &mut vault,
+            (
+                &PathBuf::from("/vault/Tasks.md"),
+                "Buy groceries for the week\n\nCall the plumber ^plumb\n\nRead [draft] notes\n",
+            ),
+        );
+        Vault::update_vault(
+            &settings,
+            &mut vault,
+            (
+                &PathBuf::from("/vault/Home.md"),
+                "# Kitchen\nFix the sink\n\n# Garden\nFix the fence\n",
+            ),
+        );
+
+        (settings, vault)
+    }
+
+    /// Completions for the end of `line`
+    fn complete(line: &str) -> Vec<CompletionItem> {
+        let (settings, mut vault) = vault();
+        let path = PathBuf::from("/vault/note.md");
+        Vault::update_vault(&settings, &mut vault, (&path, &format!("{}\n", line)));
+
+        let params = CompletionParams {
+            text_document_position: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier {
+                    uri: Url::from_file_path(&path).unwrap(),
+                },
+                position: Position {
+                    line: 0,
+                    character: line.chars().count() as u32,
+                },
+            },
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+            context: None,
+        };
+
+        match get_completions(
+            &vault,
+            &[],
+            &params,
+            &path,
+            &settings,
+            &Recency::default(),
+            &Default::default(),
+        ) {
+            Some(CompletionResponse::List(list)) => list.items,
+            None => vec![],
+            Some(response) => panic!("expected a completion list, got {:?}", response),
+        }
+    }
+
+    fn new_text(item: &CompletionItem) -> &str {
+        match &item.text_edit {
+            Some(CompletionTextEdit::Edit(edit)) => &edit.new_text,
+            _ => "",
+        }
+    }
+
+    #[test]
+    fn block_ids_of_linked_file() {
+        let completions = complete("[[Tasks#^");
+
+        assert_eq!(
+            completions.iter().map(new_text).collect_vec(),
+            vec!["Tasks#^plumb]]$0"]
+        );
+    }
+
+    #[test]
+    fn grep_finds_indexed_block() {
+        let completions = complete("[[ plumber");
+
+        let item = completions
+            .iter()
+            .find(|item| item.label == "Call the plumber ^plumb")
+            .unwrap();
+        assert_eq!(new_text(item), "Tasks#^plumb|${1:plumber}]]$0");
+        assert!(item.command.is_none());
+    }
+
+    #[test]
+    fn grep_reads_escaped_brackets() {
+        let completions = complete(r"[[ \[draft\] no");
+
+        let item = completions
+            .iter()
+            .find(|item| item.label == "Read [draft] notes")
+            .unwrap();
+        assert!(new_text(item).ends_with(r"|${1:\[draft\] no}]]$0"));
+    }
+
+    #[test]
+    fn grep_assigns_id_to_unindexed_block() {
+        let (_, vault) = vault();
+        let id = new_block_id(
+            &vault,
+            &Block {
+                text: "Buy groceries for the week",
+                range: Default::default(),
+                file: Path::new("/vault/Tasks.md"),
+            },
+        );
+
+        let completions = complete("[[ groceries");
+        let item = completions
+            .iter()
+            .find(|item| item.label == "Buy groceries for the week")
+            .unwrap();
+
+        assert_eq!(new_text(item), format!("Tasks#^{id}|${{1:groceries}}]]$0"));
+        let edit = serde_json::to_string(&item.command.as_ref().unwrap().arguments).unwrap();
+        assert!(edit.contains(&format!("   ^{id}")));
+    }
+
+    #[test]
+    fn grep_labels_blocks_with_their_heading() {
+        let labels = complete("[[ fix")
+            .into_iter()
+            .map(|item| item.label)
+            .collect_vec();
+
+        assert!(labels.contains(&"Kitchen > Fix the sink".to_string()));
+        assert!(labels.contains(&"Garden > Fix the fence".to_string()));
+    }
+
+    #[test]
+    fn grep_prefers_blocks_under_a_matching_heading() {
+        let completions = complete("[[ fix garden");
+
+        assert_eq!(
+            completions.first().map(|item| item.label.as_str()),
+            Some("Garden > Fix the fence")
+        );
+    }
+
+    #[test]
+    fn new_block_id_skips_ids_in_use() {
+        let (settings, mut vault) = vault();
+        let block = Block {
+            text: "Buy groceries for the week",
+            range: Default::default(),
+            file: Path::new("/vault/Tasks.md"),
+        };
+        let id = new_block_id(&vault, &block);
+        assert_eq!(id.len(), 5);
+        assert_eq!(new_block_id(&vault, &block), id);
+
+        Vault::update_vault(
+            &settings,
+            &mut vault,
+            (
+                &PathBuf::from("/vault/Tasks.md"),
+                &format!("Buy groceries for the week\n\nOther ^{id}\n"),
+            ),
+        );
+        assert_ne!(new_block_id(&vault, &block), id);
     }
 }
@@ -1,4 +1,6 @@
-use tower_lsp::lsp_types::Position;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use tower_lsp::lsp_types::{CompletionItem, CompletionTextEdit, InsertTextFormat, Position};
 
 use crate::vault::Rangeable as _;
 
@@ -16,3 +18,132 @@ pub fn check_in_code_block(context: &Context, line: usize, character: usize) ->
 
     in_code_block
 }
+
+/// Whether the line is a row of a table: its header row, right above the `---|---` delimiter row, or one of the rows
+/// below the delimiter row up to the next blank line, which need not start with a `|`
+pub fn in_table_row(context: &Context, line: usize) -> bool {
+    static DELIMITER_ROW: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"^\s*\|?\s*:?-+:?\s*(\|\s*:?-+:?\s*)*\|?\s*$").unwrap());
+
+    let row = |line: usize| {
+        context
+            .vault
+            .select_line(context.path, line as isize)
+            .map(String::from_iter)
+    };
+    let is_delimiter_row = |text: &str| text.contains('|') && DELIMITER_ROW.is_match(text);
+
+    if row(line).is_some_and(|text| text.contains('|'))
+        && row(line + 1).is_some_and(|text| is_delimiter_row(&text))
+    {
+        return true;
+    }
+
+    (0..=line)
+        .rev()
+        .map_while(|line| Some((line, row(line)?)))
+        .take_while(|(_, text)| !text.trim().is_empty())
+        .find(|(_, text)| is_delimiter_row(text))
+        .is_some_and(|(delimiter, _)| {
+            delimiter < line
+                && delimiter > 0
+                && row(delimiter - 1).is_some_and(|header| header.contains('|'))
+        })
+}
+
+/// A snippet completion as plain text, for clients that can not expand snippets: placeholders keep their default text and tabstops are dropped
+pub fn plain_text_completion(item: CompletionItem) -> CompletionItem {
+    if item.insert_text_format != Some(InsertTextFormat::SNIPPET) {
+        return item;
+    }
+
+    CompletionItem {
+        insert_text_format: Some(InsertTextFormat::PLAIN_TEXT),
+        insert_text: item.insert_text.as_deref().map(snippet_to_plain_text),
+        text_edit: item.text_edit.map(|text_edit| match text_edit {
+            CompletionTextEdit::Edit(mut edit) => {
+                edit.new_text = snippet_to_plain_text(&edit.new_text);
+                CompletionTextEdit::Edit(edit)
+            }
+            CompletionTextEdit::InsertAndReplace(mut edit) => {
+                edit.new_text = snippet_to_plain_text(&edit.new_text);
+                CompletionTextEdit::InsertAndReplace(edit)
+            }
+        }),
+        ..item
+    }
+}
+
+/// `[${1:display}](file)$0` as `[display](file)`
+fn snippet_to_plain_text(snippet: &str) -> String {
+    let mut chars = snippet.chars().peekable();
+    let mut text = String::with_capacity(snippet.len());
+    let mut open_placeholders = 0;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if matches!(chars.peek(), Some('$' | '}' | '\\')) => text.extend(chars.next()),
+            '$' if chars.peek().is_some_and(char::is_ascii_digit) => {
+                while chars.next_if(char::is_ascii_digit).is_some() {}
+            }
+            '$' if chars.peek() == Some(&'{') => {
+                chars.next();
+                while chars.next_if(char::is_ascii_digit).is_some() {}
+                // `${1:default}`, or a bare `${1}`
+                if chars.next() == Some(':') {
+                    open_placeholders += 1;
+                }
+            }
+            '}' if open_placeholders > 0 => open_placeholders -= 1,
+            c => text.push(c),
+        }
+    }
+
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use tower_lsp::lsp_types::{
+        CompletionItem, CompletionTextEdit, InsertTextFormat, Position, Range, TextEdit,
+    };
+
+    use super::{plain_text_completion, snippet_to_plain_text};
+
+    #[test]
+    fn snippets_as_plain_text() {
+        assert_eq!(
+            snippet_to_plain_text("notes|${1:Meeting}]]$0"),
+            "notes|Meeting]]"
+        );
+        assert_eq!(snippet_to_plain_text("[${1:x}](RealNote)"), "[x](RealNote)");
+        assert_eq!(
+            snippet_to_plain_text("> [!note] ${1:Title}\n> ${2}"),
+            "> [!note] Title\n> "
+        );
+        assert_eq!(snippet_to_plain_text(r"${1:costs \$5 \}}"), "costs $5 }");
+        assert_eq!(snippet_to_plain_text(r"\[draft\] no"), r"\[draft\] no");
+    }
+
+    #[test]
+    fn plain_text_completion_rewrites_the_edit() {
+        let edit = TextEdit {
+            range: Range::new(Position::new(0, 2), Position::new(0, 6)),
+            new_text: "RealNote]]$0".to_string(),
+        };
+        let item = plain_text_completion(CompletionItem {
+            insert_text_format: Some(InsertTextFormat::SNIPPET),
+            text_edit: Some(CompletionTextEdit::Edit(edit.clone())),
+            ..Default::default()
+        });
+
+        assert_eq!(item.insert_text_format, Some(InsertTextFormat::PLAIN_TEXT));
+        assert_eq!(
+            item.text_edit,
+            Some(CompletionTextEdit::Edit(TextEdit {
+                new_text: "RealNote]]".to_string(),
+                ..edit
+            }))
+        );
+    }
+}
@@ -5,7 +5,7 @@ use config::{Config, File};
 use indexmap::IndexMap;
 use serde::Deserialize;
 use serde_json::Value;
-use tower_lsp::lsp_types::ClientCapabilities;
+use tower_lsp::lsp_types::{ClientCapabilities, PositionEncodingKind};
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct Settings {
@@ -14,6 +14,13 @@ pub struct Settings {
     /// Diffrent pages path than default
     pub new_file_folder_path: String,
     pub daily_notes_folder: String,
+    /// Folders for daily notes inside `daily_notes_folder`, formatted with the note's date: `%Y` or `%Y/%m`
+    pub daily_notes_subfolder: String,
+    /// Folder, relative to the vault root, whose files are attachments whatever their extension; markdown files in it
+    /// are still notes
+    pub attachments_folder: String,
+    /// Note, relative to the vault root, whose text new daily notes start with
+    pub daily_note_template: String,
     pub heading_completions: bool,
     pub title_headings: bool,
     pub unresolved_diagnostics: bool,
@@ -22,16 +29,158 @@ pub struct Settings {
     pub references_in_codeblocks: bool,
     pub include_md_extension_md_link: bool,
     pub include_md_extension_wikilink: bool,
+    /// Markdown links name their file relative to the linking note's folder, `../other/note.md`; wiki links still
+    /// resolve by the shortest path
+    pub relative_markdown_links: bool,
+    /// Heading links match headings ignoring case, spaces and punctuation: `[[Note#myheading]]` links `# My Heading`
+    pub loose_heading_links: bool,
     pub hover: bool,
     pub case_matching: Case,
     pub inlay_hints: bool,
     pub block_transclusion: bool,
     pub block_transclusion_length: EmbeddedBlockTransclusionLength,
+    pub block_symbols: bool,
+    /// Paths to leave out of the vault, in .gitignore syntax relative to the vault root
+    pub ignore_patterns: Vec<String>,
+    /// Threads used to index the vault on startup; 0 lets rayon decide
+    pub indexing_threads: usize,
+    /// Characters advertised to the client as completion triggers
+    pub completion_trigger_characters: Vec<String>,
+    /// Completion requests within this window of a newer one are dropped; 0 disables debouncing
+    pub completion_debounce_ms: u64,
+    /// Preview notes and count their backlinks when a link completion is resolved; off for very large vaults
+    pub completion_previews: bool,
+    /// How much link completions are ordered by how well they match the query
+    pub completion_match_weight: f64,
+    /// How much link completions are ordered by how many links point to their note
+    pub completion_backlink_weight: f64,
+    /// How much link completions are ordered by how recently their note was opened or changed
+    pub completion_recency_weight: f64,
+    /// Most completions sent for one request; lists are always incomplete, so the client asks again as the user types
+    pub completion_max_results: usize,
+    /// Completions are sent as snippets with tabstops; off when the client can not expand them
+    pub snippet_support: bool,
+    /// Wiki link completions end in an empty `|` display text with the cursor in it
+    pub wikilink_display_tabstop: bool,
+    /// Goto definition on a tag also lists its nested tags: `#a` goes to `#a/b` as well
+    pub tag_definition_children: bool,
+    /// Whether a note's first H1 is also one of its names
+    pub title_source: TitleSource,
+    /// The note a link to a folder, `[[folder/]]`, goes to
+    pub folder_note: FolderNote,
+    /// Goto definition on a link that resolves in none of the notes of its own workspace folder looks in the other
+    /// workspace folders
+    pub cross_vault_links: bool,
+    /// Link syntax that document formatting rewrites links to
+    pub format_link_style: LinkStyle,
+    /// Document formatting drops wiki link display text that repeats the target: `[[Note|Note]]` -> `[[Note]]`
+    pub format_redundant_display_text: bool,
+    /// The extract to new note code action leaves an embed, `![[Note]]`, in place of the selection instead of a link
+    pub extract_note_embed: bool,
+    /// How the client counts columns; negotiated from its capabilities, not the settings file
+    pub position_encoding: PositionEncoding,
 }
 
-#[derive(Clone, Debug, Deserialize)]
+/// The unit of the `character` of LSP positions
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+pub enum PositionEncoding {
+    Utf16,
+    Utf32,
+}
+
+impl PositionEncoding {
+    /// UTF-32 when the client supports it, since its columns are char indices, and UTF-16 otherwise, which LSP
+    /// requires every client to support.
+    fn negotiate(capabilities: &ClientCapabilities) -> PositionEncoding {
+        let utf32 = capabilities
+            .general
+            .as_ref()
+            .and_then(|general| general.position_encodings.as_ref())
+            .is_some_and(|encodings| encodings.contains(&PositionEncodingKind::UTF32));
+
+        if utf32 {
+            PositionEncoding::Utf32
+        } else {
+            PositionEncoding::Utf16
+        }
+    }
+
+    pub fn kind(&self) -> PositionEncodingKind {
+        match self {
+            PositionEncoding::Utf16 => PositionEncodingKind::UTF16,
+            PositionEncoding::Utf32 => PositionEncodingKind::UTF32,
+        }
+    }
+
+    fn len(&self, c: char) -> u32 {
+        match self {
+            PositionEncoding::Utf16 => c.len_utf16() as u32,
+            PositionEncoding::Utf32 => 1,
+        }
+    }
+
+    /// The column of the char at `index` of `line`; indices past the end count one unit each
+    pub fn column(&self, line: &[char], index: usize) -> u32 {
+        line.iter().take(index).map(|c| self.len(*c)).sum::<u32>()
+            + index.saturating_sub(line.len()) as u32
+    }
+
+    /// The index of the char of `line` at `column`; a column inside a char is rounded to the char after it
+    pub fn char_index(&self, line: &[char], column: u32) -> usize {
+        let mut units = 0;
+        line.iter()
+            .take_while(|c| {
+                let before = units;
+                units += self.len(**c);
+                before < column
+            })
+            .count()
+    }
+}
+
+/// Where a note takes its name from besides the file name
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+pub enum TitleSource {
+    #[serde(alias = "filename")]
+    Filename,
+    /// The first H1 is indexed like an alias
+    #[serde(alias = "heading")]
+    Heading,
+}
+
+/// The note inside a folder that stands for it: `folder/folder.md` or `folder/index.md`
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+pub enum FolderNote {
+    #[serde(alias = "foldername")]
+    FolderName,
+    #[serde(alias = "index")]
+    Index,
+}
+
+impl FolderNote {
+    /// The name of the folder note of `folder`, a path like `projects/alpha`
+    pub fn name<'a>(&self, folder: &'a str) -> Option<&'a str> {
+        let name = match self {
+            FolderNote::FolderName => folder.rsplit('/').next()?,
+            FolderNote::Index => "index",
+        };
+
+        (!folder.is_empty() && !name.is_empty()).then_some(name)
+    }
+}
+
+/// The syntax of the links in a formatted document; `Keep` leaves each link in the syntax it has
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+pub enum LinkStyle {
+    Keep,
+    Wiki,
+    Markdown,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Hash)]
 pub enum Case {
     Ignore,
+    #[default]
     Smart,
     Respect,
 }
@@ -46,6 +195,7 @@ impl Settings {
     pub fn new(root_dir: &Path, capabilities: &ClientCapabilities) -> anyhow::Result<Settings> {
         let obsidian_daily_note_config = obsidian_daily_note_config(root_dir).unwrap_or_default();
         let obsidian_new_file_folder_path = obsidian_new_file_folder_path(root_dir);
+        let obsidian_attachments_folder = obsidian_attachments_folder(root_dir);
         let expanded = shellexpand::tilde("~/.config/moxide/settings");
         let settings = Config::builder()
             .add_source(File::with_name(&expanded).required(false))
@@ -66,6 +216,17 @@ impl Settings {
                 "daily_notes_folder",
                 obsidian_daily_note_config.folder.unwrap_or("".to_string()),
             )?
+            .set_default("daily_notes_subfolder", "")?
+            .set_default(
+                "attachments_folder",
+                obsidian_attachments_folder.unwrap_or("".to_string()),
+            )?
+            .set_default(
+                "daily_note_template",
+                obsidian_daily_note_config
+                    .template
+                    .unwrap_or("".to_string()),
+            )?
             .set_default(
                 "dailynote",
                 obsidian_daily_note_config
@@ -85,6 +246,30 @@ impl Settings {
             .set_default("inlay_hints", true)?
             .set_default("block_transclusion", true)?
             .set_default("block_transclusion_length", "Full")?
+            .set_default("block_symbols", false)?
+            .set_default("ignore_patterns", Vec::<String>::new())?
+            .set_default("indexing_threads", 0)?
+            .set_default(
+                "completion_trigger_characters",
+                vec!["[", " ", "(", "#", ">", "^", "|"],
+            )?
+            .set_default("completion_debounce_ms", 20)?
+            .set_default("completion_previews", true)?
+            .set_default("completion_match_weight", 1.0)?
+            .set_default("completion_backlink_weight", 0.3)?
+            .set_default("completion_recency_weight", 0.2)?
+            .set_default("completion_max_results", 20)?
+            .set_default("snippet_support", true)?
+            .set_default("wikilink_display_tabstop", false)?
+            .set_default("tag_definition_children", false)?
+            .set_default("title_source", "Filename")?
+            .set_default("folder_note", "FolderName")?
+            .set_default("cross_vault_links", false)?
+            .set_default("format_link_style", "Keep")?
+            .set_default("format_redundant_display_text", true)?
+            .set_default("extract_note_embed", false)?
+            .set_default("relative_markdown_links", false)?
+            .set_default("loose_heading_links", false)?
             .set_override_option(
                 "semantic_tokens",
                 capabilities.text_document.as_ref().and_then(|it| {
@@ -94,10 +279,35 @@ impl Settings {
                     }
                 }),
             )?
+            .set_override(
+                "position_encoding",
+                format!("{:?}", PositionEncoding::negotiate(capabilities)),
+            )?
+            .set_override_option(
+                "snippet_support",
+                capabilities.text_document.as_ref().and_then(|it| {
+                    let snippet_support = it
+                        .completion
+                        .as_ref()
+                        .and_then(|completion| completion.completion_item.as_ref())
+                        .and_then(|item| item.snippet_support);
+                    match snippet_support {
+                        Some(true) => None,
+                        _ => Some(false),
+                    }
+                }),
+            )?
             .build()
             .map_err(|err| anyhow!("Build err: {err}"))?;
 
-        let settings = settings.try_deserialize::<Settings>()?;
+        let mut settings = settings.try_deserialize::<Settings>()?;
+
+        // daily note formats may be written like Obsidian's, `YYYY-MM-DD`, rather than `%Y-%m-%d`
+        for format in [&mut settings.dailynote, &mut settings.daily_notes_subfolder] {
+            if !format.contains('%') {
+                *format = convert_momentjs_to_chrono_format(format);
+            }
+        }
 
         anyhow::Ok(settings)
     }
@@ -107,6 +317,7 @@ impl Settings {
 struct ObsidianDailyNoteConfig {
     folder: Option<String>,
     format: Option<String>,
+    template: Option<String>,
 }
 
 fn obsidian_daily_note_config(root_dir: &Path) -> Option<ObsidianDailyNoteConfig> {
@@ -117,6 +328,7 @@ fn obsidian_daily_note_config(root_dir: &Path) -> Option<ObsidianDailyNoteConfig
     Some(ObsidianDailyNoteConfig {
         folder: config.folder,
         format: config.format.map(|x| convert_momentjs_to_chrono_format(&x)),
+        template: config.template,
     })
 }
 
@@ -144,6 +356,19 @@ fn obsidian_new_file_folder_path(root_dir: &Path) -> Option<String> {
     new_file_folder_path
 }
 
+/// Obsidian's "Default location for new attachments" when it is one folder of the vault; attachments kept next to
+/// each note, `./`, are only known by their extensions
+fn obsidian_attachments_folder(root_dir: &Path) -> Option<String> {
+    let file = std::fs::read(root_dir.join(".obsidian").join("app.json")).ok()?;
+    let config: HashMap<String, Value> = serde_json::from_slice(&file).ok()?;
+    let folder = config.get("attachmentFolderPath")?.as_str()?;
+
+    match folder.trim_start_matches('/') {
+        folder if folder.is_empty() || folder.starts_with('.') => None,
+        folder => Some(folder.to_string()),
+    }
+}
+
 use std::collections::HashMap;
 
 // GPT-4 code
@@ -171,13 +396,32 @@ fn momentjs_to_chrono_format_map() -> IndexMap<&'static str, &'static str> {
     map
 }
 
-// GPT-4 code
+/// A Moment.js date format as a chrono one; text in `[brackets]` is kept as written
 fn convert_momentjs_to_chrono_format(moment_format: &str) -> String {
     let format_map = momentjs_to_chrono_format_map();
-    let mut chrono_format = moment_format.to_string();
+    let mut chrono_format = String::new();
+    let mut rest = moment_format;
 
-    for (moment_token, chrono_token) in format_map.iter() {
-        chrono_format = chrono_format.replace(moment_token, chrono_token);
+    while let Some(c) = rest.chars().next() {
+        if let Some((literal, after)) = rest
+            .strip_prefix('[')
+            .and_then(|bracketed| bracketed.split_once(']'))
+        {
+            chrono_format.push_str(&literal.replace('%', "%%"));
+            rest = after;
+        } else if let Some((moment_token, chrono_token)) = format_map
+            .iter()
+            .find(|(moment_token, _)| rest.starts_with(*moment_token))
+        {
+            chrono_format.push_str(chrono_token);
+            rest = &rest[moment_token.len()..];
+        } else {
+            match c {
+                '%' => chrono_format.push_str("%%"),
+                c => chrono_format.push(c),
+            }
+            rest = &rest[c.len_utf8()..];
+        }
     }
 
     chrono_format
@@ -188,9 +432,13 @@ mod test {
 
     use std::path::PathBuf;
 
+    use tower_lsp::lsp_types::{
+        ClientCapabilities, GeneralClientCapabilities, PositionEncodingKind,
+    };
+
     use crate::config::{
-        convert_momentjs_to_chrono_format, obsidian_daily_note_config,
-        obsidian_new_file_folder_path,
+        convert_momentjs_to_chrono_format, obsidian_attachments_folder, obsidian_daily_note_config,
+        obsidian_new_file_folder_path, PositionEncoding,
     };
 
     #[test]
@@ -200,6 +448,36 @@ mod test {
         assert_eq!(chrono_format, "%Y-%m-%d");
     }
 
+    #[test]
+    fn moment_tokens_are_converted_once() {
+        assert_eq!(
+            convert_momentjs_to_chrono_format("dddd, MMMM D YYYY"),
+            "%A, %B %-d %Y"
+        );
+        assert_eq!(
+            convert_momentjs_to_chrono_format("[Daily] DD.MM.YY ddd"),
+            "Daily %d.%m.%y %a"
+        );
+        assert_eq!(convert_momentjs_to_chrono_format("YYYY/MM-MMM"), "%Y/%m-%b");
+    }
+
+    #[test]
+    fn moment_daily_note_settings_are_read_as_chrono() {
+        let root_dir = std::env::temp_dir().join("moxide-moment-settings");
+        std::fs::create_dir_all(&root_dir).unwrap();
+        std::fs::write(
+            root_dir.join(".moxide.toml"),
+            "dailynote = \"DD.MM.YYYY\"\ndaily_notes_subfolder = \"YYYY/MM\"\n",
+        )
+        .unwrap();
+
+        let settings = crate::config::Settings::new(&root_dir, &Default::default()).unwrap();
+        std::fs::remove_dir_all(&root_dir).unwrap();
+
+        assert_eq!(settings.dailynote, "%d.%m.%Y");
+        assert_eq!(settings.daily_notes_subfolder, "%Y/%m");
+    }
+
     #[test]
     fn test_daily_note_config() {
         let daily_notes_config = obsidian_daily_note_config(&root_dir()).unwrap();
@@ -219,6 +497,44 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_obsidian_attachments_folder() {
+        assert_eq!(
+            obsidian_attachments_folder(&root_dir()),
+            Some("media".to_string())
+        );
+    }
+
+    #[test]
+    fn utf8_is_never_negotiated() {
+        let negotiate = |encodings: Option<Vec<PositionEncodingKind>>| {
+            PositionEncoding::negotiate(&ClientCapabilities {
+                general: Some(GeneralClientCapabilities {
+                    position_encodings: encodings,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            })
+        };
+
+        assert_eq!(negotiate(None), PositionEncoding::Utf16);
+        assert_eq!(
+            negotiate(Some(vec![
+                PositionEncodingKind::UTF8,
+                PositionEncodingKind::UTF16
+            ])),
+            PositionEncoding::Utf16
+        );
+        assert_eq!(
+            negotiate(Some(vec![
+                PositionEncodingKind::UTF8,
+                PositionEncodingKind::UTF16,
+                PositionEncodingKind::UTF32
+            ])),
+            PositionEncoding::Utf32
+        );
+    }
+
     fn root_dir() -> PathBuf {
         PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("TestFiles")
     }
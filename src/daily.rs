@@ -1,7 +1,311 @@
+use std::{
+    collections::HashSet,
+    fmt::Write as _,
+    fs::File,
+    io::{ErrorKind, Write as _},
+    path::{Path, PathBuf},
+};
+
+use chrono::{format::StrftimeItems, Datelike, Days, NaiveDate};
+use itertools::Itertools;
+
 use crate::config::Settings;
 
-pub fn filename_is_formatted(context: &Settings, filename: &str) -> bool {
-    let try_parsed = chrono::NaiveDate::parse_from_str(&filename, &context.dailynote);
+/// The date of the daily note named `filename`, if it is in the `dailynote` format
+pub fn daily_note_date(context: &Settings, filename: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(filename, &context.dailynote).ok()
+}
+
+/// The dates whose daily note name starts with `query`, nearest to `today` first and at most `limit` of them, like the
+/// days of January 2024 for `2024-01`. Only the days of a year typed in the query, from five years back to a year
+/// ahead, are looked at, and without one the days from two years back to a year ahead
+pub fn daily_note_dates_matching(
+    context: &Settings,
+    query: &str,
+    today: NaiveDate,
+    limit: usize,
+) -> Vec<NaiveDate> {
+    // the format is parsed once, rather than for each of the thousands of dates
+    let Ok(items) = StrftimeItems::new(&context.dailynote).parse() else {
+        return vec![];
+    };
+    if query.is_empty() {
+        return vec![];
+    }
+
+    let (first, last) = (today - Days::new(366 * 5), today + Days::new(366));
+    let chars = query.chars().collect_vec();
+    let typed_years = chars
+        .windows(4)
+        .filter(|digits| digits.iter().all(char::is_ascii_digit))
+        .filter_map(|digits| String::from_iter(digits).parse::<i32>().ok())
+        .filter(|year| (first.year()..=last.year()).contains(year))
+        .collect::<HashSet<_>>();
+    let first = match typed_years.is_empty() {
+        true => today - Days::new(366 * 2),
+        false => first,
+    };
+
+    let mut name = String::new();
+    (0..=366 * 5)
+        .flat_map(|days| [today - Days::new(days), today + Days::new(days)])
+        .filter(|date| (first..=last).contains(date))
+        .filter(|date| typed_years.is_empty() || typed_years.contains(&date.year()))
+        .unique()
+        .filter(|date| {
+            name.clear();
+            write!(name, "{}", date.format_with_items(items.iter())).is_ok()
+                && name
+                    .get(..query.len())
+                    .is_some_and(|start| start.eq_ignore_ascii_case(query))
+        })
+        .take(limit)
+        .collect()
+}
+
+/// Which way from a daily note to look for the next one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Adjacent {
+    Previous,
+    Next,
+}
+
+/// The daily note among `notes` nearest to `date` in the `direction`, skipping the days that have no note
+pub fn adjacent_daily_note<'a>(
+    context: &Settings,
+    notes: impl IntoIterator<Item = &'a Path>,
+    date: NaiveDate,
+    direction: Adjacent,
+) -> Option<(NaiveDate, &'a Path)> {
+    let dated = notes.into_iter().filter_map(|path| {
+        let date = daily_note_date(context, path.file_stem()?.to_str()?)?;
+        Some((date, path))
+    });
+
+    match direction {
+        Adjacent::Previous => dated.filter(|(day, _)| *day < date).max(),
+        Adjacent::Next => dated.filter(|(day, _)| *day > date).min(),
+    }
+}
+
+/// Where the daily note of `date` lives: in `daily_notes_folder`, then the `daily_notes_subfolder` formatted for
+/// the date, like `journals/2024/2024-01-15.md`
+pub fn daily_note_path(context: &Settings, root_dir: &Path, date: NaiveDate) -> PathBuf {
+    daily_note_folder(context, root_dir, date)
+        .join(format!("{}.md", date.format(&context.dailynote)))
+}
+
+/// The folder of the daily note of `date`
+pub fn daily_note_folder(context: &Settings, root_dir: &Path, date: NaiveDate) -> PathBuf {
+    let folder = root_dir.join(&context.daily_notes_folder);
+
+    match context.daily_notes_subfolder.as_str() {
+        "" => folder,
+        subfolder => folder.join(date.format(subfolder).to_string()),
+    }
+}
+
+/// Create the daily note of `date`, with its folders and the text of `daily_note_template`, unless it exists
+pub fn create_daily_note(
+    context: &Settings,
+    root_dir: &Path,
+    date: NaiveDate,
+) -> std::io::Result<PathBuf> {
+    let path = daily_note_path(context, root_dir, date);
+    if let Some(folder) = path.parent() {
+        std::fs::create_dir_all(folder)?;
+    }
+
+    match File::create_new(&path) {
+        Ok(mut file) => {
+            file.write_all(daily_note_template(context, root_dir, &path, date).as_bytes())?;
+            Ok(path)
+        }
+        Err(err) if err.kind() == ErrorKind::AlreadyExists => Ok(path),
+        Err(err) => Err(err),
+    }
+}
+
+/// The template's text with Obsidian's `{{title}}` and `{{date}}` filled in; empty without a readable template
+fn daily_note_template(
+    context: &Settings,
+    root_dir: &Path,
+    path: &Path,
+    date: NaiveDate,
+) -> String {
+    let template = match context.daily_note_template.as_str() {
+        "" => return String::new(),
+        template if template.ends_with(".md") => root_dir.join(template),
+        template => root_dir.join(format!("{template}.md")),
+    };
+    let title = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or_default();
+
+    std::fs::read_to_string(template)
+        .unwrap_or_default()
+        .replace("{{title}}", title)
+        .replace("{{date}}", &date.format("%Y-%m-%d").to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use chrono::NaiveDate;
+    use tower_lsp::lsp_types::ClientCapabilities;
+
+    use crate::config::Settings;
+
+    use super::{
+        adjacent_daily_note, create_daily_note, daily_note_date, daily_note_dates_matching,
+        daily_note_path, Adjacent,
+    };
+
+    fn settings(format: &str, folder: &str, subfolder: &str) -> Settings {
+        let mut settings =
+            Settings::new(Path::new("/vault"), &ClientCapabilities::default()).unwrap();
+        settings.dailynote = format.to_string();
+        settings.daily_notes_folder = folder.to_string();
+        settings.daily_notes_subfolder = subfolder.to_string();
+        settings
+    }
+
+    #[test]
+    fn daily_note_paths_follow_the_format_and_folders() {
+        let date = NaiveDate::from_ymd_opt(2024, 1, 15).unwrap();
+        let path = |settings: &Settings| daily_note_path(settings, Path::new("/vault"), date);
+
+        assert_eq!(
+            path(&settings("%Y-%m-%d", "", "")),
+            PathBuf::from("/vault/2024-01-15.md")
+        );
+        assert_eq!(
+            path(&settings("%d.%m.%Y", "daily", "")),
+            PathBuf::from("/vault/daily/15.01.2024.md")
+        );
+        assert_eq!(
+            path(&settings("%Y-%m-%d", "journals", "%Y")),
+            PathBuf::from("/vault/journals/2024/2024-01-15.md")
+        );
+        assert_eq!(
+            path(&settings("%A, %B %-d %Y", "journals", "%Y/%m-%B")),
+            PathBuf::from("/vault/journals/2024/01-January/Monday, January 15 2024.md")
+        );
+    }
+
+    #[test]
+    fn daily_note_dates_are_read_from_filenames() {
+        let settings = settings("%d.%m.%Y", "journals", "%Y");
+
+        assert_eq!(
+            daily_note_date(&settings, "15.01.2024"),
+            NaiveDate::from_ymd_opt(2024, 1, 15)
+        );
+        assert_eq!(daily_note_date(&settings, "2024-01-15"), None);
+    }
+
+    #[test]
+    fn dates_are_found_by_the_start_of_their_name() {
+        let today = NaiveDate::from_ymd_opt(2024, 3, 10).unwrap();
+        let date = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+
+        let january =
+            daily_note_dates_matching(&settings("%Y-%m-%d", "", ""), "2024-01", today, 40);
+        assert_eq!(january.len(), 31);
+        assert_eq!(january.first(), Some(&date(2024, 1, 31)));
+        assert_eq!(january.last(), Some(&date(2024, 1, 1)));
+        // a typed year reaches further back than the years around today
+        assert_eq!(
+            daily_note_dates_matching(&settings("%d.%m.%Y", "", ""), "01.05.2019", today, 3),
+            vec![date(2019, 5, 1)]
+        );
+
+        let named = settings("%B %-d, %Y", "", "");
+        assert_eq!(
+            daily_note_dates_matching(&named, "march 1,", today, 3),
+            vec![date(2024, 3, 1), date(2025, 3, 1), date(2023, 3, 1)]
+        );
+        assert_eq!(daily_note_dates_matching(&named, "", today, 3), vec![]);
+        assert_eq!(
+            daily_note_dates_matching(&named, "January 1, 2030", today, 3),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn adjacent_daily_notes_skip_days_without_a_note() {
+        let settings = settings("%Y-%m-%d", "journals", "");
+        let notes = [
+            "/vault/journals/2024-01-10.md",
+            "/vault/journals/2024-01-14.md",
+            "/vault/journals/2024-01-15.md",
+            "/vault/journals/2024-01-20.md",
+            "/vault/journals/not a date.md",
+            "/vault/2024-01-12 meeting.md",
+        ]
+        .map(Path::new);
+        let date = |d| NaiveDate::from_ymd_opt(2024, 1, d).unwrap();
+        let adjacent = |day, direction| {
+            adjacent_daily_note(&settings, notes, date(day), direction)
+                .map(|(day, path)| (day, path.to_str().unwrap()))
+        };
+
+        assert_eq!(
+            adjacent(15, Adjacent::Previous),
+            Some((date(14), "/vault/journals/2024-01-14.md"))
+        );
+        assert_eq!(
+            adjacent(14, Adjacent::Previous),
+            Some((date(10), "/vault/journals/2024-01-10.md"))
+        );
+        assert_eq!(
+            adjacent(15, Adjacent::Next),
+            Some((date(20), "/vault/journals/2024-01-20.md"))
+        );
+        // a day without a note of its own still has neighbours
+        assert_eq!(
+            adjacent(17, Adjacent::Previous),
+            Some((date(15), "/vault/journals/2024-01-15.md"))
+        );
+        assert_eq!(adjacent(10, Adjacent::Previous), None);
+        assert_eq!(adjacent(20, Adjacent::Next), None);
+    }
+
+    #[test]
+    fn created_daily_notes_get_their_folder_and_template() {
+        let root_dir =
+            std::env::temp_dir().join(format!("moxide-create-daily-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root_dir);
+        std::fs::create_dir_all(root_dir.join("templates")).unwrap();
+        std::fs::write(
+            root_dir.join("templates/Daily.md"),
+            "# {{title}}\n\ncreated {{date}}\n",
+        )
+        .unwrap();
+
+        let mut settings = settings("%d.%m.%Y", "journals", "%Y");
+        settings.daily_note_template = "templates/Daily".to_string();
+        let date = NaiveDate::from_ymd_opt(2024, 1, 15).unwrap();
+
+        let path = create_daily_note(&settings, &root_dir, date).unwrap();
+        assert_eq!(path, root_dir.join("journals/2024/15.01.2024.md"));
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "# 15.01.2024\n\ncreated 2024-01-15\n"
+        );
+
+        // an existing note is only opened
+        std::fs::write(&path, "written since").unwrap();
+        assert_eq!(create_daily_note(&settings, &root_dir, date).unwrap(), path);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "written since");
+
+        // without a template the note starts empty
+        settings.daily_note_template = String::new();
+        let path = create_daily_note(&settings, &root_dir, date.succ_opt().unwrap()).unwrap();
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "");
 
-    try_parsed.is_ok()
+        std::fs::remove_dir_all(root_dir).unwrap();
+    }
 }
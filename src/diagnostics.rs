@@ -1,11 +1,22 @@
-use std::path::{Path, PathBuf};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
 
+use itertools::{Either, Itertools};
 use rayon::prelude::*;
-use tower_lsp::lsp_types::{Diagnostic, DiagnosticSeverity, Url};
+use serde::Serialize;
+use tower_lsp::lsp_types::{
+    Diagnostic, DiagnosticRelatedInformation, DiagnosticSeverity, DiagnosticTag, Location,
+    Position, Range, Url,
+};
 
 use crate::{
     config::Settings,
-    vault::{self, Reference, Referenceable, Vault},
+    vault::{
+        self, link_target_span, normalize_for_match, MDFile, MatchOpts, Reference, ReferenceData,
+        Referenceable, Vault,
+    },
 };
 
 pub fn path_unresolved_references<'a>(
@@ -18,9 +29,14 @@ pub fn path_unresolved_references<'a>(
     let unresolved = pathreferences
         .into_par_iter()
         .filter(|(path, reference)| {
-            let matched_option = referenceables
-                .iter()
-                .find(|referenceable| reference.references(vault.root_dir(), path, referenceable));
+            let matched_option = referenceables.iter().find(|referenceable| {
+                reference.references(
+                    vault.root_dir(),
+                    vault.link_resolution(),
+                    path,
+                    referenceable,
+                )
+            });
 
             matched_option.is_some_and(|matched| {
                 matches!(
@@ -36,19 +52,108 @@ pub fn path_unresolved_references<'a>(
     Some(unresolved)
 }
 
+/// The unresolved links to one target, as listed by the `dangling_references` command
+#[derive(Debug, Serialize, PartialEq)]
+pub struct DanglingTarget {
+    pub target: String,
+    pub locations: Vec<Location>,
+}
+
+/// Every unresolved link in the vault, grouped by the target it names; the most linked targets come first
+pub fn dangling_references(vault: &Vault) -> Vec<DanglingTarget> {
+    let referenceables = vault.select_referenceable_nodes(None);
+    let unresolved = referenceables
+        .iter()
+        .filter(|referenceable| referenceable.is_unresolved())
+        .collect_vec();
+    let Some(references) = vault.select_references(None) else {
+        return vec![];
+    };
+
+    references
+        .into_par_iter()
+        // only links to an unresolved target are resolved against the whole vault
+        .filter(|(path, reference)| {
+            unresolved.iter().any(|target| {
+                reference.references(vault.root_dir(), vault.link_resolution(), path, target)
+            }) && referenceables
+                .iter()
+                .find(|referenceable| {
+                    reference.references(
+                        vault.root_dir(),
+                        vault.link_resolution(),
+                        path,
+                        referenceable,
+                    )
+                })
+                .is_some_and(Referenceable::is_unresolved)
+        })
+        .filter_map(|(path, reference)| {
+            let target = &reference.data().reference_text;
+            Some((
+                normalize_for_match(target, &MatchOpts::CASEFOLD),
+                (
+                    target.clone(),
+                    Location {
+                        uri: Url::from_file_path(path).ok()?,
+                        range: *reference.data().range,
+                    },
+                ),
+            ))
+        })
+        .collect::<Vec<_>>()
+        .into_iter()
+        .into_group_map()
+        .into_values()
+        // links that differ only in case name the same note; the group is named as its first link writes it
+        .filter_map(|links| {
+            let links = links
+                .into_iter()
+                .sorted_by(|(_, a), (_, b)| (&a.uri, a.range.start).cmp(&(&b.uri, b.range.start)))
+                .collect_vec();
+            Some(DanglingTarget {
+                target: links.first()?.0.clone(),
+                locations: links.into_iter().map(|(_, location)| location).collect(),
+            })
+        })
+        .sorted_by(|a, b| {
+            b.locations
+                .len()
+                .cmp(&a.locations.len())
+                .then_with(|| a.target.cmp(&b.target))
+        })
+        .collect()
+}
+
 pub fn diagnostics(
     vault: &Vault,
     settings: &Settings,
-    (path, _uri): (&PathBuf, &Url),
+    (path, uri): (&PathBuf, &Url),
 ) -> Option<Vec<Diagnostic>> {
+    let alias_conflicts = alias_conflict_diagnostics(vault, path)?
+        .into_iter()
+        .chain(ambiguous_link_diagnostics(vault, path)?)
+        .chain(duplicate_block_diagnostics(vault, path, uri)?)
+        .chain(unused_block_diagnostics(vault, path)?)
+        .collect::<Vec<_>>();
+
     if !settings.unresolved_diagnostics {
-        return None;
+        return Some(alias_conflicts);
     }
 
     let unresolved = path_unresolved_references(vault, path)?;
 
     let allreferences = vault.select_references(None)?;
 
+    // links into notes that exist, but to a heading or block they do not have
+    let (broken_fragments, unresolved): (Vec<_>, Vec<_>) =
+        unresolved.into_iter().partition_map(|(path, reference)| {
+            match broken_fragment(vault, path, reference) {
+                Some(fragment) => Either::Left((reference, fragment)),
+                None => Either::Right((path, reference)),
+            }
+        });
+
     let diags: Vec<Diagnostic> = unresolved
         .into_par_iter()
         .map(|(path, reference)| Diagnostic {
@@ -70,7 +175,623 @@ pub fn diagnostics(
             severity: Some(DiagnosticSeverity::INFORMATION),
             ..Default::default()
         })
+        .chain(
+            broken_fragments
+                .into_par_iter()
+                .map(|(reference, (target, _, range))| Diagnostic {
+                    range,
+                    message: format!(
+                        "{} not found in {}",
+                        match reference {
+                            Reference::WikiIndexedBlockLink(..)
+                            | Reference::MDIndexedBlockLink(..) => "Block",
+                            _ => "Heading",
+                        },
+                        vault::get_obsidian_ref_path(vault.root_dir(), target).unwrap_or_default()
+                    ),
+                    source: Some("Obsidian LS".into()),
+                    severity: Some(DiagnosticSeverity::WARNING),
+                    ..Default::default()
+                }),
+        )
+        .chain(alias_conflicts)
         .collect();
 
     Some(diags)
 }
+
+/// A heading or block link into a note that exists but has no such heading or block: the note, and the range of the link's `#heading` or `#^block`
+pub fn broken_fragment<'a>(
+    vault: &'a Vault,
+    path: &Path,
+    reference: &Reference,
+) -> Option<(&'a Path, &'a MDFile, Range)> {
+    let (data, file, fragment) = match reference {
+        Reference::WikiHeadingLink(data, file, heading)
+        | Reference::MDHeadingLink(data, file, heading) => (data, file, format!("#{heading}")),
+        Reference::WikiIndexedBlockLink(data, file, index)
+        | Reference::MDIndexedBlockLink(data, file, index) => (data, file, format!("#^{index}")),
+        _ => return None,
+    };
+
+    if vault
+        .select_referenceables_for_reference(reference, path)
+        .into_iter()
+        .any(|referenceable| {
+            matches!(
+                referenceable,
+                Referenceable::Heading(..) | Referenceable::IndexedBlock(..)
+            )
+        })
+    {
+        return None;
+    }
+
+    let file_data = ReferenceData {
+        reference_text: file.clone(),
+        ..Default::default()
+    };
+    let file_link = match reference {
+        Reference::MDHeadingLink(..) | Reference::MDIndexedBlockLink(..) => {
+            Reference::MDFileLink(file_data)
+        }
+        _ => Reference::WikiFileLink(file_data),
+    };
+    let (target, mdfile) = vault
+        .select_referenceables_for_reference(&file_link, path)
+        .into_iter()
+        .find_map(|referenceable| match referenceable {
+            Referenceable::File(target, mdfile) => Some((target.as_path(), mdfile)),
+            _ => None,
+        })?;
+
+    let line = vault.select_line(path, data.range.start.line as isize)?;
+    let link_text = String::from_iter(
+        line.get(data.range.start.character as usize..data.range.end.character as usize)?,
+    );
+    let span = link_target_span(&link_text);
+    let offset = span.start + link_text[span].find(&fragment)?;
+    let start = data.range.start.character + link_text[..offset].chars().count() as u32;
+
+    Some((
+        target,
+        mdfile,
+        Range {
+            start: Position {
+                line: data.range.start.line,
+                character: start,
+            },
+            end: Position {
+                line: data.range.start.line,
+                character: start + fragment.chars().count() as u32,
+            },
+        },
+    ))
+}
+
+/// Links by an alias that more than one file declares
+fn alias_conflict_diagnostics(vault: &Vault, path: &Path) -> Option<Vec<Diagnostic>> {
+    // the notes named or aliased by each case folded name, so a link is only checked against the notes it may link
+    let named = vault
+        .md_files
+        .iter()
+        .flat_map(|(file_path, mdfile)| {
+            mdfile
+                .file_name()
+                .into_iter()
+                .chain(
+                    mdfile
+                        .metadata
+                        .iter()
+                        .flat_map(|metadata| metadata.aliases())
+                        .map(String::as_str),
+                )
+                .map(move |name| {
+                    (
+                        normalize_for_match(name, &MatchOpts::CASEFOLD),
+                        (file_path, mdfile),
+                    )
+                })
+        })
+        .into_group_map();
+
+    let diags = vault
+        .select_references(Some(path))?
+        .into_par_iter()
+        .filter_map(|(path, reference)| {
+            let (Reference::WikiFileLink(data) | Reference::MDFileLink(data)) = reference else {
+                return None;
+            };
+
+            let targets = named
+                .get(&normalize_for_match(
+                    &data.reference_text,
+                    &MatchOpts::CASEFOLD,
+                ))?
+                .iter()
+                .unique_by(|(file_path, _)| *file_path)
+                .filter(|(file_path, mdfile)| {
+                    reference.references(
+                        vault.root_dir(),
+                        vault.link_resolution(),
+                        path,
+                        &Referenceable::File(file_path, mdfile),
+                    )
+                })
+                .collect::<Vec<_>>();
+
+            if targets.len() < 2
+                || !targets
+                    .iter()
+                    .any(|(_, mdfile)| mdfile.has_alias(&data.reference_text))
+            {
+                return None;
+            }
+
+            Some(Diagnostic {
+                range: *data.range,
+                message: format!(
+                    "Alias is shared by {}",
+                    targets
+                        .iter()
+                        .filter_map(|(file_path, _)| vault::get_obsidian_ref_path(
+                            vault.root_dir(),
+                            file_path
+                        ))
+                        .sorted()
+                        .join(", ")
+                ),
+                source: Some("Obsidian LS".into()),
+                severity: Some(DiagnosticSeverity::WARNING),
+                ..Default::default()
+            })
+        })
+        .collect();
+
+    Some(diags)
+}
+
+/// Wiki links by a bare name that several notes have, which reach every one of them; the link needs a folder to name
+/// one, which a quick fix adds
+fn ambiguous_link_diagnostics(vault: &Vault, path: &Path) -> Option<Vec<Diagnostic>> {
+    let named = vault
+        .md_files
+        .keys()
+        .filter_map(|file_path| {
+            let name = file_path.file_stem()?.to_str()?;
+            Some((normalize_for_match(name, &MatchOpts::CASEFOLD), file_path))
+        })
+        .into_group_map();
+
+    let diags = vault
+        .select_references(Some(path))?
+        .into_par_iter()
+        .filter_map(|(path, reference)| {
+            let file = match reference {
+                Reference::WikiFileLink(data) => &data.reference_text,
+                Reference::WikiHeadingLink(_, file, _)
+                | Reference::WikiIndexedBlockLink(_, file, _) => file,
+                _ => return None,
+            };
+            let namesakes = named
+                .get(&normalize_for_match(
+                    file.trim_end_matches(".md"),
+                    &MatchOpts::CASEFOLD,
+                ))
+                .filter(|namesakes| namesakes.len() > 1 && !file.contains('/'))?;
+
+            let targets = vault
+                .select_referenceables_for_reference(reference, path)
+                .into_iter()
+                .filter_map(|referenceable| match referenceable {
+                    Referenceable::File(path, _)
+                    | Referenceable::Heading(path, _)
+                    | Referenceable::IndexedBlock(path, _) => Some(path),
+                    _ => None,
+                })
+                .filter(|target| namesakes.contains(target))
+                .unique()
+                .collect_vec();
+            if targets.len() < 2 {
+                return None;
+            }
+
+            Some(Diagnostic {
+                range: *reference.data().range,
+                message: format!(
+                    "Name is shared by {}",
+                    targets
+                        .iter()
+                        .filter_map(|target| vault::get_obsidian_ref_path(vault.root_dir(), target))
+                        .sorted()
+                        .join(", ")
+                ),
+                source: Some("Obsidian LS".into()),
+                severity: Some(DiagnosticSeverity::WARNING),
+                ..Default::default()
+            })
+        })
+        .collect();
+
+    Some(diags)
+}
+
+/// Block ids a note defines more than once, which make links to them ambiguous; each one points at the others
+fn duplicate_block_diagnostics(vault: &Vault, path: &Path, uri: &Url) -> Option<Vec<Diagnostic>> {
+    let mdfile = vault.md_files.get(path)?;
+
+    let diags = mdfile
+        .indexed_blocks
+        .iter()
+        .into_group_map_by(|block| &block.index)
+        .into_iter()
+        .filter(|(_, blocks)| blocks.len() > 1)
+        .flat_map(|(index, blocks)| {
+            blocks
+                .iter()
+                .map(|block| Diagnostic {
+                    range: *block.range,
+                    message: format!("Block id ^{index} is defined {} times", blocks.len()),
+                    source: Some("Obsidian LS".into()),
+                    severity: Some(DiagnosticSeverity::WARNING),
+                    related_information: Some(
+                        blocks
+                            .iter()
+                            .filter(|other| other.range != block.range)
+                            .map(|other| DiagnosticRelatedInformation {
+                                location: Location {
+                                    uri: uri.clone(),
+                                    range: *other.range,
+                                },
+                                message: format!("^{index} is also defined here"),
+                            })
+                            .collect(),
+                    ),
+                    ..Default::default()
+                })
+                .collect_vec()
+        })
+        .sorted_by_key(|diagnostic| {
+            (
+                diagnostic.range.start.line,
+                diagnostic.range.start.character,
+            )
+        })
+        .collect();
+
+    Some(diags)
+}
+
+/// Block ids that no link in the vault points to, faded out as unused
+fn unused_block_diagnostics(vault: &Vault, path: &Path) -> Option<Vec<Diagnostic>> {
+    let (path, mdfile) = vault.md_files.get_key_value(path)?;
+
+    // a block whose id no block link names is unused without resolving the links
+    let linked_ids: HashSet<String> = vault
+        .md_files
+        .keys()
+        .flat_map(|note| vault.select_referenced_block_ids(note))
+        .map(|(_, index)| normalize_for_match(&index, &MatchOpts::CASEFOLD))
+        .collect();
+
+    let diags = mdfile
+        .indexed_blocks
+        .iter()
+        .filter(|block| {
+            !linked_ids.contains(&normalize_for_match(&block.index, &MatchOpts::CASEFOLD))
+                || vault
+                    .select_references_for_referenceable(&Referenceable::IndexedBlock(path, block))
+                    .is_none_or(|references| references.is_empty())
+        })
+        .map(|block| Diagnostic {
+            range: *block.range,
+            message: format!("Block id ^{} is not linked", block.index),
+            source: Some("Obsidian LS".into()),
+            severity: Some(DiagnosticSeverity::HINT),
+            tags: Some(vec![DiagnosticTag::UNNECESSARY]),
+            ..Default::default()
+        })
+        .collect();
+
+    Some(diags)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use tower_lsp::lsp_types::{
+        ClientCapabilities, Diagnostic, DiagnosticSeverity, Position, Range, Url,
+    };
+
+    use crate::{config::Settings, vault::Vault};
+
+    fn diagnostics_for(files: &[(&str, &str)], text: &str) -> Vec<(DiagnosticSeverity, String)> {
+        all_diagnostics(files, text)
+            .into_iter()
+            .map(|diagnostic| (diagnostic.severity.unwrap(), diagnostic.message))
+            .collect()
+    }
+
+    fn all_diagnostics(files: &[(&str, &str)], text: &str) -> Vec<Diagnostic> {
+        let root_dir = Path::new("/vault");
+        let settings = Settings::new(root_dir, &ClientCapabilities::default()).unwrap();
+        let mut vault = Vault::construct_vault(&settings, root_dir).unwrap();
+
+        for (name, text) in files {
+            Vault::update_vault(
+                &settings,
+                &mut vault,
+                (&PathBuf::from(format!("/vault/{}.md", name)), text),
+            );
+        }
+        let path = PathBuf::from("/vault/source.md");
+        Vault::update_vault(&settings, &mut vault, (&path, text));
+
+        super::diagnostics(
+            &vault,
+            &settings,
+            (&path, &Url::from_file_path(&path).unwrap()),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn alias_links_resolve() {
+        let diagnostics = diagnostics_for(
+            &[("RealNote", "---\naliases: [Foo]\n---\n")],
+            "[[Foo]] and [[foo]] and [x](Foo)",
+        );
+
+        assert_eq!(diagnostics, vec![]);
+    }
+
+    #[test]
+    fn shared_alias_conflict() {
+        let diagnostics = diagnostics_for(
+            &[
+                ("First", "---\naliases: [Shared]\n---\n"),
+                ("Second", "---\naliases: [Shared]\n---\n"),
+            ],
+            "[[Shared]]",
+        );
+
+        assert_eq!(
+            diagnostics,
+            vec![(
+                DiagnosticSeverity::WARNING,
+                "Alias is shared by First, Second".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn alias_naming_another_note_conflicts() {
+        let diagnostics = diagnostics_for(
+            &[("Shared", ""), ("Other", "---\naliases: [shared]\n---\n")],
+            "[[shared]]",
+        );
+
+        assert_eq!(
+            diagnostics,
+            vec![(
+                DiagnosticSeverity::WARNING,
+                "Alias is shared by Other, Shared".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn bare_names_shared_by_notes_are_ambiguous() {
+        let diagnostics = diagnostics_for(
+            &[("a/dup", "# Part\n"), ("b/dup", ""), ("solo", "")],
+            // only one of the notes has the heading
+            "[[Dup]] [[dup#Part]] [[a/dup]] [[solo]] [dup](dup.md)",
+        );
+
+        assert_eq!(
+            diagnostics,
+            vec![(
+                DiagnosticSeverity::WARNING,
+                "Name is shared by a/dup, b/dup".to_string()
+            )]
+        );
+    }
+
+    /// (severity, message, line, start, end) of each diagnostic for links into a note with one heading and one block
+    fn fragment_diagnostics(text: &str) -> Vec<(DiagnosticSeverity, String, u32, u32, u32)> {
+        all_diagnostics(&[("Note", "# Intro\n\ntext ^abc\n")], text)
+            .into_iter()
+            .map(
+                |Diagnostic {
+                     severity,
+                     message,
+                     range: Range { start, end },
+                     ..
+                 }| {
+                    assert_eq!(start.line, end.line);
+                    (
+                        severity.unwrap(),
+                        message,
+                        start.line,
+                        start.character,
+                        end.character,
+                    )
+                },
+            )
+            .collect()
+    }
+
+    #[test]
+    fn missing_heading_in_existing_note() {
+        assert_eq!(
+            fragment_diagnostics("[[Note#Missing Heading]] [[Note#intro]]"),
+            vec![(
+                DiagnosticSeverity::WARNING,
+                "Heading not found in Note".to_string(),
+                0,
+                6,
+                22
+            )]
+        );
+    }
+
+    #[test]
+    fn missing_block_in_existing_note() {
+        assert_eq!(
+            fragment_diagnostics("see [x](Note.md#^nope) [[Note#^abc]]"),
+            vec![(
+                DiagnosticSeverity::WARNING,
+                "Block not found in Note".to_string(),
+                0,
+                15,
+                21
+            )]
+        );
+    }
+
+    #[test]
+    fn missing_heading_is_found_in_the_target_not_the_display_text() {
+        assert_eq!(
+            fragment_diagnostics("[#Heading](Note#Heading)"),
+            vec![(
+                DiagnosticSeverity::WARNING,
+                "Heading not found in Note".to_string(),
+                0,
+                15,
+                23
+            )]
+        );
+    }
+
+    #[test]
+    fn missing_note_is_unresolved() {
+        let position = |character| Position { line: 0, character };
+
+        assert_eq!(
+            all_diagnostics(&[("Note", "# Intro\n")], "[[Missing#Intro]]")
+                .into_iter()
+                .map(|diagnostic| (diagnostic.severity.unwrap(), diagnostic.range))
+                .collect::<Vec<_>>(),
+            vec![(
+                DiagnosticSeverity::INFORMATION,
+                Range {
+                    start: position(0),
+                    end: position(17)
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn dangling_references_group_unresolved_links_by_target() {
+        let root_dir = Path::new("/vault");
+        let settings = Settings::new(root_dir, &ClientCapabilities::default()).unwrap();
+        let mut vault = Vault::construct_vault(&settings, root_dir).unwrap();
+        for (name, text) in [
+            ("Note", "# Intro\n[[Missing]] [[Note#Intro]] [[Note#Gone]]"),
+            ("a", "[[Missing]]\n[x](Other.md) [[Note]]"),
+            ("b", "---\naliases: [Alias]\n---\n[[missing]] [[Alias]]"),
+        ] {
+            Vault::update_vault(
+                &settings,
+                &mut vault,
+                (&PathBuf::from(format!("/vault/{}.md", name)), text),
+            );
+        }
+
+        let dangling = super::dangling_references(&vault)
+            .into_iter()
+            .map(|target| {
+                (
+                    target.target,
+                    target
+                        .locations
+                        .into_iter()
+                        .map(|location| {
+                            let path = location.uri.to_file_path().unwrap();
+                            (
+                                path.file_stem().unwrap().to_string_lossy().to_string(),
+                                location.range.start.line,
+                                location.range.start.character,
+                            )
+                        })
+                        .collect::<Vec<_>>(),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let location = |file: &str, line, character| (file.to_string(), line, character);
+        assert_eq!(
+            dangling,
+            vec![
+                (
+                    "Missing".to_string(),
+                    vec![
+                        location("Note", 1, 0),
+                        location("a", 0, 0),
+                        location("b", 3, 0)
+                    ]
+                ),
+                ("Note#Gone".to_string(), vec![location("Note", 1, 27)]),
+                ("Other".to_string(), vec![location("a", 1, 0)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn duplicate_block_ids_point_at_each_other() {
+        let diagnostics = all_diagnostics(
+            &[],
+            "first ^abc\nsecond ^abc\nthird ^other\n```\ncode ^abc\n```\n",
+        );
+
+        let duplicates = diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.severity == Some(DiagnosticSeverity::WARNING))
+            .map(|diagnostic| {
+                (
+                    diagnostic.message.clone(),
+                    diagnostic.range.start.line,
+                    diagnostic
+                        .related_information
+                        .iter()
+                        .flatten()
+                        .map(|related| related.location.range.start.line)
+                        .collect::<Vec<_>>(),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            duplicates,
+            vec![
+                ("Block id ^abc is defined 2 times".to_string(), 0, vec![1]),
+                ("Block id ^abc is defined 2 times".to_string(), 1, vec![0]),
+            ]
+        );
+    }
+
+    #[test]
+    fn unlinked_block_ids_are_hinted() {
+        let diagnostics = all_diagnostics(
+            &[("other", "[[source#^linked]] [[elsewhere#^loose]]")],
+            "first ^linked\nsecond ^loose\nthird ^alone\n",
+        );
+
+        let unused = diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.severity == Some(DiagnosticSeverity::HINT))
+            .map(|diagnostic| (diagnostic.message.clone(), diagnostic.range.start.line))
+            .collect::<Vec<_>>();
+
+        // `^loose` is named by a link, but the link points to another note
+        assert_eq!(
+            unused,
+            vec![
+                ("Block id ^loose is not linked".to_string(), 1),
+                ("Block id ^alone is not linked".to_string(), 2),
+            ]
+        );
+    }
+}
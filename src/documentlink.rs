@@ -0,0 +1,236 @@
+use std::path::Path;
+
+use itertools::Itertools;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use tower_lsp::lsp_types::{DocumentLink, Position, Range, Url};
+
+use crate::vault::{get_obsidian_ref_path, Reference, Referenceable, Vault};
+
+/// Clickable links for every resolved link in the file; headings and blocks are targeted with a `#L<line>` fragment
+pub fn document_links(vault: &Vault, path: &Path) -> Option<Vec<DocumentLink>> {
+    let references = vault.select_references(Some(path))?;
+
+    let note_links = references
+        .into_iter()
+        .filter(|(_, reference)| {
+            matches!(
+                reference,
+                Reference::WikiFileLink(..)
+                    | Reference::WikiHeadingLink(..)
+                    | Reference::WikiIndexedBlockLink(..)
+                    | Reference::MDFileLink(..)
+                    | Reference::MDHeadingLink(..)
+                    | Reference::MDIndexedBlockLink(..)
+            )
+        })
+        .flat_map(|(_, reference)| {
+            let referenceable = vault
+                .select_referenceables_for_reference(reference, path)
+                .into_iter()
+                .find(|referenceable| {
+                    matches!(
+                        referenceable,
+                        Referenceable::File(..)
+                            | Referenceable::Heading(..)
+                            | Referenceable::IndexedBlock(..)
+                    )
+                })?;
+
+            let mut target = Url::from_file_path(referenceable.get_path()).ok()?;
+            match referenceable {
+                Referenceable::File(..) => (),
+                _ => target.set_fragment(Some(&format!(
+                    "L{}",
+                    referenceable.get_range()?.start.line + 1
+                ))),
+            }
+
+            Some(DocumentLink {
+                range: *reference.data().range,
+                target: Some(target),
+                tooltip: get_obsidian_ref_path(vault.root_dir(), referenceable.get_path()),
+                data: None,
+            })
+        });
+
+    let attachment_links = attachment_embeds(vault, path);
+
+    Some(note_links.chain(attachment_links).collect())
+}
+
+/// Embeds of images and other attachments, like `![[image.png|200]]`; these are not references to notes
+fn attachment_embeds(vault: &Vault, path: &Path) -> Vec<DocumentLink> {
+    static EMBED_RE: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"!\[\[(?<name>[^\[\]\|\#]+)(\|[^\[\]]*)?\]\]").unwrap());
+
+    let Some(rope) = vault.ropes.get(path) else {
+        return vec![];
+    };
+
+    rope.lines()
+        .enumerate()
+        .flat_map(|(line_nr, line)| {
+            let line = line.to_string();
+
+            EMBED_RE
+                .captures_iter(&line)
+                .flat_map(|captures| {
+                    let (full, name) = (captures.get(0)?, captures.name("name")?);
+                    let attachment = vault.select_attachment(name.as_str())?;
+                    let column = |byte: usize| line[..byte].chars().count() as u32;
+
+                    Some(DocumentLink {
+                        range: Range {
+                            start: Position {
+                                line: line_nr as u32,
+                                character: column(full.start()),
+                            },
+                            end: Position {
+                                line: line_nr as u32,
+                                character: column(full.end()),
+                            },
+                        },
+                        target: Some(Url::from_file_path(attachment).ok()?),
+                        tooltip: None,
+                        data: None,
+                    })
+                })
+                .collect_vec()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use itertools::Itertools;
+    use tower_lsp::lsp_types::ClientCapabilities;
+
+    use crate::{config::Settings, vault::Vault};
+
+    use super::document_links;
+
+    #[test]
+    fn resolved_links_target_files_and_lines() {
+        let root_dir = Path::new("/vault");
+        let settings = Settings::new(root_dir, &ClientCapabilities::default()).unwrap();
+        let mut vault = Vault::construct_vault(&settings, root_dir).unwrap();
+        Vault::update_vault(
+            &settings,
+            &mut vault,
+            (
+                &PathBuf::from("/vault/folder/target.md"),
+                "intro\n## Heading\ntext ^block\n",
+            ),
+        );
+        let path = PathBuf::from("/vault/note.md");
+        Vault::update_vault(
+            &settings,
+            &mut vault,
+            (
+                &path,
+                "[[target]] [x](folder/target#heading)\n![[target#^block]] [[missing]] [[target#Nope]]",
+            ),
+        );
+
+        let links = document_links(&vault, &path)
+            .unwrap()
+            .into_iter()
+            .map(|link| {
+                (
+                    link.range.start.line,
+                    link.range.start.character,
+                    link.target.unwrap().to_string(),
+                )
+            })
+            .sorted()
+            .collect_vec();
+
+        assert_eq!(
+            links,
+            vec![
+                (0, 0, "file:///vault/folder/target.md".to_string()),
+                (0, 11, "file:///vault/folder/target.md#L2".to_string()),
+                (1, 1, "file:///vault/folder/target.md#L3".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn every_link_on_a_mixed_line_gets_its_range_and_target() {
+        let root_dir = Path::new("/vault");
+        let settings = Settings::new(root_dir, &ClientCapabilities::default()).unwrap();
+        let mut vault = Vault::construct_vault(&settings, root_dir).unwrap();
+        Vault::update_vault(
+            &settings,
+            &mut vault,
+            (
+                &PathBuf::from("/vault/folder/target.md"),
+                "## Heading\ntext ^block\n",
+            ),
+        );
+        let path = PathBuf::from("/vault/folder/note.md");
+        Vault::update_vault(
+            &settings,
+            &mut vault,
+            (
+                &path,
+                "# Here\n[[target|t]] [x](folder/target.md#heading) [[#Here]] [[target#^block]] [[missing]] #tag",
+            ),
+        );
+
+        let links = document_links(&vault, &path)
+            .unwrap()
+            .into_iter()
+            .map(|link| (link.range, link.target.map(|target| target.to_string())))
+            .sorted_by_key(|(range, _)| range.start.character)
+            .map(|(range, target)| {
+                (
+                    range.start.line,
+                    range.start.character,
+                    range.end.character,
+                    target.unwrap(),
+                )
+            })
+            .collect_vec();
+
+        assert_eq!(
+            links,
+            vec![
+                (1, 0, 12, "file:///vault/folder/target.md".to_string()),
+                (1, 13, 42, "file:///vault/folder/target.md#L1".to_string()),
+                (1, 43, 52, "file:///vault/folder/note.md#L1".to_string()),
+                (1, 53, 70, "file:///vault/folder/target.md#L2".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn attachment_embeds_target_the_attachment() {
+        let root_dir =
+            std::env::temp_dir().join(format!("moxide-documentlink-{}", std::process::id()));
+        std::fs::create_dir_all(root_dir.join("assets")).unwrap();
+        std::fs::write(root_dir.join("assets/diagram.png"), "").unwrap();
+        std::fs::write(
+            root_dir.join("note.md"),
+            "![[diagram.png|200]] ![[nothing.png]]",
+        )
+        .unwrap();
+
+        let settings = Settings::new(&root_dir, &ClientCapabilities::default()).unwrap();
+        let vault = Vault::construct_vault(&settings, &root_dir).unwrap();
+        let links = document_links(&vault, &root_dir.join("note.md")).unwrap();
+        std::fs::remove_dir_all(&root_dir).unwrap();
+
+        assert_eq!(links.len(), 1);
+        assert_eq!(links[0].range.end.character, 20);
+        assert!(links[0]
+            .target
+            .as_ref()
+            .unwrap()
+            .path()
+            .ends_with("assets/diagram.png"));
+    }
+}
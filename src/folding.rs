@@ -0,0 +1,166 @@
+use std::path::Path;
+
+use itertools::Itertools;
+use tower_lsp::lsp_types::{FoldingRange, FoldingRangeKind};
+
+use crate::vault::{Rangeable, Vault};
+
+/// Folds for the frontmatter, each heading's section, and fenced code blocks
+pub fn folding_ranges(vault: &Vault, path: &Path) -> Option<Vec<FoldingRange>> {
+    let end = vault.select_end_position(path)?;
+    // a trailing newline leaves an empty last line that sections should not fold over
+    let last_line = match end.character {
+        0 => end.line.saturating_sub(1),
+        _ => end.line,
+    };
+
+    let frontmatter = vault
+        .select_frontmatter_end_line(path)
+        .map(|end_line| fold(0, end_line as u32, FoldingRangeKind::Region));
+
+    // A section folds to the line before the next heading of the same or a higher level, or to the end of the file
+    let headings = vault.select_headings(path)?;
+    let sections = headings.iter().enumerate().map(|(i, heading)| {
+        let section_end = headings[i + 1..]
+            .iter()
+            .find(|next| next.level <= heading.level)
+            .map(|next| next.range.start.line.saturating_sub(1))
+            .unwrap_or(last_line);
+
+        fold(
+            heading.range.start.line,
+            section_end,
+            FoldingRangeKind::Region,
+        )
+    });
+
+    let codeblocks = vault
+        .md_files
+        .get(path)?
+        .codeblocks
+        .iter()
+        .map(|codeblock| {
+            fold(
+                codeblock.range().start.line,
+                codeblock.range().end.line,
+                FoldingRangeKind::Region,
+            )
+        });
+
+    Some(
+        frontmatter
+            .into_iter()
+            .chain(sections)
+            .chain(codeblocks)
+            .filter(|range| range.end_line > range.start_line)
+            .sorted_by_key(|range| (range.start_line, std::cmp::Reverse(range.end_line)))
+            .collect(),
+    )
+}
+
+fn fold(start_line: u32, end_line: u32, kind: FoldingRangeKind) -> FoldingRange {
+    FoldingRange {
+        start_line,
+        start_character: None,
+        end_line,
+        end_character: None,
+        kind: Some(kind),
+        collapsed_text: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use itertools::Itertools;
+    use tower_lsp::lsp_types::{
+        ClientCapabilities, DocumentSymbol, DocumentSymbolParams, DocumentSymbolResponse,
+        TextDocumentIdentifier, Url,
+    };
+
+    use crate::{config::Settings, symbol::document_symbol, vault::Vault};
+
+    use super::folding_ranges;
+
+    fn vault_with(text: &str) -> (Settings, Vault, PathBuf) {
+        let root_dir = Path::new("/vault");
+        let settings = Settings::new(root_dir, &ClientCapabilities::default()).unwrap();
+        let mut vault = Vault::construct_vault(&settings, root_dir).unwrap();
+        let path = PathBuf::from("/vault/note.md");
+        Vault::update_vault(&settings, &mut vault, (&path, text));
+
+        (settings, vault, path)
+    }
+
+    fn folds(text: &str) -> Vec<(u32, u32)> {
+        let (_, vault, path) = vault_with(text);
+
+        folding_ranges(&vault, &path)
+            .unwrap()
+            .into_iter()
+            .map(|range| (range.start_line, range.end_line))
+            .collect_vec()
+    }
+
+    #[test]
+    fn nested_sections_fold_to_the_next_heading() {
+        let text = "# One\ntext\n## Nested\nmore\n# Two\nlast\nlines\n";
+
+        assert_eq!(folds(text), vec![(0, 3), (2, 3), (4, 6)]);
+    }
+
+    #[test]
+    fn frontmatter_and_code_blocks_fold() {
+        let text = "---\ntags: [a]\n---\n# Code\n```rust\nlet x = 1;\n```\nafter";
+
+        assert_eq!(folds(text), vec![(0, 2), (3, 7), (4, 6)]);
+    }
+
+    #[test]
+    fn single_line_sections_do_not_fold() {
+        assert_eq!(folds("# One\n# Two\n"), vec![]);
+    }
+
+    #[test]
+    fn nested_folds_match_the_symbol_hierarchy() {
+        let text = "# A\n## B\n### C\n```\n# not a heading\n```\n## D\ntext\n# E\nend\n";
+        let (settings, vault, path) = vault_with(text);
+
+        // a symbol's range ends where the next section starts, a fold on the line before it
+        fn section_lines(symbols: &[DocumentSymbol]) -> Vec<(u32, u32)> {
+            symbols
+                .iter()
+                .flat_map(|symbol| {
+                    let end_line = match symbol.range.end.character {
+                        0 => symbol.range.end.line - 1,
+                        _ => symbol.range.end.line,
+                    };
+                    std::iter::once((symbol.range.start.line, end_line)).chain(section_lines(
+                        symbol.children.as_deref().unwrap_or_default(),
+                    ))
+                })
+                .collect()
+        }
+
+        let params = DocumentSymbolParams {
+            text_document: TextDocumentIdentifier {
+                uri: Url::from_file_path(&path).unwrap(),
+            },
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        let Some(DocumentSymbolResponse::Nested(symbols)) =
+            document_symbol(&vault, &params, &path, &settings)
+        else {
+            panic!("expected nested symbols");
+        };
+
+        let sections = section_lines(&symbols);
+        assert_eq!(sections, vec![(0, 7), (1, 5), (2, 5), (6, 7), (8, 9)]);
+        assert_eq!(
+            folds(text),
+            vec![(0, 7), (1, 5), (2, 5), (3, 5), (6, 7), (8, 9)]
+        );
+    }
+}
@@ -0,0 +1,271 @@
+use std::path::Path;
+
+use ropey::Rope;
+use tower_lsp::lsp_types::{Position, Range, TextEdit};
+
+use crate::{
+    codeactions::{markdown_to_wiki, wiki_to_markdown},
+    config::{LinkStyle, Settings},
+    vault::{unescape_wiki_link_text, Rangeable, Reference, Vault},
+};
+
+/// Edits that bring every link of the document to `format_link_style` and tidy wiki links; links in code blocks are
+/// left as they are, and so are links that are already formatted, so formatting twice gives no edits
+pub fn formatting(vault: &Vault, path: &Path, settings: &Settings) -> Option<Vec<TextEdit>> {
+    let mdfile = vault.md_files.get(path)?;
+    let rope = vault.ropes.get(path)?;
+    let encode = |position: Position| Position {
+        character: settings.position_encoding.column(
+            &vault
+                .select_line(path, position.line as isize)
+                .unwrap_or_default(),
+            position.character as usize,
+        ),
+        ..position
+    };
+
+    Some(
+        mdfile
+            .references
+            .iter()
+            .filter(|reference| {
+                !mdfile
+                    .codeblocks
+                    .iter()
+                    .any(|codeblock| codeblock.includes(*reference))
+            })
+            .filter_map(|reference| {
+                let range = *reference.data().range;
+                let text = range_text(rope, range)?;
+                let new_text = formatted_link(vault, path, settings, reference, &text)?;
+
+                (new_text != text).then_some(TextEdit {
+                    range: Range {
+                        start: encode(range.start),
+                        end: encode(range.end),
+                    },
+                    new_text,
+                })
+            })
+            .collect(),
+    )
+}
+
+/// The text of `range`, counted in chars
+pub fn range_text(rope: &Rope, range: Range) -> Option<String> {
+    let offset = |position: Position| {
+        Some(rope.try_line_to_char(position.line as usize).ok()? + position.character as usize)
+    };
+
+    Some(
+        rope.get_slice(offset(range.start)?..offset(range.end)?)?
+            .to_string(),
+    )
+}
+
+fn formatted_link(
+    vault: &Vault,
+    path: &Path,
+    settings: &Settings,
+    reference: &Reference,
+    text: &str,
+) -> Option<String> {
+    // canvases are linked with their extension, which the conversions would replace by .md
+    if text.contains(".canvas") {
+        return None;
+    }
+
+    match (reference, settings.format_link_style) {
+        (
+            Reference::WikiFileLink(..)
+            | Reference::WikiHeadingLink(..)
+            | Reference::WikiIndexedBlockLink(..),
+            LinkStyle::Markdown,
+        ) => wiki_to_markdown(vault, path, reference),
+        (
+            Reference::WikiFileLink(..)
+            | Reference::WikiHeadingLink(..)
+            | Reference::WikiIndexedBlockLink(..),
+            _,
+        ) => Some(tidy_wiki_link(text, settings)),
+        (
+            Reference::MDFileLink(..)
+            | Reference::MDHeadingLink(..)
+            | Reference::MDIndexedBlockLink(..),
+            LinkStyle::Wiki,
+        ) => Some(tidy_wiki_link(
+            &markdown_to_wiki(vault, path, reference)?,
+            settings,
+        )),
+        _ => None,
+    }
+}
+
+/// `[[Note.md|Note]]` as `[[Note]]`: the extension goes unless `include_md_extension_wikilink` is set, and so does
+/// display text that repeats the target when `format_redundant_display_text` is set
+fn tidy_wiki_link(text: &str, settings: &Settings) -> String {
+    let Some(inner) = text
+        .strip_prefix("[[")
+        .and_then(|inner| inner.strip_suffix("]]"))
+    else {
+        return text.to_string();
+    };
+
+    // the target can not have a `|`, so the first one starts the display text; in tables it is escaped, `\|`
+    let (target, separator, display) = match inner.split_once('|') {
+        Some((target, display)) => match target.strip_suffix('\\') {
+            Some(target) => (target, r"\|", Some(display)),
+            None => (target, "|", Some(display)),
+        },
+        None => (inner, "|", None),
+    };
+    let target = match settings.include_md_extension_wikilink {
+        true => target,
+        false => target.strip_suffix(".md").unwrap_or(target),
+    };
+
+    match display {
+        Some(display)
+            if !(settings.format_redundant_display_text
+                && unescape_wiki_link_text(display) == target) =>
+        {
+            format!("[[{target}{separator}{display}]]")
+        }
+        _ => format!("[[{target}]]"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use itertools::Itertools;
+    use ropey::Rope;
+    use tower_lsp::lsp_types::ClientCapabilities;
+
+    use crate::{
+        config::{LinkStyle, PositionEncoding, Settings},
+        vault::Vault,
+    };
+
+    use super::formatting;
+
+    fn settings(style: LinkStyle) -> Settings {
+        let mut settings =
+            Settings::new(Path::new("/vault"), &ClientCapabilities::default()).unwrap();
+        settings.format_link_style = style;
+        settings
+    }
+
+    /// The text after formatting it once, checking that formatting it again changes nothing
+    fn formatted(text: &str, settings: &Settings) -> String {
+        let root_dir = Path::new("/vault");
+        let mut vault = Vault::construct_vault(settings, root_dir).unwrap();
+        let note = PathBuf::from("/vault/Note.md");
+        Vault::update_vault(
+            settings,
+            &mut vault,
+            (&note, "# Some Heading\n\ntext ^abc\n"),
+        );
+        let path = PathBuf::from("/vault/source.md");
+        Vault::update_vault(settings, &mut vault, (&path, text));
+
+        let mut rope = Rope::from_str(text);
+        let edits = formatting(&vault, &path, settings).unwrap();
+        for edit in edits
+            .iter()
+            .sorted_by_key(|edit| (edit.range.start.line, edit.range.start.character))
+            .rev()
+        {
+            let offset = |position: tower_lsp::lsp_types::Position| {
+                let line = rope.line(position.line as usize).chars().collect_vec();
+                rope.line_to_char(position.line as usize)
+                    + settings
+                        .position_encoding
+                        .char_index(&line, position.character)
+            };
+            let (start, end) = (offset(edit.range.start), offset(edit.range.end));
+            rope.remove(start..end);
+            rope.insert(start, &edit.new_text);
+        }
+
+        let formatted = rope.to_string();
+        Vault::update_vault(settings, &mut vault, (&path, &formatted));
+        assert_eq!(
+            formatting(&vault, &path, settings).unwrap(),
+            vec![],
+            "formatting {formatted:?} again"
+        );
+
+        formatted
+    }
+
+    #[test]
+    fn wiki_links_lose_extensions_and_redundant_display_text() {
+        let settings = settings(LinkStyle::Keep);
+
+        assert_eq!(
+            formatted(
+                "[[Note.md]] [[Note|Note]] [[Note|Other]] [[Note#Some Heading|Note#Some Heading]] [md](Note.md)",
+                &settings
+            ),
+            "[[Note]] [[Note]] [[Note|Other]] [[Note#Some Heading]] [md](Note.md)"
+        );
+    }
+
+    #[test]
+    fn redundant_display_text_can_be_kept() {
+        let mut settings = settings(LinkStyle::Keep);
+        settings.format_redundant_display_text = false;
+        settings.include_md_extension_wikilink = true;
+
+        let text = "[[Note.md|Note]] [[Note]]";
+        assert_eq!(formatted(text, &settings), text);
+    }
+
+    #[test]
+    fn links_are_rewritten_to_the_chosen_style() {
+        let text = "[[Note|Display]] ![[Note#Some Heading]] [block](Note.md#^abc) [Note](Note.md)";
+
+        assert_eq!(
+            formatted(text, &settings(LinkStyle::Markdown)),
+            "[Display](Note.md) ![Note#Some Heading](Note.md#some-heading) [block](Note.md#^abc) [Note](Note.md)"
+        );
+        assert_eq!(
+            formatted(text, &settings(LinkStyle::Wiki)),
+            "[[Note|Display]] ![[Note#Some Heading]] [[Note#^abc|block]] [[Note]]"
+        );
+    }
+
+    #[test]
+    fn escaped_pipes_in_tables_are_kept() {
+        assert_eq!(
+            formatted(
+                "| [[Note.md\\|Other]] | [[Note\\|Note]] |",
+                &settings(LinkStyle::Keep)
+            ),
+            "| [[Note\\|Other]] | [[Note]] |"
+        );
+    }
+
+    #[test]
+    fn links_in_code_blocks_are_left_alone() {
+        let text = "```\n[[Note.md|Note]] [a](Note.md)\n```\n`[[Note|Note]]` [[Note|Note]]\n";
+
+        assert_eq!(
+            formatted(text, &settings(LinkStyle::Wiki)),
+            "```\n[[Note.md|Note]] [a](Note.md)\n```\n`[[Note|Note]]` [[Note]]\n"
+        );
+    }
+
+    #[test]
+    fn edit_columns_follow_the_position_encoding() {
+        let text = "😀 [[Note|Note]] 😀 [[Note.md]]";
+
+        for encoding in [PositionEncoding::Utf16, PositionEncoding::Utf32] {
+            let mut settings = settings(LinkStyle::Keep);
+            settings.position_encoding = encoding;
+            assert_eq!(formatted(text, &settings), "😀 [[Note]] 😀 [[Note]]");
+        }
+    }
+}
@@ -1,44 +1,300 @@
 use std::path::Path;
 
+use itertools::Itertools;
 use tower_lsp::lsp_types::{Location, Position, Url};
 
-use crate::vault::{Referenceable, Vault};
+use crate::{
+    config::Settings,
+    vault::{Reference, Referenceable, Vault},
+};
 
 pub fn goto_definition(
     vault: &Vault,
     cursor_position: Position,
     path: &Path,
+    settings: &Settings,
 ) -> Option<Vec<Location>> {
     // First, find the link that the cursor is in. Get a links for the file and match the cursor position up to one of them
     let reference = vault.select_reference_at_position(path, cursor_position)?;
+
+    // A tag is defined nowhere in particular, so every place it is used is its definition
+    if let Reference::Tag(data) = reference {
+        return tag_locations(
+            vault,
+            data.reference_text.trim_start_matches('#'),
+            settings.tag_definition_children,
+        );
+    }
     // Now we have the reference text. We need to find where this is actually referencing, or if it is referencing anything.
     // Lets get all of the referenceable nodes
 
     let referenceables = vault.select_referenceables_for_reference(reference, path);
 
-    Some(
-        referenceables
-            .into_iter()
-            .filter_map(|linkable| {
-                let range = match linkable {
-                    Referenceable::File(..) => tower_lsp::lsp_types::Range {
-                        start: Position {
-                            line: 0,
-                            character: 0,
-                        },
-                        end: Position {
-                            line: 0,
-                            character: 1,
-                        },
+    definition_locations(referenceables)
+}
+
+/// Where the link at the cursor goes in the notes of the `others` vaults, for a link that resolves in none of the notes
+/// of its own vault; tags are in every vault, so they are left out
+pub fn goto_definition_in_other_vaults<'a>(
+    vault: &Vault,
+    others: impl IntoIterator<Item = &'a Vault>,
+    cursor_position: Position,
+    path: &Path,
+) -> Option<Vec<Location>> {
+    let reference = vault.select_reference_at_position(path, cursor_position)?;
+    if let Reference::Tag(..) = reference {
+        return None;
+    }
+
+    let referenceables = others
+        .into_iter()
+        .flat_map(|other| other.select_referenceables_for_reference(reference, path))
+        .collect_vec();
+
+    definition_locations(referenceables)
+}
+
+fn definition_locations(referenceables: Vec<Referenceable>) -> Option<Vec<Location>> {
+    let locations: Vec<Location> = referenceables
+        .into_iter()
+        .filter_map(|linkable| {
+            let range = match linkable {
+                Referenceable::File(..) => tower_lsp::lsp_types::Range {
+                    start: Position {
+                        line: 0,
+                        character: 0,
+                    },
+                    end: Position {
+                        line: 0,
+                        character: 1,
                     },
-                    _ => *linkable.get_range()?,
-                };
+                },
+                _ => *linkable.get_range()?,
+            };
 
-                Some(Location {
-                    uri: Url::from_file_path(linkable.get_path().to_str()?).unwrap(),
-                    range,
-                })
+            Some(Location {
+                uri: Url::from_file_path(linkable.get_path().to_str()?).unwrap(),
+                range,
             })
-            .collect(),
-    )
+        })
+        .collect();
+
+    // Unresolved references, like a block id that does not exist yet, have no definition
+    (!locations.is_empty()).then_some(locations)
+}
+
+/// Every inline and frontmatter use of `tag`, and of the tags nested in it when `children` is set, ordered by file
+fn tag_locations(vault: &Vault, tag: &str, children: bool) -> Option<Vec<Location>> {
+    let nested = format!("{tag}/");
+
+    let locations = vault
+        .md_files
+        .iter()
+        .sorted_by_key(|(path, _)| *path)
+        .flat_map(|(path, mdfile)| {
+            mdfile
+                .tags
+                .iter()
+                .filter(|it| it.tag_ref == tag || children && it.tag_ref.starts_with(&nested))
+                .sorted_by_key(|it| (it.range.start.line, it.range.start.character))
+                .filter_map(move |it| {
+                    Some(Location {
+                        uri: Url::from_file_path(path).ok()?,
+                        range: *it.range,
+                    })
+                })
+        })
+        .collect_vec();
+
+    (!locations.is_empty()).then_some(locations)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use tower_lsp::lsp_types::{ClientCapabilities, Position, Url};
+
+    use crate::{config::Settings, vault::Vault};
+
+    fn definition_at(links: &str, character: u32) -> Option<Vec<(Url, u32)>> {
+        let root_dir = Path::new("/vault");
+        let settings = Settings::new(root_dir, &ClientCapabilities::default()).unwrap();
+        let mut vault = Vault::construct_vault(&settings, root_dir).unwrap();
+
+        let path = PathBuf::from("/vault/source.md");
+        Vault::update_vault(
+            &settings,
+            &mut vault,
+            (
+                &PathBuf::from("/vault/Note.md"),
+                "# Note\n\nfirst line\nthe block ^abc\n",
+            ),
+        );
+        Vault::update_vault(
+            &settings,
+            &mut vault,
+            (&PathBuf::from("/vault/Other.md"), "another block ^abc\n"),
+        );
+        Vault::update_vault(&settings, &mut vault, (&path, links));
+
+        super::goto_definition(&vault, Position { line: 0, character }, &path, &settings).map(
+            |locations| {
+                locations
+                    .into_iter()
+                    .map(|location| (location.uri, location.range.start.line))
+                    .collect()
+            },
+        )
+    }
+
+    #[test]
+    fn links_missing_from_their_vault_resolve_in_the_other_vaults() {
+        let settings = Settings::new(Path::new("/work"), &ClientCapabilities::default()).unwrap();
+        let mut work = Vault::construct_vault(&settings, Path::new("/work")).unwrap();
+        let mut home = Vault::construct_vault(&settings, Path::new("/home")).unwrap();
+        let path = PathBuf::from("/work/source.md");
+        Vault::update_vault(&settings, &mut work, (&path, "[[Recipes]] [[Nowhere]]"));
+        Vault::update_vault(
+            &settings,
+            &mut home,
+            (&PathBuf::from("/home/Recipes.md"), "# Recipes\n"),
+        );
+
+        let definition = |character| {
+            super::goto_definition(&work, Position { line: 0, character }, &path, &settings)
+                .or_else(|| {
+                    super::goto_definition_in_other_vaults(
+                        &work,
+                        [&home],
+                        Position { line: 0, character },
+                        &path,
+                    )
+                })
+                .map(|locations| locations.into_iter().map(|location| location.uri).collect())
+        };
+
+        assert_eq!(
+            definition(3),
+            Some(vec![Url::from_file_path("/home/Recipes.md").unwrap()])
+        );
+        assert_eq!(definition(15), None);
+    }
+
+    #[test]
+    fn block_reference_definition() {
+        assert_eq!(
+            definition_at("[[Note#^abc]]", 4),
+            Some(vec![(Url::from_file_path("/vault/Note.md").unwrap(), 3)])
+        );
+        assert_eq!(
+            definition_at("[x](Other#^abc)", 6),
+            Some(vec![(Url::from_file_path("/vault/Other.md").unwrap(), 0)])
+        );
+    }
+
+    #[test]
+    fn ambiguous_name_goes_to_every_note_with_it() {
+        let root_dir = Path::new("/vault");
+        let settings = Settings::new(root_dir, &ClientCapabilities::default()).unwrap();
+        let mut vault = Vault::construct_vault(&settings, root_dir).unwrap();
+        for (path, text) in [
+            ("/vault/a/dup.md", ""),
+            ("/vault/b/dup.md", ""),
+            ("/vault/source.md", "[[dup]] [[a/dup]]"),
+        ] {
+            Vault::update_vault(&settings, &mut vault, (&PathBuf::from(path), text));
+        }
+
+        let definitions = |character| {
+            let mut paths = super::goto_definition(
+                &vault,
+                Position { line: 0, character },
+                Path::new("/vault/source.md"),
+                &settings,
+            )
+            .unwrap()
+            .into_iter()
+            .map(|location| location.uri.path().to_string())
+            .collect::<Vec<_>>();
+            paths.sort();
+            paths
+        };
+
+        assert_eq!(definitions(3), vec!["/vault/a/dup.md", "/vault/b/dup.md"]);
+        assert_eq!(definitions(11), vec!["/vault/a/dup.md"]);
+    }
+
+    #[test]
+    fn missing_block_has_no_definition() {
+        assert_eq!(definition_at("[[Note#^missing]]", 4), None);
+    }
+
+    fn tag_definitions_at(character: u32, children: bool) -> Option<Vec<(String, u32, u32)>> {
+        let root_dir = Path::new("/vault");
+        let mut settings = Settings::new(root_dir, &ClientCapabilities::default()).unwrap();
+        settings.tag_definition_children = children;
+        let mut vault = Vault::construct_vault(&settings, root_dir).unwrap();
+
+        let files = [
+            ("/vault/a.md", "#project and #project/work\n#projects\n"),
+            ("/vault/b.md", "---\ntags: [project]\n---\nmore #project\n"),
+            ("/vault/c.md", "#other #project/work/deep"),
+        ];
+        for (path, text) in files {
+            Vault::update_vault(&settings, &mut vault, (&PathBuf::from(path), text));
+        }
+
+        super::goto_definition(
+            &vault,
+            Position { line: 0, character },
+            Path::new("/vault/a.md"),
+            &settings,
+        )
+        .map(|locations| {
+            locations
+                .into_iter()
+                .map(|location| {
+                    (
+                        location.uri.path().to_string(),
+                        location.range.start.line,
+                        location.range.start.character,
+                    )
+                })
+                .collect()
+        })
+    }
+
+    #[test]
+    fn tag_definition_lists_every_use_of_the_tag() {
+        let expected = vec![
+            ("/vault/a.md".to_string(), 0, 0),
+            ("/vault/b.md".to_string(), 1, 7),
+            ("/vault/b.md".to_string(), 3, 5),
+        ];
+
+        assert_eq!(tag_definitions_at(3, false), Some(expected));
+    }
+
+    #[test]
+    fn tag_definition_can_include_nested_tags() {
+        assert_eq!(
+            tag_definitions_at(3, true),
+            Some(vec![
+                ("/vault/a.md".to_string(), 0, 0),
+                ("/vault/a.md".to_string(), 0, 13),
+                ("/vault/b.md".to_string(), 1, 7),
+                ("/vault/b.md".to_string(), 3, 5),
+                ("/vault/c.md".to_string(), 0, 7),
+            ])
+        );
+        // from the nested tag, only it and its own children
+        assert_eq!(
+            tag_definitions_at(16, true),
+            Some(vec![
+                ("/vault/a.md".to_string(), 0, 13),
+                ("/vault/c.md".to_string(), 0, 7),
+            ])
+        );
+    }
 }
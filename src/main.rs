@@ -1,9 +1,10 @@
 use std::collections::HashSet;
 use std::ops::{Deref, DerefMut};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 
-use completion::get_completions;
+use completion::{get_completions, CompletionCache, Debouncer, Recency};
 use config::{EmbeddedBlockTransclusionLength, Settings};
 use diagnostics::diagnostics;
 use itertools::Itertools;
@@ -11,14 +12,15 @@ use rayon::prelude::*;
 use references::references;
 use serde_json::Value;
 use symbol::{document_symbol, workspace_symbol};
+use tokens::TokenCache;
 use tokio::sync::RwLock;
 
-use gotodef::goto_definition;
+use gotodef::{goto_definition, goto_definition_in_other_vaults};
 use tower_lsp::jsonrpc::{Error, ErrorCode, Result};
 
 use tower_lsp::lsp_types::*;
 use tower_lsp::{Client, LanguageServer, LspService, Server};
-use vault::{Preview, Rangeable, Reference, Vault};
+use vault::{Preview, Rangeable, Reference, Vault, Vaults};
 
 mod codeactions;
 mod codelens;
@@ -27,27 +29,38 @@ mod completion;
 mod config;
 mod daily;
 mod diagnostics;
+mod documentlink;
+mod folding;
+mod formatting;
 mod gotodef;
 mod hover;
 mod macros;
 mod references;
 mod rename;
+mod selection;
 mod symbol;
 mod tokens;
 mod ui;
 mod vault;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct Backend {
     client: Client,
-    vault: Arc<RwLock<Option<Vault>>>,
+    /// A vault for each workspace folder
+    vaults: Arc<RwLock<Vaults>>,
+    /// Notes found on startup that are still to be read into the vault
+    unread_notes: Arc<std::sync::Mutex<Vec<PathBuf>>>,
     opened_files: Arc<RwLock<HashSet<PathBuf>>>,
     settings: Arc<RwLock<Option<Settings>>>,
+    completion_debouncer: Arc<Debouncer>,
+    recency: Arc<RwLock<Recency>>,
+    completion_cache: Arc<std::sync::Mutex<CompletionCache>>,
+    semantic_tokens: Arc<RwLock<TokenCache>>,
 }
 
 struct TextDocumentItem {
     uri: Url,
-    text: String,
+    changes: Vec<TextDocumentContentChangeEvent>,
 }
 
 impl Backend {
@@ -68,14 +81,16 @@ impl Backend {
         };
 
         let guard = self
-            .bind_vault_mut(|vault| {
-                let text = &params.text;
-                Vault::update_vault(&settings, vault, (&path, text));
+            .bind_vault_mut(&path, |vault| {
+                Vault::apply_changes(&settings, vault, &path, &params.changes);
 
                 Ok(())
             })
             .await;
         drop(guard);
+        if let Ok(mut cache) = self.completion_cache.lock() {
+            cache.invalidate(Some(&path));
+        }
 
         self.client
             .log_message(MessageType::WARNING, "Update Vault Done")
@@ -113,17 +128,20 @@ impl Backend {
 
         {
             let _ = self
-                .bind_vault_mut(|vault| {
-                    let Ok(new_vault) = Vault::construct_vault(&settings, vault.root_dir()) else {
+                .bind_vaults_mut(|vaults| {
+                    let Ok(new_vaults) = Vaults::construct(&settings, &vaults.root_dirs()) else {
                         return Err(Error::new(ErrorCode::ServerError(0)));
                     };
 
-                    *vault = new_vault;
+                    *vaults = new_vaults;
 
                     Ok(())
                 })
                 .await;
         }
+        if let Ok(mut cache) = self.completion_cache.lock() {
+            cache.invalidate(None);
+        }
 
         let elapsed = timer.elapsed();
 
@@ -160,6 +178,66 @@ impl Backend {
         }
     }
 
+    /// Read the notes into the vault a batch at a time, reporting progress. Requests are served from the notes read so
+    /// far in between, and each batch is added whole, so a note is never there without its text
+    async fn index_notes(&self, notes: Vec<PathBuf>) {
+        const BATCH_SIZE: usize = 500;
+
+        let Ok(settings) = self.bind_settings(|settings| Ok(settings.clone())).await else {
+            return;
+        };
+        let progress = self
+            .client
+            .progress(ProgressToken::String("indexing".into()), "Indexing Vault")
+            .with_percentage(0)
+            .begin()
+            .await;
+        let timer = std::time::Instant::now();
+
+        let mut read = 0;
+        for batch in notes.chunks(BATCH_SIZE) {
+            read += batch.len();
+            let (batch, batch_settings) = (batch.to_vec(), settings.clone());
+            let read_notes =
+                tokio::task::spawn_blocking(move || Vault::read_notes(&batch_settings, &batch))
+                    .await
+                    .unwrap_or_default();
+
+            self.vaults.write().await.insert_notes(read_notes);
+            if let Ok(mut cache) = self.completion_cache.lock() {
+                cache.invalidate(None);
+            }
+
+            progress
+                .report_with_message(
+                    format!("{read}/{} notes", notes.len()),
+                    (read * 100 / notes.len()) as u32,
+                )
+                .await;
+        }
+
+        progress
+            .finish_with_message(format!(
+                "Indexed {} notes in {}ms",
+                notes.len(),
+                timer.elapsed().as_millis()
+            ))
+            .await;
+
+        // links to notes that were not read yet were reported as unresolved
+        if let Err(e) = self.publish_diagnostics().await {
+            self.client
+                .log_message(
+                    MessageType::ERROR,
+                    format!("Failed calculating diagnostics after indexing {:?}", e),
+                )
+                .await
+        }
+        if settings.semantic_tokens {
+            let _ = self.client.semantic_tokens_refresh().await;
+        }
+    }
+
     async fn publish_diagnostics(&self) -> Result<()> {
         let timer = std::time::Instant::now();
 
@@ -179,11 +257,12 @@ impl Backend {
         let settings = self.bind_settings(|settings| Ok(settings.clone())).await?;
 
         let diagnostics = self
-            .bind_vault(|vault| {
+            .bind_vaults(|vaults| {
                 Ok(uris
                     .par_iter()
                     .filter_map(|uri| {
                         let path = uri.to_file_path().ok()?;
+                        let vault = vaults.owning(&path)?;
 
                         diagnostics(vault, &settings, (&path, uri))
                             .map(|diags| (uri.clone(), diags))
@@ -221,17 +300,44 @@ impl Backend {
     /// TODO: Hopefully rust async closures will be more convienient to use eventually and this can accept an async closure; this would enable better logging
     /// in the call back functions. (though to get aroudn this, the callback could return a Result of a writer style monad, which could be logged async outside of
     /// the callback)
-    async fn bind_vault<T>(&self, callback: impl FnOnce(&Vault) -> Result<T>) -> Result<T> {
-        let guard = self.vault.read().await;
-        let Some(vault) = guard.deref() else {
+    ///
+    /// The vault is the one of the workspace folder holding `path`
+    async fn bind_vault<T>(
+        &self,
+        path: &Path,
+        callback: impl FnOnce(&Vault) -> Result<T>,
+    ) -> Result<T> {
+        let guard = self.vaults.read().await;
+        let Some(vault) = guard.owning(path) else {
             return Err(Error::new(ErrorCode::ServerError(0)));
         };
 
         callback(vault)
     }
 
-    async fn bind_vault_mut<T>(&self, callback: impl Fn(&mut Vault) -> Result<T>) -> Result<T> {
-        if let Err(e) = self.vault.try_write() {
+    /// The vaults of every workspace folder, for requests that are not about one note
+    async fn bind_vaults<T>(&self, callback: impl FnOnce(&Vaults) -> Result<T>) -> Result<T> {
+        let guard = self.vaults.read().await;
+        callback(guard.deref())
+    }
+
+    async fn bind_vault_mut<T>(
+        &self,
+        path: &Path,
+        callback: impl Fn(&mut Vault) -> Result<T>,
+    ) -> Result<T> {
+        self.bind_vaults_mut(|vaults| match vaults.owning_mut(path) {
+            Some(vault) => callback(vault),
+            None => Err(Error::new(ErrorCode::ServerError(0))),
+        })
+        .await
+    }
+
+    async fn bind_vaults_mut<T>(
+        &self,
+        callback: impl FnOnce(&mut Vaults) -> Result<T>,
+    ) -> Result<T> {
+        if let Err(e) = self.vaults.try_write() {
             self.client
                 .log_message(
                     MessageType::ERROR,
@@ -244,12 +350,8 @@ impl Backend {
                 .await;
         }
 
-        let mut guard = self.vault.write().await;
-        let Some(ref mut vault) = *guard else {
-            return Err(Error::new(ErrorCode::ServerError(0)));
-        };
-
-        callback(vault)
+        let mut guard = self.vaults.write().await;
+        callback(guard.deref_mut())
     }
 
     async fn bind_settings<T>(&self, callback: impl FnOnce(&Settings) -> Result<T>) -> Result<T> {
@@ -294,14 +396,21 @@ impl Backend {
 #[tower_lsp::async_trait]
 impl LanguageServer for Backend {
     async fn initialize(&self, i: InitializeParams) -> Result<InitializeResult> {
-        let root_dir = match i.root_uri {
-            Some(uri) => uri
-                .to_file_path()
+        // each workspace folder is a vault of its own; clients without folders send only the root
+        let root_dirs = match (i.workspace_folders, i.root_uri) {
+            (Some(folders), _) if !folders.is_empty() => folders
+                .into_iter()
+                .map(|folder| folder.uri.to_file_path())
+                .collect::<std::result::Result<Vec<_>, _>>()
                 .or(Err(Error::new(ErrorCode::InvalidParams)))?,
-            None => std::env::current_dir().or(Err(Error::new(ErrorCode::InvalidParams)))?,
+            (_, Some(uri)) => vec![uri
+                .to_file_path()
+                .or(Err(Error::new(ErrorCode::InvalidParams)))?],
+            _ => vec![std::env::current_dir().or(Err(Error::new(ErrorCode::InvalidParams)))?],
         };
+        let root_dir = &root_dirs[0];
 
-        let read_settings = match Settings::new(&root_dir, &i.capabilities) {
+        let read_settings = match Settings::new(root_dir, &i.capabilities) {
             Ok(settings) => settings,
             Err(e) => {
                 self.client
@@ -314,11 +423,18 @@ impl LanguageServer for Backend {
             }
         };
 
-        let Ok(vault) = Vault::construct_vault(&read_settings, &root_dir) else {
+        // the notes are read once the client is initialized, so it is not kept waiting on a large vault
+        let Ok((vaults, notes)) = Vaults::scan(&read_settings, &root_dirs) else {
             return Err(Error::new(ErrorCode::ServerError(0)));
         };
-        let mut value = self.vault.write().await;
-        *value = Some(vault);
+
+        *self.vaults.write().await = vaults;
+        if let Ok(mut unread_notes) = self.unread_notes.lock() {
+            *unread_notes = notes;
+        }
+
+        let completion_options = completion::completion_options(&read_settings);
+        let position_encoding = read_settings.position_encoding.kind();
 
         let mut settings = self.settings.write().await;
         *settings = Some(read_settings);
@@ -338,39 +454,47 @@ impl LanguageServer for Backend {
         return Ok(InitializeResult {
             server_info: None,
             capabilities: ServerCapabilities {
-                text_document_sync: Some(TextDocumentSyncCapability::Kind(
-                    TextDocumentSyncKind::FULL,
+                position_encoding: Some(position_encoding),
+                text_document_sync: Some(TextDocumentSyncCapability::Options(
+                    TextDocumentSyncOptions {
+                        open_close: Some(true),
+                        change: Some(TextDocumentSyncKind::INCREMENTAL),
+                        save: Some(TextDocumentSyncSaveOptions::Supported(true)),
+                        ..Default::default()
+                    },
                 )),
-                completion_provider: Some(CompletionOptions {
-                    resolve_provider: Some(false),
-                    trigger_characters: Some(vec![
-                        "[".into(),
-                        " ".into(),
-                        "(".into(),
-                        "#".into(),
-                        ">".into(),
-                    ]),
-                    work_done_progress_options: Default::default(),
-                    all_commit_characters: None,
-                    completion_item: None,
-                }),
+                completion_provider: Some(completion_options),
                 // definition: Some(GotoCapability::default()),,
                 inlay_hint_provider: Some(OneOf::Left(true)),
                 definition_provider: Some(OneOf::Left(true)),
                 references_provider: Some(OneOf::Left(true)),
-                rename_provider: Some(OneOf::Left(true)),
+                rename_provider: Some(OneOf::Right(RenameOptions {
+                    prepare_provider: Some(true),
+                    work_done_progress_options: Default::default(),
+                })),
                 hover_provider: Some(HoverProviderCapability::Simple(true)),
                 document_symbol_provider: Some(OneOf::Left(true)),
+                document_link_provider: Some(DocumentLinkOptions {
+                    resolve_provider: None,
+                    work_done_progress_options: Default::default(),
+                }),
+                folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
+                selection_range_provider: Some(SelectionRangeProviderCapability::Simple(true)),
+                document_formatting_provider: Some(OneOf::Left(true)),
                 workspace_symbol_provider: Some(OneOf::Left(true)),
                 code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
                 workspace: Some(WorkspaceServerCapabilities {
+                    workspace_folders: Some(WorkspaceFoldersServerCapabilities {
+                        supported: Some(true),
+                        change_notifications: Some(OneOf::Left(true)),
+                    }),
                     file_operations: Some(WorkspaceFileOperationsServerCapabilities {
                         did_create: Some(file_op_reg.clone()),
                         did_rename: Some(file_op_reg.clone()),
+                        will_rename: Some(file_op_reg.clone()),
                         did_delete: Some(file_op_reg.clone()),
                         ..Default::default()
                     }),
-                    ..Default::default()
                 }),
                 code_lens_provider: Some(CodeLensOptions {
                     resolve_provider: None,
@@ -379,6 +503,11 @@ impl LanguageServer for Backend {
                     commands: vec![
                         "apply_edits".into(),
                         "jump".into(),
+                        "createDailyNote".into(),
+                        "insertDailyNoteLink".into(),
+                        "showBacklinks".into(),
+                        "createBlockReference".into(),
+                        "dangling_references".into(),
                         "tomorrow".into(),
                         "today".into(),
                         "yesterday".into(),
@@ -402,18 +531,9 @@ impl LanguageServer for Backend {
                 semantic_tokens_provider: Some(
                     SemanticTokensServerCapabilities::SemanticTokensOptions(
                         SemanticTokensOptions {
-                            full: Some(SemanticTokensFullOptions::Bool(true)),
+                            full: Some(SemanticTokensFullOptions::Delta { delta: Some(true) }),
                             range: Some(false),
-                            legend: SemanticTokensLegend {
-                                token_types: vec![
-                                    SemanticTokenType::DECORATOR,
-                                    SemanticTokenType::COMMENT,
-                                ],
-                                token_modifiers: vec![
-                                    SemanticTokenModifier::DECLARATION,
-                                    SemanticTokenModifier::DEPRECATED,
-                                ],
-                            },
+                            legend: tokens::legend(),
                             ..Default::default()
                         },
                     ),
@@ -431,8 +551,10 @@ impl LanguageServer for Backend {
     async fn code_lens(&self, params: CodeLensParams) -> Result<Option<Vec<CodeLens>>> {
         let path = params_path!(params)?;
 
-        self.bind_vault(|vault| Ok(codelens::code_lens(vault, &path, &params)))
-            .await
+        self.bind_vault(&path, |vault| {
+            Ok(codelens::code_lens(vault, &path, &params))
+        })
+        .await
     }
 
     async fn initialized(&self, _: InitializedParams) {
@@ -444,7 +566,10 @@ impl LanguageServer for Backend {
             .log_message(MessageType::WARNING, format!("Settings: {:?}", settings))
             .await;
 
-        let Ok(root_path) = self.bind_vault(|vault| Ok(vault.root_dir().clone())).await else {
+        let Ok(Some(root_path)) = self
+            .bind_vaults(|vaults| Ok(vaults.primary().map(|vault| vault.root_dir().clone())))
+            .await
+        else {
             return;
         };
 
@@ -453,10 +578,24 @@ impl LanguageServer for Backend {
         };
 
         let value = serde_json::to_value(DidChangeWatchedFilesRegistrationOptions {
-            watchers: vec![FileSystemWatcher {
-                glob_pattern: GlobPattern::String("**/*.md".into()),
-                kind: None,
-            }],
+            watchers: vec![
+                FileSystemWatcher {
+                    glob_pattern: GlobPattern::String("**/*.{md,canvas}".into()),
+                    kind: None,
+                },
+                FileSystemWatcher {
+                    glob_pattern: GlobPattern::String(format!(
+                        "**/*.{{{}}}",
+                        vault::ATTACHMENT_EXTENSIONS.join(",")
+                    )),
+                    kind: None,
+                },
+                // ignore files decide which notes are in the vault
+                FileSystemWatcher {
+                    glob_pattern: GlobPattern::String("**/{.gitignore,.ignore}".into()),
+                    kind: None,
+                },
+            ],
         })
         .unwrap();
 
@@ -470,6 +609,13 @@ impl LanguageServer for Backend {
             .register_capability(vec![registration])
             .await
             .unwrap();
+
+        let notes = match self.unread_notes.lock() {
+            Ok(mut unread_notes) => std::mem::take(&mut *unread_notes),
+            Err(_) => Vec::new(),
+        };
+        let backend = self.clone();
+        tokio::spawn(async move { backend.index_notes(notes).await });
     }
 
     async fn did_open(&self, params: DidOpenTextDocumentParams) {
@@ -485,13 +631,21 @@ impl LanguageServer for Backend {
                 })
                 .await;
 
+            if let Ok(path) = params.text_document.uri.to_file_path() {
+                self.recency.write().await.touch(&path);
+            }
+
             self.client
                 .log_message(MessageType::LOG, "Added file")
                 .await;
 
             self.update_vault(TextDocumentItem {
                 uri: params.text_document.uri,
-                text: params.text_document.text,
+                changes: vec![TextDocumentContentChangeEvent {
+                    range: None,
+                    range_length: None,
+                    text: params.text_document.text,
+                }],
             })
             .await; // usually, this is not necesary; however some may start the LS without saving a changed file, so it is necessary
         } // drop the lock
@@ -525,10 +679,16 @@ impl LanguageServer for Backend {
         }
     }
 
-    async fn did_change(&self, mut params: DidChangeTextDocumentParams) {
+    async fn did_save(&self, params: DidSaveTextDocumentParams) {
+        if let Ok(path) = params.text_document.uri.to_file_path() {
+            self.recency.write().await.touch(&path);
+        }
+    }
+
+    async fn did_change(&self, params: DidChangeTextDocumentParams) {
         self.update_vault(TextDocumentItem {
             uri: params.text_document.uri,
-            text: params.content_changes.remove(0).text,
+            changes: params.content_changes,
         })
         .await;
     }
@@ -537,23 +697,85 @@ impl LanguageServer for Backend {
         self.reconstruct_vault().await
     }
 
+    async fn did_change_workspace_folders(&self, params: DidChangeWorkspaceFoldersParams) {
+        let Ok(settings) = self.bind_settings(|settings| Ok(settings.clone())).await else {
+            return;
+        };
+
+        let _ = self
+            .bind_vaults_mut(|vaults| {
+                let removed = params
+                    .event
+                    .removed
+                    .iter()
+                    .filter_map(|folder| folder.uri.to_file_path().ok())
+                    .collect_vec();
+                let mut root_dirs = vaults.root_dirs();
+                root_dirs.retain(|root_dir| !removed.contains(root_dir));
+                for folder in params.event.added {
+                    match folder.uri.to_file_path() {
+                        Ok(root_dir) if !root_dirs.contains(&root_dir) => root_dirs.push(root_dir),
+                        _ => (),
+                    }
+                }
+
+                // the notes of a folder nested in another move between their vaults, so all of them are read again
+                let Ok(new_vaults) = Vaults::construct(&settings, &root_dirs) else {
+                    return Err(Error::new(ErrorCode::ServerError(0)));
+                };
+                *vaults = new_vaults;
+
+                Ok(())
+            })
+            .await;
+        if let Ok(mut cache) = self.completion_cache.lock() {
+            cache.invalidate(None);
+        }
+
+        if let Err(e) = self.publish_diagnostics().await {
+            self.client
+                .log_message(
+                    MessageType::ERROR,
+                    format!("Failed calculating diagnostics on workspace change {:?}", e),
+                )
+                .await
+        }
+    }
+
     async fn goto_definition(
         &self,
         params: GotoDefinitionParams,
     ) -> Result<Option<GotoDefinitionResponse>> {
-        self.bind_vault(|vault| {
-            let path = params_path!(params.text_document_position_params)?;
-            Ok(
-                goto_definition(vault, params.text_document_position_params.position, &path)
-                    .map(GotoDefinitionResponse::Array),
-            )
+        let settings = self.bind_settings(|settings| Ok(settings.clone())).await?;
+
+        let path = params_path!(params.text_document_position_params)?;
+        let position = params.text_document_position_params.position;
+
+        self.bind_vaults(|vaults| {
+            let Some(vault) = vaults.owning(&path) else {
+                return Err(Error::new(ErrorCode::ServerError(0)));
+            };
+
+            Ok(goto_definition(vault, position, &path, &settings)
+                .or_else(|| {
+                    settings.cross_vault_links.then(|| {
+                        goto_definition_in_other_vaults(
+                            vault,
+                            vaults.others(vault),
+                            position,
+                            &path,
+                        )
+                    })?
+                })
+                .map(GotoDefinitionResponse::Array))
         })
         .await
     }
 
     async fn references(&self, params: ReferenceParams) -> Result<Option<Vec<Location>>> {
-        self.bind_vault(|vault| {
-            let path = params_position_path!(params)?;
+        let path = params_position_path!(params)?;
+
+        self.bind_vault(&path, |vault| {
             Ok(references(
                 vault,
                 params.text_document_position.position,
@@ -579,8 +801,29 @@ impl LanguageServer for Backend {
             return Err(Error::new(ErrorCode::ServerError(2)));
         }; // TODO: this is bad
 
+        // a newer request superseded this one while typing
+        if !self
+            .completion_debouncer
+            .settle(Duration::from_millis(settings.completion_debounce_ms))
+            .await
+        {
+            return Ok(None);
+        }
+
+        let recency = self.recency.read().await.clone();
+
         let res = self
-            .bind_vault(|vault| Ok(get_completions(vault, &files, &params, &path, &settings)))
+            .bind_vault(&path, |vault| {
+                Ok(get_completions(
+                    vault,
+                    &files,
+                    &params,
+                    &path,
+                    &settings,
+                    &recency,
+                    &self.completion_cache,
+                ))
+            })
             .await;
 
         let elapsed = timer.elapsed();
@@ -595,11 +838,31 @@ impl LanguageServer for Backend {
         res
     }
 
+    async fn completion_resolve(&self, item: CompletionItem) -> Result<CompletionItem> {
+        let settings = self.bind_settings(|settings| Ok(settings.clone())).await?;
+
+        let Some(path) = completion::completion_target_path(&item) else {
+            return Ok(item);
+        };
+
+        self.bind_vault(&path, |vault| {
+            Ok(completion::resolve_completion(vault, &settings, item))
+        })
+        .await
+    }
+
     async fn execute_command(&self, params: ExecuteCommandParams) -> Result<Option<Value>> {
         let settings = self.bind_settings(|settings| Ok(settings.clone())).await?;
         let root_dir = self
-            .bind_vault(|vault| Ok(vault.root_dir().to_owned()))
+            .bind_vaults(|vaults| {
+                vaults
+                    .primary()
+                    .map(|vault| vault.root_dir().to_owned())
+                    .ok_or(Error::new(ErrorCode::ServerError(0)))
+            })
             .await?;
+        // commands about a note run in its vault
+        let note = commands::note_argument(&params.arguments).unwrap_or(root_dir.clone());
 
         match params {
             ExecuteCommandParams { command, .. } if *command == *"apply_edits" => {
@@ -617,13 +880,55 @@ impl LanguageServer for Backend {
             }
             ExecuteCommandParams { command, .. } if *command == *"jump" => {
                 let jump_to = params.arguments.first().and_then(|val| val.as_str());
-                let settings = self
-                    .bind_settings(|settings| Ok(settings.to_owned()))
+                commands::jump(&self.client, &root_dir, &settings, jump_to).await
+            }
+            ExecuteCommandParams { command, .. } if *command == *"createDailyNote" => {
+                commands::create_daily_note_command(
+                    &self.client,
+                    &root_dir,
+                    &settings,
+                    params.arguments.first(),
+                )
+                .await
+            }
+            ExecuteCommandParams { command, .. } if *command == *"insertDailyNoteLink" => {
+                let edit = self
+                    .bind_vault(&note, |vault| {
+                        commands::daily_note_link_edit(vault, &settings, &params.arguments)
+                    })
                     .await?;
-                let root_dir = self
-                    .bind_vault(|vault| Ok(vault.root_dir().to_owned()))
+                let _ = self.client.apply_edit(edit).await;
+
+                Ok(None)
+            }
+            ExecuteCommandParams { command, .. } if *command == *"showBacklinks" => {
+                self.bind_vault(&note, |vault| {
+                    let backlinks = commands::show_backlinks(vault, &settings, &params.arguments)?;
+                    Ok(serde_json::to_value(backlinks).ok())
+                })
+                .await
+            }
+            ExecuteCommandParams { command, .. } if *command == *"createBlockReference" => {
+                let (link, edit) = self
+                    .bind_vault(&note, |vault| {
+                        commands::create_block_reference(vault, &settings, &params.arguments)
+                    })
                     .await?;
-                commands::jump(&self.client, &root_dir, &settings, jump_to).await
+                if let Some(edit) = edit {
+                    let _ = self.client.apply_edit(edit).await;
+                }
+
+                Ok(Some(Value::String(link)))
+            }
+            ExecuteCommandParams { command, .. } if *command == *"dangling_references" => {
+                self.bind_vaults(|vaults| {
+                    let dangling = vaults
+                        .iter()
+                        .flat_map(diagnostics::dangling_references)
+                        .collect_vec();
+                    Ok(serde_json::to_value(dangling).ok())
+                })
+                .await
             }
             ExecuteCommandParams { command, .. } => {
                 jump_to_specific(&command, &self.client, &root_dir, &settings).await
@@ -633,8 +938,8 @@ impl LanguageServer for Backend {
 
     async fn hover(&self, params: HoverParams) -> Result<Option<Hover>> {
         let settings = self.bind_settings(|settings| Ok(settings.clone())).await?;
-        self.bind_vault(|vault| {
-            let path = params_path!(params.text_document_position_params)?;
+        let path = params_path!(params.text_document_position_params)?;
+        self.bind_vault(&path, |vault| {
             Ok(hover::hover(vault, &params, &path, &settings))
         })
         .await
@@ -644,9 +949,58 @@ impl LanguageServer for Backend {
         &self,
         params: DocumentSymbolParams,
     ) -> Result<Option<DocumentSymbolResponse>> {
-        self.bind_vault(|vault| {
-            let path = params_path!(params)?;
-            Ok(document_symbol(vault, &params, &path))
+        let settings = self.bind_settings(|settings| Ok(settings.clone())).await?;
+
+        let path = params_path!(params)?;
+
+        self.bind_vault(&path, |vault| {
+            Ok(document_symbol(vault, &params, &path, &settings))
+        })
+        .await
+    }
+
+    async fn document_link(&self, params: DocumentLinkParams) -> Result<Option<Vec<DocumentLink>>> {
+        let path = params_path!(params)?;
+
+        self.bind_vault(&path, |vault| {
+            Ok(documentlink::document_links(vault, &path))
+        })
+        .await
+    }
+
+    async fn folding_range(&self, params: FoldingRangeParams) -> Result<Option<Vec<FoldingRange>>> {
+        let path = params_path!(params)?;
+
+        self.bind_vault(&path, |vault| Ok(folding::folding_ranges(vault, &path)))
+            .await
+    }
+
+    async fn selection_range(
+        &self,
+        params: SelectionRangeParams,
+    ) -> Result<Option<Vec<SelectionRange>>> {
+        let settings = self.bind_settings(|settings| Ok(settings.clone())).await?;
+
+        let path = params_path!(params)?;
+
+        self.bind_vault(&path, |vault| {
+            Ok(selection::selection_ranges(
+                vault,
+                &settings,
+                &path,
+                &params.positions,
+            ))
+        })
+        .await
+    }
+
+    async fn formatting(&self, params: DocumentFormattingParams) -> Result<Option<Vec<TextEdit>>> {
+        let settings = self.bind_settings(|settings| Ok(settings.clone())).await?;
+
+        let path = params_path!(params)?;
+
+        self.bind_vault(&path, |vault| {
+            Ok(formatting::formatting(vault, &path, &settings))
         })
         .await
     }
@@ -655,23 +1009,61 @@ impl LanguageServer for Backend {
         &self,
         params: WorkspaceSymbolParams,
     ) -> Result<Option<Vec<SymbolInformation>>> {
-        self.bind_vault(|vault| Ok(workspace_symbol(vault, &params)))
-            .await
+        let settings = self.bind_settings(|settings| Ok(settings.clone())).await?;
+
+        self.bind_vaults(|vaults| {
+            let symbols = vaults
+                .iter()
+                .filter_map(|vault| workspace_symbol(vault, &params, &settings))
+                .flatten()
+                .collect_vec();
+            Ok(Some(symbols))
+        })
+        .await
     }
 
-    async fn rename(&self, params: RenameParams) -> Result<Option<WorkspaceEdit>> {
-        self.bind_vault(|vault| {
-            let path = params_position_path!(params)?;
-            Ok(rename::rename(vault, &params, &path))
+    async fn prepare_rename(
+        &self,
+        params: TextDocumentPositionParams,
+    ) -> Result<Option<PrepareRenameResponse>> {
+        let path = params_path!(params)?;
+
+        self.bind_vault(&path, |vault| {
+            rename::prepare_rename(vault, &params, &path)
+                .map(Some)
+                .ok_or(Error::invalid_params("Nothing to rename at this position"))
         })
         .await
     }
 
+    async fn rename(&self, params: RenameParams) -> Result<Option<WorkspaceEdit>> {
+        let path = params_position_path!(params)?;
+
+        self.bind_vault(&path, |vault| Ok(rename::rename(vault, &params, &path)))
+            .await
+    }
+
+    async fn will_rename_files(&self, params: RenameFilesParams) -> Result<Option<WorkspaceEdit>> {
+        // the renamed files are in the vault of the first one
+        let Some(path) = params
+            .files
+            .first()
+            .and_then(|file| Url::parse(&file.old_uri).ok())
+            .and_then(|uri| uri.to_file_path().ok())
+        else {
+            return Ok(None);
+        };
+
+        self.bind_vault(&path, |vault| Ok(rename::rename_files(vault, &params)))
+            .await
+    }
+
     async fn code_action(&self, params: CodeActionParams) -> Result<Option<CodeActionResponse>> {
         let settings = self.bind_settings(|settings| Ok(settings.clone())).await?;
 
-        self.bind_vault(|vault| {
-            let path = params_path!(params)?;
+        let path = params_path!(params)?;
+
+        self.bind_vault(&path, |vault| {
             Ok(codeactions::code_actions(vault, &params, &path, &settings))
         })
         .await
@@ -687,12 +1079,10 @@ impl LanguageServer for Backend {
 
         let path = params_path!(params)?;
         let res = self
-            .bind_vault(|vault| {
-                Ok(tokens::semantic_tokens_full(
-                    vault, &path, params, &settings,
-                ))
+            .bind_vault(&path, |vault| {
+                Ok(tokens::document_tokens(vault, &path, &settings))
             })
-            .await;
+            .await?;
 
         let elapsed = timer.elapsed();
 
@@ -703,7 +1093,36 @@ impl LanguageServer for Backend {
             )
             .await;
 
-        return res;
+        let Some(data) = res else {
+            return Ok(None);
+        };
+
+        Ok(Some(SemanticTokensResult::Tokens(
+            self.semantic_tokens.write().await.full(&path, data),
+        )))
+    }
+
+    async fn semantic_tokens_full_delta(
+        &self,
+        params: SemanticTokensDeltaParams,
+    ) -> Result<Option<SemanticTokensFullDeltaResult>> {
+        let settings = self.bind_settings(|settings| Ok(settings.clone())).await?;
+
+        let path = params_path!(params)?;
+        let Some(data) = self
+            .bind_vault(&path, |vault| {
+                Ok(tokens::document_tokens(vault, &path, &settings))
+            })
+            .await?
+        else {
+            return Ok(None);
+        };
+
+        Ok(Some(self.semantic_tokens.write().await.delta(
+            &path,
+            &params.previous_result_id,
+            data,
+        )))
     }
 
     async fn inlay_hint(&self, params: InlayHintParams) -> Result<Option<Vec<InlayHint>>> {
@@ -712,13 +1131,13 @@ impl LanguageServer for Backend {
             return Ok(None);
         }
 
+        let path = params_path!(params)?;
         let hints = self
-            .bind_vault(|vault| {
+            .bind_vault(&path, |vault| {
                 if !settings.block_transclusion {
                     return Ok(None);
                 }
 
-                let path = params_path!(params)?;
                 let Some(references) = vault.select_references(Some(&path)) else {
                     return Ok(None);
                 };
@@ -831,9 +1250,14 @@ async fn main() {
 
     let (service, socket) = LspService::new(|client| Backend {
         client,
-        vault: Arc::new(None.into()),
+        vaults: Arc::new(Vaults::default().into()),
+        unread_notes: Arc::new(std::sync::Mutex::new(Vec::new())),
         opened_files: Arc::new(HashSet::new().into()),
         settings: Arc::new(None.into()),
+        completion_debouncer: Arc::new(Debouncer::default()),
+        recency: Arc::new(RwLock::new(Recency::default())),
+        completion_cache: Arc::new(std::sync::Mutex::new(CompletionCache::default())),
+        semantic_tokens: Arc::new(RwLock::new(TokenCache::default())),
     });
     Server::new(stdin, stdout, socket).serve(service).await;
 }
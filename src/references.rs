@@ -1,9 +1,13 @@
 use std::path::Path;
 
 use itertools::Itertools;
-use tower_lsp::lsp_types::{Location, Position, Url};
+use serde::Serialize;
+use tower_lsp::lsp_types::{Location, Position, Range, Url};
 
-use crate::vault::{Referenceable, Vault};
+use crate::{
+    config::Settings,
+    vault::{Referenceable, Vault},
+};
 
 pub fn references(vault: &Vault, cursor_position: Position, path: &Path) -> Option<Vec<Location>> {
     let references = match (
@@ -42,3 +46,85 @@ pub fn references(vault: &Vault, cursor_position: Position, path: &Path) -> Opti
             .collect::<Vec<_>>(),
     )
 }
+
+/// The links in one note to what the `showBacklinks` command was asked about
+#[derive(Debug, Serialize, PartialEq)]
+pub struct NoteBacklinks {
+    pub uri: Url,
+    pub backlinks: Vec<Backlink>,
+}
+
+/// A link, with the text of its line for context
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Backlink {
+    pub range: Range,
+    pub line: String,
+}
+
+/// Backlinks to the heading, block or tag at `position` of the note at `path`, or to the note itself, grouped by the
+/// note they are in in path order; links through the note's aliases are backlinks too
+pub fn backlinks(
+    vault: &Vault,
+    settings: &Settings,
+    path: &Path,
+    position: Option<Position>,
+) -> Vec<NoteBacklinks> {
+    let encoding = settings.position_encoding;
+    let line_chars = |path: &Path, line: u32| {
+        let mut chars = vault.select_line(path, line as isize).unwrap_or_default();
+        if chars.last() == Some(&'\n') {
+            chars.pop();
+        }
+        chars
+    };
+    let encode = |path: &Path, position: Position| Position {
+        character: encoding.column(
+            &line_chars(path, position.line),
+            position.character as usize,
+        ),
+        ..position
+    };
+
+    let referenceable = position
+        .map(|position| Position {
+            character: encoding.char_index(&line_chars(path, position.line), position.character)
+                as u32,
+            ..position
+        })
+        .and_then(|position| vault.select_referenceable_at_position(path, position))
+        .or_else(|| {
+            let (path, mdfile) = vault.md_files.get_key_value(path)?;
+            Some(Referenceable::File(path, mdfile))
+        });
+    let Some(references) = referenceable
+        .and_then(|referenceable| vault.select_references_for_referenceable(&referenceable))
+    else {
+        return vec![];
+    };
+
+    references
+        .into_iter()
+        .into_group_map()
+        .into_iter()
+        .sorted_by_key(|(path, _)| *path)
+        .filter_map(|(path, references)| {
+            Some(NoteBacklinks {
+                uri: Url::from_file_path(path).ok()?,
+                backlinks: references
+                    .into_iter()
+                    .map(|reference| *reference.data().range)
+                    .sorted_by_key(|range| (range.start.line, range.start.character))
+                    .map(|range| Backlink {
+                        range: Range {
+                            start: encode(path, range.start),
+                            end: encode(path, range.end),
+                        },
+                        line: String::from_iter(line_chars(path, range.start.line))
+                            .trim_end()
+                            .to_string(),
+                    })
+                    .collect(),
+            })
+        })
+        .collect()
+}
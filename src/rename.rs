@@ -1,31 +1,125 @@
+use std::collections::HashMap;
 use std::iter;
 use std::path::Path;
 
+use pathdiff::diff_paths;
 use tower_lsp::lsp_types::{
     DocumentChangeOperation, DocumentChanges, OneOf, OptionalVersionedTextDocumentIdentifier,
-    RenameFile, RenameParams, ResourceOp, TextDocumentEdit, TextEdit, Url, WorkspaceEdit,
+    Position, PrepareRenameResponse, Range, RenameFile, RenameFilesParams, RenameParams,
+    ResourceOp, TextDocumentEdit, TextDocumentPositionParams, TextEdit, Url, WorkspaceEdit,
 };
 
-use crate::vault::{MDHeading, Reference, Referenceable, Vault};
+use crate::vault::{
+    get_obsidian_ref_path, github_slug, link_target_span, normalize_for_match, MDHeading,
+    MatchOpts, MyRange, Rangeable, Reference, ReferenceData, Referenceable, Vault,
+};
+
+/// Only the file part of a link, a heading's text, or a tag can be renamed; anything else returns None so the editor blocks the rename
+pub fn prepare_rename(
+    vault: &Vault,
+    params: &TextDocumentPositionParams,
+    path: &Path,
+) -> Option<PrepareRenameResponse> {
+    let position = params.position;
+    let contains = |range: &Range| {
+        range.start.line == position.line
+            && range.start.character <= position.character
+            && range.end.character >= position.character
+    };
+
+    let (range, placeholder) = match vault.select_reference_at_position(path, position) {
+        Some(Reference::Tag(data)) => (
+            Range {
+                start: Position {
+                    line: data.range.start.line,
+                    // frontmatter tags may be written without the `#`
+                    character: data.range.start.character
+                        + link_text(vault, path, &data.range)?.starts_with('#') as u32,
+                },
+                end: data.range.end,
+            },
+            data.reference_text.clone(),
+        ),
+        Some(reference) => {
+            let (file, range) = link_file_range(vault, path, reference)?;
+            (range, file.to_string())
+        }
+        None => match vault.select_referenceable_at_position(path, position)? {
+            Referenceable::Heading(_, heading) => {
+                // past the `## ` of an ATX heading; a Setext heading's text starts its range
+                let start = match heading.is_setext() {
+                    true => heading.range.start.character,
+                    false => heading.range.start.character + heading.level.0 as u32 + 1,
+                };
+                (
+                    Range {
+                        start: Position {
+                            line: heading.range.start.line,
+                            character: start,
+                        },
+                        end: Position {
+                            line: heading.range.start.line,
+                            character: start + heading.heading_text.chars().count() as u32,
+                        },
+                    },
+                    heading.heading_text.clone(),
+                )
+            }
+            _ => return None,
+        },
+    };
+
+    contains(&range).then_some(PrepareRenameResponse::RangeWithPlaceholder { range, placeholder })
+}
 
 pub fn rename(vault: &Vault, params: &RenameParams, path: &Path) -> Option<WorkspaceEdit> {
     let position = params.text_document_position.position;
-    let referenceable = vault.select_referenceable_at_position(path, position)?;
+    // Renaming from a link renames the file it links to
+    let referenceable = match vault.select_reference_at_position(path, position) {
+        Some(reference) if !matches!(reference, Reference::Tag(..)) => {
+            let (file, _) = link_file_range(vault, path, reference)?;
+            vault
+                .select_referenceables_for_reference(
+                    &Reference::WikiFileLink(ReferenceData {
+                        reference_text: file.to_string(),
+                        ..Default::default()
+                    }),
+                    path,
+                )
+                .into_iter()
+                .find(|referenceable| matches!(referenceable, Referenceable::File(..)))?
+        }
+        _ => vault.select_referenceable_at_position(path, position)?,
+    };
 
     let (referenceable_document_change, new_ref_name): (Option<DocumentChangeOperation>, String) =
         match referenceable {
             Referenceable::Heading(path, heading) => {
-                let new_text = format!("{} {}", "#".repeat(heading.level.0), params.new_name); // move this obsidian syntax specific stuff to the vault
+                // a Setext heading keeps its underline, only its line of text changes
+                let (range, new_text) = match heading.is_setext() {
+                    true => (
+                        Range {
+                            start: heading.range.start,
+                            end: Position {
+                                line: heading.range.start.line,
+                                character: heading.range.start.character
+                                    + heading.heading_text.chars().count() as u32,
+                            },
+                        },
+                        params.new_name.clone(),
+                    ),
+                    false => (
+                        *heading.range,
+                        format!("{} {}", "#".repeat(heading.level.0), params.new_name), // move this obsidian syntax specific stuff to the vault
+                    ),
+                };
 
                 let change_op = DocumentChangeOperation::Edit(TextDocumentEdit {
                     text_document: tower_lsp::lsp_types::OptionalVersionedTextDocumentIdentifier {
                         uri: Url::from_file_path(path).ok()?,
                         version: None,
                     },
-                    edits: vec![OneOf::Left(TextEdit {
-                        range: *heading.range,
-                        new_text,
-                    })],
+                    edits: vec![OneOf::Left(TextEdit { range, new_text })],
                 });
 
                 // {path name}#{new name}
@@ -61,6 +155,24 @@ pub fn rename(vault: &Vault, params: &RenameParams, path: &Path) -> Option<Works
 
     let references_changes = references
         .into_iter()
+        // links by an alias keep working after a rename
+        .filter(|(_, reference)| match (&referenceable, reference) {
+            (
+                Referenceable::File(_, mdfile),
+                Reference::WikiFileLink(data) | Reference::MDFileLink(data),
+            ) => !mdfile.has_alias(&data.reference_text),
+            _ => true,
+        })
+        // tag-like text in code is left as written, even when `tags_in_codeblocks` indexes it
+        .filter(|(path, reference)| {
+            !matches!(reference, Reference::Tag(..))
+                || !vault.md_files.get(*path).is_some_and(|mdfile| {
+                    mdfile
+                        .codeblocks
+                        .iter()
+                        .any(|codeblock| codeblock.includes(*reference))
+                })
+        })
         .filter_map(|(path, reference)| {
             // update references
 
@@ -74,7 +186,10 @@ pub fn rename(vault: &Vault, params: &RenameParams, path: &Path) -> Option<Works
                         new_ref_name,
                         data.display_text
                             .as_ref()
-                            .map(|text| format!("|{text}"))
+                            .map(|text| format!(
+                                "{}{text}",
+                                display_separator(vault, path, &data.range)
+                            ))
                             .unwrap_or_else(|| String::from(""))
                     );
 
@@ -100,7 +215,10 @@ pub fn rename(vault: &Vault, params: &RenameParams, path: &Path) -> Option<Works
                         infile,
                         data.display_text
                             .as_ref()
-                            .map(|text| format!("|{text}"))
+                            .map(|text| format!(
+                                "{}{text}",
+                                display_separator(vault, path, &data.range)
+                            ))
                             .unwrap_or_else(|| String::from(""))
                     );
 
@@ -116,17 +234,10 @@ pub fn rename(vault: &Vault, params: &RenameParams, path: &Path) -> Option<Works
                         })],
                     })
                 }
-                Reference::WikiHeadingLink(data, _file, _heading)
+                Reference::WikiHeadingLink(data, _file, infile)
                     if matches!(referenceable, Referenceable::Heading(..)) =>
                 {
-                    let new_text = format!(
-                        "[[{}{}]]",
-                        new_ref_name,
-                        data.display_text
-                            .as_ref()
-                            .map(|text| format!("|{text}"))
-                            .unwrap_or_else(|| String::from(""))
-                    );
+                    let link_text = link_text(vault, path, &data.range)?;
 
                     Some(TextDocumentEdit {
                         text_document:
@@ -134,15 +245,21 @@ pub fn rename(vault: &Vault, params: &RenameParams, path: &Path) -> Option<Works
                                 uri: Url::from_file_path(path).ok()?,
                                 version: None,
                             },
-                        edits: vec![OneOf::Left(TextEdit {
-                            range: *data.range,
-                            new_text,
-                        })],
+                        edits: vec![OneOf::Left(infile_ref_edit(
+                            &link_text,
+                            &data.range,
+                            infile,
+                            &params.new_name,
+                        )?)],
                     })
                 }
                 Reference::Tag(data) => {
+                    let hash = match link_text(vault, path, &data.range)?.starts_with('#') {
+                        true => "#",
+                        false => "",
+                    };
                     let new_text = format!(
-                        "#{}",
+                        "{hash}{}",
                         data.reference_text.replacen(
                             &*referenceable.get_refname(vault.root_dir())?,
                             &new_ref_name,
@@ -210,17 +327,22 @@ pub fn rename(vault: &Vault, params: &RenameParams, path: &Path) -> Option<Works
                         })],
                     })
                 }
-                Reference::WikiHeadingLink(data, _file, _heading)
-                    if matches!(referenceable, Referenceable::Heading(..)) =>
-                {
-                    let new_text = format!(
-                        "[{}]({})",
-                        data.display_text
-                            .as_ref()
-                            .map(|text| format!("|{text}"))
-                            .unwrap_or_else(|| String::from("")),
-                        new_ref_name,
-                    );
+                Reference::MDHeadingLink(data, _file, infile) => {
+                    let Referenceable::Heading(_, heading) = referenceable else {
+                        return None;
+                    };
+                    let link_text = link_text(vault, path, &data.range)?;
+
+                    // keep linking by anchor if the link did, or if the new name would need <> that the link does not have
+                    let links_by_anchor = normalize_for_match(infile, &MatchOpts::CASEFOLD)
+                        != normalize_for_match(&heading.heading_text, &MatchOpts::CASEFOLD);
+                    let new_infile = if links_by_anchor
+                        || params.new_name.contains(' ') && !link_text.contains("(<")
+                    {
+                        github_slug(&params.new_name)
+                    } else {
+                        params.new_name.clone()
+                    };
 
                     Some(TextDocumentEdit {
                         text_document:
@@ -228,13 +350,14 @@ pub fn rename(vault: &Vault, params: &RenameParams, path: &Path) -> Option<Works
                                 uri: Url::from_file_path(path).ok()?,
                                 version: None,
                             },
-                        edits: vec![OneOf::Left(TextEdit {
-                            range: *data.range,
-                            new_text,
-                        })],
+                        edits: vec![OneOf::Left(infile_ref_edit(
+                            &link_text,
+                            &data.range,
+                            infile,
+                            &new_infile,
+                        )?)],
                     })
                 }
-                Reference::MDHeadingLink(_, _, _) => None,
                 Reference::MDIndexedBlockLink(_, _, _) => None,
                 Reference::WikiFileLink(..) => None,
                 Reference::WikiHeadingLink(..) => None,
@@ -255,3 +378,765 @@ pub fn rename(vault: &Vault, params: &RenameParams, path: &Path) -> Option<Works
         ..Default::default()
     })
 }
+
+/// Edits for `workspace/willRenameFiles`: point every link that resolved to a renamed note at its new name, touching only the file part of the link
+pub fn rename_files(vault: &Vault, params: &RenameFilesParams) -> Option<WorkspaceEdit> {
+    let mut edits: HashMap<&Path, Vec<TextEdit>> = HashMap::new();
+    let file_path = |uri: &str| Url::parse(uri).ok()?.to_file_path().ok();
+
+    for file_rename in &params.files {
+        // a uri that is not a file leaves the other renames alone
+        let (Some(old_path), Some(new_path)) = (
+            file_path(&file_rename.old_uri),
+            file_path(&file_rename.new_uri),
+        ) else {
+            continue;
+        };
+        let (Some((old_path, mdfile)), Some(new_stem)) = (
+            vault.md_files.get_key_value(&old_path),
+            new_path.file_stem().map(|stem| stem.to_string_lossy()),
+        ) else {
+            continue;
+        };
+        let Some(new_ref_path) = get_obsidian_ref_path(vault.root_dir(), &new_path) else {
+            continue;
+        };
+
+        // a bare `[[name]]` only resolved here if no other note has the same name
+        let stem_key = |path: &Path| {
+            path.file_stem()
+                .map(|stem| normalize_for_match(&stem.to_string_lossy(), &MatchOpts::CASEFOLD))
+        };
+        let shared_basename = vault
+            .md_files
+            .keys()
+            .any(|path| path != old_path && stem_key(path) == stem_key(old_path));
+
+        let referenceable = Referenceable::File(old_path, mdfile);
+        for (path, reference) in vault
+            .select_references_for_referenceable(&referenceable)
+            .unwrap_or_default()
+        {
+            if let Reference::WikiFileLink(data) | Reference::MDFileLink(data) = reference {
+                if mdfile.has_alias(&data.reference_text) {
+                    continue;
+                }
+            }
+            let Some((file, range)) = link_file_range(vault, path, reference) else {
+                continue;
+            };
+            let relative_folder = path
+                .parent()
+                .filter(|_| vault.link_resolution().is_relative(reference, file));
+            if !file.contains('/') && shared_basename && relative_folder.is_none() {
+                continue;
+            }
+
+            let new_ref = match (relative_folder, file.contains('/')) {
+                // a link relative to its note gets the path from the note's folder
+                (Some(folder), _) => {
+                    let Some(relative) = diff_paths(new_path.with_extension(""), folder) else {
+                        continue;
+                    };
+                    format!(
+                        "{}{}",
+                        if file.starts_with("./") { "./" } else { "" },
+                        relative.to_string_lossy()
+                    )
+                }
+                (None, true) => format!(
+                    "{}{}",
+                    ["./", "/"]
+                        .into_iter()
+                        .find(|prefix| file.starts_with(prefix))
+                        .unwrap_or_default(),
+                    new_ref_path
+                ),
+                (None, false) => new_stem.to_string(),
+            };
+            // a link like `[[diagram.canvas]]` keeps its extension
+            let new_ref = match file.ends_with(".canvas") {
+                true => format!("{new_ref}.canvas"),
+                false => new_ref,
+            };
+            let is_md_link = matches!(
+                reference,
+                Reference::MDFileLink(..)
+                    | Reference::MDHeadingLink(..)
+                    | Reference::MDIndexedBlockLink(..)
+            );
+            let new_text = match is_md_link
+                && !link_text(vault, path, &reference.data().range)
+                    .is_some_and(|text| text.contains("](<"))
+            {
+                true => new_ref.replace(' ', "%20"),
+                false => new_ref,
+            };
+
+            edits
+                .entry(path)
+                .or_default()
+                .push(TextEdit { range, new_text });
+        }
+    }
+
+    Some(WorkspaceEdit {
+        document_changes: Some(DocumentChanges::Edits(
+            edits
+                .into_iter()
+                .map(|(path, edits)| {
+                    Some(TextDocumentEdit {
+                        text_document: OptionalVersionedTextDocumentIdentifier {
+                            uri: Url::from_file_path(path).ok()?,
+                            version: None,
+                        },
+                        edits: edits.into_iter().map(OneOf::Left).collect(),
+                    })
+                })
+                .collect::<Option<_>>()?,
+        )),
+        ..Default::default()
+    })
+}
+
+/// The file part of a link as written, and its range; None for links without one like `[[#heading]]`
+fn link_file_range<'a>(
+    vault: &Vault,
+    path: &Path,
+    reference: &'a Reference,
+) -> Option<(&'a str, Range)> {
+    let (data, file) = match reference {
+        Reference::WikiFileLink(data) | Reference::MDFileLink(data) => {
+            (data, data.reference_text.as_str())
+        }
+        Reference::WikiHeadingLink(data, file, _)
+        | Reference::WikiIndexedBlockLink(data, file, _)
+        | Reference::MDHeadingLink(data, file, _)
+        | Reference::MDIndexedBlockLink(data, file, _) => (data, file.as_str()),
+        Reference::Tag(..) | Reference::Footnote(..) | Reference::LinkRef(..) => return None,
+    };
+
+    let link_text = link_text(vault, path, &data.range)?;
+    let file_start = match reference {
+        Reference::WikiFileLink(..)
+        | Reference::WikiHeadingLink(..)
+        | Reference::WikiIndexedBlockLink(..) => 2,
+        _ => {
+            let destination = link_text.find("](")? + 2;
+            destination + link_text[destination..].starts_with('<') as usize
+        }
+    };
+
+    if file.is_empty() || !link_text[file_start..].starts_with(file) {
+        return None;
+    }
+
+    let start = data.range.start.character + link_text[..file_start].chars().count() as u32;
+
+    Some((
+        file,
+        Range {
+            start: Position {
+                line: data.range.start.line,
+                character: start,
+            },
+            end: Position {
+                line: data.range.start.line,
+                character: start + file.chars().count() as u32,
+            },
+        },
+    ))
+}
+
+/// The text of a single line link as it is written in the document
+fn link_text(vault: &Vault, path: &Path, range: &MyRange) -> Option<String> {
+    let line = vault.ropes.get(path)?.get_line(range.start.line as usize)?;
+
+    Some(
+        line.get_slice(range.start.character as usize..range.end.character as usize)?
+            .to_string(),
+    )
+}
+
+/// The `|` before a wiki link's display text as it is written: `\|` when the link is escaped for a table
+fn display_separator(vault: &Vault, path: &Path, range: &MyRange) -> &'static str {
+    let escaped = link_text(vault, path, range)
+        .and_then(|text| Some(text[..text.find('|')?].ends_with('\\')))
+        .unwrap_or(false);
+
+    match escaped {
+        true => r"\|",
+        false => "|",
+    }
+}
+
+/// Edit replacing only the `#infile` part of a link, leaving its file and display text as written
+fn infile_ref_edit(
+    link_text: &str,
+    range: &MyRange,
+    infile: &str,
+    new_infile: &str,
+) -> Option<TextEdit> {
+    let target = link_target_span(link_text);
+    // the infile part may be written with spaces around it, like `[[file# heading ]]`
+    let byte_offset = link_text[target.clone()]
+        .match_indices('#')
+        .map(|(offset, _)| target.start + offset + 1)
+        .find_map(|after_hash| {
+            let rest = &link_text[after_hash..target.end];
+            let trimmed = rest.trim_start();
+            trimmed
+                .starts_with(infile)
+                .then(|| after_hash + rest.len() - trimmed.len())
+        })?;
+    let start = range.start.character + link_text[..byte_offset].chars().count() as u32;
+
+    Some(TextEdit {
+        range: Range {
+            start: Position {
+                line: range.start.line,
+                character: start,
+            },
+            end: Position {
+                line: range.start.line,
+                character: start + infile.chars().count() as u32,
+            },
+        },
+        new_text: new_infile.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use itertools::Itertools;
+    use tower_lsp::lsp_types::{
+        ClientCapabilities, DocumentChangeOperation, DocumentChanges, FileRename, OneOf, Position,
+        PrepareRenameResponse, Range, RenameFilesParams, RenameParams, TextDocumentEdit,
+        TextDocumentIdentifier, TextDocumentPositionParams, Url,
+    };
+
+    use crate::{config::Settings, vault::Vault};
+
+    fn rename_heading(links: &str, new_name: &str) -> Vec<(u32, u32, u32, String)> {
+        let root_dir = Path::new("/vault");
+        let settings = Settings::new(root_dir, &ClientCapabilities::default()).unwrap();
+        let mut vault = Vault::construct_vault(&settings, root_dir).unwrap();
+
+        let target = PathBuf::from("/vault/target.md");
+        let source = PathBuf::from("/vault/source.md");
+        Vault::update_vault(
+            &settings,
+            &mut vault,
+            (&target, "# Intro\n\nSome text ^block\n"),
+        );
+        Vault::update_vault(&settings, &mut vault, (&source, links));
+
+        let params = RenameParams {
+            text_document_position: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier {
+                    uri: Url::from_file_path(&target).unwrap(),
+                },
+                position: Position {
+                    line: 0,
+                    character: 3,
+                },
+            },
+            new_name: new_name.to_string(),
+            work_done_progress_params: Default::default(),
+        };
+
+        let Some(DocumentChanges::Operations(operations)) = super::rename(&vault, &params, &target)
+            .unwrap()
+            .document_changes
+        else {
+            panic!("expected document change operations")
+        };
+
+        operations
+            .into_iter()
+            .flat_map(|operation| match operation {
+                DocumentChangeOperation::Edit(edit)
+                    if edit.text_document.uri == Url::from_file_path(&source).unwrap() =>
+                {
+                    edit.edits
+                }
+                _ => vec![],
+            })
+            .map(|edit| match edit {
+                OneOf::Left(edit) => (
+                    edit.range.start.line,
+                    edit.range.start.character,
+                    edit.range.end.character,
+                    edit.new_text,
+                ),
+                OneOf::Right(edit) => panic!("unexpected annotated edit {:?}", edit),
+            })
+            .sorted()
+            .collect()
+    }
+
+    #[test]
+    fn heading_rename_updates_wiki_links() {
+        let edits = rename_heading(
+            "[[target#Intro]]\n[[target#Intro|shown]]\n[[target#^block]]\n[[target]]\n",
+            "Introduction",
+        );
+
+        assert_eq!(
+            edits,
+            vec![
+                (0, 9, 14, "Introduction".to_string()),
+                (1, 9, 14, "Introduction".to_string()),
+            ]
+        )
+    }
+
+    #[test]
+    fn heading_rename_keeps_the_spaces_around_the_heading() {
+        let edits = rename_heading(
+            "[[target# Intro ]]\n[[target#  Intro|shown]]\n",
+            "Introduction",
+        );
+
+        assert_eq!(
+            edits,
+            vec![
+                (0, 10, 15, "Introduction".to_string()),
+                (1, 11, 16, "Introduction".to_string()),
+            ]
+        )
+    }
+
+    #[test]
+    fn heading_rename_updates_markdown_links() {
+        let edits = rename_heading(
+            "[shown](target#Intro)\n[anchor](target#intro)\n[other](other#Intro)\n",
+            "Getting Started",
+        );
+
+        assert_eq!(
+            edits,
+            vec![
+                (0, 15, 20, "getting-started".to_string()),
+                (1, 16, 21, "getting-started".to_string()),
+            ]
+        )
+    }
+
+    #[test]
+    fn heading_rename_edits_the_target_and_not_the_display_text() {
+        let edits = rename_heading(
+            "[see #Intro](target#Intro)\n[[target#Intro|#Intro]]\n",
+            "Outro",
+        );
+
+        assert_eq!(
+            edits,
+            vec![
+                (0, 20, 25, "Outro".to_string()),
+                (1, 9, 14, "Outro".to_string()),
+            ]
+        )
+    }
+
+    #[test]
+    fn file_rename_keeps_an_escaped_display_pipe() {
+        let root_dir = Path::new("/vault");
+        let settings = Settings::new(root_dir, &ClientCapabilities::default()).unwrap();
+        let mut vault = Vault::construct_vault(&settings, root_dir).unwrap();
+        let source = PathBuf::from("/vault/source.md");
+        Vault::update_vault(
+            &settings,
+            &mut vault,
+            (&PathBuf::from("/vault/target.md"), "# Intro\n"),
+        );
+        Vault::update_vault(
+            &settings,
+            &mut vault,
+            (
+                &source,
+                "[[target|plain]]\n| [[target\\|shown]] | [[target#Intro\\|intro]] |\n",
+            ),
+        );
+
+        let params = RenameParams {
+            text_document_position: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier {
+                    uri: Url::from_file_path(&source).unwrap(),
+                },
+                position: Position {
+                    line: 0,
+                    character: 3,
+                },
+            },
+            new_name: "renamed".to_string(),
+            work_done_progress_params: Default::default(),
+        };
+        let Some(DocumentChanges::Operations(operations)) = super::rename(&vault, &params, &source)
+            .unwrap()
+            .document_changes
+        else {
+            panic!("expected document change operations")
+        };
+
+        let new_texts = operations
+            .into_iter()
+            .flat_map(|operation| match operation {
+                DocumentChangeOperation::Edit(edit) => edit.edits,
+                DocumentChangeOperation::Op(_) => vec![],
+            })
+            .map(|edit| match edit {
+                OneOf::Left(edit) => edit.new_text,
+                OneOf::Right(edit) => panic!("unexpected annotated edit {:?}", edit),
+            })
+            .sorted()
+            .collect_vec();
+
+        assert_eq!(
+            new_texts,
+            vec![
+                r"[[renamed#Intro\|intro]]",
+                r"[[renamed\|shown]]",
+                "[[renamed|plain]]",
+            ]
+        );
+    }
+
+    fn prepare_rename(text: &str, line: u32, character: u32) -> Option<(Range, String)> {
+        let root_dir = Path::new("/vault");
+        let settings = Settings::new(root_dir, &ClientCapabilities::default()).unwrap();
+        let mut vault = Vault::construct_vault(&settings, root_dir).unwrap();
+
+        let path = PathBuf::from("/vault/note.md");
+        Vault::update_vault(&settings, &mut vault, (&path, text));
+
+        let params = TextDocumentPositionParams {
+            text_document: TextDocumentIdentifier {
+                uri: Url::from_file_path(&path).unwrap(),
+            },
+            position: Position { line, character },
+        };
+
+        match super::prepare_rename(&vault, &params, &path)? {
+            PrepareRenameResponse::RangeWithPlaceholder { range, placeholder } => {
+                Some((range, placeholder))
+            }
+            response => panic!("unexpected response {:?}", response),
+        }
+    }
+
+    fn line_range(line: u32, start: u32, end: u32) -> Range {
+        Range {
+            start: Position {
+                line,
+                character: start,
+            },
+            end: Position {
+                line,
+                character: end,
+            },
+        }
+    }
+
+    #[test]
+    fn prepare_rename_in_file_query() {
+        let text = "# Title\n\nsee [[other#Part|shown]] and [shown](<other file>)\n";
+
+        assert_eq!(
+            prepare_rename(text, 2, 8),
+            Some((line_range(2, 6, 11), "other".to_string()))
+        );
+        assert_eq!(
+            prepare_rename(text, 2, 39),
+            Some((line_range(2, 38, 48), "other file".to_string()))
+        );
+    }
+
+    #[test]
+    fn prepare_rename_rejects_display_text() {
+        let text = "# Title\n\nsee [[other#Part|shown]] and [shown](<other file>)\n";
+
+        assert_eq!(prepare_rename(text, 2, 20), None);
+        assert_eq!(prepare_rename(text, 2, 31), None);
+        assert_eq!(prepare_rename(text, 1, 0), None);
+    }
+
+    #[test]
+    fn prepare_rename_on_heading() {
+        let text = "# Title\n\n## Sub heading\n";
+
+        assert_eq!(
+            prepare_rename(text, 2, 5),
+            Some((line_range(2, 3, 14), "Sub heading".to_string()))
+        );
+    }
+
+    #[test]
+    fn prepare_rename_on_setext_heading() {
+        let text = "Title\n=====\n\nSub heading\n---\n";
+
+        assert_eq!(
+            prepare_rename(text, 3, 2),
+            Some((line_range(3, 0, 11), "Sub heading".to_string()))
+        );
+    }
+
+    /// The text of `/vault/source.md` after applying the edits for renaming `old` to `new`
+    fn renamed_source(files: &[(&str, &str)], source: &str, old: &str, new: &str) -> String {
+        let root_dir = Path::new("/vault");
+        let settings = Settings::new(root_dir, &ClientCapabilities::default()).unwrap();
+        let mut vault = Vault::construct_vault(&settings, root_dir).unwrap();
+        let source_path = PathBuf::from("/vault/source.md");
+        for (path, text) in files.iter().chain([&("/vault/source.md", source)]) {
+            Vault::update_vault(&settings, &mut vault, (&PathBuf::from(path), text));
+        }
+
+        let params = RenameFilesParams {
+            files: vec![FileRename {
+                old_uri: Url::from_file_path(old).unwrap().to_string(),
+                new_uri: Url::from_file_path(new).unwrap().to_string(),
+            }],
+        };
+        let Some(DocumentChanges::Edits(document_edits)) = super::rename_files(&vault, &params)
+            .unwrap()
+            .document_changes
+        else {
+            panic!("expected document edits")
+        };
+
+        apply_edits(source, &source_path, document_edits)
+    }
+
+    /// `source` with the edits made to the document at `path`
+    fn apply_edits(source: &str, path: &Path, document_edits: Vec<TextDocumentEdit>) -> String {
+        let mut lines = source.lines().map(String::from).collect_vec();
+        document_edits
+            .into_iter()
+            .filter(|edit| edit.text_document.uri == Url::from_file_path(path).unwrap())
+            .flat_map(|edit| edit.edits)
+            .map(|edit| match edit {
+                OneOf::Left(edit) => edit,
+                OneOf::Right(edit) => panic!("unexpected annotated edit {:?}", edit),
+            })
+            .sorted_by_key(|edit| std::cmp::Reverse(edit.range.start))
+            .for_each(|edit| {
+                let line = &mut lines[edit.range.start.line as usize];
+                let chars = line.chars().collect_vec();
+                *line = chars[..edit.range.start.character as usize]
+                    .iter()
+                    .chain(edit.new_text.chars().collect_vec().iter())
+                    .chain(chars[edit.range.end.character as usize..].iter())
+                    .collect();
+            });
+
+        lines.join("\n")
+    }
+
+    #[test]
+    fn file_rename_updates_inbound_links() {
+        let files = [(
+            "/vault/old.md",
+            "---\naliases: [Alias]\n---\n# Heading\n\ntext ^blk\n",
+        )];
+        let source =
+            "[[old]] [[old|shown]] ![[old#Heading|shown]]\n[x](old.md) [y](old#^blk) [[Alias]]";
+
+        assert_eq!(
+            renamed_source(&files, source, "/vault/old.md", "/vault/new name.md"),
+            "[[new name]] [[new name|shown]] ![[new name#Heading|shown]]\n[x](new%20name.md) [y](new%20name#^blk) [[Alias]]"
+        );
+    }
+
+    #[test]
+    fn file_rename_skips_links_to_notes_with_the_same_name() {
+        let files = [("/vault/a/dup.md", "# A\n"), ("/vault/b/dup.md", "# B\n")];
+        let source = "[[dup]] [[a/dup]] [[b/dup]]";
+
+        assert_eq!(
+            renamed_source(&files, source, "/vault/a/dup.md", "/vault/a/renamed.md"),
+            "[[dup]] [[a/renamed]] [[b/dup]]"
+        );
+    }
+
+    #[test]
+    fn file_rename_updates_links_to_a_folder_note() {
+        let files = [("/vault/projects/projects.md", "# Plan\n")];
+        let source = "[[projects/]] [[projects/#Plan|plan]] [x](projects/)";
+
+        assert_eq!(
+            renamed_source(
+                &files,
+                source,
+                "/vault/projects/projects.md",
+                "/vault/projects/plans.md"
+            ),
+            "[[projects/plans]] [[projects/plans#Plan|plan]] [x](projects/plans)"
+        );
+    }
+
+    #[test]
+    fn file_rename_keeps_relative_markdown_links_relative() {
+        let root_dir = Path::new("/vault");
+        let mut settings = Settings::new(root_dir, &ClientCapabilities::default()).unwrap();
+        settings.relative_markdown_links = true;
+        let mut vault = Vault::construct_vault(&settings, root_dir).unwrap();
+        let source_path = PathBuf::from("/vault/a/b/source.md");
+        let source = "[x](../sibling.md) [y](../sibling.md#Intro) [z](./../sibling.md) [[sibling]]";
+        Vault::update_vault(
+            &settings,
+            &mut vault,
+            (&PathBuf::from("/vault/a/sibling.md"), "# Intro\n"),
+        );
+        Vault::update_vault(&settings, &mut vault, (&source_path, source));
+
+        let params = RenameFilesParams {
+            files: vec![FileRename {
+                old_uri: Url::from_file_path("/vault/a/sibling.md")
+                    .unwrap()
+                    .to_string(),
+                new_uri: Url::from_file_path("/vault/c/renamed.md")
+                    .unwrap()
+                    .to_string(),
+            }],
+        };
+        let Some(DocumentChanges::Edits(document_edits)) = super::rename_files(&vault, &params)
+            .unwrap()
+            .document_changes
+        else {
+            panic!("expected document edits")
+        };
+
+        assert_eq!(
+            apply_edits(source, &source_path, document_edits),
+            "[x](../../c/renamed.md) [y](../../c/renamed.md#Intro) [z](./../../c/renamed.md) [[renamed]]"
+        );
+    }
+
+    #[test]
+    fn file_rename_goes_on_past_uris_that_are_not_files() {
+        let root_dir = Path::new("/vault");
+        let settings = Settings::new(root_dir, &ClientCapabilities::default()).unwrap();
+        let mut vault = Vault::construct_vault(&settings, root_dir).unwrap();
+        let source_path = PathBuf::from("/vault/source.md");
+        let source = "[[old]]";
+        Vault::update_vault(&settings, &mut vault, (&PathBuf::from("/vault/old.md"), ""));
+        Vault::update_vault(&settings, &mut vault, (&source_path, source));
+
+        let params = RenameFilesParams {
+            files: vec![
+                FileRename {
+                    old_uri: "untitled:Untitled-1".to_string(),
+                    new_uri: "untitled:Untitled-2".to_string(),
+                },
+                FileRename {
+                    old_uri: Url::from_file_path("/vault/old.md").unwrap().to_string(),
+                    new_uri: Url::from_file_path("/vault/new.md").unwrap().to_string(),
+                },
+            ],
+        };
+        let Some(DocumentChanges::Edits(document_edits)) = super::rename_files(&vault, &params)
+            .unwrap()
+            .document_changes
+        else {
+            panic!("expected document edits")
+        };
+
+        assert_eq!(apply_edits(source, &source_path, document_edits), "[[new]]");
+    }
+
+    /// The text of each file after renaming the tag at `line` and `character` of the first one to `new`
+    fn renamed_tag(files: &[(&str, &str)], line: u32, character: u32, new: &str) -> Vec<String> {
+        let root_dir = Path::new("/vault");
+        let settings = Settings::new(root_dir, &ClientCapabilities::default()).unwrap();
+        let mut vault = Vault::construct_vault(&settings, root_dir).unwrap();
+        for (path, text) in files {
+            Vault::update_vault(&settings, &mut vault, (&PathBuf::from(path), text));
+        }
+
+        let path = PathBuf::from(files[0].0);
+        let params = RenameParams {
+            text_document_position: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier {
+                    uri: Url::from_file_path(&path).unwrap(),
+                },
+                position: Position { line, character },
+            },
+            new_name: new.to_string(),
+            work_done_progress_params: Default::default(),
+        };
+        let Some(DocumentChanges::Operations(operations)) = super::rename(&vault, &params, &path)
+            .unwrap()
+            .document_changes
+        else {
+            panic!("expected document change operations")
+        };
+        let document_edits = operations
+            .into_iter()
+            .map(|operation| match operation {
+                DocumentChangeOperation::Edit(edit) => edit,
+                DocumentChangeOperation::Op(op) => panic!("unexpected operation {:?}", op),
+            })
+            .collect_vec();
+
+        files
+            .iter()
+            .map(|(path, text)| apply_edits(text, Path::new(path), document_edits.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn tag_rename_updates_inline_and_nested_tags() {
+        let files = [
+            (
+                "/vault/a.md",
+                "#todo and #todo/urgent
+#todoist stays",
+            ),
+            ("/vault/b.md", "- [ ] write #todo/later/maybe"),
+        ];
+
+        assert_eq!(
+            renamed_tag(&files, 0, 2, "task"),
+            vec![
+                "#task and #task/urgent\n#todoist stays",
+                "- [ ] write #task/later/maybe"
+            ]
+        );
+    }
+
+    #[test]
+    fn tag_rename_updates_frontmatter_tags() {
+        let files = [
+            ("/vault/a.md", "#todo"),
+            (
+                "/vault/b.md",
+                "---\ntags: [todo, other, \"#todo/urgent\"]\n---\ntext",
+            ),
+            ("/vault/c.md", "---\ntags:\n  - todo\n---\n"),
+        ];
+
+        assert_eq!(
+            renamed_tag(&files, 0, 2, "task"),
+            vec![
+                "#task",
+                "---\ntags: [task, other, \"#task/urgent\"]\n---\ntext",
+                "---\ntags:\n  - task\n---",
+            ]
+        );
+    }
+
+    #[test]
+    fn tag_rename_skips_code() {
+        let files = [(
+            "/vault/a.md",
+            "#todo `#todo`\n```sh\necho #todo\n```\nsee https://example.com/#todo",
+        )];
+
+        assert_eq!(
+            renamed_tag(&files, 0, 2, "task"),
+            vec!["#task `#todo`\n```sh\necho #todo\n```\nsee https://example.com/#todo"]
+        );
+    }
+}
@@ -0,0 +1,454 @@
+use std::path::Path;
+
+use itertools::Itertools;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use tower_lsp::lsp_types::{Position, Range, SelectionRange};
+
+use crate::{
+    config::Settings,
+    vault::{Rangeable, Vault},
+};
+
+/// What expanding the selection grows through from each position, innermost first: the part of a link under the
+/// cursor, like its file or display text, the whole link, the list items and paragraph around it, then every heading
+/// section around it and the whole note. On a heading it grows from the heading's text to its line and its section.
+pub fn selection_ranges(
+    vault: &Vault,
+    settings: &Settings,
+    path: &Path,
+    positions: &[Position],
+) -> Option<Vec<SelectionRange>> {
+    let end = vault.select_end_position(path)?;
+    let encoding = settings.position_encoding;
+    let encode = |position: Position| Position {
+        character: encoding.column(
+            &line_chars(vault, path, position.line),
+            position.character as usize,
+        ),
+        ..position
+    };
+
+    Some(
+        positions
+            .iter()
+            .map(|position| {
+                let position = Position {
+                    character: encoding
+                        .char_index(&line_chars(vault, path, position.line), position.character)
+                        as u32,
+                    ..*position
+                };
+
+                let ranges = link_ranges(vault, path, position)
+                    .into_iter()
+                    .chain(heading_ranges(vault, path, position))
+                    .chain(block_ranges(vault, path, position))
+                    .chain(section_ranges(vault, path, position))
+                    .chain([Range {
+                        start: Position::default(),
+                        end,
+                    }]);
+
+                // each range has to hold the one before it, and an equal range would be a step that selects nothing new
+                let nested = ranges.fold(Vec::<Range>::new(), |mut nested, range| {
+                    let inner = nested.last().map_or(
+                        Range {
+                            start: position,
+                            end: position,
+                        },
+                        |inner| *inner,
+                    );
+                    if contains(&range, &inner) && Some(&range) != nested.last() {
+                        nested.push(range);
+                    }
+                    nested
+                });
+
+                nested
+                    .into_iter()
+                    .rev()
+                    .fold(None, |parent, range| {
+                        Some(SelectionRange {
+                            range: Range {
+                                start: encode(range.start),
+                                end: encode(range.end),
+                            },
+                            parent: parent.map(Box::new),
+                        })
+                    })
+                    .unwrap_or(SelectionRange {
+                        range: Range {
+                            start: encode(position),
+                            end: encode(position),
+                        },
+                        parent: None,
+                    })
+            })
+            .collect(),
+    )
+}
+
+/// The line without its line break
+fn line_chars(vault: &Vault, path: &Path, line: u32) -> Vec<char> {
+    let mut chars = vault.select_line(path, line as isize).unwrap_or_default();
+    if chars.last() == Some(&'\n') {
+        chars.pop();
+    }
+    chars
+}
+
+fn contains(outer: &Range, inner: &Range) -> bool {
+    (outer.start.line, outer.start.character) <= (inner.start.line, inner.start.character)
+        && (outer.end.line, outer.end.character) >= (inner.end.line, inner.end.character)
+}
+
+/// From the start of `start` to the end of `end`
+fn lines(vault: &Vault, path: &Path, start: u32, end: u32) -> Range {
+    Range {
+        start: Position {
+            line: start,
+            character: 0,
+        },
+        end: Position {
+            line: end,
+            character: line_chars(vault, path, end).len() as u32,
+        },
+    }
+}
+
+/// The parts of the link under the cursor that hold it, smallest first, then the link: in `[[file#heading|display]]`
+/// the file or the heading, then `file#heading`, or the display text; in `[display](file#heading)` the same
+fn link_ranges(vault: &Vault, path: &Path, position: Position) -> Vec<Range> {
+    let Some(link) = vault
+        .select_references(Some(path))
+        .into_iter()
+        .flatten()
+        .map(|(_, reference)| *reference.data().range)
+        .find(|range| {
+            range.start.line == position.line
+                && range.end.line == position.line
+                && contains(range, &Range::new(position, position))
+        })
+    else {
+        return vec![];
+    };
+
+    let line = line_chars(vault, path, position.line);
+    let (start, end) = (link.start.character as usize, link.end.character as usize);
+    let Some(text) = line.get(start..end) else {
+        return vec![];
+    };
+    let find = |from: usize, to: usize, c: char| (from..to).find(|i| text[*i] == c);
+
+    let parts = match text {
+        ['!', '[', '[', ..] | ['[', '[', ..] if text.ends_with(&[']', ']']) => {
+            let inner_start = text.iter().position(|c| *c == '[').unwrap_or(0) + 2;
+            let inner_end = text.len() - 2;
+            match find(inner_start, inner_end, '|') {
+                // a table escapes the pipe: `[[file\|display]]`
+                Some(pipe) if text[pipe - 1] == '\\' => {
+                    vec![(inner_start, pipe - 1), (pipe + 1, inner_end)]
+                }
+                Some(pipe) => vec![(inner_start, pipe), (pipe + 1, inner_end)],
+                None => vec![(inner_start, inner_end)],
+            }
+        }
+        ['!', '[', ..] | ['[', ..] if text.ends_with(&[')']) => {
+            let display_start = text.iter().position(|c| *c == '[').unwrap_or(0) + 1;
+            match (display_start..text.len())
+                .find(|i| text[*i] == ']' && text.get(i + 1) == Some(&'('))
+            {
+                Some(close) => vec![(close + 2, text.len() - 1), (display_start, close)],
+                None => vec![],
+            }
+        }
+        _ => vec![],
+    };
+
+    // the first part is the link's target, which splits into the file and what is linked in it
+    let target_parts = parts.first().and_then(|(target_start, target_end)| {
+        let hash = find(*target_start, *target_end, '#')?;
+        Some([(*target_start, hash), (hash + 1, *target_end)])
+    });
+
+    let cursor = position.character as usize - start;
+    target_parts
+        .into_iter()
+        .flatten()
+        .chain(parts)
+        .filter(|(part_start, part_end)| {
+            part_start < part_end && (*part_start..=*part_end).contains(&cursor)
+        })
+        .sorted_by_key(|(part_start, part_end)| part_end - part_start)
+        .map(|(part_start, part_end)| Range {
+            start: Position {
+                line: position.line,
+                character: (start + part_start) as u32,
+            },
+            end: Position {
+                line: position.line,
+                character: (start + part_end) as u32,
+            },
+        })
+        .chain([link])
+        .collect()
+}
+
+/// On a heading, its text without the `#`s, then the heading
+fn heading_ranges(vault: &Vault, path: &Path, position: Position) -> Vec<Range> {
+    let Some(heading) = vault
+        .select_headings(path)
+        .into_iter()
+        .flatten()
+        .find(|heading| {
+            (heading.range.start.line..=heading.range.end.line).contains(&position.line)
+        })
+    else {
+        return vec![];
+    };
+
+    let line = line_chars(vault, path, heading.range.start.line);
+    let text_start = line
+        .iter()
+        .position(|c| *c != '#' && !c.is_whitespace())
+        .unwrap_or(line.len());
+    let text_end = line
+        .iter()
+        .rposition(|c| !c.is_whitespace())
+        .map_or(text_start, |last| last + 1);
+
+    let text = Range {
+        start: Position {
+            line: heading.range.start.line,
+            character: text_start as u32,
+        },
+        end: Position {
+            line: heading.range.start.line,
+            character: text_end.max(text_start) as u32,
+        },
+    };
+
+    vec![text, *heading.range]
+}
+
+/// A list item's marker and the indent before it
+pub(crate) static LIST_ITEM: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^(?<indent>\s*)([-*+]|\d+[.)])(\s|$)").unwrap());
+
+/// The code block the cursor is in, or the list items around it, innermost first, and then their paragraph
+pub(crate) fn block_ranges(vault: &Vault, path: &Path, position: Position) -> Vec<Range> {
+    let codeblock = |line: u32| {
+        vault.md_files.get(path).and_then(|md_file| {
+            md_file.codeblocks.iter().find(|codeblock| {
+                (codeblock.range().start.line..=codeblock.range().end.line).contains(&line)
+            })
+        })
+    };
+    if let Some(codeblock) = codeblock(position.line) {
+        return vec![**codeblock.range()];
+    }
+
+    let text = |line: u32| String::from_iter(line_chars(vault, path, line));
+    let is_heading = |line: u32| {
+        vault.select_headings(path).is_some_and(|headings| {
+            headings
+                .iter()
+                .any(|heading| (heading.range.start.line..=heading.range.end.line).contains(&line))
+        })
+    };
+    let frontmatter_end = vault.select_frontmatter_end_line(path);
+    // the frontmatter is YAML and a table row is a cell of its table, so neither is paragraph text
+    let in_paragraph = |line: u32| {
+        let text = text(line);
+        !text.trim().is_empty()
+            && !text.trim_start().starts_with('|')
+            && frontmatter_end.is_none_or(|end| line as usize > end)
+            && !is_heading(line)
+            && codeblock(line).is_none()
+    };
+
+    let last_line = vault.select_end_position(path).map_or(0, |end| end.line);
+    if !in_paragraph(position.line) {
+        return vec![];
+    }
+    let paragraph_start = (0..position.line)
+        .rev()
+        .take_while(|line| in_paragraph(*line))
+        .last()
+        .unwrap_or(position.line);
+    let paragraph_end = (position.line + 1..=last_line)
+        .take_while(|line| in_paragraph(*line))
+        .last()
+        .unwrap_or(position.line);
+
+    // each list item holds the lines below it that are indented further than its marker
+    let indent = |line: u32| {
+        LIST_ITEM
+            .captures(&text(line))
+            .map(|captures| captures["indent"].chars().count())
+    };
+    let mut items = Vec::new();
+    let mut below_indent = usize::MAX;
+    for item_start in (paragraph_start..=position.line).rev() {
+        let Some(item_indent) = indent(item_start).filter(|indent| *indent < below_indent) else {
+            continue;
+        };
+        let item_end = (item_start + 1..=paragraph_end)
+            .take_while(|line| indent(*line).is_none_or(|indent| indent > item_indent))
+            .last()
+            .unwrap_or(item_start);
+        if item_end >= position.line {
+            let mut item = lines(vault, path, item_start, item_end);
+            item.start.character = item_indent as u32;
+            items.push(item);
+        }
+        below_indent = item_indent;
+    }
+
+    items
+        .into_iter()
+        .chain([lines(vault, path, paragraph_start, paragraph_end)])
+        .collect()
+}
+
+/// The sections of the headings the cursor is under, from the innermost, each without its trailing blank lines
+fn section_ranges(vault: &Vault, path: &Path, position: Position) -> Vec<Range> {
+    let Some(headings) = vault.select_headings(path) else {
+        return vec![];
+    };
+    let last_line = vault.select_end_position(path).map_or(0, |end| end.line);
+
+    headings
+        .iter()
+        .enumerate()
+        .filter_map(|(i, heading)| {
+            let section_end = headings[i + 1..]
+                .iter()
+                .find(|next| next.level <= heading.level)
+                .map(|next| next.range.start.line.saturating_sub(1))
+                .unwrap_or(last_line);
+            let section_end = (heading.range.start.line..=section_end)
+                .rev()
+                .find(|line| {
+                    !line_chars(vault, path, *line)
+                        .iter()
+                        .all(|c| c.is_whitespace())
+                })
+                .unwrap_or(heading.range.start.line);
+
+            (heading.range.start.line..=section_end)
+                .contains(&position.line)
+                .then(|| lines(vault, path, heading.range.start.line, section_end))
+        })
+        .rev()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use tower_lsp::lsp_types::{ClientCapabilities, Position, SelectionRange};
+
+    use crate::{config::Settings, vault::Vault};
+
+    use super::selection_ranges;
+
+    /// The text of each step of expanding the selection from `line`, `character`, innermost first
+    fn expansions(text: &str, line: u32, character: u32) -> Vec<String> {
+        let root_dir = Path::new("/vault");
+        let settings = Settings::new(root_dir, &ClientCapabilities::default()).unwrap();
+        let mut vault = Vault::construct_vault(&settings, root_dir).unwrap();
+        let path = PathBuf::from("/vault/note.md");
+        Vault::update_vault(&settings, &mut vault, (&path, text));
+
+        let ranges =
+            selection_ranges(&vault, &settings, &path, &[Position { line, character }]).unwrap();
+        assert_eq!(ranges.len(), 1);
+
+        let rope = ropey::Rope::from_str(text);
+        let mut texts = Vec::new();
+        let mut selection: Option<&SelectionRange> = ranges.first();
+        while let Some(current) = selection {
+            let start = rope.line_to_char(current.range.start.line as usize)
+                + current.range.start.character as usize;
+            let end = rope.line_to_char(current.range.end.line as usize)
+                + current.range.end.character as usize;
+            texts.push(rope.slice(start..end).to_string());
+            selection = current.parent.as_deref();
+        }
+        texts
+    }
+
+    #[test]
+    fn links_expand_from_their_parts_to_the_section() {
+        let text = "# Top\n\n## Tasks\n\n- first [[Other Note#Intro|the intro]] here\n  still first\n- second\n\n# Next\n";
+
+        // in the file of the link
+        assert_eq!(
+            expansions(text, 4, 12),
+            vec![
+                "Other Note",
+                "Other Note#Intro",
+                "[[Other Note#Intro|the intro]]",
+                "- first [[Other Note#Intro|the intro]] here\n  still first",
+                "- first [[Other Note#Intro|the intro]] here\n  still first\n- second",
+                "## Tasks\n\n- first [[Other Note#Intro|the intro]] here\n  still first\n- second",
+                "# Top\n\n## Tasks\n\n- first [[Other Note#Intro|the intro]] here\n  still first\n- second",
+                text,
+            ]
+        );
+        // in the display text
+        assert_eq!(
+            &expansions(text, 4, 30)[..2],
+            &["the intro", "[[Other Note#Intro|the intro]]"]
+        );
+    }
+
+    #[test]
+    fn markdown_links_expand_through_target_and_display() {
+        let text = "see [shown](folder/file.md#part) and more\nsecond line";
+
+        assert_eq!(
+            expansions(text, 0, 15),
+            vec![
+                "folder/file.md",
+                "folder/file.md#part",
+                "[shown](folder/file.md#part)",
+                "see [shown](folder/file.md#part) and more\nsecond line",
+            ]
+        );
+        assert_eq!(&expansions(text, 0, 6)[..1], &["shown"]);
+    }
+
+    #[test]
+    fn headings_expand_from_their_text_to_their_section() {
+        let text = "# Top\n\n## Sub heading\ntext\n\n";
+
+        assert_eq!(
+            expansions(text, 2, 6),
+            vec![
+                "Sub heading",
+                "## Sub heading",
+                "## Sub heading\ntext",
+                "# Top\n\n## Sub heading\ntext",
+                text,
+            ]
+        );
+    }
+
+    #[test]
+    fn nested_list_items_expand_to_their_parents() {
+        let text = "- parent\n  - child\n    more child\n  - sibling\n- next";
+
+        assert_eq!(
+            expansions(text, 1, 6),
+            vec![
+                "- child\n    more child",
+                "- parent\n  - child\n    more child\n  - sibling",
+                text,
+            ]
+        );
+    }
+}
@@ -1,31 +1,30 @@
 use std::{iter, path::Path};
 
 use itertools::Itertools;
-use nucleo_matcher::{
-    pattern::{self, Normalization},
-    Matcher,
-};
 use tower_lsp::lsp_types::{
-    DocumentSymbol, DocumentSymbolParams, DocumentSymbolResponse, Location, SymbolInformation,
-    SymbolKind, Url, WorkspaceSymbolParams,
+    DocumentSymbol, DocumentSymbolParams, DocumentSymbolResponse, Location, Position, Range,
+    SymbolInformation, SymbolKind, Url, WorkspaceSymbolParams,
+};
+
+use crate::{
+    completion::matcher::{fuzzy_match, Matchable},
+    config::Settings,
+    vault::{MDHeading, MDIndexedBlock, Referenceable, Vault},
 };
 
-use crate::vault::{MDHeading, Referenceable, Vault};
+struct MatchableSymbol(SymbolInformation);
+
+impl Matchable for MatchableSymbol {
+    fn match_string(&self) -> &str {
+        &self.0.name
+    }
+}
 
 pub fn workspace_symbol(
     vault: &Vault,
-    _params: &WorkspaceSymbolParams,
+    params: &WorkspaceSymbolParams,
+    settings: &Settings,
 ) -> Option<Vec<SymbolInformation>> {
-    // Initialize the fuzzy matcher
-    let mut matcher = Matcher::new(nucleo_matcher::Config::DEFAULT);
-    let pattern = pattern::Pattern::parse(
-        &_params.query,
-        pattern::CaseMatching::Smart,
-        Normalization::Smart,
-    );
-    let mut buf = Vec::new();
-
-    // Collect symbols and order by fuzzy matching score
     let referenceables = vault.select_referenceable_nodes(None);
     let symbol_informations = referenceables
         .into_iter()
@@ -44,50 +43,88 @@ pub fn workspace_symbol(
                 _ => *referenceable.get_range()?,
             };
 
-            Some(SymbolInformation {
-                name: referenceable.get_refname(vault.root_dir())?.to_string(),
-                kind: match referenceable {
-                    Referenceable::File(_, _) => SymbolKind::FILE,
-                    Referenceable::Tag(_, _) => SymbolKind::CONSTANT,
-                    _ => SymbolKind::KEY,
-                },
-                location: Location {
-                    uri: Url::from_file_path(referenceable.get_path()).ok()?,
-                    range,
-                },
-                container_name: None,
-                tags: None,
-                deprecated: None,
-            })
-        })
-        // Fuzzy matcher - compute match score
-        .map(|symbol| {
-            let score = pattern.score(
-                nucleo_matcher::Utf32Str::new(symbol.name.as_str(), &mut buf),
-                &mut matcher,
-            );
-            (score, symbol)
+            let location = Location {
+                uri: Url::from_file_path(referenceable.get_path()).ok()?,
+                range,
+            };
+            let name = referenceable.get_refname(vault.root_dir())?.to_string();
+
+            // files can also be found by their aliases
+            let aliases = match referenceable {
+                Referenceable::File(_, mdfile) => mdfile
+                    .metadata
+                    .iter()
+                    .flat_map(|metadata| metadata.aliases())
+                    .cloned()
+                    .collect_vec(),
+                _ => vec![],
+            };
+
+            Some(
+                iter::once(SymbolInformation {
+                    name: name.clone(),
+                    kind: match referenceable {
+                        Referenceable::File(_, _) => SymbolKind::FILE,
+                        Referenceable::Tag(_, _) => SymbolKind::CONSTANT,
+                        _ => SymbolKind::KEY,
+                    },
+                    location: location.clone(),
+                    container_name: None,
+                    tags: None,
+                    deprecated: None,
+                })
+                .chain(aliases.into_iter().map(move |alias| SymbolInformation {
+                    name: alias,
+                    kind: SymbolKind::FILE,
+                    location: location.clone(),
+                    container_name: Some(name.clone()),
+                    tags: None,
+                    deprecated: None,
+                }))
+                .collect_vec(),
+            )
         })
-        // Remove all items with no matches
-        .filter(|(score, _)| score.is_some() && score.unwrap() > 0)
-        // Sort by match score descending
-        .sorted_by(|(a, _), (b, _)| Option::cmp(b, a))
-        // Strip the score from the result
-        .map(|(_score, symbol)| symbol)
+        .flatten()
+        .map(MatchableSymbol);
+
+    // Same fuzzy matching as link completions; matches come ordered by score
+    let matches = fuzzy_match(&params.query, symbol_informations, &settings.case_matching)
+        .into_iter()
+        .map(|(symbol, _)| symbol.0)
         .collect_vec();
 
-    Some(symbol_informations)
+    Some(matches)
 }
 
 pub fn document_symbol(
     vault: &Vault,
     _params: &DocumentSymbolParams,
     path: &Path,
+    settings: &Settings,
 ) -> Option<DocumentSymbolResponse> {
     let headings = vault.select_headings(path)?;
+    let end_of_file = vault.select_end_position(path)?;
+
+    let tree = construct_tree(headings).unwrap_or_default();
+    let mut lsp = map_to_lsp_tree(tree, end_of_file);
 
-    let tree = construct_tree(headings)?;
-    let lsp = map_to_lsp_tree(tree);
+    if settings.block_symbols {
+        let blocks = vault
+            .select_referenceable_nodes(Some(path))
+            .into_iter()
+            .filter_map(|referenceable| match referenceable {
+                Referenceable::IndexedBlock(_, block) => Some(block),
+                _ => None,
+            });
+
+        for block in blocks {
+            insert_block_symbol(&mut lsp, block);
+        }
+    }
+
+    if lsp.is_empty() {
+        return None;
+    }
 
     Some(DocumentSymbolResponse::Nested(lsp))
 }
@@ -139,60 +176,153 @@ fn construct_tree(headings: &[MDHeading]) -> Option<Vec<Node>> {
     }
 }
 
-fn map_to_lsp_tree(tree: Vec<Node>) -> Vec<DocumentSymbol> {
+/// The range of each symbol covers its whole section: from the heading to the start of the next heading
+/// of the same or higher level (the next sibling), or to `end` for the last node at a level.
+fn map_to_lsp_tree(tree: Vec<Node>, end: Position) -> Vec<DocumentSymbol> {
+    let section_ends = tree
+        .iter()
+        .skip(1)
+        .map(|next| Position {
+            line: next.heading.range.start.line,
+            character: 0,
+        })
+        .chain(iter::once(end))
+        .collect_vec();
+
     tree.into_iter()
-        .map(|node| DocumentSymbol {
+        .zip(section_ends)
+        .map(|(node, section_end)| DocumentSymbol {
             name: node.heading.heading_text,
             kind: SymbolKind::STRUCT,
             deprecated: None,
             tags: None,
-            range: *node.heading.range,
+            range: Range {
+                start: node.heading.range.start,
+                end: section_end,
+            },
             detail: None,
             selection_range: *node.heading.range,
-            children: node.children.map(map_to_lsp_tree),
+            children: node
+                .children
+                .map(|children| map_to_lsp_tree(children, section_end)),
         })
         .collect()
 }
 
+/// Add an indexed block as a leaf of the deepest section containing it
+fn insert_block_symbol(symbols: &mut Vec<DocumentSymbol>, block: &MDIndexedBlock) {
+    let containing = symbols.iter_mut().find(|symbol| {
+        symbol.range.start.line <= block.range.start.line
+            && (symbol.range.end.line > block.range.start.line
+                || (symbol.range.end.line == block.range.start.line
+                    && symbol.range.end.character > 0))
+    });
+
+    match containing {
+        Some(symbol) => insert_block_symbol(symbol.children.get_or_insert_with(Vec::new), block),
+        None => {
+            symbols.push(DocumentSymbol {
+                name: format!("^{}", block.index),
+                kind: SymbolKind::KEY,
+                deprecated: None,
+                tags: None,
+                range: *block.range,
+                detail: None,
+                selection_range: *block.range,
+                children: None,
+            });
+            symbols.sort_by_key(|symbol| (symbol.range.start.line, symbol.range.start.character));
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
+    use std::path::{Path, PathBuf};
+
+    use itertools::Itertools;
+    use tower_lsp::lsp_types::{
+        ClientCapabilities, Position, Range, SymbolKind, WorkspaceSymbolParams,
+    };
+
     use crate::{
+        config::Settings,
         symbol,
-        vault::{HeadingLevel, MDHeading},
+        vault::{HeadingLevel, MDHeading, MDIndexedBlock, MyRange, Vault},
     };
 
+    fn heading(level: usize, text: &str, line: u32) -> MDHeading {
+        MDHeading {
+            level: HeadingLevel(level),
+            heading_text: text.to_string(),
+            plain_text: text.to_string(),
+            range: line_range(line, level as u32 + 1 + text.len() as u32),
+            loose_text: None,
+            anchor: String::new(),
+        }
+    }
+
+    fn line_range(line: u32, length: u32) -> MyRange {
+        Range {
+            start: Position { line, character: 0 },
+            end: Position {
+                line,
+                character: length,
+            },
+        }
+        .into()
+    }
+
     #[test]
     fn test_simple_tree() {
         let headings = vec![
             MDHeading {
                 level: HeadingLevel(1),
                 heading_text: "First".to_string(),
+                plain_text: "First".to_string(),
                 range: Default::default(),
+                loose_text: None,
+                anchor: String::new(),
             },
             MDHeading {
                 level: HeadingLevel(2),
                 heading_text: "Second".to_string(),
+                plain_text: "Second".to_string(),
                 range: Default::default(),
+                loose_text: None,
+                anchor: String::new(),
             },
             MDHeading {
                 level: HeadingLevel(3),
                 heading_text: "Third".to_string(),
+                plain_text: "Third".to_string(),
                 range: Default::default(),
+                loose_text: None,
+                anchor: String::new(),
             },
             MDHeading {
                 level: HeadingLevel(2),
                 heading_text: "Second".to_string(),
+                plain_text: "Second".to_string(),
                 range: Default::default(),
+                loose_text: None,
+                anchor: String::new(),
             },
             MDHeading {
                 level: HeadingLevel(1),
                 heading_text: "First".to_string(),
+                plain_text: "First".to_string(),
                 range: Default::default(),
+                loose_text: None,
+                anchor: String::new(),
             },
             MDHeading {
                 level: HeadingLevel(1),
                 heading_text: "First".to_string(),
+                plain_text: "First".to_string(),
                 range: Default::default(),
+                loose_text: None,
+                anchor: String::new(),
             },
         ];
 
@@ -203,20 +333,29 @@ mod test {
                 heading: MDHeading {
                     level: HeadingLevel(1),
                     heading_text: "First".to_string(),
+                    plain_text: "First".to_string(),
                     range: Default::default(),
+                    loose_text: None,
+                    anchor: String::new(),
                 },
                 children: Some(vec![
                     symbol::Node {
                         heading: MDHeading {
                             level: HeadingLevel(2),
                             heading_text: "Second".to_string(),
+                            plain_text: "Second".to_string(),
                             range: Default::default(),
+                            loose_text: None,
+                            anchor: String::new(),
                         },
                         children: Some(vec![symbol::Node {
                             heading: MDHeading {
                                 level: HeadingLevel(3),
                                 heading_text: "Third".to_string(),
+                                plain_text: "Third".to_string(),
                                 range: Default::default(),
+                                loose_text: None,
+                                anchor: String::new(),
                             },
                             children: None,
                         }]),
@@ -225,7 +364,10 @@ mod test {
                         heading: MDHeading {
                             level: HeadingLevel(2),
                             heading_text: "Second".to_string(),
+                            plain_text: "Second".to_string(),
                             range: Default::default(),
+                            loose_text: None,
+                            anchor: String::new(),
                         },
                         children: None,
                     },
@@ -235,7 +377,10 @@ mod test {
                 heading: MDHeading {
                     level: HeadingLevel(1),
                     heading_text: "First".to_string(),
+                    plain_text: "First".to_string(),
                     range: Default::default(),
+                    loose_text: None,
+                    anchor: String::new(),
                 },
                 children: None,
             },
@@ -243,7 +388,10 @@ mod test {
                 heading: MDHeading {
                     level: HeadingLevel(1),
                     heading_text: "First".to_string(),
+                    plain_text: "First".to_string(),
                     range: Default::default(),
+                    loose_text: None,
+                    anchor: String::new(),
                 },
                 children: None,
             },
@@ -258,27 +406,42 @@ mod test {
             MDHeading {
                 level: HeadingLevel(1),
                 heading_text: "First".to_string(),
+                plain_text: "First".to_string(),
                 range: Default::default(),
+                loose_text: None,
+                anchor: String::new(),
             },
             MDHeading {
                 level: HeadingLevel(2),
                 heading_text: "Second".to_string(),
+                plain_text: "Second".to_string(),
                 range: Default::default(),
+                loose_text: None,
+                anchor: String::new(),
             },
             MDHeading {
                 level: HeadingLevel(3),
                 heading_text: "Third".to_string(),
+                plain_text: "Third".to_string(),
                 range: Default::default(),
+                loose_text: None,
+                anchor: String::new(),
             },
             MDHeading {
                 level: HeadingLevel(1),
                 heading_text: "First".to_string(),
+                plain_text: "First".to_string(),
                 range: Default::default(),
+                loose_text: None,
+                anchor: String::new(),
             },
             MDHeading {
                 level: HeadingLevel(1),
                 heading_text: "First".to_string(),
+                plain_text: "First".to_string(),
                 range: Default::default(),
+                loose_text: None,
+                anchor: String::new(),
             },
         ];
 
@@ -289,19 +452,28 @@ mod test {
                 heading: MDHeading {
                     level: HeadingLevel(1),
                     heading_text: "First".to_string(),
+                    plain_text: "First".to_string(),
                     range: Default::default(),
+                    loose_text: None,
+                    anchor: String::new(),
                 },
                 children: Some(vec![symbol::Node {
                     heading: MDHeading {
                         level: HeadingLevel(2),
                         heading_text: "Second".to_string(),
+                        plain_text: "Second".to_string(),
                         range: Default::default(),
+                        loose_text: None,
+                        anchor: String::new(),
                     },
                     children: Some(vec![symbol::Node {
                         heading: MDHeading {
                             level: HeadingLevel(3),
                             heading_text: "Third".to_string(),
+                            plain_text: "Third".to_string(),
                             range: Default::default(),
+                            loose_text: None,
+                            anchor: String::new(),
                         },
                         children: None,
                     }]),
@@ -311,7 +483,10 @@ mod test {
                 heading: MDHeading {
                     level: HeadingLevel(1),
                     heading_text: "First".to_string(),
+                    plain_text: "First".to_string(),
                     range: Default::default(),
+                    loose_text: None,
+                    anchor: String::new(),
                 },
                 children: None,
             },
@@ -319,7 +494,10 @@ mod test {
                 heading: MDHeading {
                     level: HeadingLevel(1),
                     heading_text: "First".to_string(),
+                    plain_text: "First".to_string(),
                     range: Default::default(),
+                    loose_text: None,
+                    anchor: String::new(),
                 },
                 children: None,
             },
@@ -327,4 +505,125 @@ mod test {
 
         assert_eq!(tree, Some(expected))
     }
+
+    #[test]
+    fn test_skipped_level_nests_under_parent() {
+        let headings = vec![
+            heading(1, "First", 0),
+            heading(3, "Third", 2),
+            heading(2, "Second", 4),
+        ];
+
+        let tree = super::construct_tree(&headings).unwrap();
+
+        assert_eq!(tree.len(), 1);
+        let children = tree[0].children.as_ref().unwrap();
+        assert_eq!(
+            children
+                .iter()
+                .map(|node| node.heading.heading_text.as_str())
+                .collect::<Vec<_>>(),
+            vec!["Third", "Second"]
+        );
+    }
+
+    #[test]
+    fn test_section_ranges() {
+        let headings = vec![
+            heading(1, "First", 0),
+            heading(2, "Second", 3),
+            heading(2, "Another", 6),
+            heading(1, "Last", 9),
+        ];
+
+        let end = Position {
+            line: 12,
+            character: 4,
+        };
+        let symbols = super::map_to_lsp_tree(super::construct_tree(&headings).unwrap(), end);
+
+        let first = &symbols[0];
+        assert_eq!(first.range.start, Position::new(0, 0));
+        assert_eq!(first.range.end, Position::new(9, 0));
+        assert_eq!(first.selection_range, *headings[0].range);
+
+        let children = first.children.as_ref().unwrap();
+        assert_eq!(children[0].range.end, Position::new(6, 0));
+        assert_eq!(children[1].range.end, Position::new(9, 0));
+
+        assert_eq!(symbols[1].range.end, end);
+    }
+
+    #[test]
+    fn test_block_symbols_nest_in_sections() {
+        let headings = vec![heading(1, "First", 0), heading(2, "Second", 3)];
+        let mut symbols = super::map_to_lsp_tree(
+            super::construct_tree(&headings).unwrap(),
+            Position::new(8, 0),
+        );
+
+        let before_headings = MDIndexedBlock {
+            index: "top".into(),
+            range: line_range(0, 4),
+        };
+        let in_second = MDIndexedBlock {
+            index: "abc".into(),
+            range: line_range(5, 4),
+        };
+
+        super::insert_block_symbol(&mut symbols, &in_second);
+        super::insert_block_symbol(&mut symbols, &before_headings);
+
+        let first_children = symbols[0].children.as_ref().unwrap();
+        assert_eq!(first_children[0].name, "^top");
+
+        let second = &first_children[1];
+        assert_eq!(second.name, "Second");
+        assert_eq!(second.children.as_ref().unwrap()[0].name, "^abc");
+    }
+
+    #[test]
+    fn test_workspace_symbol_titles_aliases_and_headings() {
+        let root_dir = Path::new("/vault");
+        let settings = Settings::new(root_dir, &ClientCapabilities::default()).unwrap();
+        let mut vault = Vault::construct_vault(&settings, root_dir).unwrap();
+        Vault::update_vault(
+            &settings,
+            &mut vault,
+            (
+                &PathBuf::from("/vault/Meeting Notes.md"),
+                "---\naliases: [Standup]\n---\n# Agenda\n",
+            ),
+        );
+        Vault::update_vault(
+            &settings,
+            &mut vault,
+            (&PathBuf::from("/vault/Recipes.md"), "# Pasta\n"),
+        );
+
+        let query = |query: &str| {
+            let params = WorkspaceSymbolParams {
+                query: query.to_string(),
+                ..Default::default()
+            };
+            symbol::workspace_symbol(&vault, &params, &settings)
+                .unwrap()
+                .into_iter()
+                .map(|symbol| (symbol.name, symbol.kind))
+                .collect_vec()
+        };
+
+        assert_eq!(
+            query("mtg").first(),
+            Some(&("Meeting Notes".to_string(), SymbolKind::FILE))
+        );
+        assert_eq!(
+            query("standup"),
+            vec![("Standup".to_string(), SymbolKind::FILE)]
+        );
+        assert_eq!(
+            query("agenda"),
+            vec![("Meeting Notes#Agenda".to_string(), SymbolKind::KEY)]
+        );
+    }
 }
@@ -1,22 +1,80 @@
-use std::{collections::HashSet, iter, path::Path};
+use std::{
+    collections::{HashMap, HashSet},
+    iter,
+    path::{Path, PathBuf},
+};
 
 use itertools::Itertools;
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
-use tower_lsp::lsp_types::{SemanticToken, SemanticTokensParams, SemanticTokensResult};
+use tower_lsp::lsp_types::{
+    SemanticToken, SemanticTokenModifier, SemanticTokenType, SemanticTokens, SemanticTokensDelta,
+    SemanticTokensEdit, SemanticTokensFullDeltaResult, SemanticTokensLegend,
+};
 
-use crate::{config::Settings, diagnostics::path_unresolved_references, vault::Vault};
+use crate::{
+    config::{PositionEncoding, Settings},
+    diagnostics::path_unresolved_references,
+    vault::{Reference, Vault},
+};
 
-pub fn semantic_tokens_full(
+/// Token types, in the order of the legend
+const TOKEN_TYPES: [&str; 7] = [
+    "namespace",
+    "property",
+    "label",
+    "macro",
+    "string",
+    "tag",
+    "footnote",
+];
+/// The file a link points to
+const FILE: u32 = 0;
+/// A link's `#heading`
+const HEADING: u32 = 1;
+/// A link's `#^block`, or a block's `^id`
+const BLOCK: u32 = 2;
+/// The `!` of an embed
+const EMBED: u32 = 3;
+/// A link's display text
+const DISPLAY: u32 = 4;
+const TAG: u32 = 5;
+const FOOTNOTE: u32 = 6;
+
+/// Token modifier bits, in the order of the legend
+const DECLARATION: u32 = 1 << 0;
+const UNRESOLVED: u32 = 1 << 1;
+
+pub fn legend() -> SemanticTokensLegend {
+    SemanticTokensLegend {
+        token_types: TOKEN_TYPES.map(SemanticTokenType::new).to_vec(),
+        token_modifiers: vec![
+            SemanticTokenModifier::DECLARATION,
+            SemanticTokenModifier::new("unresolved"),
+        ],
+    }
+}
+
+/// A token on one line; columns are in chars
+struct Token {
+    line: u32,
+    start: u32,
+    end: u32,
+    token_type: u32,
+    modifiers: u32,
+}
+
+/// The encoded tokens of a document, or None if semantic tokens are turned off
+pub fn document_tokens(
     vault: &Vault,
     path: &Path,
-    _params: SemanticTokensParams,
     settings: &Settings,
-) -> Option<SemanticTokensResult> {
+) -> Option<Vec<SemanticToken>> {
     if !settings.semantic_tokens {
         return None;
     }
 
     let references_in_file = vault.select_references(Some(path))?;
+    let mdfile = vault.md_files.get(path)?;
 
     let path_unresolved: Option<HashSet<_>> =
         path_unresolved_references(vault, path).map(|thing| {
@@ -26,59 +84,407 @@ pub fn semantic_tokens_full(
                 .collect()
         });
 
-    let tokens = references_in_file
-        .into_iter()
-        .sorted_by_key(|(_, reference)| {
-            (
-                reference.data().range.start.line,
-                reference.data().range.start.character,
-            )
-        })
-        .fold(vec![], |acc, (_path, reference)| {
-            let range = reference.data().range;
-
-            let is_unresolved = path_unresolved
-                .as_ref()
-                .is_some_and(|unresolved| unresolved.contains(reference));
-
-            match acc[..] {
-                [] => vec![(
+    let references = references_in_file.into_iter().flat_map(|(_, reference)| {
+        let range = reference.data().range;
+
+        let is_unresolved = path_unresolved
+            .as_ref()
+            .is_some_and(|unresolved| unresolved.contains(reference));
+        let modifiers = if is_unresolved { UNRESOLVED } else { 0 };
+
+        let (start, end) = (range.start.character as usize, range.end.character as usize);
+        let parts = match reference {
+            Reference::WikiFileLink(..)
+            | Reference::WikiHeadingLink(..)
+            | Reference::WikiIndexedBlockLink(..)
+            | Reference::MDFileLink(..)
+            | Reference::MDHeadingLink(..)
+            | Reference::MDIndexedBlockLink(..)
+                if range.start.line == range.end.line =>
+            {
+                let line = vault
+                    .select_line(path, range.start.line as isize)
+                    .unwrap_or_default();
+                let wiki = matches!(
                     reference,
-                    SemanticToken {
-                        delta_line: range.start.line,
-                        delta_start: range.start.character,
-                        length: range.end.character - range.start.character,
-                        token_type: if is_unresolved { 1 } else { 0 },
-                        token_modifiers_bitset: 0,
-                    },
-                )],
-                [.., (prev_ref, _)] => acc
+                    Reference::WikiFileLink(..)
+                        | Reference::WikiHeadingLink(..)
+                        | Reference::WikiIndexedBlockLink(..)
+                );
+
+                let embed = start
+                    .checked_sub(1)
+                    .filter(|&bang| line.get(bang) == Some(&'!'))
+                    .map(|bang| (bang, start, EMBED));
+
+                embed
                     .into_iter()
-                    .chain(iter::once((
-                        reference,
-                        SemanticToken {
-                            delta_line: range.start.line - prev_ref.data().range.start.line,
-                            delta_start: if range.start.line == prev_ref.data().range.start.line {
-                                range.start.character - prev_ref.data().range.start.character
-                            } else {
-                                range.start.character
-                            },
-                            length: range.end.character - range.start.character,
-                            token_type: if is_unresolved { 1 } else { 0 },
-                            token_modifiers_bitset: 0,
-                        },
-                    )))
-                    .collect_vec(),
+                    .chain(link_parts(&line, start, end, wiki))
+                    .collect_vec()
+            }
+            Reference::WikiFileLink(..)
+            | Reference::WikiHeadingLink(..)
+            | Reference::WikiIndexedBlockLink(..)
+            | Reference::MDFileLink(..)
+            | Reference::MDHeadingLink(..)
+            | Reference::MDIndexedBlockLink(..) => vec![],
+            Reference::LinkRef(..) => vec![(start, end, FILE)],
+            Reference::Tag(..) => vec![(start, end, TAG)],
+            Reference::Footnote(..) => vec![(start, end, FOOTNOTE)],
+        };
+
+        parts
+            .into_iter()
+            .map(move |(start, end, token_type)| Token {
+                line: range.start.line,
+                start: start as u32,
+                end: end as u32,
+                token_type,
+                modifiers,
+            })
+    });
+
+    let block_ids = mdfile.indexed_blocks.iter().map(|block| Token {
+        line: block.range.start.line,
+        start: block.range.start.character,
+        end: block.range.end.character,
+        token_type: BLOCK,
+        modifiers: DECLARATION,
+    });
+
+    // only the `[^index]` of a footnote definition, not its text
+    let footnotes = mdfile.footnotes.iter().map(|footnote| Token {
+        line: footnote.range.start.line,
+        start: footnote.range.start.character,
+        end: footnote.range.start.character + footnote.index.chars().count() as u32 + 2,
+        token_type: FOOTNOTE,
+        modifiers: DECLARATION,
+    });
+
+    let tokens = references
+        .chain(block_ids)
+        .chain(footnotes)
+        .filter(|token| token.end > token.start)
+        .sorted_by_key(|token| (token.line, token.start))
+        .coalesce(
+            |prev, next| match next.line == prev.line && next.start < prev.end {
+                // tokens can not overlap; keep the first
+                true => Ok(prev),
+                false => Err((prev, next)),
+            },
+        )
+        .collect_vec();
+
+    Some(encode(vault, path, settings.position_encoding, &tokens))
+}
+
+/// The file, `#heading` or `#^block`, and display text of the link spanning `start..end` of `line`, as char ranges with their token type
+fn link_parts(line: &[char], start: usize, end: usize, wiki: bool) -> Vec<(usize, usize, u32)> {
+    let find = |from: usize, to: usize, c: char| (from..to).find(|&i| line.get(i) == Some(&c));
+
+    // `[[target|display]]` or `[display](target)`
+    let (target, display) = match wiki {
+        true => {
+            let (from, to) = (start + 2, end.saturating_sub(2));
+            match find(from, to, '|') {
+                // a table escapes the pipe: `[[file\|display]]`
+                Some(pipe) if pipe > from && line.get(pipe - 1) == Some(&'\\') => {
+                    ((from, pipe - 1), Some((pipe + 1, to)))
+                }
+                Some(pipe) => ((from, pipe), Some((pipe + 1, to))),
+                None => ((from, to), None),
+            }
+        }
+        false => {
+            let Some(close) =
+                (start..end).find(|&i| line.get(i) == Some(&']') && line.get(i + 1) == Some(&'('))
+            else {
+                return vec![];
+            };
+            let (mut from, mut to) = (close + 2, end.saturating_sub(1));
+            if to > from && line.get(from) == Some(&'<') && line.get(to - 1) == Some(&'>') {
+                (from, to) = (from + 1, to - 1);
             }
-        })
-        .into_par_iter()
-        .map(|(_, token)| token)
-        .collect::<Vec<_>>(); // TODO: holy this is bad
-
-    Some(SemanticTokensResult::Tokens(
-        tower_lsp::lsp_types::SemanticTokens {
-            result_id: None,
-            data: tokens,
-        },
-    ))
+            ((from, to), Some((start + 1, close)))
+        }
+    };
+
+    let (file, fragment) = match find(target.0, target.1, '#') {
+        Some(hash) => ((target.0, hash), Some((hash, target.1))),
+        None => (target, None),
+    };
+
+    iter::once((file.0, file.1, FILE))
+        .chain(fragment.map(|(from, to)| match line.get(from + 1) {
+            Some('^') => (from, to, BLOCK),
+            _ => (from, to, HEADING),
+        }))
+        .chain(display.map(|(from, to)| (from, to, DISPLAY)))
+        .filter(|(from, to, _)| to > from)
+        .collect()
+}
+
+/// The tokens last sent for each document, which delta requests are answered against
+#[derive(Debug, Default)]
+pub struct TokenCache {
+    next_result_id: u64,
+    sent: HashMap<PathBuf, SemanticTokens>,
+}
+
+impl TokenCache {
+    /// Remember `data` as the tokens sent for `path`, under a new result id
+    pub fn full(&mut self, path: &Path, data: Vec<SemanticToken>) -> SemanticTokens {
+        self.next_result_id += 1;
+        let tokens = SemanticTokens {
+            result_id: Some(self.next_result_id.to_string()),
+            data,
+        };
+        self.sent.insert(path.to_path_buf(), tokens.clone());
+
+        tokens
+    }
+
+    /// The edits from the tokens sent as `previous_result_id` to `data`; all of `data` if those are no longer known
+    pub fn delta(
+        &mut self,
+        path: &Path,
+        previous_result_id: &str,
+        data: Vec<SemanticToken>,
+    ) -> SemanticTokensFullDeltaResult {
+        let previous = self
+            .sent
+            .get(path)
+            .filter(|sent| sent.result_id.as_deref() == Some(previous_result_id))
+            .map(|sent| sent.data.clone());
+
+        let tokens = self.full(path, data);
+        match previous {
+            Some(previous) => SemanticTokensFullDeltaResult::TokensDelta(SemanticTokensDelta {
+                result_id: tokens.result_id,
+                edits: delta_edits(&previous, &tokens.data).into_iter().collect(),
+            }),
+            None => SemanticTokensFullDeltaResult::Tokens(tokens),
+        }
+    }
+}
+
+/// The single edit replacing what changed between the common start and end of two token lists; indexes count the five integers of each token
+fn delta_edits(
+    previous: &[SemanticToken],
+    current: &[SemanticToken],
+) -> Option<SemanticTokensEdit> {
+    let prefix = previous
+        .iter()
+        .zip(current)
+        .take_while(|(previous, current)| previous == current)
+        .count();
+    let suffix = previous[prefix..]
+        .iter()
+        .rev()
+        .zip(current[prefix..].iter().rev())
+        .take_while(|(previous, current)| previous == current)
+        .count();
+
+    let (removed, inserted) = (
+        &previous[prefix..previous.len() - suffix],
+        &current[prefix..current.len() - suffix],
+    );
+    if removed.is_empty() && inserted.is_empty() {
+        return None;
+    }
+
+    Some(SemanticTokensEdit {
+        start: prefix as u32 * 5,
+        delete_count: removed.len() as u32 * 5,
+        data: Some(inserted.to_vec()),
+    })
+}
+
+/// Delta encode sorted tokens, with columns in the negotiated position encoding
+fn encode(
+    vault: &Vault,
+    path: &Path,
+    encoding: PositionEncoding,
+    tokens: &[Token],
+) -> Vec<SemanticToken> {
+    let mut encoded = Vec::with_capacity(tokens.len());
+    let mut prev: Option<(u32, u32)> = None;
+
+    for (line_nr, line_tokens) in &tokens.iter().chunk_by(|token| token.line) {
+        let line = vault
+            .select_line(path, line_nr as isize)
+            .unwrap_or_default();
+
+        for token in line_tokens {
+            let start = encoding.column(&line, token.start as usize);
+            let end = encoding.column(&line, token.end as usize);
+
+            let (delta_line, delta_start) = match prev {
+                Some((prev_line, prev_start)) if prev_line == token.line => (0, start - prev_start),
+                Some((prev_line, _)) => (token.line - prev_line, start),
+                None => (token.line, start),
+            };
+
+            encoded.push(SemanticToken {
+                delta_line,
+                delta_start,
+                length: end - start,
+                token_type: token.token_type,
+                token_modifiers_bitset: token.modifiers,
+            });
+            prev = Some((token.line, start));
+        }
+    }
+
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use itertools::Itertools;
+    use tower_lsp::lsp_types::{ClientCapabilities, SemanticToken, SemanticTokensFullDeltaResult};
+
+    use crate::{config::Settings, vault::Vault};
+
+    use super::{
+        delta_edits, document_tokens, TokenCache, BLOCK, DECLARATION, DISPLAY, EMBED, FILE,
+        FOOTNOTE, HEADING, TAG, UNRESOLVED,
+    };
+
+    /// Decoded tokens: (line, UTF-16 start, length, type, modifiers)
+    fn tokens(text: &str) -> Vec<(u32, u32, u32, u32, u32)> {
+        let root_dir = Path::new("/vault");
+        let settings = Settings::new(root_dir, &ClientCapabilities::default()).unwrap();
+        let mut vault = Vault::construct_vault(&settings, root_dir).unwrap();
+        Vault::update_vault(
+            &settings,
+            &mut vault,
+            (
+                &PathBuf::from("/vault/target.md"),
+                "# Heading\n\ntext ^blk\n",
+            ),
+        );
+        let path = PathBuf::from("/vault/note.md");
+        Vault::update_vault(&settings, &mut vault, (&path, text));
+
+        let (mut line, mut start) = (0, 0);
+        document_tokens(&vault, &path, &settings)
+            .expect("no tokens")
+            .into_iter()
+            .map(|token| {
+                if token.delta_line > 0 {
+                    start = 0;
+                }
+                line += token.delta_line;
+                start += token.delta_start;
+                (
+                    line,
+                    start,
+                    token.length,
+                    token.token_type,
+                    token.token_modifiers_bitset,
+                )
+            })
+            .collect_vec()
+    }
+
+    #[test]
+    fn classifies_link_parts_tags_blocks_and_footnotes() {
+        let text = "[[target]] [x](target#Heading) #tag[^1]\n[[missing]] block ^id\n\n[^1]: note";
+
+        assert_eq!(
+            tokens(text),
+            vec![
+                (0, 2, 6, FILE, 0),
+                (0, 12, 1, DISPLAY, 0),
+                (0, 15, 6, FILE, 0),
+                (0, 21, 8, HEADING, 0),
+                (0, 31, 4, TAG, 0),
+                (0, 35, 4, FOOTNOTE, 0),
+                (1, 2, 7, FILE, UNRESOLVED),
+                (1, 18, 3, BLOCK, DECLARATION),
+                (3, 0, 4, FOOTNOTE, DECLARATION),
+            ]
+        );
+    }
+
+    #[test]
+    fn embeds_blocks_and_display_text() {
+        assert_eq!(
+            tokens("![[target#^blk|shown]] [a b](<target#Heading>)"),
+            vec![
+                (0, 0, 1, EMBED, 0),
+                (0, 3, 6, FILE, 0),
+                (0, 9, 5, BLOCK, 0),
+                (0, 15, 5, DISPLAY, 0),
+                (0, 24, 3, DISPLAY, 0),
+                (0, 30, 6, FILE, 0),
+                (0, 36, 8, HEADING, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn escaped_display_pipe_is_not_part_of_the_file() {
+        assert_eq!(
+            tokens(r"| [[target\|shown]] |"),
+            vec![(0, 4, 6, FILE, 0), (0, 12, 5, DISPLAY, 0)]
+        );
+    }
+
+    #[test]
+    fn columns_count_utf16_code_units() {
+        // é is one UTF-16 unit, 🚀 is two
+        assert_eq!(
+            tokens("é 🚀 [[target]] [[🚀]]"),
+            vec![(0, 7, 6, FILE, 0), (0, 18, 2, FILE, UNRESOLVED)]
+        );
+    }
+
+    fn token(delta_line: u32, delta_start: u32, length: u32) -> SemanticToken {
+        SemanticToken {
+            delta_line,
+            delta_start,
+            length,
+            token_type: FILE,
+            token_modifiers_bitset: 0,
+        }
+    }
+
+    #[test]
+    fn delta_replaces_only_the_changed_tokens() {
+        let previous = [token(0, 2, 6), token(1, 2, 7), token(2, 0, 4)];
+        let current = [
+            token(0, 2, 6),
+            token(1, 4, 3),
+            token(0, 5, 1),
+            token(2, 0, 4),
+        ];
+
+        let edit = delta_edits(&previous, &current).unwrap();
+        assert_eq!((edit.start, edit.delete_count), (5, 5));
+        assert_eq!(edit.data, Some(vec![token(1, 4, 3), token(0, 5, 1)]));
+
+        assert_eq!(delta_edits(&current, &current), None);
+    }
+
+    #[test]
+    fn delta_against_an_unknown_result_sends_all_tokens() {
+        let path = Path::new("/vault/note.md");
+        let mut cache = TokenCache::default();
+        let sent = cache.full(path, vec![token(0, 2, 6)]);
+
+        assert!(matches!(
+            cache.delta(path, "stale", vec![token(0, 2, 6)]),
+            SemanticTokensFullDeltaResult::Tokens(..)
+        ));
+        let latest = cache.full(path, vec![token(0, 2, 6)]);
+        assert_ne!(sent.result_id, latest.result_id);
+        assert!(matches!(
+            cache.delta(path, latest.result_id.as_deref().unwrap(), vec![token(0, 2, 6)]),
+            SemanticTokensFullDeltaResult::TokensDelta(delta) if delta.edits.is_empty()
+        ));
+    }
 }
@@ -81,6 +81,23 @@ pub fn preview_reference(
             let referenceables_for_reference =
                 vault.select_referenceables_for_reference(reference, reference_path);
 
+            // There is nothing to preview for links to missing notes
+            if referenceables_for_reference
+                .first()
+                .is_some_and(|referenceable| referenceable.is_unresolved())
+            {
+                return None;
+            }
+
+            if is_embed(vault, reference_path, reference) {
+                if let Some(
+                    referenceable @ (Referenceable::Heading(..) | Referenceable::IndexedBlock(..)),
+                ) = referenceables_for_reference.first()
+                {
+                    return embed_preview(vault, referenceable);
+                }
+            }
+
             let display = referenceable_string(vault, &referenceables_for_reference)?;
 
             Some(MarkupContent {
@@ -88,6 +105,169 @@ pub fn preview_reference(
                 value: display,
             })
         }
-        Tag(_) => None,
+        Tag(data) => Some(MarkupContent {
+            kind: MarkupKind::Markdown,
+            value: tag_preview(vault, data.reference_text.trim_start_matches('#'))?,
+        }),
+    }
+}
+
+/// Whether the link is an embed, `![[Note#Section]]`
+fn is_embed(vault: &Vault, path: &Path, reference: &Reference) -> bool {
+    let start = reference.data().range.start;
+    vault
+        .select_line(path, start.line as isize)
+        .zip(start.character.checked_sub(1))
+        .is_some_and(|(line, bang)| line.get(bang as usize) == Some(&'!'))
+}
+
+/// An embedded section or block shows exactly what it transcludes
+fn embed_preview(vault: &Vault, referenceable: &Referenceable) -> Option<MarkupContent> {
+    let text = vault.select_embedded_text(referenceable)?;
+    let title = match referenceable {
+        Referenceable::IndexedBlock(..) => "Embedded Block",
+        _ => "Embedded Section",
+    };
+
+    Some(MarkupContent {
+        kind: MarkupKind::Markdown,
+        value: format!("`{}:`\n\n{}", title, text),
+    })
+}
+
+/// How many notes have `tag`, with the first few of them, and how many more only have tags nested in it
+fn tag_preview(vault: &Vault, tag: &str) -> Option<String> {
+    let nested = format!("{tag}/");
+    let notes_where = |matches: &dyn Fn(&str) -> bool| {
+        vault
+            .md_files
+            .iter()
+            .filter(|(_, mdfile)| mdfile.tags.iter().any(|it| matches(&it.tag_ref)))
+            .map(|(path, _)| path)
+            .sorted()
+            .collect_vec()
+    };
+    let tagged = notes_where(&|it| it == tag);
+    let nested_only = notes_where(&|it| it.starts_with(&nested))
+        .into_iter()
+        .filter(|path| !tagged.contains(path))
+        .count();
+
+    if tagged.is_empty() && nested_only == 0 {
+        return None;
+    }
+
+    let plural = |count: usize| if count == 1 { "note" } else { "notes" };
+    let mut preview = format!("`#{}`: {} {}", tag, tagged.len(), plural(tagged.len()));
+
+    if !tagged.is_empty() {
+        let listed = tagged
+            .iter()
+            .take(10)
+            .filter_map(|path| get_obsidian_ref_path(vault.root_dir(), path))
+            .map(|path| format!("- `{}`", path))
+            .join("\n");
+        let more = match tagged.len().saturating_sub(10) {
+            0 => String::new(),
+            more => format!("\n- `...` and {} more", more),
+        };
+        preview.push_str(&format!("\n\n{}{}", listed, more));
+    }
+
+    if nested_only > 0 {
+        preview.push_str(&format!(
+            "\n\n`#{}/...`: {} more {} with nested tags",
+            tag,
+            nested_only,
+            plural(nested_only)
+        ));
+    }
+
+    Some(preview)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use tower_lsp::lsp_types::{ClientCapabilities, Position};
+
+    use crate::{config::Settings, vault::Vault};
+
+    use super::preview_reference;
+
+    #[test]
+    fn tag_hover_counts_and_lists_tagged_notes() {
+        let root_dir = Path::new("/vault");
+        let settings = Settings::new(root_dir, &ClientCapabilities::default()).unwrap();
+        let mut vault = Vault::construct_vault(&settings, root_dir).unwrap();
+        for (path, text) in [
+            ("/vault/a.md", "# Heading\n\n#project and #project again"),
+            ("/vault/folder/b.md", "---\ntags: [project]\n---\n"),
+            ("/vault/c.md", "#project/sub"),
+            ("/vault/d.md", "#other"),
+        ] {
+            Vault::update_vault(&settings, &mut vault, (&PathBuf::from(path), text));
+        }
+
+        let hover = |line: u32, character: u32| {
+            let path = Path::new("/vault/a.md");
+            let reference =
+                vault.select_reference_at_position(path, Position { line, character })?;
+            preview_reference(&vault, path, reference).map(|markup| markup.value)
+        };
+
+        assert_eq!(
+            hover(2, 3).as_deref(),
+            Some("`#project`: 2 notes\n\n- `a`\n- `folder/b`\n\n`#project/...`: 1 more note with nested tags")
+        );
+        // the heading's # is not a tag
+        assert_eq!(hover(0, 0), None);
+    }
+
+    #[test]
+    fn embeds_preview_exactly_their_section_or_block() {
+        let root_dir = Path::new("/vault");
+        let settings = Settings::new(root_dir, &ClientCapabilities::default()).unwrap();
+        let mut vault = Vault::construct_vault(&settings, root_dir).unwrap();
+        let note = "# Note\n\n## Section\n\nFirst\n\n### Nested\n\nSecond\nparagraph ^block\n\n## Next\n\n- item ^item\n  - nested\n- other\n";
+        let embeds = "![[note#Section]]\n![[note#^block]]\n![[note#Missing]]\n[[note#Section]]\n![[note#^item]]";
+        Vault::update_vault(
+            &settings,
+            &mut vault,
+            (&PathBuf::from("/vault/note.md"), note),
+        );
+        Vault::update_vault(
+            &settings,
+            &mut vault,
+            (&PathBuf::from("/vault/embeds.md"), embeds),
+        );
+
+        let hover = |line: u32| {
+            let path = Path::new("/vault/embeds.md");
+            let reference =
+                vault.select_reference_at_position(path, Position { line, character: 4 })?;
+            preview_reference(&vault, path, reference).map(|markup| markup.value)
+        };
+
+        // the section runs through its nested headings up to the next heading of its level
+        assert_eq!(
+            hover(0).as_deref(),
+            Some(
+                "`Embedded Section:`\n\n## Section\n\nFirst\n\n### Nested\n\nSecond\nparagraph ^block"
+            )
+        );
+        // a block is its whole paragraph, or its list item with the items nested in it
+        assert_eq!(
+            hover(1).as_deref(),
+            Some("`Embedded Block:`\n\nSecond\nparagraph")
+        );
+        assert_eq!(
+            hover(4).as_deref(),
+            Some("`Embedded Block:`\n\n- item\n  - nested")
+        );
+        assert_eq!(hover(2), None);
+        // a plain link keeps the usual preview with backlinks
+        assert!(hover(3).is_some_and(|preview| preview.starts_with("`Heading Preview:`")));
     }
 }
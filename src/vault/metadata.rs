@@ -1,31 +1,111 @@
 use once_cell::sync::Lazy;
 use regex::Regex;
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
 
-#[derive(Deserialize, Debug, Clone, Hash, PartialEq, Eq)]
+#[derive(Deserialize, Debug, Clone, Hash, PartialEq, Eq, Default)]
 pub struct MDMetadata {
+    #[serde(default)]
     aliases: Vec<String>,
+    #[serde(default, deserialize_with = "deserialize_tags")]
+    tags: Vec<String>,
+    /// The heading used as a name with `title_source = "Heading"`; it is the last of the aliases
+    #[serde(skip)]
+    title: Option<String>,
+    /// Every top level key with its values as text; a list has a value for each of its items
+    #[serde(skip)]
+    properties: Vec<(String, Vec<String>)>,
 }
 
 impl MDMetadata {
     pub fn new(text: &str) -> Option<MDMetadata> {
-        // find text between --- at the beginning of the file
+        let metadata_match = Self::frontmatter(text)?;
 
-        static RE: Lazy<Regex> =
-            Lazy::new(|| Regex::new(r"^---\n(?<metadata>(\n|.)*?)\n---").unwrap());
-
-        let metadata_match = RE.captures_iter(text).next()?.name("metadata");
+        // parsed once, for both the known keys and the properties
+        let yaml = serde_yaml::from_str::<serde_yaml::Value>(metadata_match.as_str()).ok()?;
+        let properties = properties(&yaml);
+        let mut md_metadata = serde_yaml::from_value::<MDMetadata>(yaml).ok()?;
+        md_metadata.properties = properties;
 
-        let metadata_match = metadata_match?;
+        Some(md_metadata)
+    }
 
-        let md_metadata = serde_yaml::from_str::<MDMetadata>(metadata_match.as_str());
+    /// The text between the `---` fences at the beginning of the file
+    pub fn frontmatter(text: &str) -> Option<regex::Match<'_>> {
+        static RE: Lazy<Regex> =
+            Lazy::new(|| Regex::new(r"^---\r?\n(?<metadata>(\n|.)*?)\r?\n---").unwrap());
 
-        md_metadata.ok()
+        RE.captures_iter(text).next()?.name("metadata")
     }
 
     pub fn aliases(&self) -> &[String] {
         &self.aliases
     }
+
+    /// Makes `title` one of the aliases, in place of the previous title
+    pub fn set_title(&mut self, title: Option<String>) {
+        if self.title.take().is_some() {
+            self.aliases.pop();
+        }
+
+        if let Some(title) = title.filter(|title| !self.aliases.contains(title)) {
+            self.aliases.push(title.clone());
+            self.title = Some(title);
+        }
+    }
+
+    /// Tags without the leading `#`
+    pub fn tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    pub fn properties(&self) -> &[(String, Vec<String>)] {
+        &self.properties
+    }
+}
+
+/// A YAML string, number or boolean as text
+fn scalar(value: &serde_yaml::Value) -> Option<String> {
+    match value {
+        serde_yaml::Value::String(string) => Some(string.clone()),
+        serde_yaml::Value::Number(number) => Some(number.to_string()),
+        serde_yaml::Value::Bool(bool) => Some(bool.to_string()),
+        _ => None,
+    }
+}
+
+fn properties(frontmatter: &serde_yaml::Value) -> Vec<(String, Vec<String>)> {
+    let serde_yaml::Value::Mapping(mapping) = frontmatter else {
+        return vec![];
+    };
+
+    mapping
+        .iter()
+        .filter_map(|(key, value)| {
+            let values = match value {
+                serde_yaml::Value::Sequence(items) => items.iter().filter_map(scalar).collect(),
+                value => scalar(value).into_iter().collect(),
+            };
+
+            Some((key.as_str()?.to_string(), values))
+        })
+        .collect()
+}
+
+/// Accepts `tags: [a, b]`, a YAML list, or a comma/space separated string `tags: a, b`. Numbers and booleans are
+/// tags as written, and anything else is left out, so a malformed `tags` never loses the rest of the metadata
+fn deserialize_tags<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    let tags: Vec<String> = match serde_yaml::Value::deserialize(deserializer)? {
+        serde_yaml::Value::Sequence(items) => items.iter().filter_map(scalar).collect(),
+        value => scalar(&value).into_iter().collect(),
+    };
+
+    Ok(tags
+        .iter()
+        .flat_map(|tag| tag.split(|c: char| c == ',' || c.is_whitespace()))
+        .map(|tag| tag.trim_start_matches('#'))
+        .filter(|tag| !tag.is_empty())
+        .map(String::from)
+        .collect())
 }
 
 #[cfg(test)]
@@ -50,4 +130,69 @@ aliases:
         .unwrap();
         assert_eq!(metadata.aliases(), &["alias1", "alias2"]);
     }
+
+    #[test]
+    fn test_tags_flow_list() {
+        let metadata = MDMetadata::new("---\ntags: [project, area/work]\n---").unwrap();
+        assert_eq!(metadata.tags(), &["project", "area/work"]);
+    }
+
+    #[test]
+    fn test_tags_block_list() {
+        let metadata = MDMetadata::new(
+            r"---
+tags:
+  - project
+  - '#area/work'
+---",
+        )
+        .unwrap();
+        assert_eq!(metadata.tags(), &["project", "area/work"]);
+    }
+
+    #[test]
+    fn test_tags_comma_separated() {
+        let metadata = MDMetadata::new("---\ntags: project, area/work\n---").unwrap();
+        assert_eq!(metadata.tags(), &["project", "area/work"]);
+    }
+
+    #[test]
+    fn test_tags_without_aliases() {
+        let metadata = MDMetadata::new("---\ntitle: Note\ntags:\n---").unwrap();
+        assert!(metadata.aliases().is_empty());
+        assert!(metadata.tags().is_empty());
+    }
+
+    #[test]
+    fn test_tags_that_are_not_strings() {
+        let metadata =
+            MDMetadata::new("---\naliases: [Other]\ntags: [2024, work, {a: b}]\n---").unwrap();
+        assert_eq!(metadata.aliases(), &["Other"]);
+        assert_eq!(metadata.tags(), &["2024", "work"]);
+
+        let metadata = MDMetadata::new("---\naliases: [Other]\ntags: 2024\n---").unwrap();
+        assert_eq!(metadata.aliases(), &["Other"]);
+        assert_eq!(metadata.tags(), &["2024"]);
+    }
+
+    #[test]
+    fn test_properties() {
+        let metadata = MDMetadata::new(
+            "---\nstatus: draft\npriority: 2\nlinks: [one, two]\nnested:\n  key: value\nempty:\n---",
+        )
+        .unwrap();
+        assert_eq!(
+            metadata.properties(),
+            &[
+                ("status".to_string(), vec!["draft".to_string()]),
+                ("priority".to_string(), vec!["2".to_string()]),
+                (
+                    "links".to_string(),
+                    vec!["one".to_string(), "two".to_string()]
+                ),
+                ("nested".to_string(), vec![]),
+                ("empty".to_string(), vec![]),
+            ]
+        );
+    }
 }
@@ -1,7 +1,9 @@
 mod metadata;
 mod parsing;
+mod vaults;
 
 use std::{
+    borrow::Cow,
     char,
     collections::{HashMap, HashSet},
     hash::Hash,
@@ -11,6 +13,10 @@ use std::{
     time::SystemTime,
 };
 
+use ignore::{
+    gitignore::{Gitignore, GitignoreBuilder},
+    WalkBuilder,
+};
 use itertools::Itertools;
 use once_cell::sync::Lazy;
 use pathdiff::diff_paths;
@@ -18,52 +24,126 @@ use rayon::prelude::*;
 use regex::{Captures, Match, Regex};
 use ropey::Rope;
 use serde::{Deserialize, Serialize};
-use tower_lsp::lsp_types::Position;
-use walkdir::WalkDir;
+use tower_lsp::lsp_types::{Position, TextDocumentContentChangeEvent};
 
 impl Vault {
+    /// The vault of one folder with all of its notes read; the server reads its vaults with [`Vaults::construct`]
+    #[cfg(test)]
     pub fn construct_vault(context: &Settings, root_dir: &Path) -> Result<Vault, std::io::Error> {
-        let md_file_paths = WalkDir::new(root_dir)
-            .into_iter()
-            .filter_entry(|e| {
-                !e.file_name()
-                    .to_str()
-                    .map(|s| s.starts_with('.') || s == "logseq") // TODO: This is a temporary fix; a hidden config is better
-                    .unwrap_or(false)
+        let (mut vault, notes) = Vault::scan(context, root_dir)?;
+        vault.insert_notes(Vault::read_notes(context, &notes));
+
+        Ok(vault)
+    }
+
+    /// The vault's files without reading them: a vault with its attachments but no notes yet, and the paths of the
+    /// notes to read into it, in path order
+    pub fn scan(
+        context: &Settings,
+        root_dir: &Path,
+    ) -> Result<(Vault, Vec<PathBuf>), std::io::Error> {
+        let ignore_patterns = ignore_patterns_matcher(context, root_dir);
+        let file_paths = WalkBuilder::new(root_dir)
+            .hidden(true) // dot files and folders like .obsidian
+            .require_git(false) // respect .gitignore files even if the vault is not a git repository
+            .filter_entry(move |e| {
+                e.file_name() != "logseq" // TODO: This is a temporary fix; a hidden config is better
+                    && !ignore_patterns
+                        .matched(e.path(), e.file_type().is_some_and(|it| it.is_dir()))
+                        .is_ignore()
             })
+            .build()
             .flatten()
-            .filter(|f| f.path().extension().and_then(|e| e.to_str()) == Some("md"))
+            .filter(|f| f.file_type().is_some_and(|it| it.is_file()))
+            .map(|f| f.into_path())
+            .sorted()
             .collect_vec();
 
-        let md_files: HashMap<PathBuf, MDFile> = md_file_paths
-            .par_iter()
-            .flat_map(|p| {
-                let text = std::fs::read_to_string(p.path())?;
-                let md_file = MDFile::new(context, &text, PathBuf::from(p.path()));
-
-                return Ok::<(PathBuf, MDFile), std::io::Error>((p.path().into(), md_file));
+        let (md_file_paths, attachments): (Vec<PathBuf>, Vec<PathBuf>) = file_paths
+            .into_iter()
+            .filter(|path| {
+                path.extension().is_some_and(|ext| ext == "md")
+                    || is_canvas(path)
+                    || is_vault_attachment(context, root_dir, path)
             })
-            .collect();
+            .partition(|path| !is_vault_attachment(context, root_dir, path));
+
+        Ok((
+            Vault {
+                ropes: HashMap::new().into(),
+                md_files: HashMap::new().into(),
+                modified: HashMap::new(),
+                attachments,
+                root_dir: root_dir.into(),
+                link_resolution: LinkResolution::new(context),
+                inbound_links: HashMap::new(),
+                ignore_files: IgnoreFiles::default(),
+            },
+            md_file_paths,
+        ))
+    }
 
-        let ropes: HashMap<PathBuf, Rope> = md_file_paths
-            .iter()
-            .flat_map(|p| {
-                let text = std::fs::read_to_string(p.path())?;
-                let rope = Rope::from_str(&text);
+    /// Read and parse the notes at `paths` in parallel; notes that can not be read are left out
+    pub fn read_notes(context: &Settings, paths: &[PathBuf]) -> Vec<ReadNote> {
+        let read = || {
+            paths
+                .par_iter()
+                .flat_map(|path| {
+                    let text = std::fs::read_to_string(path)?;
+                    let modified = std::fs::metadata(path)
+                        .and_then(|meta| meta.modified())
+                        .unwrap_or(SystemTime::UNIX_EPOCH);
+                    let text = indexed_text(path, &text);
+
+                    Ok::<ReadNote, std::io::Error>(ReadNote {
+                        md_file: MDFile::new(context, text, path.clone()),
+                        rope: Rope::from_str(text),
+                        path: path.clone(),
+                        modified,
+                    })
+                })
+                .collect::<Vec<_>>()
+        };
 
-                return Ok::<(PathBuf, Rope), std::io::Error>((p.path().into(), rope));
-            })
-            .collect();
+        match context.indexing_threads {
+            0 => read(),
+            threads => rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .map(|pool| pool.install(read))
+                .unwrap_or_else(|_| read()),
+        }
+    }
 
-        Ok(Vault {
-            ropes: ropes.into(),
-            md_files: md_files.into(),
-            root_dir: root_dir.into(),
-        })
+    /// Add notes read from disk. A note the editor sent while they were read is newer than its file, so it is kept
+    pub fn insert_notes(&mut self, notes: Vec<ReadNote>) {
+        for note in notes {
+            if self.md_files.contains_key(&note.path) {
+                continue;
+            }
+
+            let links = linked_names(&self.root_dir, &self.link_resolution, &note.md_file);
+            self.recount_inbound_links(vec![], links);
+
+            self.modified.insert(note.path.clone(), note.modified);
+            self.ropes.insert(note.path.clone(), note.rope);
+            self.md_files.insert(note.path, note.md_file);
+        }
     }
 
     pub fn update_vault(context: &Settings, old: &mut Vault, new_file: (&PathBuf, &str)) {
-        let new_md_file = MDFile::new(context, new_file.1, new_file.0.clone());
+        // New files are indexed unless they are ignored, the same as when the vault is constructed
+        if !old.md_files.contains_key(new_file.0) && old.is_ignored(context, new_file.0) {
+            return;
+        }
+
+        let text = indexed_text(new_file.0, new_file.1);
+        let new_md_file = MDFile::new(context, text, new_file.0.clone());
+        let old_links = old.md_files.get(new_file.0).map_or(vec![], |mdfile| {
+            linked_names(&old.root_dir, &old.link_resolution, mdfile)
+        });
+        let links = linked_names(&old.root_dir, &old.link_resolution, &new_md_file);
+        old.recount_inbound_links(old_links, links);
         let new = old.md_files.get_mut(new_file.0);
         match new {
             Some(file) => {
@@ -74,7 +154,12 @@ impl Vault {
             }
         };
 
-        let new_rope = Rope::from_str(new_file.1);
+        // opening a note sends its text again, which is no edit
+        if old.ropes.get(new_file.0).is_none_or(|rope| *rope != text) {
+            old.modified.insert(new_file.0.clone(), SystemTime::now());
+        }
+
+        let new_rope = Rope::from_str(text);
         let rope_entry = old.ropes.get_mut(new_file.0);
 
         match rope_entry {
@@ -88,6 +173,252 @@ impl Vault {
     }
 }
 
+impl Vault {
+    /// Apply a file's LSP content changes. Ranged changes edit the stored rope and only the lines they touch are
+    /// reparsed; changes without a range replace the whole file. References are resolved by scanning the files on
+    /// each request, so only the inbound link counts are patched here.
+    pub fn apply_changes(
+        context: &Settings,
+        vault: &mut Vault,
+        path: &Path,
+        changes: &[TextDocumentContentChangeEvent],
+    ) {
+        if is_canvas(path) {
+            return;
+        }
+
+        for change in changes {
+            let (Some(range), Some(rope)) = (change.range, vault.ropes.get_mut(path)) else {
+                Self::update_vault(context, vault, (&path.to_path_buf(), &change.text));
+                continue;
+            };
+
+            let start = position_char_index(rope, range.start, context.position_encoding);
+            let end = position_char_index(rope, range.end, context.position_encoding).max(start);
+            let removed = rope.slice(start..end).to_string();
+            let in_frontmatter =
+                frontmatter_end_line(rope).is_some_and(|end| range.start.line as usize <= end);
+
+            rope.remove(start..end);
+            rope.insert(start, &change.text);
+            vault.modified.insert(path.to_path_buf(), SystemTime::now());
+
+            let start_line = range.start.line;
+            let old_end_line = range.end.line.max(start_line);
+            let new_end_line = start_line + change.text.matches('\n').count() as u32;
+            let lines = rope
+                .slice(
+                    rope.line_to_char(start_line as usize)
+                        ..rope.line_to_char((new_end_line as usize + 1).min(rope.len_lines())),
+                )
+                .to_string();
+
+            // Frontmatter, fenced code blocks and Setext headings span several lines, so edits that may change them
+            // reparse everything; that includes edits to the line above an underline
+            let structural = |text: &str| {
+                text.contains("```")
+                    || text.contains("---")
+                    || text
+                        .lines()
+                        .any(|line| setext_underline_level(line).is_some())
+            };
+            let above_underline = rope
+                .get_line(new_end_line as usize + 1)
+                .is_some_and(|line| setext_underline_level(&line.to_string()).is_some());
+
+            let old_links = vault.md_files.get(path).map_or(vec![], |mdfile| {
+                linked_names(&vault.root_dir, &vault.link_resolution, mdfile)
+            });
+            match vault.md_files.get_mut(path) {
+                Some(mdfile)
+                    if !in_frontmatter
+                        && !above_underline
+                        && !structural(&removed)
+                        && !structural(&lines)
+                        && !mdfile.codeblocks.iter().any(|block| {
+                            block.range().start.line != block.range().end.line
+                                && block.range().start.line <= old_end_line
+                                && block.range().end.line >= start_line
+                        }) =>
+                {
+                    mdfile.reparse_lines(context, &lines, start_line, old_end_line, new_end_line)
+                }
+                Some(mdfile) => *mdfile = MDFile::new(context, &rope.to_string(), path.into()),
+                None => (),
+            }
+            let links = vault.md_files.get(path).map_or(vec![], |mdfile| {
+                linked_names(&vault.root_dir, &vault.link_resolution, mdfile)
+            });
+            vault.recount_inbound_links(old_links, links);
+        }
+    }
+
+    /// Take the links a note had out of the inbound link counts and add the links it has now
+    fn recount_inbound_links(&mut self, old_links: Vec<String>, links: Vec<String>) {
+        for name in old_links {
+            if let Some(count) = self.inbound_links.get_mut(&name) {
+                *count -= 1;
+                if *count == 0 {
+                    self.inbound_links.remove(&name);
+                }
+            }
+        }
+        for name in links {
+            *self.inbound_links.entry(name).or_insert(0) += 1;
+        }
+    }
+}
+
+/// The casefolded name of the note each link of `mdfile` points to, for the inbound link counts; links are counted by
+/// the name they end in rather than resolved, so notes that share a name share the count
+fn linked_names(root_dir: &Path, link_resolution: &LinkResolution, mdfile: &MDFile) -> Vec<String> {
+    mdfile
+        .references
+        .iter()
+        .filter_map(|reference| {
+            let file = match reference {
+                Reference::WikiFileLink(data) | Reference::MDFileLink(data) => &data.reference_text,
+                Reference::WikiHeadingLink(_, file, _)
+                | Reference::WikiIndexedBlockLink(_, file, _)
+                | Reference::MDHeadingLink(_, file, _)
+                | Reference::MDIndexedBlockLink(_, file, _) => file,
+                _ => return None,
+            };
+            let file = link_resolution.resolve(root_dir, &mdfile.path, reference, file);
+            let name = file.rsplit('/').next().unwrap_or(&file);
+
+            Some(normalize_for_match(name, &MatchOpts::CASEFOLD))
+        })
+        .collect()
+}
+
+/// Char index of an LSP position, with its column in the negotiated encoding, clamped to the rope
+fn position_char_index(rope: &Rope, position: Position, encoding: PositionEncoding) -> usize {
+    let line = position.line as usize;
+    if line >= rope.len_lines() {
+        return rope.len_chars();
+    }
+
+    let line_start = rope.line_to_char(line);
+    let line_chars = rope
+        .line(line)
+        .chars()
+        .take_while(|c| *c != '\n' && *c != '\r')
+        .collect_vec();
+
+    line_start + encoding.char_index(&line_chars, position.character)
+}
+
+/// Line of the `---` closing the frontmatter, if the file starts with one
+fn frontmatter_end_line(rope: &Rope) -> Option<usize> {
+    let is_fence = |line: ropey::RopeSlice| line.to_string().trim_end() == "---";
+
+    if !is_fence(rope.get_line(0)?) {
+        return None;
+    }
+
+    rope.lines().skip(1).position(is_fence).map(|i| i + 1)
+}
+
+/// Extensions of the files Obsidian can embed with `![[file]]`
+pub const ATTACHMENT_EXTENSIONS: &[&str] = &[
+    "png", "jpg", "jpeg", "gif", "bmp", "svg", "webp", "avif", "mp3", "wav", "m4a", "ogg", "flac",
+    "3gp", "mp4", "webm", "ogv", "mov", "mkv", "pdf",
+];
+
+pub fn is_attachment(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ATTACHMENT_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+/// Attachments by their extension, and every other file but notes and canvases in `attachments_folder`
+fn is_vault_attachment(context: &Settings, root_dir: &Path, path: &Path) -> bool {
+    is_attachment(path)
+        || (!context.attachments_folder.is_empty()
+            && path.starts_with(root_dir.join(&context.attachments_folder))
+            && path.extension().is_none_or(|ext| ext != "md")
+            && !is_canvas(path))
+}
+
+/// Obsidian canvases are JSON files that can be linked to like notes
+fn is_canvas(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "canvas")
+}
+
+/// Canvases are indexed as link targets only; their JSON is not parsed as markdown
+fn indexed_text<'a>(path: &Path, text: &'a str) -> &'a str {
+    match is_canvas(path) {
+        true => "",
+        false => text,
+    }
+}
+
+/// Matcher for the `ignore_patterns` setting
+fn ignore_patterns_matcher(context: &Settings, root_dir: &Path) -> Gitignore {
+    let mut builder = GitignoreBuilder::new(root_dir);
+    for pattern in &context.ignore_patterns {
+        let _ = builder.add_line(None, pattern);
+    }
+
+    builder.build().unwrap_or_else(|_| Gitignore::empty())
+}
+
+/// The .ignore and .gitignore of each folder a new file was checked in, read once; the vault is constructed again
+/// when the watcher sees one of these files change, which reads them again
+#[derive(Debug, Clone, Default)]
+struct IgnoreFiles(HashMap<PathBuf, [Gitignore; 2]>);
+
+impl Vault {
+    /// Whether the walk of [`Vault::scan`] would leave out a file it did not see: the file or one of its folders is
+    /// hidden, in a .ignore or .gitignore of the folders above it, or in the `ignore_patterns` setting
+    fn is_ignored(&mut self, context: &Settings, path: &Path) -> bool {
+        let Ok(relative) = path.strip_prefix(&self.root_dir) else {
+            return false;
+        };
+
+        let folders = path
+            .ancestors()
+            .skip(1)
+            .take_while(|folder| folder.starts_with(&self.root_dir))
+            .collect_vec();
+        for folder in &folders {
+            self.ignore_files
+                .0
+                .entry(folder.to_path_buf())
+                .or_insert_with(|| {
+                    [".ignore", ".gitignore"].map(|name| Gitignore::new(folder.join(name)).0)
+                });
+        }
+
+        // as in the walk, a deeper ignore file overrides the ones above it, and .ignore overrides .gitignore
+        let ignored_by_files = |entry: &Path, is_dir: bool| {
+            folders
+                .iter()
+                .filter(|folder| entry.starts_with(folder) && entry != **folder)
+                .flat_map(|folder| &self.ignore_files.0[*folder])
+                .map(|matcher| matcher.matched(entry, is_dir))
+                .find(|matched| !matched.is_none())
+                .is_some_and(|matched| matched.is_ignore())
+        };
+        let ignore_patterns = ignore_patterns_matcher(context, &self.root_dir);
+
+        // the walk does not enter an ignored folder, so the file is ignored if any of its folders are
+        let mut entry = self.root_dir.clone();
+        let components = relative.components().collect_vec();
+        components.iter().enumerate().any(|(index, component)| {
+            entry.push(component);
+            let is_dir = index + 1 < components.len();
+            let name = component.as_os_str();
+
+            name.to_str().is_some_and(|name| name.starts_with('.'))
+                || name == "logseq"
+                || ignore_patterns.matched(&entry, is_dir).is_ignore()
+                || ignored_by_files(&entry, is_dir)
+        })
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct MyHashMap<B: Hash>(HashMap<PathBuf, B>);
 
@@ -128,13 +459,29 @@ impl Hash for Vault {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+/// A note read from disk, ready to be added to a vault
+pub struct ReadNote {
+    path: PathBuf,
+    md_file: MDFile,
+    rope: Rope,
+    modified: SystemTime,
+}
+
+#[derive(Debug, Clone)]
 /// The in memory representation of the obsidian vault files. This data is exposed through an interface of methods to select the vaults data.
 /// These methods do not do any interpretation or analysis of the data. That is up to the consumer of this struct. The methods are analogous to selecting on a database.
 pub struct Vault {
     pub md_files: MyHashMap<MDFile>,
     pub ropes: MyHashMap<Rope>,
+    /// When each note last changed: its file's modification time when the vault was read, or its last edit since
+    modified: HashMap<PathBuf, SystemTime>,
+    /// Images, audio, video and PDFs that can be embedded, in path order
+    attachments: Vec<PathBuf>,
     root_dir: PathBuf,
+    link_resolution: LinkResolution,
+    /// How many links point to each note name, kept up to date as notes change
+    inbound_links: HashMap<String, usize>,
+    ignore_files: IgnoreFiles,
 }
 
 /// Methods using vaults data
@@ -159,6 +506,21 @@ impl Vault {
         }
     }
 
+    /// Select the (file, block id) pairs that the block links (`[[file#^id]]`, `[](file#^id)`) in a file point to; the file is as written in the link
+    pub fn select_referenced_block_ids(&self, path: &Path) -> Vec<(String, String)> {
+        self.select_references(Some(path))
+            .into_iter()
+            .flatten()
+            .filter_map(|(_, reference)| match reference {
+                WikiIndexedBlockLink(_, file, index) | MDIndexedBlockLink(_, file, index) => {
+                    Some((file.to_string(), index.to_string()))
+                }
+                _ => None,
+            })
+            .unique()
+            .collect()
+    }
+
     pub fn select_referenceable_at_position<'a>(
         &'a self,
         path: &'a Path,
@@ -236,66 +598,167 @@ impl Vault {
                     .par_iter()
                     .flat_map(|resolved| {
                         resolved.get_refname(self.root_dir()).and_then(|refname| {
+                            let link_file_key = refname.link_file_key()?;
+
+                            // markdown links may point to headings by their github anchor, and links to files may use their aliases
+                            let alternate_refnames = match resolved {
+                                Referenceable::Heading(_, heading) => {
+                                    let path = refname.path.as_deref()?;
+                                    vec![
+                                        format!("{}#{}", path, heading.anchor),
+                                        format!("{}#{}", link_file_key, heading.anchor),
+                                        format!("{}#{}", path, heading.plain_text),
+                                        format!("{}#{}", link_file_key, heading.plain_text),
+                                    ]
+                                }
+                                Referenceable::File(_, mdfile) => mdfile
+                                    .metadata
+                                    .iter()
+                                    .flat_map(|it| it.aliases())
+                                    .cloned()
+                                    .collect(),
+                                _ => vec![],
+                            };
+
+                            let infile_ref = refname
+                                .infile_ref
+                                .as_ref()
+                                .map(|refe| format!("#{}", refe))
+                                .unwrap_or("".to_string());
+
+                            // notes, headings and blocks are also linked by the end of their path
+                            let path_suffixes = match resolved {
+                                Referenceable::File(..)
+                                | Referenceable::Heading(..)
+                                | Referenceable::IndexedBlock(..) => refname
+                                    .path
+                                    .as_deref()
+                                    .map(|path| {
+                                        path_suffixes(path)
+                                            .map(|suffix| format!("{suffix}{infile_ref}"))
+                                            .collect_vec()
+                                    })
+                                    .unwrap_or_default(),
+                                _ => vec![],
+                            };
+
+                            // and canvases also by their name with the extension, `[[diagram.canvas]]`
+                            let canvas_refnames = match resolved {
+                                Referenceable::File(path, _) if is_canvas(path) => path_suffixes
+                                    .iter()
+                                    .map(|suffix| format!("{suffix}.canvas"))
+                                    .collect_vec(),
+                                _ => vec![],
+                            };
+
                             vec![
                                 refname.to_string(),
-                                format!(
-                                    "{}{}",
-                                    refname.link_file_key()?,
-                                    refname
-                                        .infile_ref
-                                        .map(|refe| format!("#{}", refe))
-                                        .unwrap_or("".to_string())
-                                ),
+                                format!("{}{}", link_file_key, infile_ref),
                             ]
+                            .into_iter()
+                            .chain(alternate_refnames)
+                            .chain(path_suffixes)
+                            .chain(canvas_refnames)
+                            .collect_vec()
                             .into()
                         })
                     })
                     .flatten()
                     .collect();
 
+                // with `loose_heading_links`, heading links are also resolved by their loose text
+                let loose_heading_refnames: HashSet<String> = resolved_referenceables
+                    .iter()
+                    .flat_map(|resolved| match resolved {
+                        Referenceable::Heading(
+                            _,
+                            MDHeading {
+                                loose_text: Some(loose_text),
+                                ..
+                            },
+                        ) => resolved
+                            .get_refname(self.root_dir())
+                            .and_then(|refname| refname.path)
+                            .map(|path| {
+                                path_suffixes(&path)
+                                    .map(|suffix| format!("{suffix}#{loose_text}"))
+                                    .collect_vec()
+                            })
+                            .unwrap_or_default(),
+                        _ => vec![],
+                    })
+                    .collect();
+
+                // links name their target by the file they resolve to, which for relative and folder links is not
+                // the file as it is written
                 let unresolved = self.select_references(None).map(|references| {
                     references
                         .iter()
-                        .unique_by(|(_, reference)| &reference.data().reference_text)
+                        .filter_map(|(path, reference)| {
+                            let resolve = |file| {
+                                self.link_resolution
+                                    .resolve(self.root_dir(), path, reference, file)
+                            };
+                            let (file, target) = match reference {
+                                Reference::WikiFileLink(data) | Reference::MDFileLink(data) => {
+                                    let file = resolve(&data.reference_text).into_owned();
+                                    (file.clone(), file)
+                                }
+                                Reference::WikiHeadingLink(_, file, heading)
+                                | Reference::MDHeadingLink(_, file, heading) => {
+                                    let file = resolve(file).into_owned();
+                                    let target = format!("{file}#{heading}");
+                                    (file, target)
+                                }
+                                Reference::WikiIndexedBlockLink(_, file, index)
+                                | Reference::MDIndexedBlockLink(_, file, index) => {
+                                    let file = resolve(file).into_owned();
+                                    let target = format!("{file}#^{index}");
+                                    (file, target)
+                                }
+                                Reference::Tag(..)
+                                | Reference::Footnote(..)
+                                | Reference::LinkRef(..) => return None,
+                            };
+
+                            Some((reference, file, target))
+                        })
+                        .unique_by(|(_, _, target)| target.clone())
                         .par_bridge()
                         .into_par_iter()
-                        .filter(|(_, reference)| {
+                        .filter(|(_, _, target)| {
                             !resolved_referenceables_refnames
-                                .contains(&reference.data().reference_text)
+                                .contains(target.trim_start_matches('/'))
                         })
-                        .flat_map(|(_, reference)| match reference {
-                            Reference::WikiFileLink(data) | Reference::MDFileLink(data) => {
-                                let mut path = self.root_dir().clone();
-                                path.push(&reference.data().reference_text);
-
-                                Some(Referenceable::UnresovledFile(path, &data.reference_text))
-
-                                // match data.reference_text.chars().collect_vec().as_slice() {
-
-                                //     [..,'.','m','d'] =>
-                                //     ['.', '/', rest @ ..]
-                                //     | ['/', rest @ ..]
-                                //     | rest if !rest.contains(&'.') => Some(Referenceable::UnresovledFile(path, &data.reference_text)),
-                                //     _ => None
-                                // }
-                            }
-                            Reference::WikiHeadingLink(_data, end_path, heading)
-                            | Reference::MDHeadingLink(_data, end_path, heading) => {
-                                let mut path = self.root_dir().clone();
-                                path.push(end_path);
-
-                                Some(Referenceable::UnresolvedHeading(path, end_path, heading))
-                            }
-                            Reference::WikiIndexedBlockLink(_data, end_path, index)
-                            | Reference::MDIndexedBlockLink(_data, end_path, index) => {
-                                let mut path = self.root_dir().clone();
-                                path.push(end_path);
-
-                                Some(Referenceable::UnresovledIndexedBlock(path, end_path, index))
+                        .filter(|(reference, file, _)| match reference {
+                            Reference::WikiHeadingLink(_, _, heading)
+                            | Reference::MDHeadingLink(_, _, heading) => !loose_heading_refnames
+                                .contains(&format!(
+                                    "{}#{}",
+                                    file.trim_start_matches('/'),
+                                    normalize_for_match(heading, &MatchOpts::LOOSE)
+                                )),
+                            _ => true,
+                        })
+                        .flat_map(|(reference, file, _)| {
+                            let path = self.root_dir().join(file.trim_start_matches('/'));
+
+                            match reference {
+                                Reference::WikiFileLink(data) | Reference::MDFileLink(data) => {
+                                    Some(Referenceable::UnresovledFile(path, &data.reference_text))
+                                }
+                                Reference::WikiHeadingLink(_data, end_path, heading)
+                                | Reference::MDHeadingLink(_data, end_path, heading) => {
+                                    Some(Referenceable::UnresolvedHeading(path, end_path, heading))
+                                }
+                                Reference::WikiIndexedBlockLink(_data, end_path, index)
+                                | Reference::MDIndexedBlockLink(_data, end_path, index) => Some(
+                                    Referenceable::UnresovledIndexedBlock(path, end_path, index),
+                                ),
+                                Reference::Tag(..)
+                                | Reference::Footnote(..)
+                                | Reference::LinkRef(..) => None,
                             }
-                            Reference::Tag(..)
-                            | Reference::Footnote(..)
-                            | Reference::LinkRef(..) => None,
                         })
                         .collect::<Vec<_>>()
                 });
@@ -308,13 +771,81 @@ impl Vault {
         }
     }
 
+    /// The characters of a line, ending in `\n` unless it is the last line; `\r\n` line endings are read as `\n`
     pub fn select_line(&self, path: &Path, line: isize) -> Option<Vec<char>> {
         let rope = self.ropes.get(path)?;
 
         let usize: usize = line.try_into().ok()?;
 
-        rope.get_line(usize)
-            .map(|slice| slice.chars().collect_vec())
+        rope.get_line(usize).map(|slice| {
+            let mut chars = slice.chars().collect_vec();
+            match chars.as_slice() {
+                [.., '\r', '\n'] => {
+                    chars.remove(chars.len() - 2);
+                }
+                [.., '\r'] => {
+                    chars.pop();
+                    chars.push('\n');
+                }
+                _ => (),
+            }
+            chars
+        })
+    }
+
+    /// Position just past the last character of the file
+    pub fn select_end_position(&self, path: &Path) -> Option<Position> {
+        let rope = self.ropes.get(path)?;
+        let last_line = rope.len_lines().saturating_sub(1);
+
+        Some(Position {
+            line: last_line as u32,
+            character: rope.get_line(last_line)?.len_chars() as u32,
+        })
+    }
+
+    /// Line of the closing `---` of the file's frontmatter
+    pub fn select_frontmatter_end_line(&self, path: &Path) -> Option<usize> {
+        frontmatter_end_line(self.ropes.get(path)?)
+    }
+
+    /// When the note at `path` last changed
+    pub fn select_modified(&self, path: &Path) -> Option<SystemTime> {
+        self.modified.get(path).copied()
+    }
+
+    /// How many links point to each note name, casefolded; links are counted by the name they end in rather than
+    /// resolved, so notes that share a name share the count
+    pub fn select_inbound_link_counts(&self) -> &HashMap<String, usize> {
+        &self.inbound_links
+    }
+
+    pub fn select_attachments(&self) -> &[PathBuf] {
+        &self.attachments
+    }
+
+    /// The attachment an embed like `![[image.png]]` points to, by its vault relative path or its file name
+    pub fn select_attachment(&self, name: &str) -> Option<&PathBuf> {
+        let relative = self.root_dir.join(name);
+
+        self.attachments
+            .iter()
+            .find(|path| **path == relative)
+            .or_else(|| {
+                self.attachments
+                    .iter()
+                    .find(|path| path.file_name().and_then(|it| it.to_str()) == Some(name))
+            })
+    }
+
+    /// The callout headers in the vault, with the note each is in
+    pub fn select_callouts(&self) -> impl Iterator<Item = (&Path, &MDCallout)> {
+        self.md_files.iter().flat_map(|(path, md_file)| {
+            md_file
+                .callouts
+                .iter()
+                .map(move |callout| (path.as_path(), callout))
+        })
     }
 
     pub fn select_headings(&self, path: &Path) -> Option<&Vec<MDHeading>> {
@@ -323,10 +854,57 @@ impl Vault {
         Some(headings)
     }
 
+    /// Notes that share their name with another note, with the shortest path that links to only that note:
+    /// `a/note` and `b/note` for `a/note.md` and `b/note.md`
+    pub fn select_unambiguous_refnames(&self) -> HashMap<&Path, String> {
+        self.md_files
+            .keys()
+            .filter_map(|path| {
+                let name = path.file_stem()?.to_str()?;
+                Some((normalize_for_match(name, &MatchOpts::CASEFOLD), path))
+            })
+            .into_group_map()
+            .into_values()
+            .filter(|paths| paths.len() > 1)
+            .flat_map(|paths| {
+                let refpaths = paths
+                    .into_iter()
+                    .filter_map(|path| {
+                        Some((path.as_path(), get_obsidian_ref_path(&self.root_dir, path)?))
+                    })
+                    .collect_vec();
+
+                refpaths
+                    .iter()
+                    .map(|(path, refpath)| {
+                        let shortest = path_suffixes(refpath)
+                            // a bare name matches any case, so the notes always need a folder to tell them apart
+                            .filter(|suffix| suffix.contains('/'))
+                            .filter(|suffix| {
+                                refpaths.iter().all(|(other, other_refpath)| {
+                                    other == path
+                                        || !(other_refpath == suffix
+                                            || other_refpath.ends_with(&format!("/{suffix}")))
+                                })
+                            })
+                            .last()
+                            .unwrap_or(refpath);
+
+                        (*path, shortest.to_string())
+                    })
+                    .collect_vec()
+            })
+            .collect()
+    }
+
     pub fn root_dir(&self) -> &PathBuf {
         &self.root_dir
     }
 
+    pub fn link_resolution(&self) -> &LinkResolution {
+        &self.link_resolution
+    }
+
     pub fn select_references_for_referenceable(
         &self,
         referenceable: &Referenceable,
@@ -337,7 +915,12 @@ impl Vault {
             references
                 .into_par_iter()
                 .filter(|(ref_path, reference)| {
-                    referenceable.matches_reference(&self.root_dir, reference, ref_path)
+                    referenceable.matches_reference(
+                        &self.root_dir,
+                        &self.link_resolution,
+                        reference,
+                        ref_path,
+                    ) && !self.note_shadows_canvas(reference, referenceable)
                 })
                 .map(|(path, reference)| {
                     match std::fs::metadata(path).and_then(|meta| meta.modified()) {
@@ -363,9 +946,27 @@ impl Vault {
 
         referenceables
             .into_iter()
-            .filter(|i| reference.references(self.root_dir(), reference_path, i))
+            .filter(|i| {
+                reference.references(self.root_dir(), &self.link_resolution, reference_path, i)
+                    && !self.note_shadows_canvas(reference, i)
+            })
             .collect()
     }
+
+    /// Whether `reference` leaves out the extension of the canvas `referenceable`, and a note of the same name is
+    /// there for it to link to instead; `[[diagram]]` is `diagram.md` when there is one, and `diagram.canvas` otherwise
+    fn note_shadows_canvas(&self, reference: &Reference, referenceable: &Referenceable) -> bool {
+        match (reference, referenceable) {
+            (
+                Reference::WikiFileLink(data) | Reference::MDFileLink(data),
+                Referenceable::File(path, _),
+            ) if is_canvas(path) => {
+                !data.reference_text.ends_with(".canvas")
+                    && self.md_files.contains_key(&path.with_extension("md"))
+            }
+            _ => false,
+        }
+    }
 }
 
 pub enum Preview {
@@ -402,10 +1003,12 @@ impl Vault {
                     .into(),
                 )
             }
-            Referenceable::Heading(_, _) => {
+            Referenceable::Heading(path, heading) => {
                 let range = referenceable.get_range()?;
+                let section_end = self.select_section_end(path, heading)?;
+
                 Some(
-                    (range.start.line..=range.end.line + 10)
+                    (range.start.line..section_end.min(range.end.line + 11))
                         .filter_map(|ln| self.select_line(referenceable.get_path(), ln as isize)) // flatten those options!
                         .map(String::from_iter)
                         .join("")
@@ -434,6 +1037,75 @@ impl Vault {
         }
     }
 
+    /// The line after the section of `heading`, at the next heading of the same or a higher level, or `u32::MAX` when
+    /// the section runs to the end of the note
+    fn select_section_end(&self, path: &Path, heading: &MDHeading) -> Option<u32> {
+        Some(
+            self.md_files
+                .get(path)?
+                .headings
+                .iter()
+                .filter(|other| {
+                    other.range.start.line > heading.range.start.line
+                        && other.level <= heading.level
+                })
+                .map(|other| other.range.start.line)
+                .min()
+                .unwrap_or(u32::MAX),
+        )
+    }
+
+    /// The text an embed like `![[Note#Section]]` or `![[Note#^id]]` transcludes: the whole section of a heading, or the
+    /// line of a block without its id
+    pub fn select_embedded_text(&self, referenceable: &Referenceable) -> Option<String> {
+        match referenceable {
+            Referenceable::Heading(path, heading) => {
+                let rope = self.ropes.get(*path)?;
+                let section_end = self
+                    .select_section_end(path, heading)?
+                    .min(rope.len_lines() as u32);
+
+                Some(
+                    (heading.range.start.line..section_end)
+                        .filter_map(|ln| self.select_line(path, ln as isize))
+                        .map(String::from_iter)
+                        .join("")
+                        .trim_end()
+                        .to_string(),
+                )
+            }
+            Referenceable::IndexedBlock(path, block) => {
+                // the paragraph or list item the id ends, with the items nested in it, or else the id's own line
+                let id_line = block.range.start.line;
+                let (start, end) = crate::selection::block_ranges(self, path, block.range.start)
+                    .first()
+                    .map_or((id_line, id_line), |range| {
+                        (range.start.line, range.end.line)
+                    });
+
+                Some(
+                    (start..=end)
+                        .filter_map(|ln| {
+                            let line = String::from_iter(self.select_line(path, ln as isize)?);
+                            let line = line.trim_end();
+                            Some(match ln == id_line {
+                                true => line
+                                    .strip_suffix(&format!("^{}", block.index))
+                                    .unwrap_or(line)
+                                    .trim_end()
+                                    .to_string(),
+                                false => line.to_string(),
+                            })
+                        })
+                        .join("\n")
+                        .trim_start()
+                        .to_string(),
+                )
+            }
+            _ => None,
+        }
+    }
+
     pub fn select_blocks(&self) -> Vec<Block<'_>> {
         self.ropes
             .par_iter()
@@ -464,6 +1136,36 @@ impl Vault {
             .filter(|block| !block.text.is_empty())
             .collect()
     }
+
+    /// Every note of the vault, in no particular order, for going through the vault one note at a time. The documents
+    /// borrow the vault, so it can not change while they are read
+    // nothing in the server reads the vault this way since callout types are indexed, but tools going through it do
+    #[allow(dead_code)]
+    pub fn iter_documents(&self) -> impl Iterator<Item = Document<'_>> {
+        self.ropes
+            .iter()
+            .map(|(path, rope)| Document { path, rope })
+    }
+}
+
+/// A note whose text is read a line at a time, as it is needed, from the vault's copy of it
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy)]
+pub struct Document<'a> {
+    pub path: &'a Path,
+    rope: &'a Rope,
+}
+
+#[allow(dead_code)]
+impl<'a> Document<'a> {
+    /// The lines of the note without their line breaks, `\n` or `\r\n`; a line is only copied when the vault does
+    /// not hold it in one piece
+    pub fn lines(&self) -> impl Iterator<Item = Cow<'a, str>> + 'a {
+        self.rope.lines().map(|line| match Cow::from(line) {
+            Cow::Borrowed(line) => Cow::Borrowed(line.trim_end_matches(['\n', '\r'])),
+            Cow::Owned(line) => Cow::Owned(line.trim_end_matches(['\n', '\r']).to_string()),
+        })
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, Copy)]
@@ -481,6 +1183,7 @@ impl AsRef<str> for Block<'_> {
 
 pub trait Rangeable {
     fn range(&self) -> &MyRange;
+    fn range_mut(&mut self) -> &mut MyRange;
     fn includes(&self, other: &impl Rangeable) -> bool {
         let self_range = self.range();
         let other_range = other.range();
@@ -506,36 +1209,54 @@ impl Rangeable for MDHeading {
     fn range(&self) -> &MyRange {
         &self.range
     }
+    fn range_mut(&mut self) -> &mut MyRange {
+        &mut self.range
+    }
 }
 
 impl Rangeable for MDFootnote {
     fn range(&self) -> &MyRange {
         &self.range
     }
+    fn range_mut(&mut self) -> &mut MyRange {
+        &mut self.range
+    }
 }
 
 impl Rangeable for MDIndexedBlock {
     fn range(&self) -> &MyRange {
         &self.range
     }
+    fn range_mut(&mut self) -> &mut MyRange {
+        &mut self.range
+    }
 }
 
 impl Rangeable for MDTag {
     fn range(&self) -> &MyRange {
         &self.range
     }
+    fn range_mut(&mut self) -> &mut MyRange {
+        &mut self.range
+    }
 }
 
 impl Rangeable for MDLinkReferenceDefinition {
     fn range(&self) -> &MyRange {
         &self.range
     }
+    fn range_mut(&mut self) -> &mut MyRange {
+        &mut self.range
+    }
 }
 
 impl Rangeable for Reference {
     fn range(&self) -> &MyRange {
         &self.range
     }
+    fn range_mut(&mut self) -> &mut MyRange {
+        &mut self.data_mut().range
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Default, Hash, Clone)]
@@ -549,6 +1270,9 @@ pub struct MDFile {
     pub link_reference_definitions: Vec<MDLinkReferenceDefinition>,
     pub metadata: Option<MDMetadata>,
     pub codeblocks: Vec<MDCodeBlock>,
+    pub callouts: Vec<MDCallout>,
+    /// How links are matched against the aliases, from `case_matching`
+    alias_case: Case,
 }
 
 impl MDFile {
@@ -572,6 +1296,9 @@ impl MDFile {
             .filter(|it| !code_blocks.iter().any(|codeblock| codeblock.includes(it)));
         let indexed_blocks = MDIndexedBlock::new(text)
             .filter(|it| !code_blocks.iter().any(|codeblock| codeblock.includes(it)));
+        let callouts = MDCallout::new(text)
+            .filter(|it| !code_blocks.iter().any(|codeblock| codeblock.includes(it)))
+            .collect_vec();
         let tags = match context {
             Settings {
                 tags_in_codeblocks: false,
@@ -582,8 +1309,29 @@ impl MDFile {
             _ => MDTag::new(text).collect_vec(),
         };
         let metadata = MDMetadata::new(text);
+        let (tags, links) = match &metadata {
+            Some(metadata) => {
+                let frontmatter_tags = MDTag::from_frontmatter(text, metadata);
+                let links = links
+                    .into_iter()
+                    .chain(frontmatter_tags.iter().map(|tag| {
+                        Reference::Tag(ReferenceData {
+                            display_text: None,
+                            range: tag.range,
+                            reference_text: format!("#{}", tag.tag_ref),
+                        })
+                    }))
+                    .collect_vec();
+
+                (
+                    frontmatter_tags.into_iter().chain(tags).collect_vec(),
+                    links,
+                )
+            }
+            None => (tags, links),
+        };
 
-        MDFile {
+        let mut mdfile = MDFile {
             references: links,
             headings: headings.collect(),
             indexed_blocks: indexed_blocks.collect(),
@@ -593,16 +1341,162 @@ impl MDFile {
             link_reference_definitions: link_refs.collect(),
             metadata,
             codeblocks: code_blocks,
+            callouts,
+            alias_case: context.case_matching,
+        };
+        mdfile.index_title(context);
+        mdfile.index_loose_headings(context);
+        mdfile.index_heading_anchors();
+
+        mdfile
+    }
+
+    /// Like GitHub, a heading whose anchor is already taken in the note gets `-1`, `-2`, ... appended, in note order
+    fn index_heading_anchors(&mut self) {
+        let mut seen: HashMap<String, usize> = HashMap::new();
+        for heading in self.headings.iter_mut() {
+            let slug = github_slug(&heading.plain_text);
+            let repeats = seen.entry(slug.clone()).or_default();
+            heading.anchor = match *repeats {
+                0 => slug,
+                n => format!("{}-{}", slug, n),
+            };
+            *repeats += 1;
+        }
+    }
+
+    /// With `loose_heading_links`, each heading keeps the text loose heading links are matched against
+    fn index_loose_headings(&mut self, context: &Settings) {
+        if !context.loose_heading_links {
+            return;
+        }
+
+        for heading in self.headings.iter_mut() {
+            heading.loose_text = Some(normalize_for_match(&heading.plain_text, &MatchOpts::LOOSE));
+        }
+    }
+
+    /// With `title_source = "Heading"`, the first H1 becomes an alias of the file unless it is already its name
+    fn index_title(&mut self, context: &Settings) {
+        if context.title_source != TitleSource::Heading {
+            return;
+        }
+
+        let title = self
+            .headings
+            .iter()
+            .find(|heading| heading.level.0 == 1)
+            .map(|heading| heading.heading_text.clone())
+            .filter(|title| {
+                self.file_name()
+                    .map(|name| normalize_for_match(name, &MatchOpts::CASEFOLD))
+                    != Some(normalize_for_match(title, &MatchOpts::CASEFOLD))
+            });
+
+        match (&mut self.metadata, title) {
+            (Some(metadata), title) => metadata.set_title(title),
+            (None, Some(title)) => {
+                let mut metadata = MDMetadata::default();
+                metadata.set_title(Some(title));
+                self.metadata = Some(metadata);
+            }
+            (None, None) => {}
         }
     }
 
     pub fn file_name(&self) -> Option<&str> {
         self.path.file_stem()?.to_str()
     }
+
+    /// Whether `name` is one of the aliases in the file's frontmatter, with case compared as `case_matching` says
+    pub fn has_alias(&self, name: &str) -> bool {
+        let opts = MatchOpts::for_case(&self.alias_case, name);
+
+        self.metadata
+            .iter()
+            .flat_map(|it| it.aliases())
+            .any(|alias| normalize_for_match(alias, &opts) == normalize_for_match(name, &opts))
+    }
 }
 
 impl MDFile {
-    fn get_referenceables(&self) -> Vec<Referenceable> {
+    /// Replace what was parsed from lines `start_line..=old_end_line` with `lines`, the text of
+    /// `start_line..=new_end_line` after an edit, and shift everything below by the change in line count
+    fn reparse_lines(
+        &mut self,
+        context: &Settings,
+        lines: &str,
+        start_line: u32,
+        old_end_line: u32,
+        new_end_line: u32,
+    ) {
+        fn splice<T: Rangeable>(
+            items: &mut Vec<T>,
+            new_items: Vec<T>,
+            start_line: u32,
+            old_end_line: u32,
+            new_end_line: u32,
+        ) {
+            let shift = |item: &mut T, from: u32, to: u32| {
+                let range = &mut item.range_mut().0;
+                range.start.line = range.start.line - from + to;
+                range.end.line = range.end.line - from + to;
+            };
+
+            items.retain(|item| !(start_line..=old_end_line).contains(&item.range().start.line));
+            items
+                .iter_mut()
+                .filter(|item| item.range().start.line > old_end_line)
+                .for_each(|item| shift(item, old_end_line, new_end_line));
+            items.extend(new_items.into_iter().map(|mut item| {
+                shift(&mut item, 0, start_line);
+                item
+            }));
+            items.sort_by_key(|item| (item.range().start.line, item.range().start.character));
+        }
+
+        let MDFile {
+            references,
+            headings,
+            indexed_blocks,
+            tags,
+            footnotes,
+            path: _,
+            link_reference_definitions,
+            metadata: _,
+            codeblocks,
+            callouts,
+            alias_case: _,
+        } = MDFile::new(context, lines, self.path.clone());
+
+        let lines = (start_line, old_end_line, new_end_line);
+        splice(&mut self.references, references, lines.0, lines.1, lines.2);
+        splice(&mut self.headings, headings, lines.0, lines.1, lines.2);
+        splice(
+            &mut self.indexed_blocks,
+            indexed_blocks,
+            lines.0,
+            lines.1,
+            lines.2,
+        );
+        splice(&mut self.tags, tags, lines.0, lines.1, lines.2);
+        splice(&mut self.footnotes, footnotes, lines.0, lines.1, lines.2);
+        splice(
+            &mut self.link_reference_definitions,
+            link_reference_definitions,
+            lines.0,
+            lines.1,
+            lines.2,
+        );
+        splice(&mut self.codeblocks, codeblocks, lines.0, lines.1, lines.2);
+        splice(&mut self.callouts, callouts, lines.0, lines.1, lines.2);
+        // the metadata is left alone by line edits, but the first H1 may have changed
+        self.index_title(context);
+        self.index_loose_headings(context);
+        self.index_heading_anchors();
+    }
+
+    pub fn get_referenceables(&self) -> Vec<Referenceable> {
         let MDFile {
             references: _,
             headings,
@@ -613,6 +1507,8 @@ impl MDFile {
             link_reference_definitions,
             metadata: _,
             codeblocks: _,
+            callouts: _,
+            alias_case: _,
         } = self;
 
         iter::once(Referenceable::File(&self.path, self))
@@ -686,9 +1582,13 @@ impl Default for Reference {
 
 use Reference::*;
 
-use crate::config::Settings;
+use crate::config::{Case, FolderNote, PositionEncoding, Settings, TitleSource};
 
-use self::{metadata::MDMetadata, parsing::MDCodeBlock};
+pub use self::vaults::Vaults;
+use self::{
+    metadata::MDMetadata,
+    parsing::{MDCallout, MDCodeBlock},
+};
 
 impl Reference {
     pub fn data(&self) -> &ReferenceData {
@@ -705,42 +1605,67 @@ impl Reference {
         }
     }
 
-    pub fn matches_type(&self, other: &Reference) -> bool {
-        match &other {
-            Tag(..) => matches!(self, Tag(..)),
-            WikiFileLink(..) => matches!(self, WikiFileLink(..)),
-            WikiHeadingLink(..) => matches!(self, WikiHeadingLink(..)),
-            WikiIndexedBlockLink(..) => matches!(self, WikiIndexedBlockLink(..)),
-            Footnote(..) => matches!(self, Footnote(..)),
-            MDFileLink(..) => matches!(self, MDFileLink(..)),
-            MDHeadingLink(..) => matches!(self, MDHeadingLink(..)),
-            MDIndexedBlockLink(..) => matches!(self, MDIndexedBlockLink(..)),
-            LinkRef(..) => matches!(self, LinkRef(..)),
+    pub fn data_mut(&mut self) -> &mut ReferenceData {
+        match self {
+            Tag(data, ..) => data,
+            WikiFileLink(data, ..) => data,
+            WikiHeadingLink(data, ..) => data,
+            WikiIndexedBlockLink(data, ..) => data,
+            Footnote(data) => data,
+            MDFileLink(data, ..) => data,
+            MDHeadingLink(data, ..) => data,
+            MDIndexedBlockLink(data, ..) => data,
+            LinkRef(data, ..) => data,
+        }
+    }
+
+    pub fn matches_type(&self, other: &Reference) -> bool {
+        match &other {
+            Tag(..) => matches!(self, Tag(..)),
+            WikiFileLink(..) => matches!(self, WikiFileLink(..)),
+            WikiHeadingLink(..) => matches!(self, WikiHeadingLink(..)),
+            WikiIndexedBlockLink(..) => matches!(self, WikiIndexedBlockLink(..)),
+            Footnote(..) => matches!(self, Footnote(..)),
+            MDFileLink(..) => matches!(self, MDFileLink(..)),
+            MDHeadingLink(..) => matches!(self, MDHeadingLink(..)),
+            MDIndexedBlockLink(..) => matches!(self, MDIndexedBlockLink(..)),
+            LinkRef(..) => matches!(self, LinkRef(..)),
         }
     }
 
+    /// Every link, tag, footnote and link reference in `text`, which may be a single line or a whole file.
+    /// `file_name` is the file the text is in, for links to its own headings and blocks, like `[[#heading]]`
     pub fn new<'a>(text: &'a str, file_name: &'a str) -> impl Iterator<Item = Reference> + 'a {
         static WIKI_LINK_RE: Lazy<Regex> = Lazy::new(|| {
-            Regex::new(r"\[\[(?<filepath>[^\[\]\|\.\#]+)?(\#(?<infileref>[^\[\]\.\|]+))?(?<ending>\.[^\# <>]+)?(\|(?<display>[^\[\]\.\|]+))?\]\]")
+            Regex::new(r"\[\[(?<filepath>(?:[^\[\]\|\.\#\\]|\\[^\[\]\|\.\#])+)?(\#(?<infileref>(?:[^\[\]\.\|\\]|\\[^\[\]\.\|])+))?(?<ending>\.[^\# <>\[\]\|\\]+)?(\\?\|(?<display>(?:\\[\[\]\|]|[^\[\]\.\|]|\[[^\[\]\|]*\]|\[)+))?\]\]")
 
                 .unwrap()
-        }); // A [[link]] that does not have any [ or ] in it
+        }); // A [[link]]; only the display text may have brackets in it, like [[link|see [1]]], or escaped ones: [[link|a \] b \| c]].
+            // In tables the display text follows an escaped pipe, [[link\|display]], since a bare one would end the cell
+
+        // Ranges are computed against one rope per parse; ropes are cheap to clone
+        let rope = Rope::from_str(text);
 
         let wiki_links = WIKI_LINK_RE
             .captures_iter(text)
             .filter(
                 |captures| match captures.name("ending").map(|ending| ending.as_str()) {
-                    Some(".md") | None => true,
+                    Some(".md" | ".canvas") | None => true,
                     _ => false,
                 },
             )
             .flat_map(RegexTuple::new)
-            .flat_map(|regextuple| {
-                generic_link_constructor::<WikiReferenceConstructor>(text, file_name, regextuple)
+            .flat_map({
+                let rope = rope.clone();
+                move |regextuple| {
+                    generic_link_constructor::<WikiReferenceConstructor>(
+                        &rope, file_name, regextuple,
+                    )
+                }
             });
 
         static MD_LINK_RE: Lazy<Regex> = Lazy::new(|| {
-            Regex::new(r"\[(?<display>[^\[\]\.]*)\]\(<?(?<filepath>(\.?\/)?[^\[\]\|\.\#<>]+)?(?<ending>\.[^\# <>]+)?(\#(?<infileref>[^\[\]\.\|<>]+))?>?\)")
+            Regex::new(r"\[(?<display>(?:\\[\[\]]|[^\[\]\.])*)\]\(<?(?<filepath>(\/|(\.\.?\/)+)?[^\[\]\|\.\#<>]+)?(?<ending>\.[^\# <>]+)?(\#(?<infileref>[^\[\]\.\|<>]+))?>?\)")
                 .expect("MD Link Not Constructing")
         }); // [display](relativePath)
 
@@ -748,13 +1673,16 @@ impl Reference {
             .captures_iter(text)
             .filter(
                 |captures| match captures.name("ending").map(|ending| ending.as_str()) {
-                    Some(".md") | None => true,
+                    Some(".md" | ".canvas") | None => true,
                     _ => false,
                 },
             )
             .flat_map(RegexTuple::new)
-            .flat_map(|regextuple| {
-                generic_link_constructor::<MDReferenceConstructor>(text, file_name, regextuple)
+            .flat_map({
+                let rope = rope.clone();
+                move |regextuple| {
+                    generic_link_constructor::<MDReferenceConstructor>(&rope, file_name, regextuple)
+                }
             });
 
         let tags = MDTag::new(text).map(|tag| {
@@ -778,13 +1706,15 @@ impl Reference {
                     _ => None,
                 },
             )
-            .map(|(outer, index)| {
+            .map({
+                let rope = rope.clone();
+                move |(outer, index)| {
                 Footnote(ReferenceData {
                     reference_text: index.as_str().into(),
-                    range: MyRange::from_range(&Rope::from_str(text), outer.range()),
+                    range: MyRange::from_range(&rope, outer.range()),
                     display_text: None,
                 })
-            });
+            }});
 
         let link_ref_references: Vec<Reference> = if MDLinkReferenceDefinition::new(text)
             .collect_vec()
@@ -807,7 +1737,7 @@ impl Reference {
                 .map(|(outer, index)| {
                     LinkRef(ReferenceData {
                         reference_text: index.as_str().into(),
-                        range: MyRange::from_range(&Rope::from_str(text), outer.range()),
+                        range: MyRange::from_range(&rope, outer.range()),
                         display_text: None,
                     })
                 })
@@ -818,17 +1748,63 @@ impl Reference {
             vec![]
         };
 
+        // In document order; where matches overlap, like a #tag inside [[a #tag]], the one that starts first wins,
+        // and of those starting together, the earlier kind in this chain (the sort is stable)
         wiki_links
             .into_iter()
             .chain(md_links)
             .chain(tags)
             .chain(footnote_references)
             .chain(link_ref_references)
+            .sorted_by_key(|reference| {
+                let start = reference.data().range.start;
+                (start.line, start.character)
+            })
+            .filter({
+                let mut kept_end = (0, 0);
+                move |reference| {
+                    let range = reference.data().range;
+                    let keep = (range.start.line, range.start.character) >= kept_end;
+                    if keep {
+                        kept_end = (range.end.line, range.end.character);
+                    }
+                    keep
+                }
+            })
+    }
+
+    /// Whether `file`, the file of this link in the note at `file_path`, names the file of `referenceable`. A file that
+    /// is resolved from the note's folder or a folder note names an unresolved target by its path
+    fn names_file(
+        &self,
+        root_dir: &Path,
+        links: &LinkResolution,
+        file_path: &Path,
+        file: &str,
+        referenceable: &Referenceable,
+    ) -> bool {
+        match (
+            links.resolve(root_dir, file_path, self, file),
+            referenceable,
+        ) {
+            (
+                Cow::Owned(file),
+                Referenceable::UnresovledFile(path, ..)
+                | Referenceable::UnresolvedHeading(path, ..)
+                | Referenceable::UnresovledIndexedBlock(path, ..),
+            ) => *path == root_dir.join(file.trim_start_matches('/')),
+            (file, _) => matches_path_or_file(
+                &file,
+                referenceable.get_refname(root_dir),
+                referenceable.get_path(),
+            ),
+        }
     }
 
     pub fn references(
         &self,
         root_dir: &Path,
+        links: &LinkResolution,
         file_path: &Path,
         referenceable: &Referenceable,
     ) -> bool {
@@ -875,7 +1851,10 @@ impl Reference {
                 | WikiFileLink(ReferenceData {
                     reference_text: file_ref_text,
                     ..
-                }) => matches_path_or_file(file_ref_text, referenceable.get_refname(root_dir)),
+                }) => {
+                    self.names_file(root_dir, links, file_path, file_ref_text, referenceable)
+                        || matches!(referenceable, Referenceable::File(_, mdfile) if mdfile.has_alias(file_ref_text))
+                }
                 Tag(_) => false,
                 WikiHeadingLink(_, _, _) => false,
                 WikiIndexedBlockLink(_, _, _) => false,
@@ -903,8 +1882,49 @@ impl Reference {
                 | WikiIndexedBlockLink(.., file_ref_text, link_infile_ref)
                 | MDHeadingLink(.., file_ref_text, link_infile_ref)
                 | MDIndexedBlockLink(.., file_ref_text, link_infile_ref) => {
-                    matches_path_or_file(file_ref_text, referenceable.get_refname(root_dir))
-                        && link_infile_ref.to_lowercase() == infile_ref.to_lowercase()
+                    // a markdown link written as an anchor, like `#my-heading-1`, links only the heading with that
+                    // anchor; otherwise it is matched against the heading text like a wiki link
+                    let by_anchor = matches!(self, MDHeadingLink(..))
+                        && *link_infile_ref == github_slug(link_infile_ref);
+                    let link_infile_ref =
+                        normalize_for_match(link_infile_ref, &MatchOpts::CASEFOLD);
+                    // `[[#heading]]` and links naming the note they are in stay in that note, even when other notes
+                    // share its name
+                    let own_note = !file_ref_text.contains('/')
+                        && file_path
+                            .file_stem()
+                            .and_then(|stem| stem.to_str())
+                            .is_some_and(|stem| {
+                                normalize_for_match(stem, &MatchOpts::CASEFOLD)
+                                    == normalize_for_match(file_ref_text, &MatchOpts::CASEFOLD)
+                            });
+                    (!own_note
+                        || referenceable.is_unresolved()
+                        || referenceable.get_path() == file_path)
+                        && self.names_file(root_dir, links, file_path, file_ref_text, referenceable)
+                        && match referenceable {
+                            Referenceable::Heading(_, heading) => {
+                                (match by_anchor {
+                                    true => link_infile_ref == heading.anchor,
+                                    false => {
+                                        link_infile_ref
+                                            == normalize_for_match(infile_ref, &MatchOpts::CASEFOLD)
+                                            || link_infile_ref
+                                                == normalize_for_match(
+                                                    &heading.plain_text,
+                                                    &MatchOpts::CASEFOLD,
+                                                )
+                                    }
+                                }) || heading.loose_text.as_ref().is_some_and(|loose_text| {
+                                    *loose_text
+                                        == normalize_for_match(&link_infile_ref, &MatchOpts::LOOSE)
+                                })
+                            }
+                            _ => {
+                                link_infile_ref
+                                    == normalize_for_match(infile_ref, &MatchOpts::CASEFOLD)
+                            }
+                        }
                 }
                 Tag(_) => false,
                 WikiFileLink(_) => false,
@@ -922,11 +1942,13 @@ impl Reference {
                 MDIndexedBlockLink(_, _, _) => false,
                 Footnote(_) => false,
                 LinkRef(data) => {
-                    Some(data.reference_text.to_lowercase())
-                        == referenceable
-                            .get_refname(root_dir)
-                            .as_deref()
-                            .map(|string| string.to_lowercase())
+                    Some(normalize_for_match(
+                        &data.reference_text,
+                        &MatchOpts::CASEFOLD,
+                    )) == referenceable
+                        .get_refname(root_dir)
+                        .as_deref()
+                        .map(|string| normalize_for_match(string, &MatchOpts::CASEFOLD))
                         && file_path == *path
                 }
             },
@@ -939,6 +1961,7 @@ struct RegexTuple<'a> {
     file_path: Option<Match<'a>>,
     infile_ref: Option<Match<'a>>,
     display_text: Option<Match<'a>>,
+    ending: Option<Match<'a>>,
 }
 
 impl RegexTuple<'_> {
@@ -948,12 +1971,14 @@ impl RegexTuple<'_> {
             capture.name("filepath"),
             capture.name("infileref"),
             capture.name("display"),
+            capture.name("ending"),
         ) {
-            (Some(range), file_path, infile_ref, display_text) => Some(RegexTuple {
+            (Some(range), file_path, infile_ref, display_text, ending) => Some(RegexTuple {
                 range,
                 file_path,
                 infile_ref,
                 display_text,
+                ending,
             }),
             _ => None,
         }
@@ -963,7 +1988,8 @@ impl RegexTuple<'_> {
 trait ParseableReferenceConstructor {
     fn new_heading(data: ReferenceData, path: &str, heading: &str) -> Reference;
     fn new_file_link(data: ReferenceData) -> Reference;
-    fn new_indexed_block_link(data: ReferenceData, path: &str, index: &str) -> Reference;
+    /// A block link, or nothing when `index` can not be a block id in this syntax
+    fn new_indexed_block_link(data: ReferenceData, path: &str, index: &str) -> Option<Reference>;
 } // TODO: Turn this into a macro
 
 struct WikiReferenceConstructor;
@@ -976,8 +2002,12 @@ impl ParseableReferenceConstructor for WikiReferenceConstructor {
     fn new_file_link(data: ReferenceData) -> Reference {
         Reference::WikiFileLink(data)
     }
-    fn new_indexed_block_link(data: ReferenceData, path: &str, index: &str) -> Reference {
-        Reference::WikiIndexedBlockLink(data, path.into(), index.into())
+    fn new_indexed_block_link(data: ReferenceData, path: &str, index: &str) -> Option<Reference> {
+        Some(Reference::WikiIndexedBlockLink(
+            data,
+            path.into(),
+            index.into(),
+        ))
     }
 }
 
@@ -988,19 +2018,27 @@ impl ParseableReferenceConstructor for MDReferenceConstructor {
     fn new_file_link(data: ReferenceData) -> Reference {
         Reference::MDFileLink(data)
     }
-    fn new_indexed_block_link(data: ReferenceData, path: &str, index: &str) -> Reference {
-        Reference::MDIndexedBlockLink(data, path.into(), index.into())
+    /// The destination is a URL fragment, so `#^` has to be followed by a whole block id; `[x](Note#^)` or
+    /// `[x](Note#^a b)` are not block links at all, rather than links to a block that can never exist
+    fn new_indexed_block_link(data: ReferenceData, path: &str, index: &str) -> Option<Reference> {
+        let block_id = !index.is_empty()
+            && index
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '-' || c == '_');
+
+        block_id.then(|| Reference::MDIndexedBlockLink(data, path.into(), index.into()))
     }
 }
 
 fn generic_link_constructor<T: ParseableReferenceConstructor>(
-    text: &str,
+    rope: &Rope,
     file_name: &str,
     RegexTuple {
         range,
         file_path,
         infile_ref,
         display_text,
+        ending,
     }: RegexTuple,
 ) -> Option<Reference> {
     if file_path.is_some_and(|path| path.as_str().starts_with("http://")
@@ -1010,32 +2048,49 @@ fn generic_link_constructor<T: ParseableReferenceConstructor>(
         return None;
     }
 
-    match (range, file_path.map(|it| it.as_str()).unwrap_or(file_name), infile_ref, display_text) {
+    // Like Obsidian, ignore the spaces around the file and heading, as in [[ Note Name ]]; the range still covers them
+    // Notes are named without `.md`, so `[x](Note.md)` and `[[Note]]` link the same note; any other extension, like
+    // `.canvas`, is kept to tell the file apart from a note of the same name
+    let file_path = file_path
+        .map(|it| it.as_str().trim())
+        .filter(|it| !it.is_empty())
+        .map(|path| match ending.map(|it| it.as_str()) {
+            Some(".md") | None => path.to_string(),
+            Some(ending) => format!("{path}{ending}"),
+        });
+    let infile_ref = infile_ref.map(|it| it.as_str().trim());
+
+    match (
+        range,
+        file_path.as_deref().unwrap_or(file_name),
+        infile_ref,
+        display_text,
+    ) {
         // Pure file reference as there is no infileref such as #... for headings or #^... for indexed blocks
         (full, filepath, None, display) => Some(T::new_file_link(ReferenceData {
             reference_text: filepath.into(),
-            range: MyRange::from_range(&Rope::from_str(text), full.range()),
+            range: MyRange::from_range(rope, full.range()),
             display_text: display.map(|d| d.as_str().into()),
         })),
-        (full, filepath, Some(infile), display) if infile.as_str().get(0..1) == Some("^") => {
-            Some(T::new_indexed_block_link(
+        (full, filepath, Some(infile), display) if infile.get(0..1) == Some("^") => {
+            T::new_indexed_block_link(
                 ReferenceData {
-                    reference_text: format!("{}#{}", filepath, infile.as_str()),
-                    range: MyRange::from_range(&Rope::from_str(text), full.range()),
+                    reference_text: format!("{}#{}", filepath, infile),
+                    range: MyRange::from_range(rope, full.range()),
                     display_text: display.map(|d| d.as_str().into()),
                 },
                 filepath,
-                &infile.as_str()[1..], // drop the ^ for the index
-            ))
+                &infile[1..], // drop the ^ for the index
+            )
         }
         (full, filepath, Some(infile), display) => Some(T::new_heading(
             ReferenceData {
-                reference_text: format!("{}#{}", filepath, infile.as_str()),
-                range: MyRange::from_range(&Rope::from_str(text), full.range()),
+                reference_text: format!("{}#{}", filepath, infile),
+                range: MyRange::from_range(rope, full.range()),
                 display_text: display.map(|d| d.as_str().into()),
             },
             filepath,
-            infile.as_str(),
+            infile,
         )),
     }
 }
@@ -1052,8 +2107,14 @@ impl Default for HeadingLevel {
 #[derive(Debug, Default, PartialEq, Eq, Clone)]
 pub struct MDHeading {
     pub heading_text: String,
+    /// The heading text with inline markdown removed, as Obsidian matches it: `My **bold** heading` -> `My bold heading`
+    pub plain_text: String,
     pub range: MyRange,
     pub level: HeadingLevel,
+    /// With `loose_heading_links`, the heading as loose links match it: its letters and digits, lowercased
+    pub loose_text: Option<String>,
+    /// The GitHub anchor markdown links use for the heading, `my-heading`; repeats in a note get `-1`, `-2`, ...
+    pub anchor: String,
 }
 
 impl Hash for MDHeading {
@@ -1071,6 +2132,11 @@ impl MyRange {
         // convert from byte offset to char offset
         let char_start = rope.byte_to_char(range.start);
         let char_end = rope.byte_to_char(range.end);
+        // a match running to the end of a `\r\n` line stops before the `\r`, as it would before the `\n`
+        let char_end = match char_end > char_start && rope.get_char(char_end - 1) == Some('\r') {
+            true => char_end - 1,
+            false => char_end,
+        };
 
         let start_line = rope.char_to_line(char_start);
         let start_offset = char_start - rope.line_to_char(start_line);
@@ -1119,6 +2185,7 @@ impl MDHeading {
         static HEADING_RE: Lazy<Regex> =
             Lazy::new(|| Regex::new(r"(?<starter>#+) (?<heading_text>.+)").unwrap());
 
+        let rope = Rope::from_str(text);
         let headings = HEADING_RE
             .captures_iter(text)
             .flat_map(
@@ -1127,15 +2194,116 @@ impl MDHeading {
                     _ => None,
                 },
             )
-            .map(|(full_heading, heading_match, starter)| {
+            .map(move |(full_heading, heading_match, starter)| {
+                let plain_text = strip_inline_formatting(heading_match.as_str());
                 return MDHeading {
                     heading_text: heading_match.as_str().trim_end().into(),
-                    range: MyRange::from_range(&Rope::from_str(text), full_heading.range()),
+                    anchor: github_slug(&plain_text),
+                    plain_text,
+                    range: MyRange::from_range(&rope, full_heading.range()),
                     level: HeadingLevel(starter.as_str().len()),
+                    loose_text: None,
                 };
             });
 
         headings
+            .chain(Self::setext(text))
+            .sorted_by_key(|heading| (heading.range.start.line, heading.range.start.character))
+    }
+
+    /// Setext headings: a line of text underlined with `===` for level 1 or `---` for level 2. The text has to start
+    /// its paragraph, so a `---` under a blank line stays a thematic break; frontmatter fences are never underlines
+    fn setext(text: &str) -> Vec<MDHeading> {
+        let lines = text.lines().collect_vec();
+        let frontmatter_end = match lines.first() {
+            Some(first) if first.trim_end() == "---" => lines
+                .iter()
+                .skip(1)
+                .position(|line| line.trim_end() == "---")
+                .map(|i| i + 1),
+            _ => None,
+        };
+
+        // lines that are blocks of their own rather than paragraph text: headings, fences, quotes, list items and table
+        // rows; after the first two of those, and after thematic breaks, a new paragraph starts
+        static BLOCK_RE: Lazy<Regex> = Lazy::new(|| {
+            Regex::new(r"^(#+(\s|$)|```|~~~|>|[-*+](\s|$)|\d+[.)](\s|$)|\|)").unwrap()
+        });
+        static THEMATIC_BREAK_RE: Lazy<Regex> =
+            Lazy::new(|| Regex::new(r"^([-*_]\s*){3,}$").unwrap());
+
+        let starts_paragraph = |i: usize| {
+            let Some(previous) = i.checked_sub(1).map(|previous| lines[previous].trim()) else {
+                return true;
+            };
+            Some(i - 1) == frontmatter_end
+                || previous.is_empty()
+                || previous.starts_with(['#', '`', '~'])
+                || THEMATIC_BREAK_RE.is_match(previous)
+                || setext_underline_level(previous).is_some()
+        };
+        let is_paragraph_text = |line: &str| {
+            let indent = line.len() - line.trim_start().len();
+            let text = line.trim();
+            indent <= 3
+                && !text.is_empty()
+                && !BLOCK_RE.is_match(text)
+                && !THEMATIC_BREAK_RE.is_match(text)
+                && setext_underline_level(line).is_none()
+        };
+
+        lines
+            .iter()
+            .enumerate()
+            .tuple_windows()
+            .filter(|((i, _), _)| frontmatter_end.is_none_or(|end| *i > end))
+            .filter_map(|((i, line), (_, underline))| {
+                let level = setext_underline_level(underline)?;
+                if !is_paragraph_text(line) || !starts_paragraph(i) {
+                    return None;
+                }
+
+                let heading_text = line.trim();
+                let indent = line.chars().take_while(|c| c.is_whitespace()).count();
+                let plain_text = strip_inline_formatting(heading_text);
+
+                Some(MDHeading {
+                    heading_text: heading_text.to_string(),
+                    anchor: github_slug(&plain_text),
+                    plain_text,
+                    range: MyRange(tower_lsp::lsp_types::Range {
+                        start: Position {
+                            line: i as u32,
+                            character: indent as u32,
+                        },
+                        end: Position {
+                            line: i as u32 + 1,
+                            character: underline.trim_end().chars().count() as u32,
+                        },
+                    }),
+                    level: HeadingLevel(level),
+                    loose_text: None,
+                })
+            })
+            .collect()
+    }
+
+    /// Whether the heading is a Setext one, its text on one line and its underline on the next
+    pub fn is_setext(&self) -> bool {
+        self.range.start.line != self.range.end.line
+    }
+}
+
+/// The level of the Setext heading that `line` underlines, if it is an underline: 1 for `===`, 2 for `---`
+fn setext_underline_level(line: &str) -> Option<usize> {
+    let indent = line.len() - line.trim_start().len();
+    let underline = line.trim();
+
+    match underline.chars().next()? {
+        _ if indent > 3 => None,
+        '=' if underline.chars().all(|c| c == '=') => Some(1),
+        '-' if underline.chars().all(|c| c == '-') => Some(2),
+        _ => None,
     }
 }
 
@@ -1157,15 +2325,16 @@ impl MDIndexedBlock {
         static INDEXED_BLOCK_RE: Lazy<Regex> =
             Lazy::new(|| Regex::new(r".+ (\^(?<index>\w+))").unwrap());
 
+        let rope = Rope::from_str(text);
         let indexed_blocks = INDEXED_BLOCK_RE
             .captures_iter(text)
             .flat_map(|c| match (c.get(1), c.name("index")) {
                 (Some(full), Some(index)) => Some((full, index)),
                 _ => None,
             })
-            .map(|(full, index)| MDIndexedBlock {
+            .map(move |(full, index)| MDIndexedBlock {
                 index: index.as_str().into(),
-                range: MyRange::from_range(&Rope::from_str(text), full.range()),
+                range: MyRange::from_range(&rope, full.range()),
             });
 
         indexed_blocks
@@ -1192,6 +2361,7 @@ impl MDFootnote {
         static FOOTNOTE_RE: Lazy<Regex> =
             Lazy::new(|| Regex::new(r"\[(?<index>\^[^ \[\]]+)\]\:(?<text>.+)").unwrap());
 
+        let rope = Rope::from_str(text);
         let footnotes = FOOTNOTE_RE
             .captures_iter(text)
             .flat_map(|c| match (c.get(0), c.name("index"), c.name("text")) {
@@ -1200,10 +2370,14 @@ impl MDFootnote {
                 }
                 _ => None,
             })
-            .map(|(full, index, footnote_text)| MDFootnote {
-                footnote_text: footnote_text.as_str().trim_start().into(),
+            .map(move |(full, index, footnote_text)| MDFootnote {
+                footnote_text: footnote_text
+                    .as_str()
+                    .trim_start()
+                    .trim_end_matches('\r')
+                    .into(),
                 index: index.as_str().into(),
-                range: MyRange::from_range(&Rope::from_str(text), full.range()),
+                range: MyRange::from_range(&rope, full.range()),
             });
 
         footnotes
@@ -1225,9 +2399,13 @@ impl Hash for MDTag {
 impl MDTag {
     fn new(text: &str) -> impl Iterator<Item = MDTag> + '_ {
         static TAG_RE: Lazy<Regex> = Lazy::new(|| {
-            Regex::new(r"(\n|\A| )(?<full>#(?<tag>[a-zA-Z_\-\/][0-9a-zA-Z_\-\/]*))").unwrap()
+            Regex::new(
+                r"(\n|\A| )(?<full>#(?<tag>[a-zA-Z_\-\/][0-9a-zA-Z_\-\/]*(?::[0-9a-zA-Z_\-\/]+)?))",
+            )
+            .unwrap()
         });
 
+        let rope = Rope::from_str(text);
         let tagged_blocks = TAG_RE
             .captures_iter(text)
             .flat_map(|c| match (c.name("full"), c.name("tag")) {
@@ -1235,13 +2413,78 @@ impl MDTag {
                 _ => None,
             })
             .filter(|(_, index)| index.as_str().chars().any(|c| c.is_alphabetic()))
-            .map(|(full, index)| MDTag {
+            .map(move |(full, index)| MDTag {
                 tag_ref: index.as_str().into(),
-                range: MyRange::from_range(&Rope::from_str(text), full.range()),
+                range: MyRange::from_range(&rope, full.range()),
             });
 
         tagged_blocks
     }
+
+    /// The tag without its value: `status` for `#status:done` and `area` for `#area/work`
+    pub fn base(&self) -> &str {
+        self.split_value().0
+    }
+
+    /// The value after a tag's base, written as `#key:value` or as the last segment of `#key/value`
+    pub fn value(&self) -> Option<&str> {
+        self.split_value().1
+    }
+
+    fn split_value(&self) -> (&str, Option<&str>) {
+        match self
+            .tag_ref
+            .split_once(':')
+            .or_else(|| self.tag_ref.rsplit_once('/'))
+        {
+            Some((base, value)) => (base, Some(value)),
+            None => (&self.tag_ref, None),
+        }
+    }
+
+    /// Tags declared in the frontmatter's `tags` key; ranges cover the tag as written, with or without a `#`
+    fn from_frontmatter(text: &str, metadata: &MDMetadata) -> Vec<MDTag> {
+        static TAGS_KEY_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?m)^tags:").unwrap());
+
+        let Some(frontmatter) = MDMetadata::frontmatter(text) else {
+            return vec![];
+        };
+        let Some(key) = TAGS_KEY_RE.find(frontmatter.as_str()) else {
+            return vec![];
+        };
+
+        let rope = Rope::from_str(text);
+        let key = frontmatter.start() + key.start()..frontmatter.start() + key.end();
+        let mut search_from = key.end;
+
+        metadata
+            .tags()
+            .iter()
+            .map(|tag| {
+                let found = text
+                    .get(search_from..frontmatter.end())
+                    .and_then(|rest| rest.find(tag.as_str()))
+                    .map(|index| search_from + index);
+                let range = match found {
+                    Some(start) => {
+                        let end = start + tag.len();
+                        search_from = end;
+                        match text[..start].ends_with('#') {
+                            true => start - 1..end,
+                            false => start..end,
+                        }
+                    }
+                    // a tag not written as its value, like the quoted 'it''s', is placed on the `tags:` key
+                    None => key.clone(),
+                };
+
+                MDTag {
+                    tag_ref: tag.clone(),
+                    range: MyRange::from_range(&rope, range),
+                }
+            })
+            .collect()
+    }
 }
 
 #[derive(Clone, Hash, Eq, PartialEq, Debug)]
@@ -1257,16 +2500,17 @@ impl MDLinkReferenceDefinition {
         static REGEX: Lazy<Regex> =
             Lazy::new(|| Regex::new(r"\[(?<index>[^\^][^ \[\]]+)\]\:(?<text>.+)").unwrap());
 
+        let rope = Rope::from_str(text);
         let result = REGEX
             .captures_iter(text)
             .flat_map(|c| match (c.get(0), c.name("index"), c.name("text")) {
                 (Some(full), Some(index), Some(text)) => Some((full, index, text)),
                 _ => None,
             })
-            .flat_map(|(full, index, url)| {
+            .flat_map(move |(full, index, url)| {
                 Some(MDLinkReferenceDefinition {
                     link_ref_name: index.as_str().to_string(),
-                    range: MyRange::from_range(&Rope::from_str(text), full.range()),
+                    range: MyRange::from_range(&rope, full.range()),
                     url: url.as_str().trim().to_string(),
                     title: None,
                 })
@@ -1299,6 +2543,163 @@ pub enum Referenceable<'a> {
     LinkRefDef(&'a PathBuf, &'a MDLinkReferenceDefinition),
 }
 
+/// How [`normalize_for_match`] canonicalizes text before two strings are compared
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MatchOpts {
+    /// Compare case insensitively
+    pub casefold: bool,
+    /// Trim and treat any run of whitespace as a single space
+    pub collapse_whitespace: bool,
+    /// Treat `%20` and `\ ` as plain spaces, as they are written in link paths
+    pub unify_separators: bool,
+    /// Compare `é` as `e`
+    pub strip_accents: bool,
+    /// Compare only letters and digits, leaving out spaces and punctuation
+    pub alphanumeric_only: bool,
+}
+
+impl MatchOpts {
+    pub const CASEFOLD: MatchOpts = MatchOpts {
+        casefold: true,
+        collapse_whitespace: false,
+        unify_separators: false,
+        strip_accents: false,
+        alphanumeric_only: false,
+    };
+
+    /// How heading links are matched with `loose_heading_links`
+    pub const LOOSE: MatchOpts = MatchOpts {
+        casefold: true,
+        collapse_whitespace: false,
+        unify_separators: false,
+        strip_accents: false,
+        alphanumeric_only: true,
+    };
+
+    /// How `query` is compared with a name under a `case_matching` setting: `Smart` ignores case unless the query has
+    /// an uppercase letter
+    pub fn for_case(case: &Case, query: &str) -> MatchOpts {
+        MatchOpts {
+            casefold: match case {
+                Case::Ignore => true,
+                Case::Smart => !query.chars().any(char::is_uppercase),
+                Case::Respect => false,
+            },
+            ..Default::default()
+        }
+    }
+}
+
+/// The one canonical form used when names, headings, and link text are compared
+pub fn normalize_for_match(s: &str, opts: &MatchOpts) -> String {
+    let s = match opts.unify_separators {
+        true => s.replace("%20", " ").replace(r"\ ", " "),
+        false => s.to_string(),
+    };
+
+    let s = match opts.collapse_whitespace {
+        true => s.split_whitespace().join(" "),
+        false => s,
+    };
+
+    let s = match opts.strip_accents {
+        true => s.chars().map(nucleo_matcher::chars::normalize).collect(),
+        false => s,
+    };
+
+    let s = match opts.casefold {
+        true => s.to_lowercase(),
+        false => s,
+    };
+
+    match opts.alphanumeric_only {
+        true => s.chars().filter(|c| c.is_alphanumeric()).collect(),
+        false => s,
+    }
+}
+
+/// Slugify a heading the way GitHub renders anchors: `My Heading!` -> `my-heading`
+///
+/// Like GitHub, emoji are dropped along with punctuation, but the space after them is kept: `🚀 Launch` -> `-launch`
+pub fn github_slug(heading_text: &str) -> String {
+    heading_text
+        .to_lowercase()
+        .chars()
+        .filter(|c| c.is_alphanumeric() || matches!(c, ' ' | '-' | '_'))
+        .map(|c| if c == ' ' { '-' } else { c })
+        .collect()
+}
+
+/// Link text with `\[` and `\]` read as the brackets they stand for
+pub fn unescape_link_brackets(text: &str) -> String {
+    text.replace(r"\[", "[").replace(r"\]", "]")
+}
+
+/// Escape the brackets in `text` so it can sit inside a link; brackets that are already escaped are left as they are
+pub fn escape_link_brackets(text: &str) -> String {
+    unescape_link_brackets(text)
+        .replace('[', r"\[")
+        .replace(']', r"\]")
+}
+
+/// Escape `text` so it can be the display text of a wiki link, where a bare `|` or `]` would end it
+pub fn escape_wiki_link_text(text: &str) -> String {
+    escape_link_brackets(text)
+        .replace(r"\|", "|")
+        .replace('|', r"\|")
+}
+
+/// Wiki link display text with its escaped brackets and pipes read as the characters they stand for
+pub fn unescape_wiki_link_text(text: &str) -> String {
+    unescape_link_brackets(text).replace(r"\|", "|")
+}
+
+/// Byte range of the part of a link's text naming its target: before the `|` of a wiki link, or after the `](` of a
+/// markdown link. The display text may repeat the target's `#heading`, so edits to the target search only here
+pub fn link_target_span(link_text: &str) -> Range<usize> {
+    match link_text.trim_start_matches('!').starts_with("[[") {
+        true => 0..link_text.find('|').unwrap_or(link_text.len()),
+        false => link_text.rfind("](").map_or(0, |index| index + 2)..link_text.len(),
+    }
+}
+
+/// Remove inline markdown, keeping what it renders as: links become their display text, code spans their contents
+pub fn strip_inline_formatting(text: &str) -> String {
+    static CODE_SPAN_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"`([^`]*)`").unwrap());
+    static WIKI_LINK_RE: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"\[\[([^\]\|]*)(?:\|([^\]]*))?\]\]").unwrap());
+    static MD_LINK_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"!?\[([^\]]*)\]\([^\)]*\)").unwrap());
+    static EMPHASIS_RE: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"\*\*|__|~~|==|\*|\b_|_\b").unwrap());
+
+    let strip = |text: &str| {
+        let text = WIKI_LINK_RE.replace_all(text, |captures: &regex::Captures| {
+            captures
+                .get(2)
+                .or(captures.get(1))
+                .map(|it| it.as_str().to_string())
+                .unwrap_or_default()
+        });
+        let text = MD_LINK_RE.replace_all(&text, "$1");
+        EMPHASIS_RE.replace_all(&text, "").to_string()
+    };
+
+    // code span contents are kept as they are written
+    let mut plain = String::new();
+    let mut last = 0;
+    for captures in CODE_SPAN_RE.captures_iter(text) {
+        let (Some(span), Some(code)) = (captures.get(0), captures.get(1)) else {
+            continue;
+        };
+        plain.push_str(&strip(&text[last..span.start()]));
+        plain.push_str(code.as_str());
+        last = span.end();
+    }
+    plain.push_str(&strip(&text[last..]));
+
+    plain.trim().to_string()
+}
+
 /// Utility function
 pub fn get_obsidian_ref_path(root_dir: &Path, path: &Path) -> Option<String> {
     diff_paths(path, root_dir).and_then(|diff| diff.with_extension("").to_str().map(String::from))
@@ -1420,6 +2821,7 @@ impl Referenceable<'_> {
     pub fn matches_reference(
         &self,
         root_dir: &Path,
+        links: &LinkResolution,
         reference: &Reference,
         reference_path: &Path,
     ) -> bool {
@@ -1461,14 +2863,16 @@ impl Referenceable<'_> {
                 })
                 | MDHeadingLink(.., file_ref_text, _)
                 | MDIndexedBlockLink(.., file_ref_text, _) => {
-                    matches_path_or_file(file_ref_text, self.get_refname(root_dir))
+                    reference.names_file(root_dir, links, reference_path, file_ref_text, self)
+                        || matches!(reference, WikiFileLink(..) | MDFileLink(..))
+                            && matches!(self, Referenceable::File(_, mdfile) if mdfile.has_alias(file_ref_text))
                 }
                 Tag(_) => false,
                 Footnote(_) => false,
                 LinkRef(_) => false,
             },
 
-            _ => reference.references(root_dir, reference_path, self),
+            _ => reference.references(root_dir, links, reference_path, self),
         }
     }
 
@@ -1510,26 +2914,136 @@ impl Referenceable<'_> {
     }
 }
 
-fn matches_path_or_file(file_ref_text: &str, refname: Option<Refname>) -> bool {
+/// The settings that decide which file the file of a link, as it is written, names
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct LinkResolution {
+    relative_markdown_links: bool,
+    folder_note: FolderNote,
+}
+
+impl LinkResolution {
+    pub fn new(context: &Settings) -> LinkResolution {
+        LinkResolution {
+            relative_markdown_links: context.relative_markdown_links,
+            folder_note: context.folder_note,
+        }
+    }
+
+    /// The file that `file`, written in a link of the note at `note`, names. A folder, `folder/`, names the folder note
+    /// the `folder_note` setting picks, like `folder/folder`, and with `relative_markdown_links` the file of a markdown
+    /// link is relative to the note's folder, so it names the path from the vault root, `/folder/file`. Other files are
+    /// returned as they are written
+    fn resolve<'a>(
+        &self,
+        root_dir: &Path,
+        note: &Path,
+        reference: &Reference,
+        file: &'a str,
+    ) -> Cow<'a, str> {
+        let file = match file
+            .strip_suffix('/')
+            .and_then(|folder| self.folder_note.name(folder))
+        {
+            Some(name) => Cow::Owned(format!("{file}{name}")),
+            None => Cow::Borrowed(file),
+        };
+
+        match note
+            .parent()
+            .and_then(|folder| folder.strip_prefix(root_dir).ok())
+        {
+            Some(folder) if self.is_relative(reference, &file) => {
+                Cow::Owned(vault_path(folder, &file))
+            }
+            _ => file,
+        }
+    }
+
+    /// Whether `file`, written in `reference`, is relative to the folder of the note the link is in
+    pub fn is_relative(&self, reference: &Reference, file: &str) -> bool {
+        self.relative_markdown_links
+            && matches!(
+                reference,
+                MDFileLink(..) | MDHeadingLink(..) | MDIndexedBlockLink(..)
+            )
+            && !file.starts_with('/')
+    }
+}
+
+/// The vault root relative path, `/folder/file`, of `path` relative to `folder`, with its `.` and `..` resolved: `../b/c`
+/// from the folder `a/d` is `/a/b/c`. A path that climbs out of the vault stays out of it, `/../c`, so it links to nothing
+fn vault_path(folder: &Path, path: &str) -> String {
+    let mut segments = folder
+        .components()
+        .filter_map(|component| component.as_os_str().to_str())
+        .collect_vec();
+    let mut outside = false;
+
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => (),
+            ".." => outside |= segments.pop().is_none(),
+            segment => segments.push(segment),
+        }
+    }
+
+    match outside {
+        true => format!("/../{}", segments.join("/")),
+        false => format!("/{}", segments.join("/")),
+    }
+}
+
+/// The path and every shorter path made of its last folders and name: `a/b/note`, `b/note` and `note`
+fn path_suffixes(path: &str) -> impl Iterator<Item = &str> {
+    iter::once(path).chain(path.match_indices('/').map(|(i, _)| &path[i + 1..]))
+}
+
+fn matches_path_or_file(file_ref_text: &str, refname: Option<Refname>, path: &Path) -> bool {
     (|| {
-        let refname = refname?;
+        let mut refname = refname?;
+        // `[[diagram.canvas]]` is only the canvas, while `[[diagram]]` may be either
+        let file_ref_text = match file_ref_text.strip_suffix(".canvas") {
+            Some(stem) if is_canvas(path) => {
+                refname.path = refname
+                    .path
+                    .map(|path| path.trim_end_matches(".canvas").to_string());
+                stem
+            }
+            Some(_) => return None,
+            None => file_ref_text,
+        };
+
         let refname_path = refname.path.clone()?; // this function should not be used for tags, ... only for heading, files, indexed blocks
 
         if file_ref_text.contains('/') {
-            let file_ref_text = file_ref_text.replace(r"%20", " ");
-            let file_ref_text = file_ref_text.replace(r"\ ", " ");
+            let file_ref_text = normalize_for_match(
+                file_ref_text,
+                &MatchOpts {
+                    unify_separators: true,
+                    ..Default::default()
+                },
+            );
 
             let chars: Vec<char> = file_ref_text.chars().collect();
             match chars.as_slice() {
                 &['.', '/', ref path @ ..] | &['/', ref path @ ..] => {
                     Some(String::from_iter(path) == refname_path)
                 }
-                path => Some(String::from_iter(path) == refname_path),
+                // a path without a leading slash may leave out the folders that every note with the name shares, and
+                // the `../` of a relative path are left out the same way
+                path => {
+                    let path = String::from_iter(path);
+                    let path = path.trim_start_matches("../");
+                    Some(refname_path == path || refname_path.ends_with(&format!("/{path}")))
+                }
             }
         } else {
             let last_segment = refname.link_file_key()?;
 
-            Some(file_ref_text.to_lowercase() == last_segment.to_lowercase())
+            Some(
+                normalize_for_match(file_ref_text, &MatchOpts::CASEFOLD)
+                    == normalize_for_match(&last_segment, &MatchOpts::CASEFOLD),
+            )
         }
     })()
     .is_some_and(|b| b)
@@ -1538,16 +3052,41 @@ fn matches_path_or_file(file_ref_text: &str, refname: Option<Refname>) -> bool {
 // tests
 #[cfg(test)]
 mod vault_tests {
-    use std::path::Path;
+    use std::path::{Path, PathBuf};
 
     use itertools::Itertools;
-    use tower_lsp::lsp_types::{Position, Range};
+    use tower_lsp::lsp_types::{
+        ClientCapabilities, Position, Range, TextDocumentContentChangeEvent,
+    };
+
+    use crate::config::{Case, FolderNote, Settings, TitleSource};
 
     use crate::vault::{HeadingLevel, MyRange, ReferenceData};
     use crate::vault::{MDLinkReferenceDefinition, Refname};
 
     use super::Reference::*;
-    use super::{MDFile, MDFootnote, MDHeading, MDIndexedBlock, MDTag, Reference, Referenceable};
+    use super::{
+        escape_link_brackets, escape_wiki_link_text, github_slug, normalize_for_match,
+        unescape_link_brackets, unescape_wiki_link_text, LinkResolution, MDFile, MDFootnote,
+        MDHeading, MDIndexedBlock, MDTag, MatchOpts, Rangeable, Reference, Referenceable, Vault,
+    };
+
+    /// The notes each link of `note` resolves to, in the order of the links
+    fn resolved_targets(vault: &Vault, note: &Path) -> Vec<Vec<PathBuf>> {
+        vault.md_files[note]
+            .references
+            .iter()
+            .map(|reference| {
+                vault
+                    .select_referenceables_for_reference(reference, note)
+                    .into_iter()
+                    .filter(|referenceable| !referenceable.is_unresolved())
+                    .map(|referenceable| referenceable.get_path().to_path_buf())
+                    .sorted()
+                    .collect_vec()
+            })
+            .collect_vec()
+    }
 
     #[test]
     fn wiki_link_parsing() {
@@ -1605,6 +3144,162 @@ mod vault_tests {
         assert_eq!(parsed, expected)
     }
 
+    #[test]
+    fn parses_every_link_on_a_line() {
+        let line = "See [[one]], [two](two.md#Intro), ![[three#^block|3]] and #tag[^1] [[one]]";
+
+        let parsed = Reference::new(line, "test")
+            .sorted_by_key(|reference| reference.data().range.start.character)
+            .map(|reference| {
+                let data = reference.data();
+                (
+                    data.reference_text.clone(),
+                    data.range.start.character,
+                    data.range.end.character,
+                )
+            })
+            .collect_vec();
+
+        assert_eq!(
+            parsed,
+            vec![
+                ("one".to_string(), 4, 11),
+                ("two#Intro".to_string(), 13, 32),
+                ("three#^block".to_string(), 35, 53),
+                ("#tag".to_string(), 58, 62),
+                ("^1".to_string(), 62, 66),
+                ("one".to_string(), 67, 74),
+            ]
+        );
+    }
+
+    #[test]
+    fn links_come_in_document_order() {
+        let kinds = |text: &str| {
+            Reference::new(text, "test")
+                .map(|reference| {
+                    (
+                        reference.data().reference_text.clone(),
+                        reference.data().range.start.character,
+                    )
+                })
+                .collect_vec()
+        };
+
+        assert_eq!(
+            kinds("[[one]] and [[two#Heading]]"),
+            vec![("one".to_string(), 0), ("two#Heading".to_string(), 12)]
+        );
+        assert_eq!(
+            kinds("[md](first) then [[wiki]] then [md](last)"),
+            vec![
+                ("first".to_string(), 0),
+                ("wiki".to_string(), 17),
+                ("last".to_string(), 31),
+            ]
+        );
+    }
+
+    #[test]
+    fn overlapping_matches_keep_the_link() {
+        let parsed = Reference::new("[[my #tag]] #other", "test").collect_vec();
+
+        assert!(matches!(
+            parsed.as_slice(),
+            [WikiHeadingLink(_, file, heading), Tag(tag)]
+                if file == "my" && heading == "tag" && tag.reference_text == "#other"
+        ));
+    }
+
+    #[test]
+    fn wiki_link_display_with_brackets() {
+        for (text, display) in [
+            ("[[file|see [1]]] after", "see [1]"),
+            ("[[file|see (x)]] after", "see (x)"),
+            ("[[file|a [ stray]] after", "a [ stray"),
+            ("[[file|[1] and [2]]] after", "[1] and [2]"),
+            (r"[[file|a \] b \| c]] after", r"a \] b \| c"),
+            (r"[[file|\[x\]]] after", r"\[x\]"),
+        ] {
+            let parsed = Reference::new(text, "test.md").collect_vec();
+
+            let [WikiFileLink(data)] = parsed.as_slice() else {
+                panic!("{:?} parsed to {:?}", text, parsed)
+            };
+            assert_eq!(data.reference_text, "file");
+            assert_eq!(data.display_text.as_deref(), Some(display));
+            assert_eq!(
+                data.range.end.character as usize,
+                text.find(" after").unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn wiki_link_display_after_escaped_pipe_in_table() {
+        let text =
+            r"| [[Note\|Alias]] | [[Note.md\|Other]] | [[Note#Heading\|Third]] | [[Note|a \| b]] |";
+        let parsed = Reference::new(text, "test.md").collect_vec();
+
+        let [WikiFileLink(first), WikiFileLink(second), WikiHeadingLink(third, file, heading), WikiFileLink(fourth)] =
+            parsed.as_slice()
+        else {
+            panic!("{:?} parsed to {:?}", text, parsed)
+        };
+        assert_eq!(first.reference_text, "Note");
+        assert_eq!(first.display_text.as_deref(), Some("Alias"));
+        assert_eq!(first.range.start.character, 2);
+        assert_eq!(first.range.end.character, 17);
+        assert_eq!(second.reference_text, "Note");
+        assert_eq!(second.display_text.as_deref(), Some("Other"));
+        assert_eq!((file.as_str(), heading.as_str()), ("Note", "Heading"));
+        assert_eq!(third.display_text.as_deref(), Some("Third"));
+        // an escaped pipe after the separator is part of the display text
+        assert_eq!(fourth.display_text.as_deref(), Some(r"a \| b"));
+    }
+
+    #[test]
+    fn wiki_link_display_follows_a_plain_or_escaped_pipe() {
+        for text in ["[[a|b]]", r"[[a\|b]]"] {
+            let parsed = Reference::new(text, "test.md").collect_vec();
+
+            let [WikiFileLink(data)] = parsed.as_slice() else {
+                panic!("{:?} parsed to {:?}", text, parsed)
+            };
+            assert_eq!(data.reference_text, "a");
+            assert_eq!(data.display_text.as_deref(), Some("b"));
+            assert_eq!(data.range.end.character as usize, text.len());
+        }
+    }
+
+    #[test]
+    fn wiki_link_spaces_around_file_and_heading_are_ignored() {
+        let text = "[[ Note Name ]] and [[File# some heading ]] and [[ #^abc ]]";
+        let parsed = Reference::new(text, "test").collect_vec();
+
+        let [WikiFileLink(file), WikiHeadingLink(heading, heading_file, heading_text), WikiIndexedBlockLink(block, block_file, index)] =
+            parsed.as_slice()
+        else {
+            panic!("{:?} parsed to {:?}", text, parsed)
+        };
+        assert_eq!(file.reference_text, "Note Name");
+        assert_eq!(
+            (file.range.start.character, file.range.end.character),
+            (0, 15)
+        );
+        assert_eq!(heading.reference_text, "File#some heading");
+        assert_eq!(
+            (heading_file.as_str(), heading_text.as_str()),
+            ("File", "some heading")
+        );
+        assert_eq!(
+            (heading.range.start.character, heading.range.end.character),
+            (20, 43)
+        );
+        assert_eq!(block.reference_text, "test#^abc");
+        assert_eq!((block_file.as_str(), index.as_str()), ("test", "abc"));
+    }
+
     #[test]
     fn wiki_link_heading_parsing() {
         let text = "This is a [[link#heading]]";
@@ -1947,6 +3642,41 @@ mod vault_tests {
         assert_eq!(parsed, expected)
     }
 
+    #[test]
+    fn md_block_links_carry_display_and_need_a_whole_block_id() {
+        let blocks = |text: &str| {
+            Reference::new(text, "source")
+                .map(|reference| match reference {
+                    Reference::MDIndexedBlockLink(data, file, index) => {
+                        Some((data.display_text, file, index))
+                    }
+                    _ => None,
+                })
+                .collect_vec()
+        };
+        let block = |display: &str, file: &str, index: &str| {
+            vec![Some((
+                Some(display.to_string()),
+                file.to_string(),
+                index.to_string(),
+            ))]
+        };
+
+        // the display text is the link text; a pipe in it is only text
+        assert_eq!(blocks("[a|b](Note#^abc)"), block("a|b", "Note", "abc"));
+        assert_eq!(blocks("[](Note#^abc)"), block("", "Note", "abc"));
+        assert_eq!(blocks("[x](#^abc)"), block("x", "source", "abc"));
+        assert_eq!(
+            blocks("[x](<My Note.md#^block-id_2>)"),
+            block("x", "My Note", "block-id_2")
+        );
+
+        // a fragment that can not be a block id is no link, not a link to a block that can never exist
+        assert_eq!(blocks("[x](Note#^)"), vec![]);
+        assert_eq!(blocks("[x](<Note#^abc def>)"), vec![]);
+        assert_eq!(blocks("[x](Note#^^abc)"), vec![]);
+    }
+
     #[test]
     fn footnote_link_parsing() {
         let text = "This is a footnote[^1]
@@ -2031,6 +3761,108 @@ mod vault_tests {
         assert_eq!(parsed, expected)
     }
 
+    #[test]
+    fn wiki_links_allow_parentheses_and_symbols_in_names() {
+        let root_dir = Path::new("/vault");
+        let settings = Settings::new(root_dir, &ClientCapabilities::default()).unwrap();
+        let mut vault = Vault::construct_vault(&settings, root_dir).unwrap();
+        let rock = PathBuf::from("/vault/Rock (music).md");
+        let soul = PathBuf::from("/vault/R&B + Soul.md");
+        let note = PathBuf::from("/vault/note.md");
+        Vault::update_vault(&settings, &mut vault, (&rock, "# Genres\n"));
+        Vault::update_vault(&settings, &mut vault, (&soul, ""));
+        Vault::update_vault(
+            &settings,
+            &mut vault,
+            (
+                &note,
+                "[[Rock (music)]] [[Rock (music)#Genres]] [[Rock (music)|rock]] [[R&B + Soul]]",
+            ),
+        );
+
+        let links = vault.md_files[&note]
+            .references
+            .iter()
+            .map(|reference| reference.data().reference_text.as_str())
+            .zip(resolved_targets(&vault, &note))
+            .collect_vec();
+
+        assert_eq!(
+            links,
+            vec![
+                ("Rock (music)", vec![rock.clone()]),
+                ("Rock (music)#Genres", vec![rock.clone()]),
+                ("Rock (music)", vec![rock.clone()]),
+                ("R&B + Soul", vec![soul.clone()]),
+            ]
+        );
+    }
+
+    #[test]
+    fn crlf_notes_parse_to_the_same_ranges_as_lf_notes() {
+        let settings = Settings::new(Path::new("/vault"), &ClientCapabilities::default()).unwrap();
+        let lf = "---\ntags: [project]\n---\n# Heading *one*\ntext #tag [[link]] [md](other.md)\nline ^block\n[^1]: footnote text\n[ref]: https://example.com\n```\ncode\n```\n## Two\n";
+        let crlf = lf.replace('\n', "\r\n");
+
+        let lf_file = MDFile::new(&settings, lf, PathBuf::from("/vault/note.md"));
+        let crlf_file = MDFile::new(&settings, &crlf, PathBuf::from("/vault/note.md"));
+
+        assert!(lf_file.metadata.is_some());
+        assert_eq!(crlf_file, lf_file);
+    }
+
+    #[test]
+    fn documents_are_read_a_line_at_a_time() {
+        let root_dir = Path::new("/vault");
+        let settings = Settings::new(root_dir, &ClientCapabilities::default()).unwrap();
+        let mut vault = Vault::construct_vault(&settings, root_dir).unwrap();
+        let first = PathBuf::from("/vault/first.md");
+        let second = PathBuf::from("/vault/second.md");
+        Vault::update_vault(&settings, &mut vault, (&first, "# First\ntext\n"));
+        Vault::update_vault(&settings, &mut vault, (&second, "windows\r\nlines"));
+
+        let documents = vault
+            .iter_documents()
+            .map(|document| (document.path.to_path_buf(), document.lines().collect_vec()))
+            .sorted()
+            .collect_vec();
+
+        assert_eq!(
+            documents,
+            vec![
+                (first, vec!["# First".into(), "text".into(), "".into()]),
+                (second, vec!["windows".into(), "lines".into()]),
+            ]
+        );
+    }
+
+    #[test]
+    fn folder_links_resolve_to_the_folder_note() {
+        let root_dir = Path::new("/vault");
+        let mut settings = Settings::new(root_dir, &ClientCapabilities::default()).unwrap();
+        let same_name = PathBuf::from("/vault/projects/projects.md");
+        let index = PathBuf::from("/vault/projects/index.md");
+        let note = PathBuf::from("/vault/note.md");
+
+        let targets = |settings: &Settings| {
+            let mut vault = Vault::construct_vault(settings, root_dir).unwrap();
+            Vault::update_vault(settings, &mut vault, (&same_name, "# Plan\n"));
+            Vault::update_vault(settings, &mut vault, (&index, "# Plan\n"));
+            Vault::update_vault(
+                settings,
+                &mut vault,
+                (&note, "[[projects/]] [[projects/#Plan]] [x](projects/)"),
+            );
+
+            resolved_targets(&vault, &note)
+        };
+
+        assert_eq!(targets(&settings), vec![vec![same_name.clone()]; 3]);
+
+        settings.folder_note = FolderNote::Index;
+        assert_eq!(targets(&settings), vec![vec![index.clone()]; 3]);
+    }
+
     #[test]
     fn link_parsing_with_png() {
         let text = "This is a png [[link.png]] [[link|display.png]]";
@@ -2059,6 +3891,8 @@ more text
         let expected = vec![
             MDHeading {
                 heading_text: "This is a heading".into(),
+                plain_text: "This is a heading".into(),
+                anchor: "this-is-a-heading".into(),
                 range: tower_lsp::lsp_types::Range {
                     start: tower_lsp::lsp_types::Position {
                         line: 0,
@@ -2074,6 +3908,8 @@ more text
             },
             MDHeading {
                 heading_text: "This shoudl be a heading!".into(),
+                plain_text: "This shoudl be a heading!".into(),
+                anchor: "this-shoudl-be-a-heading".into(),
                 range: tower_lsp::lsp_types::Range {
                     start: tower_lsp::lsp_types::Position {
                         line: 11,
@@ -2086,6 +3922,7 @@ more text
                 }
                 .into(),
                 level: HeadingLevel(2),
+                loose_text: None,
             },
         ];
 
@@ -2458,8 +4295,47 @@ Continued
     }
 
     #[test]
-    fn preceding_char_not_tag() {
-        let text = "This is not a#tag";
+    fn tags_split_into_base_and_value() {
+        let text = "#status:done and #area/work, then a bare #tag.";
+
+        let parsed = MDTag::new(text)
+            .map(|tag| {
+                (
+                    tag.tag_ref.clone(),
+                    tag.base().to_string(),
+                    tag.value().map(str::to_string),
+                    tag.range.end.character,
+                )
+            })
+            .collect_vec();
+
+        assert_eq!(
+            parsed,
+            vec![
+                (
+                    "status:done".into(),
+                    "status".into(),
+                    Some("done".into()),
+                    12
+                ),
+                ("area/work".into(), "area".into(), Some("work".into()), 27),
+                ("tag".into(), "tag".into(), None, 45),
+            ]
+        );
+    }
+
+    #[test]
+    fn trailing_colon_is_not_part_of_a_tag() {
+        let text = "#todo: call back";
+
+        let parsed = MDTag::new(text).map(|tag| tag.tag_ref).collect_vec();
+
+        assert_eq!(parsed, vec!["todo".to_string()]);
+    }
+
+    #[test]
+    fn preceding_char_not_tag() {
+        let text = "This is not a#tag";
 
         let parsed = MDTag::new(text).collect_vec();
         let expected: Vec<MDTag> = vec![];
@@ -2498,4 +4374,1203 @@ Continued
 
         assert_eq!(expected, parsed)
     }
+
+    #[test]
+    fn github_slug_punctuation_and_spaces() {
+        assert_eq!(github_slug("My Heading!"), "my-heading");
+        assert_eq!(github_slug("What's new in v1.2?"), "whats-new-in-v12");
+        assert_eq!(
+            github_slug("snake_case and-dashes"),
+            "snake_case-and-dashes"
+        );
+        assert_eq!(github_slug("Two  Spaces"), "two--spaces");
+    }
+
+    #[test]
+    fn github_slug_unicode() {
+        assert_eq!(github_slug("Über Café"), "über-café");
+        assert_eq!(github_slug("日本語 見出し"), "日本語-見出し");
+    }
+
+    #[test]
+    fn setext_headings_are_told_apart_from_breaks_and_frontmatter() {
+        let text = "---\ntitle: not a heading\n---\nTitle\n=====\n\nSome text\n\n---\n\n  Sub *heading*\n---\nmore\n- item\n---\n## Atx\nAfter atx\n---\n";
+        let settings = Settings::new(Path::new("/vault"), &ClientCapabilities::default()).unwrap();
+        let mdfile = MDFile::new(&settings, text, PathBuf::from("/vault/note.md"));
+
+        assert_eq!(
+            mdfile
+                .headings
+                .iter()
+                .map(|heading| (
+                    heading.heading_text.as_str(),
+                    heading.plain_text.as_str(),
+                    heading.level.0,
+                    heading.range.start.line,
+                    heading.range.start.character,
+                    heading.is_setext()
+                ))
+                .collect_vec(),
+            vec![
+                ("Title", "Title", 1, 3, 0, true),
+                ("Sub *heading*", "Sub heading", 2, 10, 2, true),
+                ("Atx", "Atx", 2, 15, 0, false),
+                ("After atx", "After atx", 2, 16, 0, true),
+            ]
+        );
+        assert_eq!(mdfile.headings[0].range.end.line, 4);
+        assert_eq!(mdfile.headings[0].range.end.character, 5);
+    }
+
+    #[test]
+    fn editing_above_an_underline_keeps_the_setext_heading() {
+        let root_dir = Path::new("/vault");
+        let settings = Settings::new(root_dir, &ClientCapabilities::default()).unwrap();
+        let mut vault = Vault::construct_vault(&settings, root_dir).unwrap();
+        let path = PathBuf::from("/vault/note.md");
+        Vault::update_vault(&settings, &mut vault, (&path, "Title\n=====\n\ntext\n"));
+
+        Vault::apply_changes(&settings, &mut vault, &path, &[change((0, 5), (0, 5), "s")]);
+        let headings = |vault: &Vault| {
+            vault.md_files[&path]
+                .headings
+                .iter()
+                .map(|heading| heading.heading_text.clone())
+                .collect_vec()
+        };
+        assert_eq!(headings(&vault), vec!["Titles"]);
+
+        Vault::apply_changes(
+            &settings,
+            &mut vault,
+            &path,
+            &[change((3, 4), (3, 4), "\n===")],
+        );
+        assert_eq!(headings(&vault), vec!["Titles", "text"]);
+    }
+
+    #[test]
+    fn inbound_link_counts_follow_edits() {
+        let root_dir = Path::new("/vault");
+        let settings = Settings::new(root_dir, &ClientCapabilities::default()).unwrap();
+        let mut vault = Vault::construct_vault(&settings, root_dir).unwrap();
+        let note = PathBuf::from("/vault/note.md");
+        let other = PathBuf::from("/vault/other.md");
+        Vault::update_vault(
+            &settings,
+            &mut vault,
+            (&note, "[[Target]] [[a/target#Heading]]\n"),
+        );
+        Vault::update_vault(
+            &settings,
+            &mut vault,
+            (&other, "[x](target.md) [[other]]\n"),
+        );
+
+        let counts = |vault: &Vault| {
+            vault
+                .select_inbound_link_counts()
+                .iter()
+                .map(|(name, count)| (name.clone(), *count))
+                .sorted()
+                .collect_vec()
+        };
+        assert_eq!(
+            counts(&vault),
+            vec![("other".to_string(), 1), ("target".to_string(), 3)]
+        );
+
+        // a line edit drops one link and adds another, and replacing a note drops all of its links
+        Vault::apply_changes(
+            &settings,
+            &mut vault,
+            &note,
+            &[change((0, 0), (0, 10), "[[other]]")],
+        );
+        Vault::update_vault(&settings, &mut vault, (&other, "no links\n"));
+        assert_eq!(
+            counts(&vault),
+            vec![("other".to_string(), 1), ("target".to_string(), 1)]
+        );
+    }
+
+    #[test]
+    fn change_columns_are_in_the_negotiated_encoding() {
+        let root_dir = Path::new("/vault");
+        let settings = Settings::new(root_dir, &ClientCapabilities::default()).unwrap();
+        let mut vault = Vault::construct_vault(&settings, root_dir).unwrap();
+        let path = PathBuf::from("/vault/note.md");
+        Vault::update_vault(&settings, &mut vault, (&path, "😀 ab"));
+
+        // the emoji is two UTF-16 code units, so column 3 is right after the space
+        Vault::apply_changes(&settings, &mut vault, &path, &[change((0, 3), (0, 3), "X")]);
+
+        assert_eq!(vault.ropes[&path].to_string(), "😀 Xab");
+    }
+
+    #[test]
+    fn heading_anchor_duplicates() {
+        let text = "# Notes\n## Setup\n## Setup!\n# Notes";
+        let settings = Settings::new(Path::new("/vault"), &ClientCapabilities::default()).unwrap();
+        let mdfile = MDFile::new(&settings, text, PathBuf::from("/vault/note.md"));
+
+        let anchors = mdfile
+            .headings
+            .iter()
+            .map(|heading| heading.anchor.as_str())
+            .collect_vec();
+
+        assert_eq!(anchors, vec!["notes", "setup", "setup-1", "notes-1"])
+    }
+
+    #[test]
+    fn same_file_links_resolve_in_the_current_note() {
+        let root_dir = Path::new("/vault");
+        let settings = Settings::new(root_dir, &ClientCapabilities::default()).unwrap();
+        let mut vault = Vault::construct_vault(&settings, root_dir).unwrap();
+        let note = PathBuf::from("/vault/one/note.md");
+        let other = PathBuf::from("/vault/two/note.md");
+        Vault::update_vault(&settings, &mut vault, (&other, "# Intro\ntext ^blk\n"));
+        Vault::update_vault(
+            &settings,
+            &mut vault,
+            (
+                &note,
+                "# Intro\ntext ^blk\n[[#Intro]] [[#^blk]] [[note#Intro]] [[two/note#Intro]]",
+            ),
+        );
+
+        assert_eq!(
+            resolved_targets(&vault, &note),
+            vec![
+                vec![note.clone()],
+                vec![note.clone()],
+                vec![note.clone()],
+                // a path still reaches the other note of the same name
+                vec![other.clone()],
+            ]
+        );
+    }
+
+    #[test]
+    fn markdown_links_resolve_github_anchors() {
+        let root_dir = Path::new("/vault");
+        let settings = Settings::new(root_dir, &ClientCapabilities::default()).unwrap();
+        let mut vault = Vault::construct_vault(&settings, root_dir).unwrap();
+        let target = PathBuf::from("/vault/target.md");
+        let note = PathBuf::from("/vault/note.md");
+        Vault::update_vault(
+            &settings,
+            &mut vault,
+            (&target, "# What's new in v1.2?\n## Setup\n## Setup\n"),
+        );
+        Vault::update_vault(
+            &settings,
+            &mut vault,
+            (
+                &note,
+                "[a](target.md#whats-new-in-v12) [b](target.md#setup) [c](target.md#setup-1) [[target#setup-1]] [[target#Setup]]",
+            ),
+        );
+
+        let heading_lines = vault.md_files[&note]
+            .references
+            .iter()
+            .map(|reference| {
+                vault
+                    .select_referenceables_for_reference(reference, &note)
+                    .into_iter()
+                    .filter_map(|referenceable| match referenceable {
+                        Referenceable::Heading(_, heading) => Some(heading.range.start.line),
+                        _ => None,
+                    })
+                    .sorted()
+                    .collect_vec()
+            })
+            .collect_vec();
+
+        assert_eq!(
+            heading_lines,
+            vec![
+                vec![0],
+                // the first of the repeated headings has the plain anchor, the second one `-1`
+                vec![1],
+                vec![2],
+                // wiki links match the heading text, never the anchor
+                vec![],
+                vec![1, 2],
+            ]
+        );
+    }
+
+    #[test]
+    fn emoji_heading_links() {
+        let path = Path::new("/home/vault/launch.md");
+        let root_dir = Path::new("/home/vault");
+        let settings = Settings::new(root_dir, &ClientCapabilities::default()).unwrap();
+        let link_resolution = LinkResolution::new(&settings);
+
+        let heading = MDHeading::new("## 🚀 Launch").next().unwrap();
+        assert_eq!(heading.heading_text, "🚀 Launch");
+        assert_eq!(github_slug(&heading.heading_text), "-launch");
+
+        let path_buf = path.to_path_buf();
+        let referenceable = Referenceable::Heading(&path_buf, &heading);
+
+        let links =
+            Reference::new("[[launch#🚀 Launch]] [x](launch#-launch)", "other").collect_vec();
+        assert_eq!(links.len(), 2);
+        assert!(links.iter().all(|link| link.references(
+            root_dir,
+            &link_resolution,
+            path,
+            &referenceable
+        )));
+    }
+
+    #[test]
+    fn loose_heading_links_ignore_case_spacing_and_punctuation() {
+        let root_dir = Path::new("/vault");
+        let mut settings = Settings::new(root_dir, &ClientCapabilities::default()).unwrap();
+        let target = PathBuf::from("/vault/target.md");
+        let note = PathBuf::from("/vault/note.md");
+        let text = "[[target#myheading]] [[target#my  heading]] [[target#My-Heading]] [[target#What's next]] [[target#Missing]]";
+
+        // which links resolve to a heading, and how many of them are unresolved
+        let resolved = |settings: &Settings| {
+            let mut vault = Vault::construct_vault(settings, root_dir).unwrap();
+            Vault::update_vault(
+                settings,
+                &mut vault,
+                (&target, "# My Heading\n## What's Next?\n"),
+            );
+            Vault::update_vault(settings, &mut vault, (&note, text));
+
+            let unresolved = vault
+                .select_referenceable_nodes(None)
+                .into_iter()
+                .filter(|referenceable| referenceable.is_unresolved())
+                .count();
+            let resolved = vault.md_files[&note]
+                .references
+                .iter()
+                .map(|reference| {
+                    vault
+                        .select_referenceables_for_reference(reference, &note)
+                        .iter()
+                        .any(|referenceable| matches!(referenceable, Referenceable::Heading(..)))
+                })
+                .collect_vec();
+
+            (resolved, unresolved)
+        };
+
+        assert_eq!(
+            resolved(&settings),
+            (vec![false, false, false, false, false], 5)
+        );
+
+        settings.loose_heading_links = true;
+        assert_eq!(
+            resolved(&settings),
+            (vec![true, true, true, true, false], 1)
+        );
+    }
+
+    #[test]
+    fn relative_markdown_links_resolve_from_the_note_folder() {
+        let root_dir = Path::new("/vault");
+        let mut settings = Settings::new(root_dir, &ClientCapabilities::default()).unwrap();
+        let note = PathBuf::from("/vault/a/b/note.md");
+        let text =
+            "[s](../sibling.md) [c](./child.md#Intro) [w](child) [[sibling]] [o](../../../out.md)";
+
+        let targets = |settings: &Settings| {
+            let mut vault = Vault::construct_vault(settings, root_dir).unwrap();
+            for file in [
+                "a/sibling.md",
+                "a/b/child.md",
+                "child.md",
+                "other/sibling.md",
+                "out.md",
+            ] {
+                Vault::update_vault(settings, &mut vault, (&root_dir.join(file), "# Intro\n"));
+            }
+            Vault::update_vault(settings, &mut vault, (&note, text));
+
+            resolved_targets(&vault, &note)
+                .into_iter()
+                .map(|paths| {
+                    paths
+                        .iter()
+                        .map(|path| {
+                            path.strip_prefix(root_dir)
+                                .unwrap()
+                                .to_str()
+                                .unwrap()
+                                .to_string()
+                        })
+                        .collect_vec()
+                })
+                .collect_vec()
+        };
+
+        settings.relative_markdown_links = true;
+        assert_eq!(
+            targets(&settings),
+            vec![
+                vec!["a/sibling.md"],
+                vec!["a/b/child.md"],
+                vec!["a/b/child.md"],
+                vec!["a/sibling.md", "other/sibling.md"],
+                // climbing out of the vault links to nothing
+                vec![],
+            ]
+        );
+
+        // without the setting, `./` starts at the vault root and `../` is left out
+        settings.relative_markdown_links = false;
+        assert_eq!(
+            targets(&settings),
+            vec![
+                vec!["a/sibling.md", "other/sibling.md"],
+                vec!["child.md"],
+                vec!["a/b/child.md", "child.md"],
+                vec!["a/sibling.md", "other/sibling.md"],
+                vec!["out.md"],
+            ]
+        );
+    }
+
+    #[test]
+    fn heading_plain_text_strips_inline_formatting() {
+        let plain = |line: &str| MDHeading::new(line).next().unwrap().plain_text;
+
+        assert_eq!(plain("## My **bold** heading"), "My bold heading");
+        assert_eq!(
+            plain("## An *italic* and _other_ heading"),
+            "An italic and other heading"
+        );
+        assert_eq!(plain("## The `**raw**` config"), "The **raw** config");
+        assert_eq!(
+            plain("## See [the docs](docs.md) and [[Page|this page]]"),
+            "See the docs and this page"
+        );
+        assert_eq!(plain("## snake_case  "), "snake_case");
+    }
+
+    #[test]
+    fn links_match_headings_without_formatting() {
+        let path = Path::new("/home/vault/note.md");
+        let root_dir = Path::new("/home/vault");
+        let settings = Settings::new(root_dir, &ClientCapabilities::default()).unwrap();
+        let link_resolution = LinkResolution::new(&settings);
+        let path_buf = path.to_path_buf();
+
+        for (heading, link) in [
+            ("## My **bold** heading", "[[note#My bold heading]]"),
+            ("## An *italic* one", "[[note#An italic one]]"),
+            ("## The `config` file", "[[note#The config file]]"),
+            ("## See [the docs](docs.md)", "[[note#See the docs]]"),
+            ("## My **bold** heading", "[[note#My **bold** heading]]"),
+            ("## See [the docs](docs.md)", "[x](note#see-the-docs)"),
+        ] {
+            let heading = MDHeading::new(heading).next().unwrap();
+            let referenceable = Referenceable::Heading(&path_buf, &heading);
+            let link = Reference::new(link, "other").next().unwrap();
+
+            assert!(
+                link.references(root_dir, &link_resolution, path, &referenceable),
+                "{:?}",
+                link
+            );
+        }
+    }
+
+    #[test]
+    fn link_bracket_escaping() {
+        assert_eq!(unescape_link_brackets(r"a \[b\] [c] \d"), r"a [b] [c] \d");
+        assert_eq!(escape_link_brackets(r"a \[b\] [c] \d"), r"a \[b\] \[c\] \d");
+        assert_eq!(escape_link_brackets(r"\[half]"), r"\[half\]");
+
+        for text in ["no brackets here", ""] {
+            assert_eq!(unescape_link_brackets(text), text);
+            assert_eq!(escape_link_brackets(text), text);
+        }
+
+        assert_eq!(escape_wiki_link_text(r"a|b \| [c]"), r"a\|b \| \[c\]");
+        assert_eq!(unescape_wiki_link_text(r"a\|b \[c\]"), "a|b [c]");
+    }
+
+    #[test]
+    fn markdown_link_display_with_escaped_brackets() {
+        let text = r"[see \[1\]](file) after";
+        let parsed = Reference::new(text, "test.md").collect_vec();
+
+        let [MDFileLink(data)] = parsed.as_slice() else {
+            panic!("{:?} parsed to {:?}", text, parsed)
+        };
+        assert_eq!(data.reference_text, "file");
+        assert_eq!(data.display_text.as_deref(), Some(r"see \[1\]"));
+    }
+
+    #[test]
+    fn referenced_block_ids() {
+        let root_dir = Path::new("/vault");
+        let settings = Settings::new(root_dir, &ClientCapabilities::default()).unwrap();
+        let mut vault = Vault::construct_vault(&settings, root_dir).unwrap();
+        let path = PathBuf::from("/vault/source.md");
+        Vault::update_vault(
+            &settings,
+            &mut vault,
+            (
+                &path,
+                "[[target#^first]] and [link](folder/other#^second)\n[[target#Heading]] [[target#^first]]",
+            ),
+        );
+
+        assert_eq!(
+            vault.select_referenced_block_ids(&path),
+            vec![
+                ("target".to_string(), "first".to_string()),
+                ("folder/other".to_string(), "second".to_string()),
+            ]
+        )
+    }
+
+    #[test]
+    fn heading_preview_stops_at_section_end() {
+        let root_dir = Path::new("/vault");
+        let settings = Settings::new(root_dir, &ClientCapabilities::default()).unwrap();
+        let mut vault = Vault::construct_vault(&settings, root_dir).unwrap();
+        let path = PathBuf::from("/vault/note.md");
+        Vault::update_vault(
+            &settings,
+            &mut vault,
+            (
+                &path,
+                "# Note\n## First\ntext\n### Nested\nmore\n## Second\nother\n",
+            ),
+        );
+
+        let mdfile = vault.md_files.get(&path).unwrap();
+        let first = &mdfile.headings[1];
+        let preview = vault.select_referenceable_preview(&Referenceable::Heading(&path, first));
+
+        assert!(matches!(
+            preview,
+            Some(super::Preview::Text(text)) if text == "## First\ntext\n### Nested\nmore\n"
+        ))
+    }
+
+    #[test]
+    fn normalize_for_match_options() {
+        let text = "  Café%20Notes\\ and\tMore  ";
+        let with = |opts: MatchOpts| normalize_for_match(text, &opts);
+
+        assert_eq!(with(MatchOpts::default()), text);
+        assert_eq!(with(MatchOpts::CASEFOLD), "  café%20notes\\ and\tmore  ");
+        assert_eq!(
+            with(MatchOpts {
+                collapse_whitespace: true,
+                ..Default::default()
+            }),
+            "Café%20Notes\\ and More"
+        );
+        assert_eq!(
+            with(MatchOpts {
+                unify_separators: true,
+                ..Default::default()
+            }),
+            "  Café Notes and\tMore  "
+        );
+        assert_eq!(
+            with(MatchOpts {
+                strip_accents: true,
+                ..Default::default()
+            }),
+            "  Cafe%20Notes\\ and\tMore  "
+        );
+        assert_eq!(
+            with(MatchOpts {
+                casefold: true,
+                collapse_whitespace: true,
+                unify_separators: true,
+                strip_accents: true,
+                alphanumeric_only: false,
+            }),
+            "cafe notes and more"
+        );
+        assert_eq!(with(MatchOpts::LOOSE), "café20notesandmore");
+    }
+
+    #[test]
+    fn normalize_for_match_is_idempotent() {
+        let texts = ["  Café%20Notes\\ and\tMore  ", "ÜBER  straße", "a\\ b%20c"];
+
+        for bits in 0..32 {
+            let opts = MatchOpts {
+                casefold: bits & 1 != 0,
+                collapse_whitespace: bits & 2 != 0,
+                unify_separators: bits & 4 != 0,
+                strip_accents: bits & 8 != 0,
+                alphanumeric_only: bits & 16 != 0,
+            };
+
+            for text in texts {
+                let once = normalize_for_match(text, &opts);
+                assert_eq!(normalize_for_match(&once, &opts), once, "{:?}", opts);
+            }
+        }
+    }
+
+    #[test]
+    fn alias_link_references_file() {
+        let root_dir = Path::new("/vault");
+        let settings = Settings::new(root_dir, &ClientCapabilities::default()).unwrap();
+        let mut vault = Vault::construct_vault(&settings, root_dir).unwrap();
+        let target = PathBuf::from("/vault/RealNote.md");
+        let source = PathBuf::from("/vault/source.md");
+        Vault::update_vault(
+            &settings,
+            &mut vault,
+            (&target, "---\naliases: [Foo]\n---\n"),
+        );
+        Vault::update_vault(&settings, &mut vault, (&source, "[[Foo]] [[Bar]]"));
+
+        let mdfile = vault.md_files.get(&target).unwrap();
+        let references = vault
+            .select_references_for_referenceable(&Referenceable::File(&target, mdfile))
+            .unwrap();
+
+        assert_eq!(
+            references
+                .into_iter()
+                .map(|(_, reference)| reference.data().reference_text.as_str())
+                .collect_vec(),
+            vec!["Foo"]
+        );
+    }
+
+    #[test]
+    fn alias_matching_follows_case_matching() {
+        let mut settings =
+            Settings::new(Path::new("/vault"), &ClientCapabilities::default()).unwrap();
+
+        for (case, matches) in [
+            (Case::Ignore, [true, true, true]),
+            (Case::Smart, [true, true, false]),
+            (Case::Respect, [false, true, false]),
+        ] {
+            settings.case_matching = case;
+            let mdfile = MDFile::new(
+                &settings,
+                "---\naliases: [Foo]\n---\n",
+                PathBuf::from("/vault/note.md"),
+            );
+
+            assert_eq!(
+                ["foo", "Foo", "FOO"].map(|name| mdfile.has_alias(name)),
+                matches,
+                "{case:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn heading_title_references_file() {
+        let root_dir = Path::new("/vault");
+        let mut settings = Settings::new(root_dir, &ClientCapabilities::default()).unwrap();
+        settings.title_source = TitleSource::Heading;
+        let mut vault = Vault::construct_vault(&settings, root_dir).unwrap();
+        let target = PathBuf::from("/vault/2024-01-01.md");
+        let untitled = PathBuf::from("/vault/untitled.md");
+        let source = PathBuf::from("/vault/source.md");
+        Vault::update_vault(
+            &settings,
+            &mut vault,
+            (
+                &target,
+                "---\naliases: [Foo]\n---\n## Sub\n# My Title\n# Second\n",
+            ),
+        );
+        Vault::update_vault(&settings, &mut vault, (&untitled, "## Only a subheading\n"));
+        Vault::update_vault(
+            &settings,
+            &mut vault,
+            (
+                &source,
+                "[[My Title]] [[Foo]] [[2024-01-01]] [[Second]] [[untitled]]",
+            ),
+        );
+
+        let reference_texts = |vault: &Vault, path: &PathBuf| {
+            let mdfile = vault.md_files.get(path).unwrap();
+            vault
+                .select_references_for_referenceable(&Referenceable::File(path, mdfile))
+                .unwrap()
+                .into_iter()
+                .map(|(_, reference)| reference.data().reference_text.clone())
+                .sorted()
+                .collect_vec()
+        };
+
+        assert_eq!(
+            reference_texts(&vault, &target),
+            vec!["2024-01-01", "Foo", "My Title"]
+        );
+        assert_eq!(reference_texts(&vault, &untitled), vec!["untitled"]);
+        assert!(vault.md_files.get(&untitled).unwrap().metadata.is_none());
+
+        // renaming the heading through a line edit renames the title
+        Vault::apply_changes(
+            &settings,
+            &mut vault,
+            &target,
+            &[change((4, 2), (4, 10), "Second")],
+        );
+        let mdfile = vault.md_files.get(&target).unwrap();
+        assert!(mdfile.has_alias("Second"));
+        assert!(!mdfile.has_alias("My Title"));
+        assert!(mdfile.has_alias("Foo"));
+    }
+
+    #[test]
+    fn shortest_unique_paths_resolve_notes_sharing_a_name() {
+        let root_dir = Path::new("/vault");
+        let settings = Settings::new(root_dir, &ClientCapabilities::default()).unwrap();
+        let mut vault = Vault::construct_vault(&settings, root_dir).unwrap();
+        let first = PathBuf::from("/vault/work/a/dup.md");
+        let second = PathBuf::from("/vault/work/b/dup.md");
+        let solo = PathBuf::from("/vault/notes/solo.md");
+        let source = PathBuf::from("/vault/source.md");
+        for path in [&first, &second, &solo] {
+            Vault::update_vault(&settings, &mut vault, (path, ""));
+        }
+        Vault::update_vault(
+            &settings,
+            &mut vault,
+            (
+                &source,
+                "[[solo]]\n[[a/dup]]\n[[work/b/dup]]\n[[dup]]\n[[c/dup]]\n",
+            ),
+        );
+
+        assert_eq!(
+            resolved_targets(&vault, &source),
+            vec![
+                vec![solo.clone()],
+                vec![first.clone()],
+                vec![second.clone()],
+                // a bare name still reaches every note with it
+                vec![first.clone(), second.clone()],
+                vec![],
+            ]
+        );
+
+        let unresolved = vault
+            .select_referenceable_nodes(None)
+            .into_iter()
+            .filter(|referenceable| referenceable.is_unresolved())
+            .filter_map(|referenceable| referenceable.get_refname(vault.root_dir()))
+            .map(|refname| refname.to_string())
+            .collect_vec();
+        assert_eq!(unresolved, vec!["c/dup"]);
+
+        assert_eq!(
+            vault
+                .select_unambiguous_refnames()
+                .into_iter()
+                .sorted()
+                .collect_vec(),
+            vec![
+                (first.as_path(), "a/dup".to_string()),
+                (second.as_path(), "b/dup".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn frontmatter_tags_join_inline_tags() {
+        let settings = Settings::new(Path::new("/vault"), &ClientCapabilities::default()).unwrap();
+        let text = "---\ntags:\n  - project\n  - area/work\n---\n\nSome #inline text";
+        let mdfile = MDFile::new(&settings, text, PathBuf::from("/vault/note.md"));
+
+        assert_eq!(
+            mdfile
+                .tags
+                .iter()
+                .map(|tag| tag.tag_ref.as_str())
+                .collect_vec(),
+            vec!["project", "area/work", "inline"]
+        );
+        assert_eq!(
+            mdfile.tags[1].range,
+            MyRange(tower_lsp::lsp_types::Range {
+                start: Position {
+                    line: 3,
+                    character: 4
+                },
+                end: Position {
+                    line: 3,
+                    character: 13
+                },
+            })
+        );
+        assert!(mdfile
+            .references
+            .iter()
+            .any(|reference| matches!(reference, Reference::Tag(data) if data.reference_text == "#area/work")));
+    }
+
+    #[test]
+    fn frontmatter_tags_not_written_as_their_value_stay_in_the_frontmatter() {
+        let settings = Settings::new(Path::new("/vault"), &ClientCapabilities::default()).unwrap();
+        let text = "---\ntags: ['it''s', x]\n---\nit's body x\n";
+        let mdfile = MDFile::new(&settings, text, PathBuf::from("/vault/note.md"));
+
+        let tags = mdfile
+            .tags
+            .iter()
+            .map(|tag| (tag.tag_ref.as_str(), tag.range.start, tag.range.end))
+            .collect_vec();
+
+        assert_eq!(
+            tags,
+            vec![
+                (
+                    "it's",
+                    Position {
+                        line: 1,
+                        character: 0
+                    },
+                    Position {
+                        line: 1,
+                        character: 5
+                    }
+                ),
+                (
+                    "x",
+                    Position {
+                        line: 1,
+                        character: 16
+                    },
+                    Position {
+                        line: 1,
+                        character: 17
+                    }
+                ),
+            ]
+        );
+    }
+
+    fn change(
+        (start_line, start_character): (u32, u32),
+        (end_line, end_character): (u32, u32),
+        text: &str,
+    ) -> TextDocumentContentChangeEvent {
+        TextDocumentContentChangeEvent {
+            range: Some(Range {
+                start: Position {
+                    line: start_line,
+                    character: start_character,
+                },
+                end: Position {
+                    line: end_line,
+                    character: end_character,
+                },
+            }),
+            range_length: None,
+            text: text.into(),
+        }
+    }
+
+    /// References and code blocks are parsed kind by kind, so compare them in document order
+    fn in_document_order(mut mdfile: MDFile) -> MDFile {
+        let position = |range: &MyRange| (range.start.line, range.start.character);
+        mdfile
+            .references
+            .sort_by_key(|reference| position(&reference.data().range));
+        mdfile
+            .codeblocks
+            .sort_by_key(|block| position(block.range()));
+        mdfile
+    }
+
+    #[test]
+    fn incremental_changes_match_full_reparse() {
+        let settings = Settings::new(Path::new("/vault"), &ClientCapabilities::default()).unwrap();
+        let mut vault = Vault::construct_vault(&settings, Path::new("/vault")).unwrap();
+        let path = PathBuf::from("/vault/note.md");
+        Vault::update_vault(
+            &settings,
+            &mut vault,
+            (
+                &path,
+                "# Title\n\nSee [[Other]] and #tag\n\n## Section\n\nA block ^id\n\n[^1]: footnote\n",
+            ),
+        );
+
+        let changes = [
+            // edit inside a line
+            change((2, 6), (2, 11), "Another#Heading"),
+            // new lines with links in the middle of the file
+            change((3, 0), (3, 0), "[md](Other.md)\n[[Third]] #new/tag\n"),
+            // remove the section heading and the line after it
+            change((6, 0), (8, 0), ""),
+            // join two lines
+            change((2, 32), (3, 0), " "),
+            // a fenced code block needs the whole file
+            change((0, 0), (0, 0), "```\n[[InCode]]\n```\n"),
+        ];
+
+        for change in changes {
+            Vault::apply_changes(&settings, &mut vault, &path, &[change]);
+
+            let text = vault.ropes.get(&path).unwrap().to_string();
+            let full = MDFile::new(&settings, &text, path.clone());
+            assert_eq!(
+                in_document_order(vault.md_files.get(&path).unwrap().clone()),
+                in_document_order(full),
+                "after edit, text is {text:?}"
+            );
+        }
+
+        assert_eq!(
+            vault.ropes.get(&path).unwrap().to_string(),
+            "```\n[[InCode]]\n```\n# Title\n\nSee [[Another#Heading]] and #tag [md](Other.md)\n[[Third]] #new/tag\n\nA block ^id\n\n[^1]: footnote\n"
+        );
+    }
+
+    fn large_file_text() -> String {
+        (0..2000)
+            .map(|i| match i % 10 {
+                0 => format!("## Heading {i}\n"),
+                _ => {
+                    format!("Line {i} links [[Note{i}]] and [md](Note{i}.md#part) #tag{i} ^b{i}\n")
+                }
+            })
+            .join("")
+    }
+
+    /// Time to parse a 2,000 line file with a link, tag and block on most lines;
+    /// run with `cargo test --release bench_parse_large_file -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_parse_large_file() {
+        let settings = Settings::new(Path::new("/vault"), &ClientCapabilities::default()).unwrap();
+        let text = large_file_text();
+
+        let parses = 20;
+        let timer = std::time::Instant::now();
+        for _ in 0..parses {
+            std::hint::black_box(MDFile::new(
+                &settings,
+                &text,
+                PathBuf::from("/vault/large.md"),
+            ));
+        }
+
+        println!(
+            "parsing a 2000 line file: {:?} per parse",
+            timer.elapsed() / parses
+        );
+    }
+
+    /// Compares reparsing a 2,000 line file on every keystroke with applying the keystrokes incrementally;
+    /// run with `cargo test --release bench_incremental_reparse -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_incremental_reparse() {
+        let settings = Settings::new(Path::new("/vault"), &ClientCapabilities::default()).unwrap();
+        let mut vault = Vault::construct_vault(&settings, Path::new("/vault")).unwrap();
+        let path = PathBuf::from("/vault/large.md");
+        let text = large_file_text();
+        Vault::update_vault(&settings, &mut vault, (&path, &text));
+
+        let keystrokes = 20;
+        let timer = std::time::Instant::now();
+        let mut full_text = text.clone();
+        for _ in 0..keystrokes {
+            full_text.insert(full_text.find('\n').unwrap() + 1, 'x');
+            std::hint::black_box(MDFile::new(&settings, &full_text, path.clone()));
+        }
+        let full = timer.elapsed();
+
+        let timer = std::time::Instant::now();
+        for i in 0..keystrokes {
+            Vault::apply_changes(
+                &settings,
+                &mut vault,
+                &path,
+                &[change((1000, i), (1000, i), "x")],
+            );
+        }
+        let incremental = timer.elapsed();
+
+        println!(
+            "{keystrokes} keystrokes on a 2000 line file: full reparse {full:?}, incremental {incremental:?}"
+        );
+    }
+
+    #[test]
+    fn capped_indexing_matches_default() {
+        let root_dir = std::env::temp_dir().join(format!("moxide-threads-{}", std::process::id()));
+        for i in 0..20 {
+            let path = root_dir.join(format!("folder{}/note{}.md", i % 3, i));
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, format!("# Note {i}\n\n[[note{}]] #tag{i}\n", i + 1)).unwrap();
+        }
+
+        let mut settings = Settings::new(&root_dir, &ClientCapabilities::default()).unwrap();
+        let default = Vault::construct_vault(&settings, &root_dir).unwrap();
+        settings.indexing_threads = 1;
+        let capped = Vault::construct_vault(&settings, &root_dir).unwrap();
+
+        std::fs::remove_dir_all(&root_dir).unwrap();
+
+        assert_eq!(default.md_files.len(), 20);
+        assert_eq!(default.ropes.len(), 20);
+        assert_eq!(default.md_files, capped.md_files);
+        assert_eq!(default.ropes, capped.ropes);
+        assert_eq!(default.inbound_links, capped.inbound_links);
+    }
+
+    #[test]
+    fn batched_indexing_grows_to_the_full_vault() {
+        let root_dir = std::env::temp_dir().join(format!("moxide-batches-{}", std::process::id()));
+        for i in 0..10 {
+            let path = root_dir.join(format!("note{i}.md"));
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, format!("# Note {i}\n\n[[note{}]] ^block{i}\n", i + 1)).unwrap();
+        }
+
+        let settings = Settings::new(&root_dir, &ClientCapabilities::default()).unwrap();
+        let full = Vault::construct_vault(&settings, &root_dir).unwrap();
+
+        let (mut vault, notes) = Vault::scan(&settings, &root_dir).unwrap();
+        assert!(vault.md_files.is_empty());
+        assert_eq!(notes.len(), 10);
+
+        // a note opened in the editor before its batch is read
+        let opened = root_dir.join("note9.md");
+        Vault::update_vault(&settings, &mut vault, (&opened, "# Edited\n"));
+
+        for batch in notes.chunks(3) {
+            vault.insert_notes(Vault::read_notes(&settings, batch));
+
+            // every note indexed so far is complete and the same as in a full index
+            assert_eq!(vault.md_files.len(), vault.ropes.len());
+            for (path, md_file) in vault.md_files.iter().filter(|(path, _)| **path != opened) {
+                assert_eq!(Some(md_file), full.md_files.get(path));
+                assert_eq!(vault.ropes.get(path), full.ropes.get(path));
+            }
+        }
+
+        std::fs::remove_dir_all(&root_dir).unwrap();
+
+        assert_eq!(vault.md_files.len(), 10);
+        assert_eq!(vault.ropes.get(&opened).unwrap().to_string(), "# Edited\n");
+
+        Vault::update_vault(
+            &settings,
+            &mut vault,
+            (&opened, "# Note 9\n\n[[note10]] ^block9\n"),
+        );
+        assert_eq!(vault.md_files, full.md_files);
+        assert_eq!(vault.ropes, full.ropes);
+    }
+
+    #[test]
+    fn vault_respects_ignore_files_and_patterns() {
+        let root_dir = std::env::temp_dir().join(format!("moxide-ignore-{}", std::process::id()));
+        let files = [
+            (".gitignore", "archive/\n*.draft.md\n!keep.draft.md\n"),
+            ("note.md", "# Note"),
+            ("archive/old.md", "# Old"),
+            ("nested/deep/archive/older.md", "# Older"),
+            ("nested/idea.draft.md", "# Idea"),
+            ("nested/keep.draft.md", "# Keep"),
+            ("node_modules/package.md", "# Package"),
+            ("journal/2024.md", "# Journal"),
+            ("nested/.gitignore", "scratch.md\n"),
+            (".ignore", "drafts/\n"),
+            ("drafts/plan.md", "# Plan"),
+        ];
+        for (path, text) in files {
+            let path = root_dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, text).unwrap();
+        }
+
+        let mut settings = Settings::new(&root_dir, &ClientCapabilities::default()).unwrap();
+        settings.ignore_patterns = vec!["node_modules/".into(), "journal/*".into()];
+        let mut vault = Vault::construct_vault(&settings, &root_dir).unwrap();
+
+        Vault::update_vault(
+            &settings,
+            &mut vault,
+            (&root_dir.join("archive/new.md"), "# New"),
+        );
+        // new files are checked against the same ignore files as the walk
+        for new in [
+            "other.md",
+            "nested/other.md",
+            "nested/scratch.md",
+            "nested/deep/archive/newer.md",
+            "drafts/new.md",
+            ".hidden/new.md",
+        ] {
+            Vault::update_vault(&settings, &mut vault, (&root_dir.join(new), "# New"));
+        }
+
+        let indexed = vault
+            .md_files
+            .keys()
+            .map(|path| path.strip_prefix(&root_dir).unwrap().to_path_buf())
+            .sorted()
+            .collect_vec();
+
+        std::fs::remove_dir_all(&root_dir).unwrap();
+
+        assert_eq!(
+            indexed,
+            vec![
+                PathBuf::from("nested/keep.draft.md"),
+                PathBuf::from("nested/other.md"),
+                PathBuf::from("note.md"),
+                PathBuf::from("other.md"),
+            ]
+        );
+    }
+
+    #[test]
+    fn links_to_ignored_files_are_unresolved_until_unignored() {
+        let root_dir = std::env::temp_dir().join(format!("moxide-unignore-{}", std::process::id()));
+        std::fs::create_dir_all(root_dir.join("node_modules")).unwrap();
+        std::fs::write(root_dir.join(".gitignore"), "node_modules/\n").unwrap();
+        std::fs::write(root_dir.join("node_modules/package.md"), "# Package").unwrap();
+        std::fs::write(root_dir.join("note.md"), "[[package]]").unwrap();
+
+        let settings = Settings::new(&root_dir, &ClientCapabilities::default()).unwrap();
+        let unresolved = |vault: &Vault| {
+            crate::diagnostics::path_unresolved_references(vault, &root_dir.join("note.md"))
+                .unwrap()
+                .into_iter()
+                .map(|(_, reference)| reference.data().reference_text.clone())
+                .collect_vec()
+        };
+
+        let vault = Vault::construct_vault(&settings, &root_dir).unwrap();
+        let ignored = unresolved(&vault);
+
+        std::fs::write(root_dir.join(".gitignore"), "").unwrap();
+        let vault = Vault::construct_vault(&settings, &root_dir).unwrap();
+        let unignored = unresolved(&vault);
+
+        std::fs::remove_dir_all(&root_dir).unwrap();
+
+        assert_eq!(ignored, vec!["package"]);
+        assert!(unignored.is_empty());
+    }
+
+    #[test]
+    fn canvas_files_are_link_targets() {
+        let root_dir = std::env::temp_dir().join(format!("moxide-canvas-{}", std::process::id()));
+        std::fs::create_dir_all(&root_dir).unwrap();
+        std::fs::write(
+            root_dir.join("diagram.canvas"),
+            r##"{"nodes":[{"id":"1","type":"text","text":"# Not a heading [[nowhere]]"}]}"##,
+        )
+        .unwrap();
+        std::fs::write(root_dir.join("note.md"), "[[diagram]] [[diagram.canvas]]").unwrap();
+
+        let settings = Settings::new(&root_dir, &ClientCapabilities::default()).unwrap();
+        let vault = Vault::construct_vault(&settings, &root_dir).unwrap();
+        std::fs::remove_dir_all(&root_dir).unwrap();
+
+        let canvas_path = root_dir.join("diagram.canvas");
+        let canvas = vault.md_files.get(&canvas_path).unwrap();
+        assert!(canvas.headings.is_empty() && canvas.references.is_empty());
+
+        assert_eq!(
+            resolved_targets(&vault, &root_dir.join("note.md")),
+            vec![vec![canvas_path.clone()], vec![canvas_path.clone()]]
+        );
+    }
+
+    #[test]
+    fn note_is_preferred_over_a_canvas_of_the_same_name() {
+        let root_dir =
+            std::env::temp_dir().join(format!("moxide-canvas-note-{}", std::process::id()));
+        std::fs::create_dir_all(&root_dir).unwrap();
+        std::fs::write(root_dir.join("diagram.md"), "# Diagram").unwrap();
+        std::fs::write(root_dir.join("diagram.canvas"), r#"{"nodes":[]}"#).unwrap();
+        std::fs::write(
+            root_dir.join("note.md"),
+            "[[diagram]] [a](diagram) [[diagram.canvas]]",
+        )
+        .unwrap();
+
+        let settings = Settings::new(&root_dir, &ClientCapabilities::default()).unwrap();
+        let vault = Vault::construct_vault(&settings, &root_dir).unwrap();
+        std::fs::remove_dir_all(&root_dir).unwrap();
+
+        let note = root_dir.join("diagram.md");
+        let canvas = root_dir.join("diagram.canvas");
+        assert_eq!(
+            resolved_targets(&vault, &root_dir.join("note.md")),
+            vec![vec![note.clone()], vec![note.clone()], vec![canvas.clone()]]
+        );
+
+        let canvas_backlinks = vault
+            .select_references_for_referenceable(&Referenceable::File(
+                &canvas,
+                &vault.md_files[&canvas],
+            ))
+            .unwrap()
+            .into_iter()
+            .map(|(_, reference)| reference.data().reference_text.clone())
+            .collect_vec();
+        assert_eq!(canvas_backlinks, vec!["diagram.canvas"]);
+    }
+
+    #[test]
+    fn md_extension_is_optional_and_other_extensions_are_kept() {
+        let root_dir =
+            std::env::temp_dir().join(format!("moxide-extensions-{}", std::process::id()));
+        std::fs::create_dir_all(&root_dir).unwrap();
+        std::fs::write(root_dir.join("photo.md"), "# Photo").unwrap();
+        std::fs::write(root_dir.join("diagram.md"), "# Diagram").unwrap();
+        std::fs::write(root_dir.join("diagram.canvas"), r#"{"nodes":[]}"#).unwrap();
+        std::fs::write(
+            root_dir.join("note.md"),
+            "[a](photo.md) [b](photo) [c](photo.png) [d](diagram.canvas) [[diagram.canvas]]",
+        )
+        .unwrap();
+
+        let settings = Settings::new(&root_dir, &ClientCapabilities::default()).unwrap();
+        let vault = Vault::construct_vault(&settings, &root_dir).unwrap();
+        std::fs::remove_dir_all(&root_dir).unwrap();
+
+        let note_path = root_dir.join("note.md");
+        let targets = vault.md_files[&note_path]
+            .references
+            .iter()
+            .map(|reference| reference.data().reference_text.as_str())
+            .zip(resolved_targets(&vault, &note_path))
+            .collect_vec();
+
+        let photo = root_dir.join("photo.md");
+        let canvas = root_dir.join("diagram.canvas");
+        assert_eq!(
+            targets,
+            vec![
+                ("photo", vec![photo.clone()]),
+                ("photo", vec![photo.clone()]),
+                ("diagram.canvas", vec![canvas.clone()]),
+                ("diagram.canvas", vec![canvas.clone()]),
+            ]
+        );
+    }
 }
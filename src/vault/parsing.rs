@@ -25,12 +25,10 @@ impl MDCodeBlock {
 
         let short_captures = SHORT_RE.captures_iter(text);
 
-        captures.chain(short_captures).flat_map(|captures| {
+        let rope = Rope::from_str(text);
+        captures.chain(short_captures).flat_map(move |captures| {
             Some(MDCodeBlock {
-                range: MyRange::from_range(
-                    &Rope::from_str(text),
-                    captures.name("fullblock")?.range(),
-                ),
+                range: MyRange::from_range(&rope, captures.name("fullblock")?.range()),
             })
         })
     }
@@ -40,6 +38,42 @@ impl Rangeable for MDCodeBlock {
     fn range(&self) -> &MyRange {
         &self.range
     }
+    fn range_mut(&mut self) -> &mut MyRange {
+        &mut self.range
+    }
+}
+
+/// A callout header, `> [!type]`, at the start of a line
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MDCallout {
+    pub callout_type: String,
+    pub range: MyRange,
+}
+
+impl MDCallout {
+    pub fn new(text: &str) -> impl Iterator<Item = MDCallout> + '_ {
+        static RE: Lazy<Regex> = Lazy::new(|| {
+            Regex::new(r"(?m)^(> *)+\[!(?<type>[^\]\s]+)\]")
+                .expect("Callout Regex Not Constructing")
+        });
+
+        let rope = Rope::from_str(text);
+        RE.captures_iter(text).flat_map(move |captures| {
+            Some(MDCallout {
+                callout_type: captures.name("type")?.as_str().to_string(),
+                range: MyRange::from_range(&rope, captures.get(0)?.range()),
+            })
+        })
+    }
+}
+
+impl Rangeable for MDCallout {
+    fn range(&self) -> &MyRange {
+        &self.range
+    }
+    fn range_mut(&mut self) -> &mut MyRange {
+        &mut self.range
+    }
 }
 
 #[cfg(test)]
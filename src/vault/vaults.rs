@@ -0,0 +1,207 @@
+use std::path::{Path, PathBuf};
+
+use itertools::Itertools;
+
+use crate::config::Settings;
+
+use super::{ReadNote, Vault};
+
+/// The vaults of a workspace, one for each of its folders, in the order the client listed the folders. A note belongs
+/// to the vault of the innermost folder holding it, and its links resolve within that vault
+#[derive(Debug, Default)]
+pub struct Vaults(Vec<Vault>);
+
+impl Vaults {
+    /// The vaults of the workspace folders at `root_dirs` without their notes, and the paths of the notes to read into
+    /// them; the notes of a folder nested in another are found in both, and listed once
+    pub fn scan(
+        context: &Settings,
+        root_dirs: &[PathBuf],
+    ) -> Result<(Vaults, Vec<PathBuf>), std::io::Error> {
+        let mut vaults = Vec::new();
+        let mut notes = Vec::new();
+        for root_dir in root_dirs {
+            let (vault, vault_notes) = Vault::scan(context, root_dir)?;
+            vaults.push(vault);
+            notes.extend(vault_notes);
+        }
+        notes.sort();
+        notes.dedup();
+
+        Ok((Vaults(vaults), notes))
+    }
+
+    /// The vaults of the workspace folders at `root_dirs`, with each note read into the vault owning it
+    pub fn construct(context: &Settings, root_dirs: &[PathBuf]) -> Result<Vaults, std::io::Error> {
+        let (mut vaults, notes) = Vaults::scan(context, root_dirs)?;
+        vaults.insert_notes(Vault::read_notes(context, &notes));
+
+        Ok(vaults)
+    }
+
+    pub fn root_dirs(&self) -> Vec<PathBuf> {
+        self.0
+            .iter()
+            .map(|vault| vault.root_dir().clone())
+            .collect()
+    }
+
+    /// The vault of the innermost workspace folder holding `path`
+    pub fn owning(&self, path: &Path) -> Option<&Vault> {
+        self.0
+            .iter()
+            .filter(|vault| path.starts_with(vault.root_dir()))
+            .max_by_key(|vault| vault.root_dir().components().count())
+    }
+
+    pub fn owning_mut(&mut self, path: &Path) -> Option<&mut Vault> {
+        self.0
+            .iter_mut()
+            .filter(|vault| path.starts_with(vault.root_dir()))
+            .max_by_key(|vault| vault.root_dir().components().count())
+    }
+
+    /// The vault of the first workspace folder; commands that are not about a note, like opening a daily note, use it
+    pub fn primary(&self) -> Option<&Vault> {
+        self.0.first()
+    }
+
+    /// The vaults other than `vault`, for links that resolve in none of its notes
+    pub fn others<'a>(&'a self, vault: &'a Vault) -> impl Iterator<Item = &'a Vault> {
+        self.0
+            .iter()
+            .filter(move |other| other.root_dir() != vault.root_dir())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Vault> {
+        self.0.iter()
+    }
+
+    /// Insert each note into the vault owning it; a note is read once even when it is in nested workspace folders
+    pub fn insert_notes(&mut self, notes: Vec<ReadNote>) {
+        let by_vault = notes
+            .into_iter()
+            .filter_map(|note| {
+                let root_dir = self.owning(&note.path)?.root_dir().clone();
+                Some((root_dir, note))
+            })
+            .into_group_map();
+
+        for (root_dir, notes) in by_vault {
+            if let Some(vault) = self
+                .0
+                .iter_mut()
+                .find(|vault| *vault.root_dir() == root_dir)
+            {
+                vault.insert_notes(notes);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use itertools::Itertools;
+    use tower_lsp::lsp_types::ClientCapabilities;
+
+    use crate::{config::Settings, vault::Vault};
+
+    use super::Vaults;
+
+    /// Vaults at `/work` and `/home`, both with a `shared` note, and a `/work/nested` vault inside the first
+    fn vaults(settings: &Settings) -> Vaults {
+        let mut vaults = ["/work", "/home", "/work/nested"]
+            .into_iter()
+            .map(|root_dir| Vault::construct_vault(settings, Path::new(root_dir)).unwrap())
+            .collect_vec();
+
+        for (vault, note) in vaults.iter_mut().zip(["/work", "/home"]) {
+            Vault::update_vault(
+                settings,
+                vault,
+                (&PathBuf::from(format!("{note}/shared.md")), "# Shared\n"),
+            );
+            Vault::update_vault(
+                settings,
+                vault,
+                (
+                    &PathBuf::from(format!("{note}/note.md")),
+                    "[[shared]] [[shared#Shared]]",
+                ),
+            );
+        }
+
+        Vaults(vaults)
+    }
+
+    #[test]
+    fn notes_belong_to_the_innermost_folder_holding_them() {
+        let settings = Settings::new(Path::new("/work"), &ClientCapabilities::default()).unwrap();
+        let vaults = vaults(&settings);
+        let owner = |path: &str| {
+            vaults
+                .owning(Path::new(path))
+                .map(|vault| vault.root_dir().clone())
+        };
+
+        assert_eq!(owner("/work/note.md"), Some(PathBuf::from("/work")));
+        assert_eq!(owner("/home/note.md"), Some(PathBuf::from("/home")));
+        assert_eq!(
+            owner("/work/nested/deep/note.md"),
+            Some(PathBuf::from("/work/nested"))
+        );
+        assert_eq!(owner("/elsewhere/note.md"), None);
+    }
+
+    #[test]
+    fn constructed_vaults_hold_each_note_once() {
+        let root_dir = std::env::temp_dir().join(format!("moxide-vaults-{}", std::process::id()));
+        let nested = root_dir.join("nested");
+        for path in [root_dir.join("outer.md"), nested.join("inner.md")] {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "# Note\n").unwrap();
+        }
+
+        let settings = Settings::new(&root_dir, &ClientCapabilities::default()).unwrap();
+        let vaults = Vaults::construct(&settings, &[root_dir.clone(), nested.clone()]).unwrap();
+        std::fs::remove_dir_all(&root_dir).unwrap();
+
+        let notes = vaults
+            .iter()
+            .map(|vault| vault.md_files.keys().cloned().sorted().collect_vec())
+            .collect_vec();
+        assert_eq!(
+            notes,
+            vec![
+                vec![root_dir.join("outer.md")],
+                vec![nested.join("inner.md")]
+            ]
+        );
+        assert_eq!(vaults.root_dirs(), vec![root_dir, nested]);
+    }
+
+    #[test]
+    fn links_resolve_within_their_own_vault() {
+        let settings = Settings::new(Path::new("/work"), &ClientCapabilities::default()).unwrap();
+        let vaults = vaults(&settings);
+
+        for root_dir in ["/work", "/home"] {
+            let note = PathBuf::from(format!("{root_dir}/note.md"));
+            let vault = vaults.owning(&note).unwrap();
+
+            let targets = vault.md_files[&note]
+                .references
+                .iter()
+                .flat_map(|reference| vault.select_referenceables_for_reference(reference, &note))
+                .map(|referenceable| referenceable.get_path().to_path_buf())
+                .collect_vec();
+
+            assert_eq!(
+                targets,
+                vec![PathBuf::from(format!("{root_dir}/shared.md")); 2]
+            );
+        }
+    }
+}